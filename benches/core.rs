@@ -0,0 +1,58 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Benchmarks of core paths on the manifests in `tests/manifests`.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use homebins::bench::{self, ChecksumWorkload};
+use homebins::checksum::Algorithm;
+use homebins::manifest::{Arch, Os, Platform};
+use homebins::ManifestStore;
+
+fn store() -> ManifestStore {
+    ManifestStore::open(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/manifests").into())
+}
+
+fn manifests(c: &mut Criterion) {
+    let store = store();
+    c.bench_function("parse manifests", |b| {
+        b.iter(|| bench::parse_manifests(&store).unwrap())
+    });
+
+    let manifests = bench::parse_manifests(&store).unwrap();
+    let platform = Platform {
+        os: Os::Linux,
+        arch: Arch::X86_64,
+    };
+    c.bench_function("plan installs", |b| {
+        b.iter(|| bench::plan_installs(&manifests, platform))
+    });
+}
+
+fn checksums(c: &mut Criterion) {
+    let mut group = c.benchmark_group("validate checksum");
+    for algorithm in [Algorithm::Sha256, Algorithm::Sha512, Algorithm::B3] {
+        let workload = ChecksumWorkload::new(4 * 1024 * 1024, algorithm);
+        group.throughput(Throughput::Bytes(workload.size() as u64));
+        group.bench_function(algorithm.to_string(), |b| {
+            b.iter(|| workload.validate().unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn store_scan(c: &mut Criterion) {
+    let store = store();
+    c.bench_function("scan store", |b| {
+        b.iter(|| bench::scan_store(&store).unwrap())
+    });
+    c.bench_function("index store", |b| {
+        b.iter(|| bench::index_store(&store).unwrap())
+    });
+}
+
+criterion_group!(benches, manifests, checksums, store_scan);
+criterion_main!(benches);
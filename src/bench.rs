@@ -0,0 +1,297 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Workloads of core paths, for benchmarks.
+//!
+//! `cargo bench` measures these workloads with criterion on the manifests in `tests/manifests`,
+//! and `homebins bench-selftest` measures them on the manifests of the configured repositories,
+//! to compare changes to performance-sensitive code on real machines, with real manifests.
+//! A [`Baseline`] of an earlier run turns `bench-selftest` into a regression gate, which fails if
+//! any workload became slower than the baseline allows.
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context, Error, Result};
+use fehler::{throw, throws};
+
+use crate::checksum::{Algorithm, Hasher, ValidationError};
+use crate::manifest::{Checksums, ManifestIndex, Platform};
+use crate::state::{read_state, write_atomically};
+use crate::{Manifest, ManifestStore};
+
+/// Read and parse all manifests in `store`, bypassing its cache.
+#[throws]
+pub fn parse_manifests(store: &ManifestStore) -> Vec<Manifest> {
+    store
+        .manifest_paths()?
+        .iter()
+        .map(Manifest::read_from_path)
+        .collect::<Result<Vec<_>>>()?
+}
+
+/// Read summaries of all manifests in `store`, and return their number.
+#[throws]
+pub fn scan_store(store: &ManifestStore) -> usize {
+    store.summaries()?.collect::<Result<Vec<_>>>()?.len()
+}
+
+/// Index all manifests in `store` from scratch, and return the number of entries.
+#[throws]
+pub fn index_store(store: &ManifestStore) -> usize {
+    ManifestIndex::build(store, "bench", None)?.entries().len()
+}
+
+/// Plan the installation of all `manifests` on `platform`, and return the number of operations.
+pub fn plan_installs(manifests: &[Manifest], platform: Platform) -> usize {
+    manifests
+        .iter()
+        .map(|manifest| crate::operations::install_manifest(manifest, platform).len())
+        .sum()
+}
+
+/// Data to validate against a checksum.
+#[derive(Debug, Clone)]
+pub struct ChecksumWorkload {
+    data: Vec<u8>,
+    algorithm: Algorithm,
+    checksums: Checksums,
+}
+
+impl ChecksumWorkload {
+    /// Create `size` bytes of data with a checksum of `algorithm`.
+    pub fn new(size: usize, algorithm: Algorithm) -> ChecksumWorkload {
+        // Deterministic data which doesn't compress to nothing
+        let data: Vec<u8> = (0..size)
+            .map(|index| (index.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect();
+        let mut hasher = Hasher::new(&[algorithm]);
+        hasher.update(&data);
+        let checksum = hasher.finalize().remove(0).1;
+        let mut checksums = Checksums::default();
+        match algorithm {
+            Algorithm::B2 => checksums.b2 = Some(checksum),
+            Algorithm::Sha512 => checksums.sha512 = Some(checksum),
+            Algorithm::B3 => checksums.b3 = Some(checksum),
+            Algorithm::Sha3_256 => checksums.sha3_256 = Some(checksum),
+            Algorithm::Sha256 => checksums.sha256 = Some(checksum),
+            Algorithm::Sha1 => checksums.sha1 = Some(checksum),
+        }
+        ChecksumWorkload {
+            data,
+            algorithm,
+            checksums,
+        }
+    }
+
+    /// The number of bytes to validate.
+    pub fn size(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Hash all data and validate it against its checksum.
+    pub fn validate(&self) -> Result<Algorithm, ValidationError> {
+        let mut hasher = Hasher::new(&[self.algorithm]);
+        // Hash in chunks, like downloads
+        for chunk in self.data.chunks(64 * 1024) {
+            hasher.update(chunk);
+        }
+        hasher.validate(&self.checksums)
+    }
+}
+
+/// How long a workload took.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Measurement {
+    /// The name of the workload.
+    pub name: String,
+    /// How often the workload ran.
+    pub iterations: usize,
+    /// The fastest run.
+    pub min: Duration,
+    /// The median of all runs.
+    pub median: Duration,
+}
+
+impl Display for Measurement {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: median {:.2?}, min {:.2?} ({} runs)",
+            self.name, self.median, self.min, self.iterations
+        )
+    }
+}
+
+/// Run `workload` `iterations` times, and measure how long each run took.
+///
+/// Run the workload once more up front, to warm up caches.  Fail if any run fails.
+#[throws]
+pub fn measure<F: FnMut() -> Result<()>>(
+    name: &str,
+    iterations: usize,
+    mut workload: F,
+) -> Measurement {
+    workload().with_context(|| format!("Workload {} failed", name))?;
+    let mut runs = Vec::with_capacity(iterations);
+    for _ in 0..iterations.max(1) {
+        let start = Instant::now();
+        workload().with_context(|| format!("Workload {} failed", name))?;
+        runs.push(start.elapsed());
+    }
+    runs.sort();
+    Measurement {
+        name: name.to_string(),
+        iterations: runs.len(),
+        min: runs[0],
+        median: runs[runs.len() / 2],
+    }
+}
+
+/// Medians of workloads from an earlier run, to find regressions.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Baseline {
+    medians: BTreeMap<String, Duration>,
+}
+
+impl Baseline {
+    /// Take the medians of `measurements` as baseline.
+    pub fn from_measurements(measurements: &[Measurement]) -> Baseline {
+        Baseline {
+            medians: measurements
+                .iter()
+                .map(|measurement| (measurement.name.clone(), measurement.median))
+                .collect(),
+        }
+    }
+
+    /// Read a baseline from the file at `path`.
+    #[throws]
+    pub fn read_from_path<P: AsRef<Path>>(path: P) -> Baseline {
+        let path = path.as_ref();
+        let contents = read_state(path)?
+            .ok_or_else(|| anyhow!("Baseline {} does not exist", path.display()))?;
+        let nanos: BTreeMap<String, u64> = serde_json::from_str(&contents)
+            .with_context(|| format!("Invalid baseline in {}", path.display()))?;
+        Baseline {
+            medians: nanos
+                .into_iter()
+                .map(|(name, nanos)| (name, Duration::from_nanos(nanos)))
+                .collect(),
+        }
+    }
+
+    /// Write this baseline to the file at `path`, with medians in nanoseconds.
+    #[throws]
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> () {
+        let nanos: BTreeMap<&str, u64> = self
+            .medians
+            .iter()
+            .map(|(name, median)| (name.as_str(), median.as_nanos() as u64))
+            .collect();
+        write_atomically(path, serde_json::to_vec_pretty(&nanos)?)?;
+    }
+
+    /// Describe all `measurements` whose median exceeds the baseline by more than
+    /// `max_slowdown` percent.
+    ///
+    /// Ignore workloads which the baseline doesn't know.
+    pub fn regressions(&self, measurements: &[Measurement], max_slowdown: u32) -> Vec<String> {
+        measurements
+            .iter()
+            .filter_map(|measurement| {
+                let baseline = *self.medians.get(&measurement.name)?;
+                let limit = baseline + baseline * max_slowdown / 100;
+                if limit < measurement.median {
+                    Some(format!(
+                        "{}: median {:.2?}, more than {}% slower than {:.2?}",
+                        measurement.name, measurement.median, max_slowdown, baseline
+                    ))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Fail if any of `measurements` exceeds the baseline by more than `max_slowdown` percent.
+    #[throws]
+    pub fn check(&self, measurements: &[Measurement], max_slowdown: u32) -> () {
+        let regressions = self.regressions(measurements, max_slowdown);
+        if !regressions.is_empty() {
+            throw!(anyhow!("Workloads regressed:\n{}", regressions.join("\n")));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::{Arch, Os};
+    use pretty_assertions::assert_eq;
+    use std::path::PathBuf;
+
+    #[test]
+    fn run_workloads() {
+        let store = ManifestStore::open(PathBuf::from("tests/manifests"));
+        let manifests = parse_manifests(&store).unwrap();
+        assert_eq!(manifests.len(), scan_store(&store).unwrap());
+        assert_eq!(manifests.len(), index_store(&store).unwrap());
+        let platform = Platform {
+            os: Os::Linux,
+            arch: Arch::X86_64,
+        };
+        assert!(manifests.len() < plan_installs(&manifests, platform));
+
+        let workload = ChecksumWorkload::new(1024, Algorithm::B3);
+        assert_eq!(workload.size(), 1024);
+        assert_eq!(workload.validate().unwrap(), Algorithm::B3);
+
+        let mut runs = 0;
+        let measurement = measure("count", 3, || {
+            runs += 1;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(runs, 4);
+        assert_eq!(measurement.iterations, 3);
+        assert!(measurement.min <= measurement.median);
+    }
+
+    fn measurement(name: &str, median_millis: u64) -> Measurement {
+        Measurement {
+            name: name.to_string(),
+            iterations: 1,
+            min: Duration::from_millis(median_millis),
+            median: Duration::from_millis(median_millis),
+        }
+    }
+
+    #[test]
+    fn find_regressions_against_baseline() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("baseline.json");
+        Baseline::from_measurements(&[measurement("parse", 100), measurement("scan", 10)])
+            .save(&path)
+            .unwrap();
+        let baseline = Baseline::read_from_path(&path).unwrap();
+
+        let measurements = [
+            measurement("parse", 120),
+            measurement("scan", 13),
+            measurement("index", 1000),
+        ];
+        assert_eq!(
+            baseline.regressions(&measurements, 20),
+            vec!["scan: median 13.00ms, more than 20% slower than 10.00ms"]
+        );
+        assert!(baseline.check(&measurements, 20).is_err());
+        assert!(baseline.check(&measurements, 30).is_ok());
+        assert!(Baseline::read_from_path(dir.path().join("missing.json")).is_err());
+    }
+}
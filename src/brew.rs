@@ -0,0 +1,324 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Import Homebrew formulas and casks as draft manifests.
+//!
+//! Homebrew builds most formulas from source, and casks mostly contain macOS app bundles, so
+//! the import is a best effort: It takes the name, version, license, homepage and download of a
+//! formula or cask from the Homebrew API and leaves a draft manifest with `TODO` comments for
+//! everything that needs manual completion.
+
+use std::fmt::Write;
+
+use anyhow::{Context, Error};
+use fehler::throws;
+use serde::Deserialize;
+
+use crate::tools::fetch_text;
+
+/// The stable download of a formula.
+#[derive(Debug, Deserialize)]
+struct FormulaUrl {
+    url: String,
+    checksum: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FormulaUrls {
+    stable: FormulaUrl,
+}
+
+#[derive(Debug, Deserialize)]
+struct FormulaVersions {
+    stable: String,
+}
+
+/// A formula from the Homebrew API.
+#[derive(Debug, Deserialize)]
+struct Formula {
+    name: String,
+    desc: Option<String>,
+    homepage: String,
+    license: Option<String>,
+    versions: FormulaVersions,
+    urls: FormulaUrls,
+}
+
+/// A cask from the Homebrew API.
+#[derive(Debug, Deserialize)]
+struct Cask {
+    token: String,
+    desc: Option<String>,
+    homepage: String,
+    version: String,
+    url: String,
+    sha256: Option<String>,
+    #[serde(default)]
+    artifacts: Vec<serde_json::Value>,
+}
+
+impl Cask {
+    /// Get the file names of all binaries of this cask.
+    fn binaries(&self) -> Vec<&str> {
+        self.artifacts
+            .iter()
+            .filter_map(|artifact| artifact.get("binary")?.as_array())
+            .filter_map(|binary| binary.first()?.as_str())
+            .map(|path| path.rsplit('/').next().unwrap_or(path))
+            .collect()
+    }
+}
+
+/// The fields of a draft manifest.
+pub(crate) struct Draft<'a> {
+    /// Where the draft comes from, e.g. `Homebrew formula jq`.
+    pub origin: String,
+    pub name: &'a str,
+    pub desc: Option<&'a str>,
+    pub version: &'a str,
+    pub homepage: &'a str,
+    pub license: Option<&'a str>,
+    pub url: &'a str,
+    pub sha256: Option<&'a str>,
+    pub binaries: Vec<&'a str>,
+    /// Whether the download is a source archive rather than a prebuilt binary.
+    pub from_source: bool,
+}
+
+/// Quote `s` as TOML string.
+fn quote(s: &str) -> String {
+    toml::Value::String(s.to_string()).to_string()
+}
+
+/// Whether `url` points to an archive homebins can extract.
+fn is_archive(url: &str) -> bool {
+    [".tar.gz", ".tgz", ".tar.xz", ".tar.bz2", ".zip"]
+        .iter()
+        .any(|extension| url.ends_with(extension))
+}
+
+/// Get the GitHub repository of `url` as `owner/name`, if any.
+pub(crate) fn github_repo(url: &str) -> Option<String> {
+    let mut parts = url.strip_prefix("https://github.com/")?.split('/');
+    match (parts.next(), parts.next()) {
+        (Some(owner), Some(name)) if !owner.is_empty() && !name.is_empty() => {
+            Some(format!("{}/{}", owner, name))
+        }
+        _ => None,
+    }
+}
+
+impl<'a> Draft<'a> {
+    /// Render this draft as manifest.
+    pub(crate) fn render(&self) -> String {
+        let download = if self.version.is_empty() {
+            self.url.to_string()
+        } else {
+            self.url.replace(self.version, "{version}")
+        };
+        let binaries = if self.binaries.is_empty() {
+            vec![self.name]
+        } else {
+            self.binaries.clone()
+        };
+        let mut manifest = String::new();
+        // Writing to a string never fails
+        let mut line = |s: String| writeln!(manifest, "{}", s).unwrap();
+        line(format!(
+            "# Draft manifest imported from {}; complete all TODOs before use.",
+            self.origin
+        ));
+        line(String::new());
+        line("[info]".to_string());
+        line(format!("name = {}", quote(self.name)));
+        line(format!("version = {}", quote(self.version)));
+        line(format!("url = {}", quote(self.homepage)));
+        if let Some(desc) = self.desc {
+            line(format!("description = {}", quote(desc)));
+        }
+        match self.license {
+            Some(license) => line(format!("license = {}", quote(license))),
+            None => {
+                line("# TODO: Add the SPDX license expression".to_string());
+                line("license = \"TODO\"".to_string());
+            }
+        }
+        line(String::new());
+        line("[discover]".to_string());
+        line(format!("binary = {}", quote(binaries[0])));
+        line("version_check.args = [\"--version\"]".to_string());
+        line(format!(
+            "# TODO: Check the version output of {} and adapt the pattern",
+            binaries[0]
+        ));
+        line(format!(
+            "version_check.pattern = {}",
+            quote(r"(\d+(?:\.\d+)+)")
+        ));
+        line(String::new());
+        line("[[install]]".to_string());
+        if self.from_source {
+            line(format!(
+                "# TODO: Homebrew builds {} from source; replace with a prebuilt download",
+                self.name
+            ));
+        }
+        line(format!("download = {}", quote(&download)));
+        match self.sha256 {
+            Some(sha256) => line(format!("checksums.sha256 = {}", quote(sha256))),
+            None => line("# TODO: Add a checksum for the download".to_string()),
+        }
+        if is_archive(self.url) {
+            line("# TODO: Adapt the paths of the binaries in the archive".to_string());
+            let files: Vec<String> = binaries
+                .iter()
+                .map(|binary| format!("{{ source = {}, type = \"bin\" }}", quote(binary)))
+                .collect();
+            line(format!("files = [{}]", files.join(", ")));
+        } else {
+            line("# TODO: Check that the download is a binary".to_string());
+            line(format!("name = {}", quote(binaries[0])));
+            line("type = \"bin\"".to_string());
+        }
+        if let Some(repo) = github_repo(self.url).or_else(|| github_repo(self.homepage)) {
+            line(String::new());
+            line("[upstream]".to_string());
+            line("provider = \"github\"".to_string());
+            line(format!("repo = {}", quote(&repo)));
+        }
+        manifest
+    }
+}
+
+/// Convert a formula in Homebrew API `json` to a draft manifest.
+#[throws]
+fn formula_to_manifest(json: &str) -> String {
+    let formula: Formula = serde_json::from_str(json).with_context(|| "Invalid formula")?;
+    Draft {
+        origin: format!("Homebrew formula {}", formula.name),
+        name: &formula.name,
+        desc: formula.desc.as_deref(),
+        version: &formula.versions.stable,
+        homepage: &formula.homepage,
+        license: formula.license.as_deref(),
+        url: &formula.urls.stable.url,
+        sha256: formula.urls.stable.checksum.as_deref(),
+        binaries: Vec::new(),
+        from_source: true,
+    }
+    .render()
+}
+
+/// Convert a cask in Homebrew API `json` to a draft manifest.
+#[throws]
+fn cask_to_manifest(json: &str) -> String {
+    let cask: Cask = serde_json::from_str(json).with_context(|| "Invalid cask")?;
+    Draft {
+        origin: format!("Homebrew cask {}", cask.token),
+        name: &cask.token,
+        desc: cask.desc.as_deref(),
+        version: &cask.version,
+        homepage: &cask.homepage,
+        license: None,
+        url: &cask.url,
+        // Casks use "no_check" for downloads without checksum
+        sha256: cask.sha256.as_deref().filter(|s| *s != "no_check"),
+        binaries: cask.binaries(),
+        from_source: false,
+    }
+    .render()
+}
+
+/// Import the Homebrew formula or `cask` with the given `name` as draft manifest.
+///
+/// Fetch the formula or cask from the Homebrew API and return the text of the draft manifest.
+#[throws]
+pub fn import(name: &str, cask: bool) -> String {
+    let kind = if cask { "cask" } else { "formula" };
+    let url = format!("https://formulae.brew.sh/api/{}/{}.json", kind, name);
+    let json = fetch_text(&url).with_context(|| format!("Failed to fetch {} {}", kind, name))?;
+    if cask {
+        cask_to_manifest(&json)?
+    } else {
+        formula_to_manifest(&json)?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn import_formula() {
+        let json = r#"{
+  "name": "jq",
+  "full_name": "jq",
+  "desc": "Lightweight and flexible command-line JSON processor",
+  "license": "MIT",
+  "homepage": "https://jqlang.github.io/jq/",
+  "versions": { "stable": "1.7.1", "head": "HEAD", "bottle": true },
+  "urls": {
+    "stable": {
+      "url": "https://github.com/jqlang/jq/releases/download/jq-1.7.1/jq-1.7.1.tar.gz",
+      "tag": null,
+      "checksum": "478c9ca129fd2e3443fe27314b455e211e0d8c60bc8ff7df703873deeee580c2"
+    }
+  }
+}"#;
+        assert_eq!(
+            formula_to_manifest(json).unwrap(),
+            r#"# Draft manifest imported from Homebrew formula jq; complete all TODOs before use.
+
+[info]
+name = "jq"
+version = "1.7.1"
+url = "https://jqlang.github.io/jq/"
+description = "Lightweight and flexible command-line JSON processor"
+license = "MIT"
+
+[discover]
+binary = "jq"
+version_check.args = ["--version"]
+# TODO: Check the version output of jq and adapt the pattern
+version_check.pattern = "(\\d+(?:\\.\\d+)+)"
+
+[[install]]
+# TODO: Homebrew builds jq from source; replace with a prebuilt download
+download = "https://github.com/jqlang/jq/releases/download/jq-{version}/jq-{version}.tar.gz"
+checksums.sha256 = "478c9ca129fd2e3443fe27314b455e211e0d8c60bc8ff7df703873deeee580c2"
+# TODO: Adapt the paths of the binaries in the archive
+files = [{ source = "jq", type = "bin" }]
+
+[upstream]
+provider = "github"
+repo = "jqlang/jq"
+"#
+        );
+    }
+
+    #[test]
+    fn import_cask_with_binaries() {
+        let json = r#"{
+  "token": "foo",
+  "desc": null,
+  "homepage": "https://example.com/foo",
+  "version": "2.0",
+  "url": "https://example.com/foo-2.0.zip",
+  "sha256": "no_check",
+  "artifacts": [
+    { "app": ["Foo.app"] },
+    { "binary": ["$APPDIR/Foo.app/Contents/MacOS/foo-cli", { "target": "foo" }] }
+  ]
+}"#;
+        let manifest = cask_to_manifest(json).unwrap();
+        assert!(manifest.contains("license = \"TODO\"\n"));
+        assert!(manifest.contains("binary = \"foo-cli\"\n"));
+        assert!(manifest.contains("download = \"https://example.com/foo-{version}.zip\"\n"));
+        assert!(manifest.contains("# TODO: Add a checksum for the download\n"));
+        assert!(!manifest.contains("[upstream]"));
+    }
+}
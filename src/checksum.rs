@@ -4,11 +4,16 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-//! Checksum validation.
+//! Checksums.
+//!
+//! Compute checksums of data with a [`Hasher`], incrementally from any source and for several
+//! algorithms at once, and validate data against the [`Checksums`] of a manifest.
 
 use crate::manifest::Checksums;
-use digest::Digest;
-use std::io::{Read, Write};
+use digest::{Digest, DynDigest};
+use rayon::prelude::*;
+use std::fmt::{Display, Formatter};
+use std::io::{ErrorKind, Read, Write};
 use thiserror::Error;
 
 /// A checksum validation error.
@@ -16,7 +21,10 @@ use thiserror::Error;
 pub enum ValidationError {
     /// The checksum didn't match.
     #[error("The checksum didn't match, got {actual}")]
-    ChecksumMismatch { actual: String },
+    ChecksumMismatch {
+        /// The actual checksum of the data, as hex string.
+        actual: String,
+    },
     /// The checksum was empty.
     #[error("The checksum was empty")]
     ChecksumEmpty,
@@ -25,47 +33,380 @@ pub enum ValidationError {
     IO(#[from] std::io::Error),
 }
 
-pub trait Validate {
-    /// Validate the data read from the given source.
-    fn validate<R: Read>(&self, source: &mut R) -> Result<(), ValidationError>;
+/// A checksum algorithm.
+///
+/// Algorithms order from the strongest to the weakest.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Algorithm {
+    /// BLAKE2b.
+    B2,
+    /// SHA-512.
+    Sha512,
+    /// BLAKE3.
+    B3,
+    /// SHA3-256.
+    Sha3_256,
+    /// SHA-256.
+    Sha256,
+    /// SHA-1.
+    Sha1,
 }
 
-fn validate<D: Digest + Write, R: Read>(
-    reader: &mut R,
-    checksum: &[u8],
-) -> Result<(), ValidationError> {
-    if checksum.is_empty() {
-        Err(ValidationError::ChecksumEmpty)
-    } else {
-        let mut digest = D::new();
-        std::io::copy(reader, &mut digest)?;
-        let hash = digest.finalize();
-        if hash.as_slice() == checksum {
-            Ok(())
-        } else {
-            Err(ValidationError::ChecksumMismatch {
-                actual: hex::encode(hash),
-            })
+impl Algorithm {
+    /// All algorithms, strongest first.
+    pub const ALL: [Algorithm; 6] = [
+        Algorithm::B2,
+        Algorithm::Sha512,
+        Algorithm::B3,
+        Algorithm::Sha3_256,
+        Algorithm::Sha256,
+        Algorithm::Sha1,
+    ];
+
+    /// The name of this algorithm, as in the checksums of a manifest.
+    pub fn name(self) -> &'static str {
+        match self {
+            Algorithm::B2 => "b2",
+            Algorithm::Sha512 => "sha512",
+            Algorithm::B3 => "b3",
+            Algorithm::Sha3_256 => "sha3_256",
+            Algorithm::Sha256 => "sha256",
+            Algorithm::Sha1 => "sha1",
+        }
+    }
+
+    /// Get the checksum for this algorithm from `checksums`, if any.
+    pub fn checksum(self, checksums: &Checksums) -> Option<&[u8]> {
+        match self {
+            Algorithm::B2 => checksums.b2.as_deref(),
+            Algorithm::Sha512 => checksums.sha512.as_deref(),
+            Algorithm::B3 => checksums.b3.as_deref(),
+            Algorithm::Sha3_256 => checksums.sha3_256.as_deref(),
+            Algorithm::Sha256 => checksums.sha256.as_deref(),
+            Algorithm::Sha1 => checksums.sha1.as_deref(),
+        }
+    }
+
+    fn digest(self) -> Box<dyn DynDigest + Send> {
+        match self {
+            Algorithm::B2 => Box::new(blake2::Blake2b::new()),
+            Algorithm::Sha512 => Box::new(sha2::Sha512::new()),
+            Algorithm::B3 => Box::new(blake3::Hasher::new()),
+            Algorithm::Sha3_256 => Box::new(sha3::Sha3_256::new()),
+            Algorithm::Sha256 => Box::new(sha2::Sha256::new()),
+            Algorithm::Sha1 => Box::new(sha1::Sha1::new()),
+        }
+    }
+}
+
+impl Display for Algorithm {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Compute checksums of data incrementally, for several algorithms at once.
+///
+/// Feed data with [`Hasher::update`], [`Hasher::read_from`], or as [`Write`], e.g. with
+/// [`std::io::copy`], and get the checksums with [`Hasher::finalize`].  With more than one
+/// algorithm the hasher updates all checksums in parallel.
+pub struct Hasher {
+    digests: Vec<(Algorithm, Box<dyn DynDigest + Send>)>,
+}
+
+impl std::fmt::Debug for Hasher {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Hasher")
+            .field("algorithms", &self.algorithms().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl Hasher {
+    /// Create a hasher for the given `algorithms`.
+    pub fn new(algorithms: &[Algorithm]) -> Hasher {
+        Hasher {
+            digests: algorithms
+                .iter()
+                .map(|algorithm| (*algorithm, algorithm.digest()))
+                .collect(),
+        }
+    }
+
+    /// Create a hasher for all algorithms which `checksums` has a checksum for.
+    pub fn for_checksums(checksums: &Checksums) -> Hasher {
+        let algorithms: Vec<Algorithm> = Algorithm::ALL
+            .iter()
+            .copied()
+            .filter(|algorithm| algorithm.checksum(checksums).is_some())
+            .collect();
+        Hasher::new(&algorithms)
+    }
+
+    /// The algorithms of this hasher.
+    pub fn algorithms(&self) -> impl Iterator<Item = Algorithm> + '_ {
+        self.digests.iter().map(|(algorithm, _)| *algorithm)
+    }
+
+    /// Hash `data`.
+    pub fn update(&mut self, data: &[u8]) {
+        match self.digests.as_mut_slice() {
+            [(_, digest)] => digest.update(data),
+            digests => digests
+                .par_iter_mut()
+                .for_each(|(_, digest)| digest.update(data)),
+        }
+    }
+
+    /// Hash all data read from `source`, and return the number of bytes read.
+    pub fn read_from<R: Read>(&mut self, source: &mut R) -> std::io::Result<u64> {
+        let mut buffer = vec![0; 64 * 1024];
+        let mut read = 0;
+        loop {
+            match source.read(&mut buffer) {
+                Ok(0) => return Ok(read),
+                Ok(n) => {
+                    self.update(&buffer[..n]);
+                    read += n as u64;
+                }
+                Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    /// Get the checksums of all data hashed so far, by algorithm.
+    pub fn finalize(self) -> Vec<(Algorithm, Vec<u8>)> {
+        self.digests
+            .into_iter()
+            .map(|(algorithm, digest)| (algorithm, digest.finalize().to_vec()))
+            .collect()
+    }
+
+    /// Validate all data hashed so far against `checksums`.
+    ///
+    /// Validate the checksums of all algorithms of this hasher, and return the strongest
+    /// algorithm.  Fail if this hasher has no algorithms, or if `checksums` lacks a checksum for
+    /// any of its algorithms.
+    pub fn validate(self, checksums: &Checksums) -> Result<Algorithm, ValidationError> {
+        let hashes = self.finalize();
+        for (algorithm, hash) in &hashes {
+            let expected = algorithm
+                .checksum(checksums)
+                .filter(|checksum| !checksum.is_empty())
+                .ok_or(ValidationError::ChecksumEmpty)?;
+            if hash.as_slice() != expected {
+                return Err(ValidationError::ChecksumMismatch {
+                    actual: hex::encode(hash),
+                });
+            }
         }
+        hashes
+            .into_iter()
+            .map(|(algorithm, _)| algorithm)
+            .min()
+            .ok_or(ValidationError::ChecksumEmpty)
     }
 }
 
+impl Write for Hasher {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Get the algorithm and value of the strongest checksum in `checksums`.
+///
+/// This is the checksum which [`Validate`] validates downloads with.
+pub fn strongest_algorithm(checksums: &Checksums) -> Option<(Algorithm, &[u8])> {
+    Algorithm::ALL.iter().find_map(|algorithm| {
+        algorithm
+            .checksum(checksums)
+            .map(|checksum| (*algorithm, checksum))
+    })
+}
+
+/// Get the name and value of the strongest checksum in `checksums`.
+///
+/// This is the checksum which [`Validate`] validates downloads with.
+pub fn strongest(checksums: &Checksums) -> Option<(&'static str, &[u8])> {
+    strongest_algorithm(checksums).map(|(algorithm, checksum)| (algorithm.name(), checksum))
+}
+
+/// Validate the data read from `source` against all checksums in `checksums`.
+///
+/// Unlike [`Validate`] this doesn't just validate the strongest checksum.  It still reads
+/// `source` only once, and computes all checksums in parallel.  Return the strongest algorithm.
+pub fn validate_all<R: Read>(
+    checksums: &Checksums,
+    source: &mut R,
+) -> Result<Algorithm, ValidationError> {
+    let mut hasher = Hasher::for_checksums(checksums);
+    if hasher.digests.is_empty()
+        || hasher
+            .algorithms()
+            .any(|algorithm| algorithm.checksum(checksums).is_some_and(<[u8]>::is_empty))
+    {
+        return Err(ValidationError::ChecksumEmpty);
+    }
+    hasher.read_from(source)?;
+    hasher.validate(checksums)
+}
+
+/// Find the checksum of the file with the given `filename` in `sums`.
+///
+/// `sums` has one checksum per line, followed by whitespace, an optional `*` to denote binary
+/// mode, and the file name, as printed by `sha256sum` and similar tools.  Ignore directories in
+/// file names.  Return `None` if `sums` has no line for `filename`.
+pub fn find_checksum(sums: &str, filename: &str) -> Option<Vec<u8>> {
+    sums.lines().find_map(|line| {
+        let mut parts = line.split_whitespace();
+        let checksum = parts.next()?;
+        let name = parts.next()?.trim_start_matches('*');
+        if name.rsplit('/').next() == Some(filename) {
+            hex::decode(checksum).ok()
+        } else {
+            None
+        }
+    })
+}
+
+/// Validate data against checksums.
+pub trait Validate {
+    /// Validate the data read from the given source, and return the algorithm which matched.
+    fn validate<R: Read>(&self, source: &mut R) -> Result<Algorithm, ValidationError>;
+}
+
 impl Validate for Checksums {
-    fn validate<R: Read>(&self, source: &mut R) -> Result<(), ValidationError> {
-        match self {
-            Checksums { b2: Some(b2), .. } => validate::<blake2::Blake2b, _>(source, &b2),
-            Checksums {
-                sha512: Some(sha512),
-                ..
-            } => validate::<sha2::Sha512, _>(source, &sha512),
-            Checksums {
-                sha256: Some(sha256),
-                ..
-            } => validate::<sha2::Sha256, _>(source, &sha256),
-            Checksums {
-                sha1: Some(sha1), ..
-            } => validate::<sha1::Sha1, _>(source, &sha1),
-            Checksums { sha1: None, .. } => Err(ValidationError::ChecksumEmpty),
+    /// Validate the data read from `source` against the strongest checksum.
+    fn validate<R: Read>(&self, source: &mut R) -> Result<Algorithm, ValidationError> {
+        match strongest_algorithm(self) {
+            Some((_, [])) | None => Err(ValidationError::ChecksumEmpty),
+            Some((algorithm, _)) => {
+                let mut hasher = Hasher::new(&[algorithm]);
+                hasher.read_from(source)?;
+                hasher.validate(self)
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checksums(data: &[u8]) -> Checksums {
+        Checksums {
+            b2: Some(blake2::Blake2b::digest(data).to_vec()),
+            sha512: None,
+            b3: Some(blake3::Hasher::digest(data).to_vec()),
+            sha3_256: Some(sha3::Sha3_256::digest(data).to_vec()),
+            sha256: Some(sha2::Sha256::digest(data).to_vec()),
+            sha1: Some(sha1::Sha1::digest(data).to_vec()),
+            sha256_url: None,
+        }
+    }
+
+    #[test]
+    fn validate_all_checksums() {
+        let data = b"Hello world".repeat(10000);
+        assert_eq!(
+            validate_all(&checksums(&data), &mut data.as_slice()).unwrap(),
+            Algorithm::B2
+        );
+    }
+
+    #[test]
+    fn hash_incrementally() {
+        let data = b"Hello world".repeat(10000);
+        let mut hasher = Hasher::new(&[Algorithm::Sha256, Algorithm::B3]);
+        for chunk in data.chunks(1000) {
+            hasher.update(chunk);
+        }
+        assert_eq!(
+            hasher.finalize(),
+            vec![
+                (Algorithm::Sha256, sha2::Sha256::digest(&data).to_vec()),
+                (Algorithm::B3, blake3::Hasher::digest(&data).to_vec())
+            ]
+        );
+
+        let mut hasher = Hasher::for_checksums(&checksums(&data));
+        assert_eq!(
+            hasher.algorithms().collect::<Vec<_>>(),
+            vec![
+                Algorithm::B2,
+                Algorithm::B3,
+                Algorithm::Sha3_256,
+                Algorithm::Sha256,
+                Algorithm::Sha1
+            ]
+        );
+        std::io::copy(&mut data.as_slice(), &mut hasher).unwrap();
+        assert_eq!(hasher.validate(&checksums(&data)).unwrap(), Algorithm::B2);
+    }
+
+    #[test]
+    fn validate_all_checksums_with_weak_mismatch() {
+        let data = b"Hello world".repeat(10000);
+        let mut checksums = checksums(&data);
+        checksums.sha1 = Some(sha1::Sha1::digest(b"Hello").to_vec());
+        // The strongest checksum still matches…
+        checksums.validate(&mut data.as_slice()).unwrap();
+        // …but not all of them do
+        let error = validate_all(&checksums, &mut data.as_slice()).unwrap_err();
+        assert!(matches!(error, ValidationError::ChecksumMismatch { .. }));
+    }
+
+    #[test]
+    fn validate_blake3_and_sha3() {
+        let data = b"Hello world";
+        let b3 = Checksums {
+            b3: Some(
+                hex::decode("e7e6fb7d2869d109b62cdb1227208d4016cdaa0af6603d95223c6a698137d945")
+                    .unwrap(),
+            ),
+            ..Checksums::default()
+        };
+        assert_eq!(b3.validate(&mut data.as_ref()).unwrap(), Algorithm::B3);
+        assert_eq!(strongest(&b3).map(|(name, _)| name), Some("b3"));
+        let sha3_256 = Checksums {
+            sha3_256: Some(
+                hex::decode("369183d3786773cef4e56c7b849e7ef5f742867510b676d6b38f8e38a222d8a2")
+                    .unwrap(),
+            ),
+            sha256: Some(vec![0xab]),
+            ..Checksums::default()
+        };
+        sha3_256.validate(&mut data.as_ref()).unwrap();
+        assert_eq!(strongest(&sha3_256).map(|(name, _)| name), Some("sha3_256"));
+    }
+
+    #[test]
+    fn find_checksum_in_sums() {
+        let sums = "\
+e7e6fb7d2869d109b62cdb1227208d4016cdaa0af6603d95223c6a698137d945  foo-1.0-linux.tar.gz
+369183d3786773cef4e56c7b849e7ef5f742867510b676d6b38f8e38a222d8a2 *./dist/foo-1.0-macos.tar.gz
+";
+        assert_eq!(
+            find_checksum(sums, "foo-1.0-macos.tar.gz").map(hex::encode),
+            Some("369183d3786773cef4e56c7b849e7ef5f742867510b676d6b38f8e38a222d8a2".to_string())
+        );
+        assert_eq!(
+            find_checksum(sums, "foo-1.0-linux.tar.gz").map(hex::encode),
+            Some("e7e6fb7d2869d109b62cdb1227208d4016cdaa0af6603d95223c6a698137d945".to_string())
+        );
+        assert_eq!(find_checksum(sums, "foo-1.0.tar.gz"), None);
+    }
+
+    #[test]
+    fn validate_all_without_checksums() {
+        let error = validate_all(&Checksums::default(), &mut b"foo".as_ref()).unwrap_err();
+        assert!(matches!(error, ValidationError::ChecksumEmpty));
+    }
+}
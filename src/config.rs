@@ -0,0 +1,159 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Homebins configuration.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Error};
+use fehler::throws;
+use serde::Deserialize;
+
+use crate::operations::DestinationKind;
+use crate::repos::check_repo_configs;
+use crate::secrets::Secret;
+use crate::{HookIsolation, RepoConfig};
+
+/// What to do with files for destinations which the system doesn't use.
+///
+/// E.g. fish completions if fish is not installed, or systemd user units if systemd isn't
+/// running.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MissingDestinations {
+    /// Skip these files, and tell which kinds of files homebins skipped.
+    Skip,
+    /// Install these files regardless.
+    #[default]
+    Create,
+    /// Ask whether to install these files, and skip them if there's no terminal to ask.
+    Ask,
+}
+
+/// The homebins configuration.
+#[derive(Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Keep the working directory of a manifest if applying its operations failed.
+    pub keep_workdir: bool,
+    /// Kinds of destinations to never install files to.
+    pub skip_destinations: Vec<DestinationKind>,
+    /// A file to log integrity events of downloads to.
+    pub event_log: Option<PathBuf>,
+    /// Seconds to wait for version checks of binaries before killing them.
+    pub version_check_timeout: Option<u64>,
+    /// Seconds to use cached upstream responses without revalidating them.
+    pub upstream_cache_ttl: Option<u64>,
+    /// Run commands of manifests with a command upstream in `upstream-outdated`.
+    pub allow_upstream_commands: bool,
+    /// Seconds to use manifest repositories without fetching them again.
+    pub repo_fetch_ttl: Option<u64>,
+    /// Tokens to download from hosts with, by host name.
+    pub tokens: HashMap<String, Secret>,
+    /// Install binaries with versioned names, unless a manifest says otherwise.
+    pub versioned_binaries: bool,
+    /// Refuse downloads whose minisign signature doesn't verify with a trusted key.
+    pub require_trusted_keys: bool,
+    /// How to isolate hooks of manifests.
+    pub hook_isolation: HookIsolation,
+    /// What to do with files for destinations which the system doesn't use.
+    pub missing_destinations: MissingDestinations,
+    /// How many files of a manifest to download at once.
+    pub jobs: Option<usize>,
+    /// The manifest repositories to install from, if not the [`crate::default_repos`].
+    pub repos: Option<Vec<RepoConfig>>,
+}
+
+impl Config {
+    /// Read configuration from the file denoted by the given `path`.
+    ///
+    /// Return the default configuration if `path` does not exist.
+    #[throws]
+    pub fn read_from_path<P: AsRef<Path>>(path: P) -> Config {
+        match std::fs::read_to_string(path.as_ref()) {
+            Ok(contents) => {
+                let config: Config = toml::from_str(&contents).with_context(|| {
+                    format!("File {} is no valid configuration", path.as_ref().display())
+                })?;
+                if let Some(repos) = &config.repos {
+                    check_repo_configs(repos).with_context(|| {
+                        format!(
+                            "Invalid manifest repositories in {}",
+                            path.as_ref().display()
+                        )
+                    })?;
+                }
+                config
+            }
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => Config::default(),
+            Err(error) => {
+                Err(error).with_context(|| format!("Failed to read {}", path.as_ref().display()))?
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::Shell;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn read_non_existing_config() {
+        let config = Config::read_from_path("tests/non-existing-config.toml").unwrap();
+        assert_eq!(config, Config::default());
+    }
+
+    #[test]
+    fn deserialize_config() {
+        let config: Config = toml::from_str(
+            "keep_workdir = true\nskip_destinations = [\"systemd_user_units\", \"fish_completions\"]",
+        )
+        .unwrap();
+        assert_eq!(
+            config,
+            Config {
+                keep_workdir: true,
+                skip_destinations: vec![
+                    DestinationKind::SystemdUserUnits,
+                    DestinationKind::ShellCompletions(Shell::Fish)
+                ],
+                event_log: None,
+                version_check_timeout: None,
+                upstream_cache_ttl: None,
+                allow_upstream_commands: false,
+                repo_fetch_ttl: None,
+                tokens: HashMap::new(),
+                versioned_binaries: false,
+                require_trusted_keys: false,
+                hook_isolation: HookIsolation::Off,
+                missing_destinations: MissingDestinations::Create,
+                jobs: None,
+                repos: None,
+            }
+        );
+    }
+
+    #[test]
+    fn deserialize_hook_isolation() {
+        let config: Config = toml::from_str("hook_isolation = \"no_network\"").unwrap();
+        assert_eq!(config.hook_isolation, HookIsolation::NoNetwork);
+    }
+
+    #[test]
+    fn deserialize_missing_destinations() {
+        let config: Config = toml::from_str("missing_destinations = \"ask\"").unwrap();
+        assert_eq!(config.missing_destinations, MissingDestinations::Ask);
+    }
+
+    #[test]
+    fn deserialize_config_with_unknown_destination() {
+        let error = toml::from_str::<Config>("skip_destinations = [\"spam\"]").unwrap_err();
+        assert!(error.to_string().contains("Unknown kind of destination"));
+    }
+}
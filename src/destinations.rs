@@ -0,0 +1,212 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Destinations which the system doesn't use.
+//!
+//! Fish completions are of no use without fish, manpages none without man, and systemd user
+//! units none without systemd.  Homebins finds these destinations, and installs, skips or asks
+//! about their files as [`MissingDestinations`] says.
+
+use std::ffi::{OsStr, OsString};
+use std::path::Path;
+
+use anyhow::{Error, Result};
+use fehler::throws;
+
+use crate::config::MissingDestinations;
+use crate::manifest::Shell;
+use crate::operations::DestinationKind;
+
+/// Shells which have completions, with the name of their binary.
+const COMPLETION_SHELLS: [(Shell, &str); 1] = [(Shell::Fish, "fish")];
+
+/// A kind of destination which the system doesn't use, and why.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnusedDestination {
+    /// The kind of destination.
+    pub kind: DestinationKind,
+    /// Why the system doesn't use this destination, e.g. "fish is not installed".
+    pub reason: String,
+}
+
+/// Whether `binary` is in any directory of `path`, in the format of `$PATH`.
+fn in_path(path: &OsStr, binary: &str) -> bool {
+    std::env::split_paths(path).any(|dir| dir.join(binary).is_file())
+}
+
+/// Get `$PATH`.
+fn path_var() -> OsString {
+    std::env::var_os("PATH").unwrap_or_default()
+}
+
+/// Get the kinds of completions for shells which are not in `path`, in the format of `$PATH`.
+pub fn unused_completions_in(path: &OsStr) -> Vec<DestinationKind> {
+    COMPLETION_SHELLS
+        .iter()
+        .filter(|(_, binary)| !in_path(path, binary))
+        .map(|(shell, _)| DestinationKind::ShellCompletions(*shell))
+        .collect()
+}
+
+/// Get the kinds of completions for shells which are not in `$PATH`.
+pub fn unused_completions() -> Vec<DestinationKind> {
+    unused_completions_in(&path_var())
+}
+
+/// Get the destinations which a system with `path` doesn't use, and why.
+///
+/// `path` is in the format of `$PATH`; `systemd_running` tells whether systemd is running.
+pub fn unused_destinations_in(path: &OsStr, systemd_running: bool) -> Vec<UnusedDestination> {
+    let mut unused: Vec<UnusedDestination> = COMPLETION_SHELLS
+        .iter()
+        .filter(|(_, binary)| !in_path(path, binary))
+        .map(|(shell, binary)| UnusedDestination {
+            kind: DestinationKind::ShellCompletions(*shell),
+            reason: format!("{} is not installed", binary),
+        })
+        .collect();
+    if !in_path(path, "man") {
+        unused.push(UnusedDestination {
+            kind: DestinationKind::Manpages,
+            reason: "man is not installed".to_string(),
+        });
+    }
+    if !systemd_running {
+        unused.push(UnusedDestination {
+            kind: DestinationKind::SystemdUserUnits,
+            reason: "systemd is not running".to_string(),
+        });
+    }
+    unused
+}
+
+/// Get the destinations which this system doesn't use, and why.
+pub fn unused_destinations() -> Vec<UnusedDestination> {
+    // See sd_booted(3)
+    unused_destinations_in(&path_var(), Path::new("/run/systemd/system").is_dir())
+}
+
+/// Whether `skipped` kinds of destinations already include `kind`.
+fn already_skipped(skipped: &[DestinationKind], kind: DestinationKind) -> bool {
+    skipped.contains(&kind)
+        || (matches!(kind, DestinationKind::ShellCompletions(_))
+            && skipped.contains(&DestinationKind::Completions))
+}
+
+/// Get the `unused` destinations to skip with the given `policy`.
+///
+/// Leave out destinations which `skipped` already includes.  For [`MissingDestinations::Ask`]
+/// call `ask` for every destination, and skip it unless `ask` returns `true`.
+#[throws]
+pub fn destinations_to_skip<F>(
+    unused: Vec<UnusedDestination>,
+    skipped: &[DestinationKind],
+    policy: MissingDestinations,
+    mut ask: F,
+) -> Vec<UnusedDestination>
+where
+    F: FnMut(&UnusedDestination) -> Result<bool>,
+{
+    let mut to_skip = Vec::new();
+    if policy == MissingDestinations::Create {
+        return to_skip;
+    }
+    for destination in unused {
+        if already_skipped(skipped, destination.kind) {
+            continue;
+        }
+        let install = policy == MissingDestinations::Ask && ask(&destination)?;
+        if !install {
+            to_skip.push(destination);
+        }
+    }
+    to_skip
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn unused(kind: DestinationKind, reason: &str) -> UnusedDestination {
+        UnusedDestination {
+            kind,
+            reason: reason.to_string(),
+        }
+    }
+
+    #[test]
+    fn unused_destinations_of_bare_system() {
+        let bin = tempfile::tempdir().unwrap();
+        assert_eq!(
+            unused_destinations_in(bin.path().as_os_str(), false),
+            vec![
+                unused(
+                    DestinationKind::ShellCompletions(Shell::Fish),
+                    "fish is not installed"
+                ),
+                unused(DestinationKind::Manpages, "man is not installed"),
+                unused(DestinationKind::SystemdUserUnits, "systemd is not running"),
+            ]
+        );
+        assert_eq!(
+            unused_completions_in(bin.path().as_os_str()),
+            vec![DestinationKind::ShellCompletions(Shell::Fish)]
+        );
+
+        std::fs::write(bin.path().join("fish"), "").unwrap();
+        std::fs::write(bin.path().join("man"), "").unwrap();
+        assert!(unused_destinations_in(bin.path().as_os_str(), true).is_empty());
+        assert!(unused_completions_in(bin.path().as_os_str()).is_empty());
+    }
+
+    #[test]
+    fn skip_unused_destinations_by_policy() {
+        let all = vec![
+            unused(
+                DestinationKind::ShellCompletions(Shell::Fish),
+                "fish is not installed",
+            ),
+            unused(DestinationKind::Manpages, "man is not installed"),
+        ];
+        let never_asked = |_: &UnusedDestination| -> Result<bool> { panic!("Asked") };
+        assert_eq!(
+            destinations_to_skip(all.clone(), &[], MissingDestinations::Create, never_asked)
+                .unwrap(),
+            vec![]
+        );
+        assert_eq!(
+            destinations_to_skip(all.clone(), &[], MissingDestinations::Skip, never_asked).unwrap(),
+            all
+        );
+        assert_eq!(
+            destinations_to_skip(
+                all.clone(),
+                &[DestinationKind::Completions],
+                MissingDestinations::Skip,
+                never_asked
+            )
+            .unwrap(),
+            all[1..].to_vec()
+        );
+        let mut asked = Vec::new();
+        assert_eq!(
+            destinations_to_skip(all.clone(), &[], MissingDestinations::Ask, |destination| {
+                asked.push(destination.kind);
+                Ok(destination.kind == DestinationKind::Manpages)
+            })
+            .unwrap(),
+            all[..1].to_vec()
+        );
+        assert_eq!(
+            asked,
+            vec![
+                DestinationKind::ShellCompletions(Shell::Fish),
+                DestinationKind::Manpages
+            ]
+        );
+    }
+}
@@ -4,14 +4,20 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-use crate::manifest::Shell;
+use crate::manifest::{IconSize, IconTheme, Shell};
 use crate::operations::{DestinationDirectory, SourceDirectory};
-use crate::Manifest;
+use crate::secrets::{token_for, Secret};
+use crate::trust::TrustedKey;
+use crate::{HookIsolation, Manifest};
 use anyhow::{Context, Result};
 use directories::{BaseDirs, ProjectDirs};
 use std::borrow::Cow;
+use std::collections::HashMap;
+use std::ffi::OsString;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use tempfile::{tempdir, TempDir};
+use url::Url;
 
 /// Get the project directories for homebins.
 fn project_dirs() -> Result<ProjectDirs> {
@@ -27,7 +33,16 @@ fn project_dirs() -> Result<ProjectDirs> {
 #[derive(Debug)]
 pub struct HomebinProjectDirs {
     repos_dir: PathBuf,
+    index_dir: PathBuf,
     download_dir: PathBuf,
+    upstream_cache_file: PathBuf,
+    outdated_cache_file: PathBuf,
+    config_file: PathBuf,
+    pins_file: PathBuf,
+    trusted_keys_file: PathBuf,
+    provenance_file: PathBuf,
+    login_hosts_file: PathBuf,
+    lock_file: PathBuf,
 }
 
 impl HomebinProjectDirs {
@@ -35,20 +50,94 @@ impl HomebinProjectDirs {
     pub fn open() -> Result<HomebinProjectDirs> {
         project_dirs().map(|dirs| HomebinProjectDirs {
             repos_dir: dirs.cache_dir().join("manifest_repos"),
+            index_dir: dirs.cache_dir().join("manifest_index"),
             download_dir: dirs.cache_dir().join("downloads"),
+            upstream_cache_file: dirs.cache_dir().join("upstream.json"),
+            outdated_cache_file: dirs.cache_dir().join("outdated.json"),
+            config_file: dirs.config_dir().join("config.toml"),
+            pins_file: dirs.config_dir().join("pins"),
+            trusted_keys_file: dirs.config_dir().join("trusted_keys"),
+            provenance_file: dirs.data_dir().join("provenance.json"),
+            login_hosts_file: dirs.data_dir().join("login_hosts.json"),
+            lock_file: dirs.data_dir().join("lock"),
         })
     }
 
+    /// Use project directories below the given `root` directory.
+    ///
+    /// Put the cache to `root/cache`, the configuration to `root/config`, and data to
+    /// `root/data`.  This is mostly useful for testing.
+    pub fn in_directory(root: &Path) -> HomebinProjectDirs {
+        HomebinProjectDirs {
+            repos_dir: root.join("cache").join("manifest_repos"),
+            index_dir: root.join("cache").join("manifest_index"),
+            download_dir: root.join("cache").join("downloads"),
+            upstream_cache_file: root.join("cache").join("upstream.json"),
+            outdated_cache_file: root.join("cache").join("outdated.json"),
+            config_file: root.join("config").join("config.toml"),
+            pins_file: root.join("config").join("pins"),
+            trusted_keys_file: root.join("config").join("trusted_keys"),
+            provenance_file: root.join("data").join("provenance.json"),
+            login_hosts_file: root.join("data").join("login_hosts.json"),
+            lock_file: root.join("data").join("lock"),
+        }
+    }
+
+    /// Get the path of the configuration file.
+    pub fn config_file(&self) -> &Path {
+        &self.config_file
+    }
+
+    /// Get the file with pinned manifests.
+    pub fn pins_file(&self) -> &Path {
+        &self.pins_file
+    }
+
+    /// Get the file with trusted minisign keys.
+    pub fn trusted_keys_file(&self) -> &Path {
+        &self.trusted_keys_file
+    }
+
+    /// Get the file with provenance records of installed files.
+    pub fn provenance_file(&self) -> &Path {
+        &self.provenance_file
+    }
+
+    /// Get the file with the hosts of `homebins auth login`, see [`crate::secrets::LoginHosts`].
+    pub fn login_hosts_file(&self) -> &Path {
+        &self.login_hosts_file
+    }
+
+    /// Get the file to lock homebins state with, see [`crate::StateLock`].
+    pub fn lock_file(&self) -> &Path {
+        &self.lock_file
+    }
+
     /// Get the directory for manifest repositories.
     pub fn repos_dir(&self) -> &Path {
         &self.repos_dir
     }
 
+    /// Get the directory for indexes of manifest repositories.
+    pub fn index_dir(&self) -> &Path {
+        &self.index_dir
+    }
+
     /// Get the directory for manifest downloads.
     pub fn download_dir(&self) -> &Path {
         &self.download_dir
     }
 
+    /// Get the file to cache upstream responses in.
+    pub fn upstream_cache_file(&self) -> &Path {
+        &self.upstream_cache_file
+    }
+
+    /// Get the file to cache the names of outdated manifests in.
+    pub fn outdated_cache_file(&self) -> &Path {
+        &self.outdated_cache_file
+    }
+
     /// The download directory for a specific manifest.
     ///
     /// This is a subdirectory of the download directory with the name and
@@ -56,7 +145,7 @@ impl HomebinProjectDirs {
     pub fn manifest_download_dir(&self, manifest: &Manifest) -> PathBuf {
         self.download_dir
             .join(&manifest.info.name)
-            .join(&manifest.info.version.to_string())
+            .join(manifest.info.version.to_string())
     }
 }
 
@@ -69,23 +158,60 @@ pub struct InstallDirs {
     man_base_dir: PathBuf,
     systemd_user_unit_dir: PathBuf,
     fish_completion_dir: PathBuf,
+    desktop_entry_dir: PathBuf,
+    icon_base_dir: PathBuf,
+    font_dir: PathBuf,
+    info_dir: PathBuf,
+    data_dir: PathBuf,
+    license_dir: PathBuf,
 }
 
 impl InstallDirs {
+    /// Determine installation directories from the given binary, data and config directories.
+    fn from_dirs(bin_dir: &Path, data_local_dir: &Path, config_dir: &Path) -> InstallDirs {
+        InstallDirs {
+            bin_dir: bin_dir.to_path_buf(),
+            man_base_dir: data_local_dir.join("man"),
+            // According to systemd.unit(5) this is the place for units of packages installed to $HOME
+            systemd_user_unit_dir: data_local_dir.join("systemd").join("user"),
+            fish_completion_dir: config_dir.join("fish").join("completions"),
+            desktop_entry_dir: data_local_dir.join("applications"),
+            icon_base_dir: data_local_dir.join("icons"),
+            font_dir: data_local_dir.join("fonts"),
+            info_dir: data_local_dir.join("info"),
+            data_dir: data_local_dir.to_path_buf(),
+            license_dir: data_local_dir.join("licenses"),
+        }
+    }
+
+    /// Build installation directories from explicit paths, see [`InstallDirsBuilder`].
+    pub fn builder() -> InstallDirsBuilder {
+        InstallDirsBuilder::default()
+    }
+
     /// Determine installation directories from user base dirs.
     pub fn from_base_dirs(dirs: &BaseDirs) -> Result<InstallDirs> {
-        Ok(InstallDirs {
-            bin_dir: dirs
-                .executable_dir()
-                .with_context(|| {
-                    "Cannot determine executable directory from base dirs".to_string()
-                })?
-                .to_path_buf(),
-            man_base_dir: dirs.data_local_dir().join("man"),
-            // According to systemd.unit(5) this is the place for units of packages installed to $HOME
-            systemd_user_unit_dir: dirs.data_local_dir().join("systemd").join("user"),
-            fish_completion_dir: dirs.config_dir().join("fish").join("completions"),
-        })
+        InstallDirs::builder().base_dirs(dirs).build()
+    }
+
+    /// Use the default installation directories of a user with the given `home` directory.
+    ///
+    /// Install binaries to `home/.local/bin`, data files to `home/.local/share` and
+    /// configuration files to `home/.config`, as per the XDG base directory specification.
+    pub fn in_home(home: &Path) -> InstallDirs {
+        InstallDirs::from_dirs(
+            &home.join(".local").join("bin"),
+            &home.join(".local").join("share"),
+            &home.join(".config"),
+        )
+    }
+
+    /// Use installation directories below the given `root` directory.
+    ///
+    /// Install binaries to `root/bin`, data files to `root/share` and configuration files to
+    /// `root/config`, much like a home directory.  This is mostly useful for testing.
+    pub fn in_directory(root: &Path) -> InstallDirs {
+        InstallDirs::from_dirs(&root.join("bin"), &root.join("share"), &root.join("config"))
     }
 
     /// The directory for binaries.
@@ -117,6 +243,41 @@ impl InstallDirs {
         }
     }
 
+    /// The directory for desktop entries.
+    pub fn desktop_entry_dir(&self) -> &Path {
+        &self.desktop_entry_dir
+    }
+
+    /// The directory for application icons of the given `theme` and `size`.
+    pub fn icon_dir(&self, theme: IconTheme, size: IconSize) -> PathBuf {
+        self.icon_base_dir
+            .join(theme.to_string())
+            .join(size.to_string())
+            .join("apps")
+    }
+
+    /// The directory for fonts.
+    pub fn font_dir(&self) -> &Path {
+        &self.font_dir
+    }
+
+    /// The directory for info pages.
+    pub fn info_dir(&self) -> &Path {
+        &self.info_dir
+    }
+
+    /// The base directory for data files.
+    pub fn data_dir(&self) -> &Path {
+        &self.data_dir
+    }
+
+    /// The base directory for license files.
+    ///
+    /// License files of a manifest go to a subdirectory named after the manifest.
+    pub fn license_dir(&self) -> &Path {
+        &self.license_dir
+    }
+
     /// Get the path for the given destination directory.
     pub fn path(&self, directory: DestinationDirectory) -> Cow<Path> {
         match directory {
@@ -126,8 +287,146 @@ impl InstallDirs {
             DestinationDirectory::CompletionDir(shell) => {
                 Cow::from(self.shell_completion_dir(shell))
             }
+            DestinationDirectory::DesktopEntryDir => Cow::from(&self.desktop_entry_dir),
+            DestinationDirectory::IconDir(theme, size) => Cow::from(self.icon_dir(theme, size)),
+            DestinationDirectory::FontDir => Cow::from(&self.font_dir),
+            DestinationDirectory::InfoDir => Cow::from(&self.info_dir),
+            DestinationDirectory::DataDir => Cow::from(&self.data_dir),
+            DestinationDirectory::LicenseDir => Cow::from(&self.license_dir),
+        }
+    }
+}
+
+/// Environment variables which override installation directories.
+///
+/// See [`InstallDirsBuilder::env_overrides`].
+pub const INSTALL_DIRS_ENV_VARS: [&str; 6] = [
+    "HOMEBINS_BIN_DIR",
+    "HOMEBINS_DATA_DIR",
+    "HOMEBINS_CONFIG_DIR",
+    "HOMEBINS_MAN_DIR",
+    "HOMEBINS_SYSTEMD_USER_UNIT_DIR",
+    "HOMEBINS_FISH_COMPLETION_DIR",
+];
+
+/// Build [`InstallDirs`] from explicit paths.
+///
+/// Homebins derives all installation directories from the directory for binaries, the base
+/// directory for data files, and the base directory for configuration files, unless given
+/// explicit directories for manpages, systemd user units or fish completions.
+#[derive(Debug, Default, Clone)]
+pub struct InstallDirsBuilder {
+    bin_dir: Option<PathBuf>,
+    data_dir: Option<PathBuf>,
+    config_dir: Option<PathBuf>,
+    man_dir: Option<PathBuf>,
+    systemd_user_unit_dir: Option<PathBuf>,
+    fish_completion_dir: Option<PathBuf>,
+}
+
+impl InstallDirsBuilder {
+    /// Use the directories of user base `dirs`.
+    ///
+    /// Leave the directory for binaries unset if `dirs` has no executable directory.
+    pub fn base_dirs(mut self, dirs: &BaseDirs) -> InstallDirsBuilder {
+        self.bin_dir = dirs.executable_dir().map(Path::to_path_buf);
+        self.data_dir = Some(dirs.data_local_dir().to_path_buf());
+        self.config_dir = Some(dirs.config_dir().to_path_buf());
+        self
+    }
+
+    /// Install binaries to `dir`.
+    pub fn bin_dir<P: Into<PathBuf>>(mut self, dir: P) -> InstallDirsBuilder {
+        self.bin_dir = Some(dir.into());
+        self
+    }
+
+    /// Install data files below `dir`, like `$XDG_DATA_HOME`.
+    pub fn data_dir<P: Into<PathBuf>>(mut self, dir: P) -> InstallDirsBuilder {
+        self.data_dir = Some(dir.into());
+        self
+    }
+
+    /// Install configuration files below `dir`, like `$XDG_CONFIG_HOME`.
+    pub fn config_dir<P: Into<PathBuf>>(mut self, dir: P) -> InstallDirsBuilder {
+        self.config_dir = Some(dir.into());
+        self
+    }
+
+    /// Install manpages below `dir` instead of `man` in the data directory.
+    pub fn man_dir<P: Into<PathBuf>>(mut self, dir: P) -> InstallDirsBuilder {
+        self.man_dir = Some(dir.into());
+        self
+    }
+
+    /// Install systemd user units to `dir` instead of `systemd/user` in the data directory.
+    pub fn systemd_user_unit_dir<P: Into<PathBuf>>(mut self, dir: P) -> InstallDirsBuilder {
+        self.systemd_user_unit_dir = Some(dir.into());
+        self
+    }
+
+    /// Install fish completions to `dir` instead of `fish/completions` in the config directory.
+    pub fn fish_completion_dir<P: Into<PathBuf>>(mut self, dir: P) -> InstallDirsBuilder {
+        self.fish_completion_dir = Some(dir.into());
+        self
+    }
+
+    /// Override directories with variables from `lookup`, see [`INSTALL_DIRS_ENV_VARS`].
+    ///
+    /// Ignore variables which `lookup` doesn't find or which are empty.
+    pub fn overrides_from<F>(self, lookup: F) -> InstallDirsBuilder
+    where
+        F: Fn(&str) -> Option<OsString>,
+    {
+        let [bin, data, config, man, units, fish] = INSTALL_DIRS_ENV_VARS;
+        let get = |name: &str| {
+            lookup(name)
+                .filter(|value| !value.is_empty())
+                .map(PathBuf::from)
+        };
+        InstallDirsBuilder {
+            bin_dir: get(bin).or(self.bin_dir),
+            data_dir: get(data).or(self.data_dir),
+            config_dir: get(config).or(self.config_dir),
+            man_dir: get(man).or(self.man_dir),
+            systemd_user_unit_dir: get(units).or(self.systemd_user_unit_dir),
+            fish_completion_dir: get(fish).or(self.fish_completion_dir),
         }
     }
+
+    /// Override directories with environment variables like `HOMEBINS_BIN_DIR`.
+    ///
+    /// See [`INSTALL_DIRS_ENV_VARS`] for all variables.
+    pub fn env_overrides(self) -> InstallDirsBuilder {
+        self.overrides_from(|name| std::env::var_os(name))
+    }
+
+    /// Build installation directories.
+    ///
+    /// Fail if the directory for binaries, data files or configuration files is missing.
+    pub fn build(self) -> Result<InstallDirs> {
+        let bin_dir = self.bin_dir.with_context(|| {
+            "Cannot determine directory for binaries; set HOMEBINS_BIN_DIR".to_string()
+        })?;
+        let data_dir = self.data_dir.with_context(|| {
+            "Cannot determine directory for data files; set HOMEBINS_DATA_DIR".to_string()
+        })?;
+        let config_dir = self.config_dir.with_context(|| {
+            "Cannot determine directory for configuration files; set HOMEBINS_CONFIG_DIR"
+                .to_string()
+        })?;
+        let mut dirs = InstallDirs::from_dirs(&bin_dir, &data_dir, &config_dir);
+        if let Some(man_dir) = self.man_dir {
+            dirs.man_base_dir = man_dir;
+        }
+        if let Some(unit_dir) = self.systemd_user_unit_dir {
+            dirs.systemd_user_unit_dir = unit_dir;
+        }
+        if let Some(fish_dir) = self.fish_completion_dir {
+            dirs.fish_completion_dir = fish_dir;
+        }
+        Ok(dirs)
+    }
 }
 
 /// Directories for operations of a single manifest.
@@ -136,6 +435,12 @@ pub struct ManifestOperationDirs<'a> {
     install_dirs: &'a mut InstallDirs,
     download_dir: PathBuf,
     work_dir: TempDir,
+    verify_all_checksums: bool,
+    tokens: HashMap<String, Secret>,
+    trusted_keys: Vec<TrustedKey>,
+    require_trusted_keys: bool,
+    hook_isolation: HookIsolation,
+    downloaded_bytes: AtomicU64,
 }
 
 impl<'a> ManifestOperationDirs<'a> {
@@ -156,9 +461,75 @@ impl<'a> ManifestOperationDirs<'a> {
                 work_dir,
                 install_dirs,
                 download_dir: dirs.manifest_download_dir(manifest),
+                verify_all_checksums: false,
+                tokens: HashMap::new(),
+                trusted_keys: Vec::new(),
+                require_trusted_keys: false,
+                hook_isolation: HookIsolation::default(),
+                downloaded_bytes: AtomicU64::new(0),
             })
     }
 
+    /// Whether to verify downloads against all checksums instead of just the strongest one.
+    pub fn verify_all_checksums(&self) -> bool {
+        self.verify_all_checksums
+    }
+
+    /// Set whether to verify downloads against all checksums.
+    pub fn set_verify_all_checksums(&mut self, verify_all: bool) {
+        self.verify_all_checksums = verify_all;
+    }
+
+    /// Set the tokens to download from hosts with.
+    pub fn set_tokens(&mut self, tokens: HashMap<String, Secret>) {
+        self.tokens = tokens;
+    }
+
+    /// Set the trusted minisign keys, and whether to require a trusted key for signatures.
+    pub fn set_trusted_keys(&mut self, trusted_keys: Vec<TrustedKey>, require: bool) {
+        self.trusted_keys = trusted_keys;
+        self.require_trusted_keys = require;
+    }
+
+    /// Get the trusted key with the given `public_key`, if any.
+    pub fn trusted_key(&self, public_key: &str) -> Option<&TrustedKey> {
+        self.trusted_keys
+            .iter()
+            .find(|key| key.public_key == public_key)
+    }
+
+    /// Whether signatures must verify with a trusted key.
+    pub fn require_trusted_keys(&self) -> bool {
+        self.require_trusted_keys
+    }
+
+    /// Set how to isolate hooks.
+    pub fn set_hook_isolation(&mut self, isolation: HookIsolation) {
+        self.hook_isolation = isolation;
+    }
+
+    /// How to isolate hooks.
+    pub fn hook_isolation(&self) -> HookIsolation {
+        self.hook_isolation
+    }
+
+    /// Count `bytes` as downloaded.
+    pub fn record_download(&self, bytes: u64) {
+        self.downloaded_bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// All bytes downloaded for operations in these directories so far.
+    pub fn downloaded_bytes(&self) -> u64 {
+        self.downloaded_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Get the token to download `url` with, if any.
+    ///
+    /// This reveals the token, so only call it right before downloading `url`.
+    pub fn token_for(&self, url: &Url) -> Result<Option<String>> {
+        token_for(&self.tokens, url)
+    }
+
     /// The directories to install to.
     pub fn install_dirs(&self) -> &InstallDirs {
         self.install_dirs
@@ -192,6 +563,13 @@ impl<'a> ManifestOperationDirs<'a> {
         })
     }
 
+    /// Keep the working directory, and return its path.
+    ///
+    /// The working directory is not deleted when this object is dropped.
+    pub fn keep(self) -> PathBuf {
+        self.work_dir.keep()
+    }
+
     /// Close these directories, i.e. delete the working directory.
     ///
     /// Also happens when dropped.
@@ -204,19 +582,19 @@ impl<'a> ManifestOperationDirs<'a> {
 
 #[cfg(test)]
 mod tests {
-    use crate::manifest::Shell;
+    use crate::manifest::{IconSize, IconTheme, Shell};
     use crate::operations::DestinationDirectory;
     use crate::InstallDirs;
-    use directories::BaseDirs;
     use pretty_assertions::assert_eq;
     use std::path::Path;
 
     #[test]
-    fn install_dirs_from_base_dirs() {
-        std::env::set_var("XDG_CONFIG_HOME", "/test/config");
-        std::env::set_var("XDG_DATA_HOME", "/test/data_home");
-        std::env::set_var("XDG_BIN_DIR", "/test/bin");
-        let dirs = InstallDirs::from_base_dirs(&BaseDirs::new().expect("base dirs"))
+    fn install_dirs_from_explicit_dirs() {
+        let dirs = InstallDirs::builder()
+            .bin_dir("/test/bin")
+            .data_dir("/test/data_home")
+            .config_dir("/test/config")
+            .build()
             .expect("install dirs");
         assert_eq!(
             dirs.path(DestinationDirectory::BinDir),
@@ -234,5 +612,69 @@ mod tests {
             dirs.path(DestinationDirectory::CompletionDir(Shell::Fish)),
             Path::new("/test/config/fish/completions")
         );
+        assert_eq!(
+            dirs.path(DestinationDirectory::DesktopEntryDir),
+            Path::new("/test/data_home/applications")
+        );
+        assert_eq!(
+            dirs.path(DestinationDirectory::IconDir(
+                IconTheme::Hicolor,
+                IconSize::Fixed(48)
+            )),
+            Path::new("/test/data_home/icons/hicolor/48x48/apps")
+        );
+        assert_eq!(
+            dirs.path(DestinationDirectory::FontDir),
+            Path::new("/test/data_home/fonts")
+        );
+        assert_eq!(
+            dirs.path(DestinationDirectory::InfoDir),
+            Path::new("/test/data_home/info")
+        );
+        assert_eq!(
+            dirs.path(DestinationDirectory::DataDir),
+            Path::new("/test/data_home")
+        );
+        assert_eq!(
+            dirs.path(DestinationDirectory::LicenseDir),
+            Path::new("/test/data_home/licenses")
+        );
+    }
+
+    #[test]
+    fn install_dirs_with_overrides() {
+        let dirs = InstallDirs::builder()
+            .bin_dir("/test/bin")
+            .data_dir("/test/data_home")
+            .config_dir("/test/config")
+            .man_dir("/test/man")
+            .overrides_from(|name| match name {
+                "HOMEBINS_BIN_DIR" => Some("/override/bin".into()),
+                "HOMEBINS_FISH_COMPLETION_DIR" => Some("/override/fish".into()),
+                "HOMEBINS_SYSTEMD_USER_UNIT_DIR" => Some("".into()),
+                _ => None,
+            })
+            .build()
+            .expect("install dirs");
+        assert_eq!(dirs.bin_dir(), Path::new("/override/bin"));
+        assert_eq!(dirs.man_section_dir(1), Path::new("/test/man/man1"));
+        assert_eq!(
+            dirs.shell_completion_dir(Shell::Fish),
+            Path::new("/override/fish")
+        );
+        assert_eq!(
+            dirs.systemd_user_unit_dir(),
+            Path::new("/test/data_home/systemd/user")
+        );
+
+        assert_eq!(
+            InstallDirs::builder()
+                .data_dir("/test/data_home")
+                .config_dir("/test/config")
+                .build()
+                .unwrap_err()
+                .to_string(),
+            "Cannot determine directory for binaries; set HOMEBINS_BIN_DIR"
+        );
     }
 }
@@ -0,0 +1,299 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Structured events about the integrity of downloads.
+//!
+//! Homebins emits an event when it starts a download, when it validates the checksum of a
+//! download, and when it verifies the minisign signature of a download.
+//!
+//! Homebins writes these events as JSON lines to an event log, for consumption by security
+//! tooling.
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Error};
+use fehler::throws;
+use serde::Serialize;
+
+use crate::checksum::ValidationError;
+use crate::manifest::Checksums;
+
+/// The result of validating a download.
+#[derive(Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum ChecksumResult {
+    /// The checksum matched.
+    Valid,
+    /// The checksum didn't match.
+    Mismatch {
+        /// The actual checksum of the download.
+        actual: String,
+    },
+    /// Downloading or validation failed for another reason.
+    Failed {
+        /// The error.
+        error: String,
+    },
+}
+
+impl ChecksumResult {
+    /// Get the checksum result from the result of a download operation.
+    pub fn from_result(result: &Result<(), Error>) -> ChecksumResult {
+        match result {
+            Ok(_) => ChecksumResult::Valid,
+            Err(error) => match error
+                .chain()
+                .find_map(|cause| cause.downcast_ref::<ValidationError>())
+            {
+                Some(ValidationError::ChecksumMismatch { actual }) => ChecksumResult::Mismatch {
+                    actual: actual.clone(),
+                },
+                _ => ChecksumResult::Failed {
+                    error: format!("{:#}", error),
+                },
+            },
+        }
+    }
+}
+
+/// The result of verifying the signature of a download.
+#[derive(Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum SignatureResult {
+    /// The signature verified.
+    Valid,
+    /// Downloading or verifying the signature failed, or homebins refused to verify it.
+    Failed {
+        /// The error.
+        error: String,
+    },
+}
+
+impl SignatureResult {
+    /// Get the signature result from the result of a verify operation.
+    pub fn from_result(result: &Result<(), Error>) -> SignatureResult {
+        match result {
+            Ok(_) => SignatureResult::Valid,
+            Err(error) => SignatureResult::Failed {
+                error: format!("{:#}", error),
+            },
+        }
+    }
+}
+
+/// An event about the integrity of a download.
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum IntegrityEvent<'a> {
+    /// Homebins started to download a file.
+    Download {
+        /// The name of the manifest.
+        manifest: &'a str,
+        /// The URL of the download.
+        url: &'a str,
+        /// The name of the downloaded file.
+        file: &'a str,
+    },
+    /// Homebins validated the checksum of a downloaded file.
+    Checksum {
+        /// The name of the manifest.
+        manifest: &'a str,
+        /// The URL of the download.
+        url: &'a str,
+        /// The name of the downloaded file.
+        file: &'a str,
+        /// The checksum algorithm used for validation.
+        algorithm: Option<&'static str>,
+        /// The expected checksum.
+        expected: Option<String>,
+        /// The result of validation.
+        #[serde(flatten)]
+        result: ChecksumResult,
+    },
+    /// Homebins verified the minisign signature of a downloaded file.
+    Signature {
+        /// The name of the manifest.
+        manifest: &'a str,
+        /// The URL of the signature.
+        url: &'a str,
+        /// The name of the downloaded file.
+        file: &'a str,
+        /// The public key to verify the signature with.
+        public_key: &'a str,
+        /// The name of the public key, if trusted.
+        trusted_key: Option<&'a str>,
+        /// The result of verification.
+        #[serde(flatten)]
+        result: SignatureResult,
+    },
+}
+
+impl<'a> IntegrityEvent<'a> {
+    /// Create a checksum event for the given `checksums` and the `result` of a download.
+    pub fn checksum(
+        manifest: &'a str,
+        url: &'a str,
+        file: &'a str,
+        checksums: &Checksums,
+        result: &Result<(), Error>,
+    ) -> IntegrityEvent<'a> {
+        let strongest = crate::checksum::strongest(checksums);
+        IntegrityEvent::Checksum {
+            manifest,
+            url,
+            file,
+            algorithm: strongest.map(|(algorithm, _)| algorithm),
+            expected: strongest.map(|(_, checksum)| hex::encode(checksum)),
+            result: ChecksumResult::from_result(result),
+        }
+    }
+}
+
+/// A log of integrity events.
+#[derive(Debug)]
+pub struct EventLog {
+    file: Option<File>,
+}
+
+impl EventLog {
+    /// Open an event log which appends to the file at `path`.
+    ///
+    /// If `path` is `None` return a log which discards all events.
+    #[throws]
+    pub fn open(path: Option<&Path>) -> EventLog {
+        let file = match path {
+            None => None,
+            Some(path) => Some(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .with_context(|| format!("Failed to open event log at {}", path.display()))?,
+            ),
+        };
+        EventLog { file }
+    }
+
+    /// Append `event` to this log, as single line of JSON with a timestamp.
+    #[throws]
+    pub fn emit(&mut self, event: &IntegrityEvent<'_>) -> () {
+        if let Some(file) = self.file.as_mut() {
+            #[derive(Serialize)]
+            struct Timestamped<'a, 'b> {
+                timestamp: u64,
+                #[serde(flatten)]
+                event: &'b IntegrityEvent<'a>,
+            }
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            let mut line = serde_json::to_string(&Timestamped { timestamp, event })?;
+            line.push('\n');
+            // Write the entire line at once to avoid interleaving with other processes, and flush
+            // it to disk, so that a crash never leaves half a line behind
+            file.write_all(line.as_bytes())
+                .and_then(|_| file.sync_data())
+                .with_context(|| "Failed to write to event log")?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+    use pretty_assertions::assert_eq;
+
+    fn checksums() -> Checksums {
+        Checksums {
+            sha256: Some(vec![0xab, 0xcd]),
+            sha1: Some(vec![0x12]),
+            ..Checksums::default()
+        }
+    }
+
+    #[test]
+    fn checksum_result_from_mismatch() {
+        let result = Err(Error::new(ValidationError::ChecksumMismatch {
+            actual: "1234".to_string(),
+        })
+        .context("Failed to validate foo"));
+        assert_eq!(
+            ChecksumResult::from_result(&result),
+            ChecksumResult::Mismatch {
+                actual: "1234".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn serialize_checksum_event() {
+        let event = IntegrityEvent::checksum(
+            "foo",
+            "https://example.com/foo",
+            "foo",
+            &checksums(),
+            &Err(anyhow!("curl failed")),
+        );
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"event":"checksum","manifest":"foo","url":"https://example.com/foo","file":"foo","algorithm":"sha256","expected":"abcd","result":"failed","error":"curl failed"}"#
+        );
+    }
+
+    #[test]
+    fn serialize_signature_event() {
+        let event = IntegrityEvent::Signature {
+            manifest: "foo",
+            url: "https://example.com/foo.minisig",
+            file: "foo",
+            public_key: "RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3",
+            trusted_key: Some("minisign"),
+            result: SignatureResult::from_result(&Err(anyhow!("minisign failed"))),
+        };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"event":"signature","manifest":"foo","url":"https://example.com/foo.minisig","file":"foo","public_key":"RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3","trusted_key":"minisign","result":"failed","error":"minisign failed"}"#
+        );
+        assert_eq!(
+            SignatureResult::from_result(&Ok(())),
+            SignatureResult::Valid
+        );
+    }
+
+    #[test]
+    fn append_events_to_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.jsonl");
+        let mut log = EventLog::open(Some(&path)).unwrap();
+        let download = IntegrityEvent::Download {
+            manifest: "foo",
+            url: "https://example.com/foo",
+            file: "foo",
+        };
+        log.emit(&download).unwrap();
+        log.emit(&IntegrityEvent::checksum(
+            "foo",
+            "https://example.com/foo",
+            "foo",
+            &checksums(),
+            &Ok(()),
+        ))
+        .unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with(r#"{"timestamp":"#));
+        assert!(lines[0].ends_with(
+            r#""event":"download","manifest":"foo","url":"https://example.com/foo","file":"foo"}"#
+        ));
+        assert!(lines[1].ends_with(r#""algorithm":"sha256","expected":"abcd","result":"valid"}"#));
+    }
+}
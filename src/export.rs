@@ -0,0 +1,529 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Export installed manifests for other tools.
+//!
+//! Exports let users reproduce their set of binaries where homebins can't run: As a `Brewfile`
+//! for Homebrew, as `.tool-versions` for asdf and mise, or as a plain shell script which
+//! installs all binaries with `curl`.  A bundle reproduces binaries with homebins, from copies
+//! of the exact manifest files, so it still works after the manifest repository changed.
+
+use std::fmt::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::str::FromStr;
+
+use url::Url;
+use versions::Versioning;
+
+use crate::checksum::{self, Algorithm};
+use crate::manifest::{Manifest, Platform};
+use crate::operations::{self, Operation, Source, SourceDirectory};
+use crate::tools::shell_quote;
+use crate::InstallDirs;
+
+/// A format to export installed manifests to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// A `Brewfile` for `brew bundle`.
+    Brewfile,
+    /// A `.tool-versions` file for asdf and mise.
+    ToolVersions,
+    /// A shell script which downloads and installs all files with curl.
+    Script,
+    /// A shell script which installs all manifests with homebins from embedded manifest files.
+    Bundle,
+}
+
+/// The files of exported manifests, to embed into bundles.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ManifestFiles {
+    /// The revision of the manifest repositories the files come from, if known.
+    pub revision: Option<String>,
+    /// The file name and the contents of every manifest file, in the order to install them.
+    pub files: Vec<(String, String)>,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "brewfile" => Ok(ExportFormat::Brewfile),
+            "tool-versions" => Ok(ExportFormat::ToolVersions),
+            "script" => Ok(ExportFormat::Script),
+            "bundle" => Ok(ExportFormat::Bundle),
+            _ => Err(format!("Unknown export format: {:?}", s)),
+        }
+    }
+}
+
+/// Get the command to check the file `name` against the `expected` checksum of `algorithm`.
+///
+/// The command fails if the checksum doesn't match.  coreutils have no command for SHA3, so use
+/// OpenSSL for SHA3-256 checksums.
+fn verify_checksum_command(algorithm: Algorithm, expected: &[u8], name: &str) -> String {
+    let command = match algorithm {
+        Algorithm::B2 => "b2sum",
+        Algorithm::Sha512 => "sha512sum",
+        Algorithm::B3 => "b3sum",
+        Algorithm::Sha256 => "sha256sum",
+        Algorithm::Sha1 => "sha1sum",
+        Algorithm::Sha3_256 => {
+            return format!(
+                "test \"$(openssl dgst -sha3-256 -r {} | cut -d ' ' -f 1)\" = {} || {{ echo {} >&2; exit 1; }}",
+                shell_quote(name),
+                hex::encode(expected),
+                shell_quote(&format!("{}: FAILED", name))
+            )
+        }
+    };
+    format!(
+        "echo {} | {} -c -",
+        shell_quote(&format!("{}  {}", hex::encode(expected), name)),
+        command
+    )
+}
+
+/// Get the command to check the file `name` against its SHA256 checksum in the file at `url`.
+///
+/// Look up the checksum like [`checksum::find_checksum`], and fail if the file has no checksum
+/// for `name` or if the checksum doesn't match.
+fn verify_sha256_url_command(url: &Url, name: &str) -> String {
+    format!(
+        "curl -fsSL {} | awk -v f={} '{{ n = $2; sub(/^\\*/, \"\", n); sub(/.*\\//, \"\", n); if (n == f) {{ print $1 \"  \" f; exit }} }}' | sha256sum -c -",
+        shell_quote(url.as_str()),
+        shell_quote(name)
+    )
+}
+
+/// Get a shell word for the relative `path` below `$HOME`.
+fn home_path(path: &Path) -> String {
+    format!("\"$HOME\"/{}", shell_quote(&path.to_string_lossy()))
+}
+
+/// Get the path of `source` relative to the download directory of a manifest.
+fn source_path(source: &Source<'_>) -> String {
+    match source.directory() {
+        SourceDirectory::Download => source.name().to_string(),
+        SourceDirectory::WorkDir => format!("work/{}", source.name()),
+    }
+}
+
+/// Get shell commands for `operation`, with `home` as install directories relative to `$HOME`.
+///
+/// The commands run in the download directory of the manifest, and extract archives to `work`.
+fn shell_commands(operation: &Operation<'_>, home: &InstallDirs) -> Vec<String> {
+    use Operation::*;
+    match operation {
+        Download(url, name, checksums) => {
+            let mut commands = vec![format!(
+                "curl -fL -o {} {}",
+                shell_quote(name),
+                shell_quote(url.as_str())
+            )];
+            match checksum::strongest_algorithm(checksums) {
+                Some((algorithm, expected)) => {
+                    commands.push(verify_checksum_command(algorithm, expected, name))
+                }
+                None => {
+                    if let Some(url) = &checksums.sha256_url {
+                        commands.push(verify_sha256_url_command(url, name));
+                    }
+                }
+            }
+            commands
+        }
+        VerifyMinisign(url, name, public_key) => {
+            let signature = format!("{}.minisig", name);
+            vec![
+                format!(
+                    "curl -fL -o {} {}",
+                    shell_quote(&signature),
+                    shell_quote(url.as_str())
+                ),
+                format!(
+                    "minisign -Vq -P {} -m {} -x {}",
+                    shell_quote(public_key),
+                    shell_quote(name),
+                    shell_quote(&signature)
+                ),
+            ]
+        }
+        Extract(name, 0) if name.ends_with("zip") => {
+            vec![format!("unzip -q {} -d work", shell_quote(name))]
+        }
+        Extract(name, strip) if name.ends_with("zip") => vec![
+            format!(
+                "mkdir -p extract && unzip -q {} -d extract",
+                shell_quote(name)
+            ),
+            format!("mv extract/{}* work", "*/".repeat(*strip)),
+        ],
+        Extract(name, 0) => vec![format!("tar -xf {} -C work", shell_quote(name))],
+        Extract(name, strip) => vec![format!(
+            "tar -xf {} -C work --strip-components={}",
+            shell_quote(name),
+            strip
+        )],
+        CopyOptional(source, destination, permissions) => {
+            let copy = Copy(source.clone(), destination.clone(), *permissions);
+            let commands = shell_commands(&copy, home).join(" && ");
+            let source = source_path(source);
+            vec![format!(
+                "if [ -e {} ]; then {}; fi",
+                shell_quote(&source),
+                commands
+            )]
+        }
+        Copy(source, destination, permissions) => {
+            let source = source_path(source);
+            let target = home.path(destination.directory()).join(destination.name());
+            let target_dir = target.parent().unwrap_or(&target);
+            vec![
+                format!("mkdir -p {}", home_path(target_dir)),
+                format!(
+                    "install -m {:o} {} {}",
+                    permissions.to_unix_permissions().mode(),
+                    shell_quote(&source),
+                    home_path(&target)
+                ),
+            ]
+        }
+        Hardlink(source, target) => vec![format!(
+            "ln -f {} {}",
+            home_path(&home.bin_dir().join(source.as_ref())),
+            home_path(&home.bin_dir().join(target.as_ref()))
+        )],
+        UpdateDesktopDatabase => vec![format!(
+            "update-desktop-database {} || true",
+            home_path(home.desktop_entry_dir())
+        )],
+        UpdateFontCache => vec![format!(
+            "fc-cache -f {} || true",
+            home_path(home.font_dir())
+        )],
+        SystemdDaemonReload => vec!["systemctl --user daemon-reload".to_string()],
+        SystemdEnable(unit) => vec![format!(
+            "systemctl --user enable --now {}",
+            shell_quote(unit)
+        )],
+        InstallInfo(name) => vec![format!(
+            "install-info --info-dir={} {} || true",
+            home_path(home.info_dir()),
+            home_path(&home.info_dir().join(name.as_ref()))
+        )],
+        Run(command) => {
+            let command: Vec<String> = command.iter().map(|s| shell_quote(s)).collect();
+            vec![format!(
+                "(cd work && PATH=\"$HOME/.local/bin:$PATH\" {})",
+                command.join(" ")
+            )]
+        }
+        // Installation plans never remove anything
+        Remove(_, _) | SystemdDisable(_) | RemoveInfo(_) => Vec::new(),
+    }
+}
+
+/// Export `installed` manifests with their installed versions as a Brewfile.
+///
+/// Assume that Homebrew has formulas of the same name.
+fn brewfile(installed: &[(&Manifest, Versioning)]) -> String {
+    let mut brewfile = "# Homebrew formula names may differ from homebins manifests\n".to_string();
+    for (manifest, _) in installed {
+        writeln!(brewfile, "brew \"{}\"", manifest.info.name).unwrap();
+    }
+    brewfile
+}
+
+/// Export `installed` manifests with their installed versions as `.tool-versions`.
+fn tool_versions(installed: &[(&Manifest, Versioning)]) -> String {
+    let mut tool_versions = String::new();
+    for (manifest, version) in installed {
+        writeln!(tool_versions, "{} {}", manifest.info.name, version).unwrap();
+    }
+    tool_versions
+}
+
+/// Export `installed` manifests as shell script for `platform`.
+///
+/// The script installs the version of each manifest, regardless of the installed version.
+fn script(installed: &[(&Manifest, Versioning)], platform: Platform) -> String {
+    let home = InstallDirs::in_home(Path::new(""));
+    let mut script = "#!/bin/sh
+# Install binaries to $HOME with curl, exported from homebins.
+set -eu
+downloads=\"$(mktemp -d)\"
+trap 'rm -rf \"$downloads\"' EXIT
+"
+    .to_string();
+    for (manifest, _) in installed {
+        let name = &manifest.info.name;
+        writeln!(script, "\n# {} {}\n(", name, manifest.info.version).unwrap();
+        writeln!(
+            script,
+            "  mkdir -p \"$downloads\"/{}/work",
+            shell_quote(name)
+        )
+        .unwrap();
+        writeln!(script, "  cd \"$downloads\"/{}", shell_quote(name)).unwrap();
+        if manifest.check_platform(platform).is_err() {
+            writeln!(
+                script,
+                "  echo {} >&2",
+                shell_quote(&format!(
+                    "{} not available on {} {}",
+                    name, platform.os, platform.arch
+                ))
+            )
+            .unwrap();
+            writeln!(script, "  exit 1").unwrap();
+        } else {
+            for operation in operations::install_manifest(manifest, platform) {
+                for command in shell_commands(&operation, &home) {
+                    writeln!(script, "  {}", command).unwrap();
+                }
+            }
+        }
+        writeln!(script, ")").unwrap();
+    }
+    script
+}
+
+/// Export manifest `files` as a shell script which installs them with homebins.
+///
+/// Embed every file in a here document, with a delimiter which doesn't occur in the file.
+fn bundle(files: &ManifestFiles) -> String {
+    let mut bundle = "#!/bin/sh
+# Install binaries with homebins from the manifests in this file, exported from homebins.
+"
+    .to_string();
+    if let Some(revision) = &files.revision {
+        writeln!(bundle, "# Manifest revision: {}", revision).unwrap();
+    }
+    bundle.push_str(
+        "set -eu
+manifests=\"$(mktemp -d)\"
+trap 'rm -rf \"$manifests\"' EXIT
+",
+    );
+    let mut paths = Vec::with_capacity(files.files.len());
+    for (name, contents) in &files.files {
+        let mut delimiter = "HOMEBINS_MANIFEST".to_string();
+        while contents.lines().any(|line| line == delimiter) {
+            delimiter.push('_');
+        }
+        let path = format!("\"$manifests\"/{}", shell_quote(name));
+        writeln!(bundle, "\ncat > {} <<'{}'", path, delimiter).unwrap();
+        bundle.push_str(contents);
+        if !contents.ends_with('\n') {
+            bundle.push('\n');
+        }
+        writeln!(bundle, "{}", delimiter).unwrap();
+        paths.push(path);
+    }
+    if !paths.is_empty() {
+        writeln!(bundle, "\nhomebins manifest-install {}", paths.join(" ")).unwrap();
+    }
+    bundle
+}
+
+/// Describe all `installed` manifests whose installed version differs from the manifest.
+///
+/// Bundles embed manifest files as they are, so they'd install other versions of these
+/// manifests than those actually installed.
+pub fn bundle_mismatches(installed: &[(&Manifest, Versioning)]) -> Vec<String> {
+    installed
+        .iter()
+        .filter(|(manifest, version)| &manifest.info.version != version)
+        .map(|(manifest, version)| {
+            format!(
+                "{} {} (manifest has {})",
+                manifest.info.name, version, manifest.info.version
+            )
+        })
+        .collect()
+}
+
+/// Export `installed` manifests with their installed versions to `format`.
+///
+/// Scripts install manifests for `platform`.  Bundles embed manifest `files`; all other
+/// formats ignore `files`.
+pub fn export(
+    installed: &[(&Manifest, Versioning)],
+    files: &ManifestFiles,
+    format: ExportFormat,
+    platform: Platform,
+) -> String {
+    match format {
+        ExportFormat::Brewfile => brewfile(installed),
+        ExportFormat::ToolVersions => tool_versions(installed),
+        ExportFormat::Script => script(installed, platform),
+        ExportFormat::Bundle => bundle(files),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::{Arch, Os};
+    use pretty_assertions::assert_eq;
+
+    fn installed() -> Vec<Manifest> {
+        vec![Manifest::read_from_path("tests/manifests/shfmt.toml").unwrap()]
+    }
+
+    #[test]
+    fn export_tool_versions_and_brewfile() {
+        let manifests = installed();
+        let installed = vec![(&manifests[0], Versioning::new("3.0.0").unwrap())];
+        let platform = Platform {
+            os: Os::Linux,
+            arch: Arch::X86_64,
+        };
+        assert_eq!(
+            export(
+                &installed,
+                &ManifestFiles::default(),
+                ExportFormat::ToolVersions,
+                platform
+            ),
+            "shfmt 3.0.0\n"
+        );
+        assert_eq!(
+            export(
+                &installed,
+                &ManifestFiles::default(),
+                ExportFormat::Brewfile,
+                platform
+            ),
+            "# Homebrew formula names may differ from homebins manifests\nbrew \"shfmt\"\n"
+        );
+    }
+
+    #[test]
+    fn export_script() {
+        let manifests = installed();
+        let installed = vec![(&manifests[0], Versioning::new("3.0.0").unwrap())];
+        let platform = Platform {
+            os: Os::Linux,
+            arch: Arch::X86_64,
+        };
+        let script = export(
+            &installed,
+            &ManifestFiles::default(),
+            ExportFormat::Script,
+            platform,
+        );
+        assert!(script.starts_with("#!/bin/sh\n"));
+        assert!(script.contains(
+            "\n  curl -fL -o 'shfmt_v3.1.1_linux_amd64' 'https://github.com/mvdan/sh/releases/download/v3.1.1/shfmt_v3.1.1_linux_amd64'\n"
+        ));
+        assert!(script.contains("  shfmt_v3.1.1_linux_amd64' | b2sum -c -\n"));
+        assert!(script.contains(
+            "\n  install -m 755 'shfmt_v3.1.1_linux_amd64' \"$HOME\"/'.local/bin/shfmt'\n"
+        ));
+    }
+
+    #[test]
+    fn export_bundle() {
+        let shfmt = std::fs::read_to_string("tests/manifests/shfmt.toml").unwrap();
+        let files = ManifestFiles {
+            revision: Some("abc".to_string()),
+            files: vec![
+                ("shfmt.toml".to_string(), shfmt.clone()),
+                (
+                    "odd.toml".to_string(),
+                    "HOMEBINS_MANIFEST\nHOMEBINS_MANIFEST_".to_string(),
+                ),
+            ],
+        };
+        let platform = Platform {
+            os: Os::Linux,
+            arch: Arch::X86_64,
+        };
+        let bundle = export(&[], &files, ExportFormat::Bundle, platform);
+        assert!(bundle.starts_with("#!/bin/sh\n"));
+        assert!(bundle.contains("\n# Manifest revision: abc\n"));
+        assert!(bundle.contains(&format!(
+            "\ncat > \"$manifests\"/'shfmt.toml' <<'HOMEBINS_MANIFEST'\n{}HOMEBINS_MANIFEST\n",
+            shfmt
+        )));
+        assert!(bundle.contains(
+            "\ncat > \"$manifests\"/'odd.toml' <<'HOMEBINS_MANIFEST__'\nHOMEBINS_MANIFEST\nHOMEBINS_MANIFEST_\nHOMEBINS_MANIFEST__\n"
+        ));
+        assert!(bundle.ends_with(
+            "\nhomebins manifest-install \"$manifests\"/'shfmt.toml' \"$manifests\"/'odd.toml'\n"
+        ));
+    }
+
+    #[test]
+    fn bundle_mismatches_of_outdated_manifests() {
+        let manifests = installed();
+        let shfmt = &manifests[0];
+        assert!(bundle_mismatches(&[(shfmt, shfmt.info.version.clone())]).is_empty());
+        assert_eq!(
+            bundle_mismatches(&[(shfmt, Versioning::new("0.0.1").unwrap())]),
+            vec![format!("shfmt 0.0.1 (manifest has {})", shfmt.info.version)]
+        );
+    }
+
+    /// Run the shell `command` in `directory`, and tell whether it succeeded.
+    fn run_in(directory: &Path, command: &str) -> bool {
+        std::process::Command::new("sh")
+            .args(["-eu", "-c", command])
+            .current_dir(directory)
+            .stderr(std::process::Stdio::null())
+            .stdout(std::process::Stdio::null())
+            .status()
+            .unwrap()
+            .success()
+    }
+
+    #[test]
+    fn verify_sha3_256_checksums() {
+        let directory = tempfile::tempdir().unwrap();
+        std::fs::write(directory.path().join("foo.bin"), "foo").unwrap();
+        let expected =
+            hex::decode("76d3bc41c9f588f7fcd0d5bf4718f8f84b1c41b20882703100b9eb9413807c01")
+                .unwrap();
+        assert!(run_in(
+            directory.path(),
+            &verify_checksum_command(Algorithm::Sha3_256, &expected, "foo.bin")
+        ));
+        assert!(!run_in(
+            directory.path(),
+            &verify_checksum_command(Algorithm::Sha3_256, &[0, 1], "foo.bin")
+        ));
+    }
+
+    #[test]
+    fn verify_checksums_from_sha256_url() {
+        let directory = tempfile::tempdir().unwrap();
+        std::fs::write(directory.path().join("foo.bin"), "foo").unwrap();
+        std::fs::write(directory.path().join("bar.bin"), "bar").unwrap();
+        let sums = directory.path().join("SHA256SUMS");
+        std::fs::write(
+            &sums,
+            "2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae *./dist/foo.bin\n\
+             0000000000000000000000000000000000000000000000000000000000000000  bar.bin\n",
+        )
+        .unwrap();
+        let url = Url::from_file_path(&sums).unwrap();
+        assert!(run_in(
+            directory.path(),
+            &verify_sha256_url_command(&url, "foo.bin")
+        ));
+        assert!(!run_in(
+            directory.path(),
+            &verify_sha256_url_command(&url, "bar.bin")
+        ));
+        assert!(!run_in(
+            directory.path(),
+            &verify_sha256_url_command(&url, "baz.bin")
+        ));
+    }
+}
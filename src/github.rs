@@ -0,0 +1,212 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Draft manifests from GitHub releases.
+//!
+//! Take the name, description, license and homepage of a GitHub repository and the latest
+//! release of the repository, pick the asset of the release which most likely contains a Linux
+//! binary for the host architecture, and download the asset to compute its checksum.  Leave a
+//! draft manifest with `TODO` comments for everything that needs manual completion.
+
+use anyhow::{anyhow, Context, Error};
+use fehler::throws;
+use serde::Deserialize;
+use url::Url;
+
+use crate::brew::{github_repo, Draft};
+use crate::checksum::{Algorithm, Hasher};
+use crate::manifest::Arch;
+use crate::tools::{download, fetch_text};
+
+/// A GitHub repository from the GitHub API.
+#[derive(Debug, Deserialize)]
+struct Repository {
+    name: String,
+    description: Option<String>,
+    homepage: Option<String>,
+    html_url: String,
+    license: Option<License>,
+}
+
+/// The license of a GitHub repository.
+#[derive(Debug, Deserialize)]
+struct License {
+    spdx_id: Option<String>,
+}
+
+/// A release from the GitHub API.
+#[derive(Debug, Deserialize)]
+struct Release {
+    tag_name: String,
+    #[serde(default)]
+    assets: Vec<Asset>,
+}
+
+/// A downloadable asset of a release.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Asset {
+    /// The file name of the asset.
+    pub name: String,
+    /// The URL to download the asset from.
+    pub browser_download_url: String,
+}
+
+/// Names of `arch` in file names of release assets.
+fn arch_names(arch: Arch) -> &'static [&'static str] {
+    match arch {
+        Arch::X86_64 => &["x86_64", "amd64", "x64"],
+        Arch::Aarch64 => &["aarch64", "arm64"],
+        Arch::Armv7 => &["armv7", "armhf"],
+    }
+}
+
+/// Score how likely `asset` contains a Linux binary for `arch`.
+///
+/// Return `None` if `asset` is not for Linux or `arch`, or not a binary at all, e.g. a checksum
+/// file or a package for a Linux distribution.
+fn score_asset(asset: &Asset, arch: Arch) -> Option<u32> {
+    let name = asset.name.to_lowercase();
+    let not_binaries = [
+        ".sha256", ".sha512", ".sum", ".asc", ".sig", ".minisig", ".pem", ".sbom", ".json", ".txt",
+        ".deb", ".rpm", ".apk",
+    ];
+    if !name.contains("linux")
+        || !arch_names(arch).iter().any(|arch| name.contains(arch))
+        || not_binaries
+            .iter()
+            .any(|extension| name.ends_with(extension))
+    {
+        return None;
+    }
+    let mut score = 1;
+    // Prefer static binaries which run on any distribution…
+    if name.contains("musl") {
+        score += 2;
+    }
+    // …and archives which likely contain completions and manpages as well
+    if [".tar.gz", ".tgz", ".tar.xz", ".zip"]
+        .iter()
+        .any(|extension| name.ends_with(extension))
+    {
+        score += 1;
+    }
+    Some(score)
+}
+
+/// Pick the asset of `assets` which most likely contains a Linux binary for `arch`.
+///
+/// Among equally likely assets pick the first one.
+pub fn pick_asset(assets: &[Asset], arch: Arch) -> Option<&Asset> {
+    assets
+        .iter()
+        .filter_map(|asset| score_asset(asset, arch).map(|score| (score, asset)))
+        .fold(
+            None,
+            |best: Option<(u32, &Asset)>, (score, asset)| match best {
+                Some((best_score, _)) if score <= best_score => best,
+                _ => Some((score, asset)),
+            },
+        )
+        .map(|(_, asset)| asset)
+}
+
+/// Create a draft manifest for the latest release of the GitHub repository at `url`.
+///
+/// Pick a Linux asset for `arch` from the latest release, download it to compute its checksum,
+/// and return the text of the draft manifest.
+#[throws]
+pub fn new_manifest(url: &str, arch: Arch) -> String {
+    let repo = github_repo(url.trim_end_matches('/').trim_end_matches(".git"))
+        .ok_or_else(|| anyhow!("Not a GitHub repository: {}", url))?;
+    let api = format!("https://api.github.com/repos/{}", repo);
+    let repository: Repository = serde_json::from_str(
+        &fetch_text(&api).with_context(|| format!("Failed to fetch repository {}", repo))?,
+    )
+    .with_context(|| format!("Invalid repository from {}", api))?;
+    let releases = format!("{}/releases/latest", api);
+    let release: Release = serde_json::from_str(
+        &fetch_text(&releases)
+            .with_context(|| format!("Failed to fetch latest release of {}", repo))?,
+    )
+    .with_context(|| format!("Invalid release from {}", releases))?;
+    let asset = pick_asset(&release.assets, arch).ok_or_else(|| {
+        anyhow!(
+            "No Linux {} asset in release {} of {}",
+            arch,
+            release.tag_name,
+            repo
+        )
+    })?;
+    let url = Url::parse(&asset.browser_download_url)
+        .with_context(|| format!("Invalid download URL {}", asset.browser_download_url))?;
+    let directory = tempfile::tempdir().with_context(|| "Failed to create download directory")?;
+    let file = directory.path().join(&asset.name);
+    download(&url, &file, None).with_context(|| format!("Failed to download {}", url))?;
+    let mut hasher = Hasher::new(&[Algorithm::Sha256]);
+    std::fs::File::open(&file)
+        .and_then(|mut source| hasher.read_from(&mut source))
+        .with_context(|| format!("Failed to read {}", file.display()))?;
+    let sha256 = hex::encode(&hasher.finalize()[0].1);
+    let version = release
+        .tag_name
+        .trim_start_matches(|c: char| !c.is_ascii_digit());
+    Draft {
+        origin: format!("GitHub release {} of {}", release.tag_name, repo),
+        name: &repository.name,
+        desc: repository.description.as_deref(),
+        version,
+        homepage: repository
+            .homepage
+            .as_deref()
+            .filter(|homepage| !homepage.is_empty())
+            .unwrap_or(&repository.html_url),
+        license: repository
+            .license
+            .as_ref()
+            .and_then(|license| license.spdx_id.as_deref())
+            .filter(|id| *id != "NOASSERTION"),
+        url: &asset.browser_download_url,
+        sha256: Some(&sha256),
+        binaries: Vec::new(),
+        from_source: false,
+    }
+    .render()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn asset(name: &str) -> Asset {
+        Asset {
+            name: name.to_string(),
+            browser_download_url: format!("https://github.com/o/r/releases/download/v1/{}", name),
+        }
+    }
+
+    #[test]
+    fn pick_linux_asset() {
+        let assets = vec![
+            asset("ripgrep-14.1.0-x86_64-apple-darwin.tar.gz"),
+            asset("ripgrep-14.1.0-x86_64-unknown-linux-gnu.tar.gz"),
+            asset("ripgrep-14.1.0-x86_64-unknown-linux-musl.tar.gz.sha256"),
+            asset("ripgrep-14.1.0-x86_64-unknown-linux-musl.tar.gz"),
+            asset("ripgrep-14.1.0-aarch64-unknown-linux-gnu.tar.gz"),
+            asset("ripgrep_14.1.0-1_amd64_linux.deb"),
+        ];
+        let name = |arch| pick_asset(&assets, arch).map(|asset| asset.name.as_str());
+        assert_eq!(
+            name(Arch::X86_64),
+            Some("ripgrep-14.1.0-x86_64-unknown-linux-musl.tar.gz")
+        );
+        assert_eq!(
+            name(Arch::Aarch64),
+            Some("ripgrep-14.1.0-aarch64-unknown-linux-gnu.tar.gz")
+        );
+        assert_eq!(name(Arch::Armv7), None);
+    }
+}
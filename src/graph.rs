@@ -0,0 +1,317 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! The graph of manifests, their files and their relations.
+//!
+//! The graph has a node for every manifest and every file a manifest installs, and edges for
+//! the files a manifest installs, links between binaries, and requirements and conflicts between
+//! manifests.  Homebins renders the graph as Graphviz dot for visualization or JSON for tooling.
+
+use std::borrow::Borrow;
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::str::FromStr;
+
+use anyhow::Error;
+use fehler::throws;
+use serde::Serialize;
+
+use crate::manifest::{Manifest, Platform};
+use crate::operations::{self, DestinationDirectory, Operation};
+use crate::InstallDirs;
+
+/// A format to render a graph in.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GraphFormat {
+    /// Graphviz dot.
+    Dot,
+    /// JSON with a list of nodes and a list of edges.
+    Json,
+}
+
+impl FromStr for GraphFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "dot" => Ok(GraphFormat::Dot),
+            "json" => Ok(GraphFormat::Json),
+            _ => Err(format!("Unknown graph format: {:?}", s)),
+        }
+    }
+}
+
+/// The kind of a node.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NodeKind {
+    /// A manifest.
+    Manifest,
+    /// A file a manifest installs.
+    File,
+}
+
+/// A node in the graph.
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct Node {
+    /// The unique ID of this node.
+    pub id: String,
+    /// The kind of this node.
+    pub kind: NodeKind,
+    /// The label of this node, i.e. name and version of a manifest or the path of a file.
+    pub label: String,
+}
+
+/// The relation an edge denotes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Relation {
+    /// A manifest installs a file.
+    Installs,
+    /// A binary is a hard link to another binary.
+    Links,
+    /// A manifest requires another manifest.
+    Requires,
+    /// A manifest conflicts with another manifest.
+    Conflicts,
+}
+
+impl Relation {
+    fn as_str(self) -> &'static str {
+        match self {
+            Relation::Installs => "installs",
+            Relation::Links => "links",
+            Relation::Requires => "requires",
+            Relation::Conflicts => "conflicts",
+        }
+    }
+}
+
+/// An edge in the graph.
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct Edge {
+    /// The ID of the node this edge starts at.
+    pub from: String,
+    /// The ID of the node this edge points to.
+    pub to: String,
+    /// The relation this edge denotes.
+    pub relation: Relation,
+}
+
+/// The graph of manifests, their files and their relations.
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Graph {
+    /// All nodes.
+    pub nodes: Vec<Node>,
+    /// All edges.
+    pub edges: Vec<Edge>,
+}
+
+fn manifest_id(name: &str) -> String {
+    format!("manifest:{}", name)
+}
+
+impl Graph {
+    /// Add a node unless a node with the same ID already exists.
+    fn add_node(&mut self, seen: &mut HashSet<String>, id: String, kind: NodeKind, label: String) {
+        if seen.insert(id.clone()) {
+            self.nodes.push(Node { id, kind, label });
+        }
+    }
+
+    fn add_edge(&mut self, from: &str, to: String, relation: Relation) {
+        self.edges.push(Edge {
+            from: from.to_string(),
+            to,
+            relation,
+        });
+    }
+
+    /// Build the graph of `manifests` installed to `dirs` on `platform`.
+    ///
+    /// Manifests without downloads for `platform` have no files.  Requirements and conflicts
+    /// refer to manifest nodes even if the other manifest isn't in `manifests`.
+    pub fn of_manifests<M: Borrow<Manifest>>(
+        dirs: &InstallDirs,
+        manifests: &[M],
+        platform: Platform,
+    ) -> Graph {
+        let mut graph = Graph::default();
+        let mut seen = HashSet::new();
+        for manifest in manifests {
+            let manifest = manifest.borrow();
+            let id = manifest_id(&manifest.info.name);
+            graph.add_node(
+                &mut seen,
+                id.clone(),
+                NodeKind::Manifest,
+                format!("{} {}", manifest.info.name, manifest.info.version),
+            );
+            for other in &manifest.info.requires {
+                graph.add_edge(&id, manifest_id(other), Relation::Requires);
+            }
+            for other in &manifest.info.conflicts {
+                graph.add_edge(&id, manifest_id(other), Relation::Conflicts);
+            }
+            if manifest.check_platform(platform).is_err() {
+                continue;
+            }
+            let operations = operations::install_manifest(manifest, platform);
+            for destination in operations::operation_destinations(operations.iter()) {
+                let path = dirs.path(destination.directory()).join(destination.name());
+                let path = path.display().to_string();
+                let file = format!("file:{}", path);
+                graph.add_node(&mut seen, file.clone(), NodeKind::File, path);
+                graph.add_edge(&id, file, Relation::Installs);
+            }
+            for operation in &operations {
+                if let Operation::Hardlink(source, target) = operation {
+                    let bin_dir = dirs.path(DestinationDirectory::BinDir);
+                    let source = bin_dir.join(source.as_ref()).display().to_string();
+                    let target = bin_dir.join(target.as_ref()).display().to_string();
+                    graph.add_edge(
+                        &format!("file:{}", target),
+                        format!("file:{}", source),
+                        Relation::Links,
+                    );
+                }
+            }
+        }
+        // Add nodes for manifests which only appear in relations
+        let missing: Vec<String> = graph
+            .edges
+            .iter()
+            .map(|edge| edge.to.clone())
+            .filter(|id| id.starts_with("manifest:") && !seen.contains(id))
+            .collect();
+        for id in missing {
+            let label = id.trim_start_matches("manifest:").to_string();
+            graph.add_node(&mut seen, id, NodeKind::Manifest, label);
+        }
+        graph
+    }
+
+    /// Render this graph as Graphviz dot.
+    pub fn to_dot(&self) -> String {
+        fn quote(s: &str) -> String {
+            format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+        }
+        // Writing to a string never fails
+        let mut dot = "digraph homebins {\n".to_string();
+        for node in &self.nodes {
+            let shape = match node.kind {
+                NodeKind::Manifest => "box",
+                NodeKind::File => "note",
+            };
+            writeln!(
+                dot,
+                "  {} [label={}, shape={}];",
+                quote(&node.id),
+                quote(&node.label),
+                shape
+            )
+            .unwrap();
+        }
+        for edge in &self.edges {
+            writeln!(
+                dot,
+                "  {} -> {} [label={}];",
+                quote(&edge.from),
+                quote(&edge.to),
+                edge.relation.as_str()
+            )
+            .unwrap();
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Render this graph in the given `format`.
+    #[throws]
+    pub fn render(&self, format: GraphFormat) -> String {
+        match format {
+            GraphFormat::Dot => self.to_dot(),
+            GraphFormat::Json => serde_json::to_string_pretty(self)? + "\n",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::{Arch, Os};
+    use pretty_assertions::assert_eq;
+    use std::path::Path;
+
+    fn graph() -> Graph {
+        let mut manifest = Manifest::read_from_path("tests/manifests/shfmt.toml").unwrap();
+        manifest.info.requires.push("go".to_string());
+        let platform = Platform {
+            os: Os::Linux,
+            arch: Arch::X86_64,
+        };
+        Graph::of_manifests(
+            &InstallDirs::in_directory(Path::new("/home")),
+            &[manifest],
+            platform,
+        )
+    }
+
+    #[test]
+    fn graph_of_manifest() {
+        let graph = graph();
+        assert_eq!(
+            graph.nodes,
+            vec![
+                Node {
+                    id: "manifest:shfmt".to_string(),
+                    kind: NodeKind::Manifest,
+                    label: "shfmt 3.1.1".to_string()
+                },
+                Node {
+                    id: "file:/home/bin/shfmt".to_string(),
+                    kind: NodeKind::File,
+                    label: "/home/bin/shfmt".to_string()
+                },
+                Node {
+                    id: "manifest:go".to_string(),
+                    kind: NodeKind::Manifest,
+                    label: "go".to_string()
+                },
+            ]
+        );
+        assert_eq!(
+            graph.edges,
+            vec![
+                Edge {
+                    from: "manifest:shfmt".to_string(),
+                    to: "manifest:go".to_string(),
+                    relation: Relation::Requires
+                },
+                Edge {
+                    from: "manifest:shfmt".to_string(),
+                    to: "file:/home/bin/shfmt".to_string(),
+                    relation: Relation::Installs
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn render_dot() {
+        assert_eq!(
+            graph().to_dot(),
+            r#"digraph homebins {
+  "manifest:shfmt" [label="shfmt 3.1.1", shape=box];
+  "file:/home/bin/shfmt" [label="/home/bin/shfmt", shape=note];
+  "manifest:go" [label="go", shape=box];
+  "manifest:shfmt" -> "manifest:go" [label=requires];
+  "manifest:shfmt" -> "file:/home/bin/shfmt" [label=installs];
+}
+"#
+        );
+    }
+}
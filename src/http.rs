@@ -0,0 +1,360 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Download without curl.
+//!
+//! With the `native-http` feature homebins downloads with ureq instead of curl, with the same
+//! semantics as our curl invocations: Follow redirects, retry transient failures three times,
+//! resume partial downloads, and report progress.  Like curl only send tokens along redirects to
+//! the same host, use proxies from `$https_proxy` and friends, and read `file://` URLs from the
+//! local file system.
+//!
+//! Set `$HOMEBINS_DOWNLOADER` to `curl` to download with curl anyway, see
+//! [`crate::tools::download`].
+
+use std::fs::OpenOptions;
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use ureq::{Agent, AgentBuilder, RedirectAuthHeaders, Request, Response};
+use url::Url;
+
+use crate::progress::DownloadProgress;
+use crate::tools::disposition_filename;
+
+/// How often to retry transient failures.
+const RETRIES: u32 = 3;
+
+/// How long to wait before retrying.
+const RETRY_DELAY: Duration = Duration::from_secs(3);
+
+/// How many redirects to follow.
+const MAX_REDIRECTS: u32 = 30;
+
+/// The agent to make all requests with.
+fn agent() -> Agent {
+    AgentBuilder::new()
+        .redirects(MAX_REDIRECTS)
+        .redirect_auth_headers(RedirectAuthHeaders::SameHost)
+        .try_proxy_from_env(true)
+        .user_agent(concat!("homebins/", env!("CARGO_PKG_VERSION")))
+        .build()
+}
+
+/// Whether `error` is transient, i.e. worth retrying.
+///
+/// Like curl consider timeouts, failed connections, and HTTP status codes 408, 429, 500, 502,
+/// 503 and 504 as transient.
+fn is_transient(error: &ureq::Error) -> bool {
+    match error {
+        ureq::Error::Status(status, _) => matches!(status, 408 | 429 | 500 | 502 | 503 | 504),
+        ureq::Error::Transport(transport) => matches!(
+            transport.kind(),
+            ureq::ErrorKind::ConnectionFailed | ureq::ErrorKind::Io
+        ),
+    }
+}
+
+/// Turn `error` from requesting `url` into an IO error which tells what went wrong.
+fn to_io_error(url: &str, error: ureq::Error) -> Error {
+    match error {
+        ureq::Error::Status(status, response) => Error::other(
+            format!(
+                "{} failed with HTTP status {} {}",
+                url,
+                status,
+                response.status_text()
+            )
+            .trim_end(),
+        ),
+        ureq::Error::Transport(transport) => Error::other(format!("{} failed: {}", url, transport)),
+    }
+}
+
+/// The result of an attempt to request a URL.
+///
+/// Box errors, because ureq has large errors.
+type Attempt<T> = std::result::Result<T, Box<ureq::Error>>;
+
+/// Fail an attempt with an IO `error`, e.g. when writing the body to disk.
+fn io_failure(error: Error) -> Box<ureq::Error> {
+    Box::new(error.into())
+}
+
+/// Run `attempt` for `url` until it succeeds, retrying transient failures.
+fn with_retries<T, F>(url: &str, mut attempt: F) -> Result<T>
+where
+    F: FnMut() -> Attempt<T>,
+{
+    let mut retries = RETRIES;
+    loop {
+        match attempt() {
+            Ok(value) => return Ok(value),
+            Err(error) if 0 < retries && is_transient(&error) => {
+                crate::estatus!(
+                    "Warning: {}; retrying in {} seconds, {} retries left",
+                    to_io_error(url, *error),
+                    RETRY_DELAY.as_secs(),
+                    retries
+                );
+                retries -= 1;
+                std::thread::sleep(RETRY_DELAY);
+            }
+            Err(error) => return Err(to_io_error(url, *error)),
+        }
+    }
+}
+
+/// The local file `url` refers to, if it's a `file://` URL.
+fn local_file(url: &Url) -> Option<PathBuf> {
+    Some(url)
+        .filter(|url| url.scheme() == "file")
+        .and_then(|url| url.to_file_path().ok())
+}
+
+/// Add `token` as bearer token to `request`, if any.
+fn authorized(request: Request, token: Option<&str>) -> Request {
+    match token {
+        Some(token) => request.set("Authorization", &format!("Bearer {}", token)),
+        None => request,
+    }
+}
+
+/// Write the body of `response` to `target`, appending after `offset` bytes.
+fn write_body(url: &Url, response: Response, target: &Path, offset: u64) -> Attempt<()> {
+    let total = response
+        .header("Content-Length")
+        .and_then(|length| length.parse::<u64>().ok())
+        .map(|length| offset + length);
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(0 < offset)
+        .truncate(offset == 0)
+        .open(target)
+        .map_err(io_failure)?;
+    let mut progress = DownloadProgress::start(url);
+    progress.set_total(total);
+    let mut received = offset;
+    progress.set_received(received);
+    let mut body = response.into_reader();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = match body.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+            Err(error) => return Err(io_failure(error)),
+        };
+        file.write_all(&buffer[..read]).map_err(io_failure)?;
+        received += read as u64;
+        progress.set_received(received);
+    }
+    file.sync_all().map_err(io_failure)?;
+    if let Some(total) = total.filter(|total| received < *total) {
+        return Err(io_failure(Error::new(
+            ErrorKind::UnexpectedEof,
+            format!("Received only {} of {} bytes", received, total),
+        )));
+    }
+    progress.finish();
+    Ok(())
+}
+
+/// Download `url` to `target`, see [`crate::tools::download`].
+///
+/// Resume a partial download at `target`; if the server doesn't support ranges download
+/// everything again.  Consider a download complete if the server has nothing left to send.
+///
+/// Return the file name from the `Content-Disposition` header of the response, if any.
+pub fn download(url: &Url, target: &Path, token: Option<&str>) -> Result<Option<String>> {
+    if let Some(source) = local_file(url) {
+        std::fs::copy(&source, target)
+            .map_err(|error| Error::new(error.kind(), format!("{} failed: {}", url, error)))?;
+        return Ok(None);
+    }
+    let agent = agent();
+    with_retries(url.as_str(), || {
+        // Check the size again for every attempt, to resume what a failed attempt received
+        let offset = target.metadata().map_or(0, |metadata| metadata.len());
+        let mut request = authorized(agent.get(url.as_str()), token);
+        if 0 < offset {
+            request = request.set("Range", &format!("bytes={}-", offset));
+        }
+        let response = match request.call() {
+            Err(ureq::Error::Status(416, _)) if 0 < offset => return Ok(None),
+            response => response?,
+        };
+        let filename = response
+            .header("Content-Disposition")
+            .and_then(disposition_filename);
+        let offset = if response.status() == 206 { offset } else { 0 };
+        write_body(url, response, target, offset)?;
+        Ok(filename)
+    })
+}
+
+/// Fetch the contents of `url` as text, see [`crate::tools::fetch_text`].
+pub fn fetch_text(url: &str) -> Result<String> {
+    let agent = agent();
+    with_retries(url, || {
+        let mut text = String::new();
+        agent
+            .get(url)
+            .call()?
+            .into_reader()
+            .read_to_string(&mut text)
+            .map_err(io_failure)?;
+        Ok(text)
+    })
+}
+
+/// Fetch the contents of `url` as text, unless its response still has the given `etag`.
+///
+/// Return `None` if the response didn't change, and the new ETag if any and the contents
+/// otherwise.
+pub fn fetch_conditional(
+    url: &str,
+    etag: Option<&str>,
+) -> Result<Option<(Option<String>, String)>> {
+    let agent = agent();
+    with_retries(url, || {
+        let mut request = agent.get(url);
+        if let Some(etag) = etag {
+            request = request.set("If-None-Match", etag);
+        }
+        let response = request.call()?;
+        if response.status() == 304 {
+            return Ok(None);
+        }
+        let etag = response.header("ETag").map(str::to_string);
+        let mut text = String::new();
+        response
+            .into_reader()
+            .read_to_string(&mut text)
+            .map_err(io_failure)?;
+        Ok(Some((etag, text)))
+    })
+}
+
+/// Check that `url` is reachable with a HEAD request, see [`crate::tools::check_url`].
+pub fn check_url(url: &Url) -> Result<()> {
+    if let Some(file) = local_file(url) {
+        return file.metadata().map(|_| ());
+    }
+    let agent = agent();
+    with_retries(url.as_str(), || {
+        agent.head(url.as_str()).call()?;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+
+    /// Serve `body` on localhost with support for ranges, for `requests` requests.
+    ///
+    /// Return the URL to download `body` from, and the headers of all requests.
+    fn serve(body: &'static [u8], requests: usize) -> (Url, std::thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!(
+            "http://{}/download?id=42",
+            listener.local_addr().unwrap()
+        ))
+        .unwrap();
+        let server = std::thread::spawn(move || {
+            let mut all_headers = Vec::new();
+            for stream in listener.incoming().take(requests) {
+                let mut stream = stream.unwrap();
+                let mut headers = String::new();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    headers.push_str(&line);
+                }
+                let start = headers
+                    .lines()
+                    .find_map(|line| line.strip_prefix("Range: bytes="))
+                    .and_then(|range| range.trim_end_matches('-').parse::<usize>().ok());
+                let response = match start {
+                    Some(start) if body.len() <= start => {
+                        "HTTP/1.1 416 Range Not Satisfiable\r\nContent-Length: 0\r\n".to_string()
+                    }
+                    Some(start) => format!(
+                        "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {}-{}/{}\r\nContent-Length: {}\r\n",
+                        start,
+                        body.len() - 1,
+                        body.len(),
+                        body.len() - start
+                    ),
+                    None => format!(
+                        "HTTP/1.1 200 OK\r\nContent-Disposition: attachment; filename=\"hello-1.0.tar.gz\"\r\nContent-Length: {}\r\n",
+                        body.len()
+                    ),
+                };
+                stream
+                    .write_all(format!("{}Connection: close\r\n\r\n", response).as_bytes())
+                    .unwrap();
+                if response.starts_with("HTTP/1.1 2") {
+                    stream.write_all(&body[start.unwrap_or(0)..]).unwrap();
+                }
+                all_headers.push(headers);
+            }
+            all_headers
+        });
+        (url, server)
+    }
+
+    #[test]
+    fn download_and_resume() {
+        let body = b"Hello world, this is a download";
+        let (url, server) = serve(body, 3);
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("download");
+
+        let filename = download(&url, &target, Some("secret")).unwrap();
+        assert_eq!(filename.as_deref(), Some("hello-1.0.tar.gz"));
+        assert_eq!(std::fs::read(&target).unwrap(), body);
+
+        // Resume a partial download, and accept a complete download
+        std::fs::write(&target, &body[..10]).unwrap();
+        assert_eq!(download(&url, &target, None).unwrap(), None);
+        assert_eq!(std::fs::read(&target).unwrap(), body);
+        assert_eq!(download(&url, &target, None).unwrap(), None);
+        assert_eq!(std::fs::read(&target).unwrap(), body);
+
+        let headers = server.join().unwrap();
+        assert!(headers[0].contains("Authorization: Bearer secret"));
+        assert!(!headers[0].contains("Range:"));
+        assert!(headers[1].contains("Range: bytes=10-"));
+        assert!(!headers[1].contains("Authorization"));
+        assert!(headers[2].contains(&format!("Range: bytes={}-", body.len())));
+    }
+
+    #[test]
+    fn transient_errors() {
+        let response = |status| ureq::Error::Status(status, Response::new(status, "", "").unwrap());
+        assert!(is_transient(&response(503)));
+        assert!(is_transient(&response(429)));
+        assert!(!is_transient(&response(404)));
+        assert!(is_transient(&ureq::Error::from(Error::from(
+            ErrorKind::ConnectionReset
+        ))));
+        assert_eq!(
+            to_io_error("https://example.com/foo", response(404)).to_string(),
+            "https://example.com/foo failed with HTTP status 404"
+        );
+    }
+}
@@ -10,31 +10,99 @@
 
 #![deny(warnings, clippy::all, missing_docs)]
 
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::fs::File;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
 
 use anyhow::{anyhow, Context, Error, Result};
 use colored::Colorize;
-use fehler::throws;
+use fehler::{throw, throws};
+use rayon::prelude::*;
+use regex::Regex;
+use serde::Deserialize;
 use versions::Versioning;
 
+pub use config::{Config, MissingDestinations};
 pub use dirs::*;
-pub use manifest::{Manifest, ManifestRepo, ManifestStore};
-pub use repos::HomebinRepos;
+pub use manifest::{Manifest, ManifestRepo, ManifestStore, ManifestSummary};
+pub use remote::install_manifest_over_ssh;
+pub use repos::{
+    archive_extension, check_archive_pin, check_repo_name, check_repo_remote, default_repos,
+    repo_token, write_repo_configs, HomebinRepos, RepoConfig, DEFAULT_FETCH_TTL,
+};
+pub use state::{write_atomically, StateLock};
 
-use crate::operations::{ApplyOperation, Operation};
+use crate::checksum::{Algorithm, Hasher, ValidationError};
+use crate::events::{EventLog, IntegrityEvent, SignatureResult};
+use crate::manifest::{Arch, Discover, Platform, VersionCheck};
+use crate::operations::{ApplyOperation, DestinationKind, Operation};
+use crate::process::CommandExt;
+use crate::provenance::{Provenance, ProvenanceStore};
+use crate::secrets::Secret;
 use crate::tools::{manpath, path_contains};
+use crate::trust::TrustedKey;
 
-mod checksum;
+mod config;
 mod dirs;
+mod events;
+#[cfg(feature = "native-http")]
+mod http;
 mod process;
+mod progress;
+mod remote;
 mod repos;
+mod state;
 mod tools;
 
+/// Workloads of core paths, for benchmarks.
+pub mod bench;
+/// Import draft manifests from Homebrew.
+pub mod brew;
+/// Checksums of downloads.
+pub mod checksum;
+/// Destinations which the system doesn't use.
+pub mod destinations;
+/// Export installed manifests for other tools.
+pub mod export;
+/// Draft manifests from GitHub releases.
+pub mod github;
+/// The graph of manifests, their files and their relations.
+pub mod graph;
+/// Lint manifest files.
+pub mod lint;
 /// Manifest types and loading.
 pub mod manifest;
+/// Test manifests end-to-end.
+pub mod manifest_test;
 /// Operations to apply manifests to a home directory.
 pub mod operations;
+/// A cache of outdated manifests, for shell prompts.
+pub mod outdated;
+/// Check permissions of installed files.
+pub mod permissions;
+/// Pinned manifests.
+pub mod pins;
+/// Provenance of installed files.
+pub mod provenance;
+/// Search manifests.
+pub mod search;
+/// Secrets for authenticated downloads.
+pub mod secrets;
+/// Status output for terminals and logs.
+pub mod status;
+/// Summaries of commands.
+pub mod summary;
+/// Support for testing with manifests, see the `test-support` feature.
+#[cfg(any(test, feature = "test-support"))]
+pub mod testing;
+/// Trusted minisign keys.
+pub mod trust;
+/// Check upstream projects for new versions.
+pub mod upstream;
 
 /// Check whether the environment is ok, and print warnings to stderr if not.
 ///
@@ -74,141 +142,967 @@ pub fn check_environment(install_dirs: &InstallDirs) -> () {
     }
 }
 
+/// How to resolve a conflict with an existing file when installing a manifest.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ConflictResolution {
+    /// Overwrite the existing file.
+    Overwrite,
+    /// Keep the existing file and don't install the file of the manifest.
+    Skip,
+    /// Move the existing file aside to `<file>.bak`, and install the file of the manifest.
+    ///
+    /// Never overwrite an earlier backup, but number backups instead, and move the file back
+    /// if installation fails.
+    Backup,
+}
+
+/// How to isolate hooks of manifests from the rest of the system.
+///
+/// Hooks run code straight out of freshly downloaded archives; isolation limits what this code
+/// gets to see.  Hooks always run in the working directory of the manifest.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HookIsolation {
+    /// Run hooks with the environment of homebins.
+    #[default]
+    Off,
+    /// Run hooks with a clean environment, which only has a few basic variables like `HOME`.
+    Clean,
+    /// Like `Clean`, but also run hooks without network access, if user namespaces are available.
+    NoNetwork,
+    /// Like `NoNetwork`, but refuse to run hooks if user namespaces are not available.
+    Strict,
+}
+
+/// How many files of a manifest to download at once, by default.
+pub const DEFAULT_JOBS: usize = 4;
+
+/// Options for applying operations of a manifest.
+#[derive(Debug, Default, Clone)]
+pub struct ApplyOptions {
+    /// Skip operations which were already applied.
+    ///
+    /// This resumes a failed operation, e.g. an installation which failed half-way through,
+    /// without downloading and copying everything again.
+    pub resume: bool,
+    /// Keep the working directory if applying operations failed.
+    ///
+    /// This helps to debug manifests, e.g. to find out why a file from an archive is missing.
+    pub keep_workdir: bool,
+    /// Kinds of destinations to skip when installing or updating.
+    ///
+    /// Files for these destinations are not installed; this does not affect removal though.
+    pub skip_destinations: Vec<DestinationKind>,
+    /// A file to log integrity events of downloads to.
+    ///
+    /// Homebins appends an event for every download and its checksum validation as a line of JSON.
+    pub event_log: Option<PathBuf>,
+    /// Install even if files of the manifest already exist.
+    pub force: bool,
+    /// Verify downloads against all checksums of a manifest, not just the strongest one.
+    pub verify_all: bool,
+    /// Install binaries for this architecture instead of the architecture of the host.
+    pub target_arch: Option<Arch>,
+    /// Skip operations which affect the running system, like hooks, systemd units and caches.
+    ///
+    /// Use this when installing into a directory which the running system doesn't use, e.g. the
+    /// home directory of another system, or binaries of another architecture.
+    pub skip_host_operations: bool,
+    /// Kill version checks of binaries which take longer than this.
+    ///
+    /// If `None` use a timeout of ten seconds.
+    pub version_check_timeout: Option<Duration>,
+    /// Tokens to download from hosts with, by host name.
+    pub tokens: HashMap<String, Secret>,
+    /// How to resolve conflicts with existing files, by the path of the file.
+    ///
+    /// Installing fails if any file of a manifest exists without a resolution, unless `force`
+    /// or `resume` are set.
+    pub conflict_resolutions: HashMap<PathBuf, ConflictResolution>,
+    /// Install binaries with versioned names, e.g. `rg-14.1.0`, and link their names to them.
+    ///
+    /// Manifests can override this with `versioned_binaries` in their info.
+    pub versioned_binaries: bool,
+    /// Trusted minisign keys, to tell which downloads a trusted key signed.
+    pub trusted_keys: Vec<TrustedKey>,
+    /// Refuse downloads whose signature doesn't verify with one of `trusted_keys`.
+    pub require_trusted_keys: bool,
+    /// How to isolate hooks.
+    pub hook_isolation: HookIsolation,
+    /// The Git revision of the manifest repository, to record in the provenance of files.
+    pub manifest_revision: Option<String>,
+    /// The name of the manifest repository of the manifest, to record in the provenance of files.
+    pub manifest_repository: Option<String>,
+    /// How many files of a manifest to download at once.
+    ///
+    /// If `None` download up to [`DEFAULT_JOBS`] files at once.
+    pub jobs: Option<usize>,
+}
+
+/// Check whether applying `operations` of `manifest` to `dirs` needs elevated privileges.
+///
+/// Warn about hooks which ask for elevated privileges, and fail with suggestions if any operation
+/// would fail without elevated privileges.
+#[throws]
+fn check_privileges(dirs: &InstallDirs, manifest: &Manifest, operations: &[Operation<'_>]) -> () {
+    let mut failures = Vec::new();
+    for operation in operations::privileged_operations(operations, dirs) {
+        if operation.fails() {
+            failures.push(format!("{}; {}", operation, operation.suggestion()));
+        } else {
+            crate::estatus!(
+                "{}",
+                format!("WARNING: {}; {}", operation, operation.suggestion()).yellow()
+            );
+        }
+    }
+    if !failures.is_empty() {
+        throw!(anyhow!(
+            "{} needs elevated privileges:\n{}",
+            manifest.info.name,
+            failures.join("\n")
+        ));
+    }
+}
+
+/// Apply `operations` of `manifest` to `install_dirs`.
+///
+/// Return the number of bytes downloaded.
 #[throws]
 fn apply_operations(
     dirs: &HomebinProjectDirs,
     install_dirs: &mut InstallDirs,
     manifest: &Manifest,
     operations: &[Operation<'_>],
-) -> () {
-    let op_dirs = ManifestOperationDirs::for_manifest(dirs, install_dirs, manifest)?;
+    options: &ApplyOptions,
+) -> u64 {
+    let escaping = operations::escaping_names(operations);
+    if !escaping.is_empty() {
+        throw!(anyhow!(
+            "{} has file names outside of their directories: {}",
+            manifest.info.name,
+            escaping.join(", ")
+        ));
+    }
+    check_privileges(install_dirs, manifest, operations)?;
+    let mut op_dirs = ManifestOperationDirs::for_manifest(dirs, install_dirs, manifest)?;
+    op_dirs.set_verify_all_checksums(options.verify_all);
+    op_dirs.set_tokens(options.tokens.clone());
+    op_dirs.set_trusted_keys(options.trusted_keys.clone(), options.require_trusted_keys);
+    op_dirs.set_hook_isolation(options.hook_isolation);
     op_dirs.ensure()?;
-    for operation in operations {
-        operation.apply_operation(&op_dirs)?;
+    let event_log = Mutex::new(EventLog::open(options.event_log.as_deref())?);
+    let apply = |operation: &Operation<'_>| -> Result<()> {
+        if options.resume && operation.is_applied(&op_dirs)? {
+            Ok(())
+        } else if let Operation::Download(url, name, checksums) = operation {
+            let (manifest, url, file) = (manifest.info.name.as_str(), url.as_str(), name.as_ref());
+            let emit = |event: &IntegrityEvent<'_>| event_log.lock().unwrap().emit(event);
+            emit(&IntegrityEvent::Download {
+                manifest,
+                url,
+                file,
+            })?;
+            let result = operation.apply_operation(&op_dirs);
+            emit(&IntegrityEvent::checksum(
+                manifest, url, file, checksums, &result,
+            ))?;
+            result
+        } else if let Operation::VerifyMinisign(url, name, public_key) = operation {
+            let result = operation.apply_operation(&op_dirs);
+            event_log.lock().unwrap().emit(&IntegrityEvent::Signature {
+                manifest: &manifest.info.name,
+                url: url.as_str(),
+                file: name,
+                public_key,
+                trusted_key: op_dirs.trusted_key(public_key).map(|key| key.name.as_str()),
+                result: SignatureResult::from_result(&result),
+            })?;
+            result
+        } else {
+            operation.apply_operation(&op_dirs)
+        }
+    };
+    // Download all files at once up front, and then extract and copy in order
+    let (downloads, others): (Vec<_>, Vec<_>) = operations
+        .iter()
+        .partition(|operation| matches!(operation, Operation::Download(..)));
+    let started = Instant::now();
+    let result =
+        download_all(&downloads, options.jobs.unwrap_or(DEFAULT_JOBS), apply).and_then(|_| {
+            let downloaded = op_dirs.downloaded_bytes();
+            if 0 < downloaded {
+                let elapsed = started.elapsed().as_secs_f64();
+                crate::estatus!(
+                    "Downloaded {} of {} in {:.1}s ({}/s)",
+                    summary::format_bytes(downloaded),
+                    manifest.info.name,
+                    elapsed,
+                    summary::format_bytes((downloaded as f64 / elapsed.max(0.001)) as u64)
+                );
+            }
+            others.into_iter().try_for_each(apply)
+        });
+    if let Err(error) = result {
+        if options.keep_workdir {
+            let work_dir = op_dirs.keep();
+            eprintln!(
+                "{}",
+                format!("Kept working directory at {}", work_dir.display()).yellow()
+            );
+        }
+        throw!(error);
+    }
+    op_dirs.downloaded_bytes()
+}
+
+/// Apply all `downloads` with `apply`, at most `jobs` at once.
+///
+/// Refuse downloads which go to the same file up front, because concurrent downloads would
+/// clobber each other.  Fail with the error of any failed download, after all running downloads
+/// finished.
+#[throws]
+fn download_all<F>(downloads: &[&Operation<'_>], jobs: usize, apply: F) -> ()
+where
+    F: Fn(&Operation<'_>) -> Result<()> + Sync,
+{
+    let mut urls_by_name: HashMap<&str, Vec<&str>> = HashMap::new();
+    for operation in downloads {
+        if let Operation::Download(url, name, _) = operation {
+            urls_by_name
+                .entry(name.as_ref())
+                .or_default()
+                .push(url.as_str());
+        }
+    }
+    let mut duplicates: Vec<String> = urls_by_name
+        .into_iter()
+        .filter(|(_, urls)| 1 < urls.len())
+        .map(|(name, urls)| format!("{} from {}", name, urls.join(", ")))
+        .collect();
+    if !duplicates.is_empty() {
+        duplicates.sort();
+        throw!(anyhow!(
+            "Several downloads go to the same file: {}",
+            duplicates.join("; ")
+        ));
+    }
+    if jobs <= 1 || downloads.len() <= 1 {
+        downloads
+            .iter()
+            .try_for_each(|operation| apply(operation))?;
+    } else {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(jobs.min(downloads.len()))
+            .build()
+            .with_context(|| "Failed to start threads for downloads".to_string())?
+            .install(|| {
+                downloads
+                    .par_iter()
+                    .try_for_each(|operation| apply(operation))
+            })?;
+    }
+}
+
+/// Record the provenance of all files which `operations` of `manifest` installed to `dirs`.
+///
+/// The files are in place already, so only warn if recording their provenance fails.
+fn record_provenance(
+    dirs: &HomebinProjectDirs,
+    install_dirs: &InstallDirs,
+    manifest: &Manifest,
+    operations: &[Operation<'_>],
+    options: &ApplyOptions,
+) {
+    if let Err(error) = write_provenance(dirs, install_dirs, manifest, operations, options) {
+        crate::estatus!(
+            "{}",
+            format!(
+                "WARNING: Failed to record provenance of {}: {:#}",
+                manifest.info.name, error
+            )
+            .yellow()
+            .bold()
+        );
+    }
+}
+
+/// Write the provenance of all files which `operations` of `manifest` installed to `dirs`.
+///
+/// Replace all previous records of `manifest`, e.g. of files of the previous version.
+#[throws]
+fn write_provenance(
+    dirs: &HomebinProjectDirs,
+    install_dirs: &InstallDirs,
+    manifest: &Manifest,
+    operations: &[Operation<'_>],
+    options: &ApplyOptions,
+) -> () {
+    let download_dir = dirs.manifest_download_dir(manifest);
+    let installed_at = SystemTime::now();
+    let mut checksums: HashMap<&str, String> = HashMap::new();
+    let mut records = Vec::new();
+    for (destination, url, name) in operations::download_destinations(operations) {
+        let file = install_dirs
+            .path(destination.directory())
+            .join(destination.name());
+        // Skip optional files which didn't exist in the archive
+        if !file.exists() {
+            continue;
+        }
+        let sha256 = match checksums.get(name) {
+            Some(sha256) => sha256.clone(),
+            None => {
+                let download = download_dir.join(name);
+                let mut hasher = Hasher::new(&[Algorithm::Sha256]);
+                File::open(&download)
+                    .and_then(|mut source| hasher.read_from(&mut source))
+                    .with_context(|| format!("Failed to read {}", download.display()))?;
+                let sha256 = hex::encode(&hasher.finalize()[0].1);
+                checksums.insert(name, sha256.clone());
+                sha256
+            }
+        };
+        records.push(Provenance {
+            file,
+            manifest: manifest.info.name.clone(),
+            version: manifest.info.version.to_string(),
+            url: url.to_string(),
+            sha256,
+            repository: options.manifest_repository.clone(),
+            revision: options.manifest_revision.clone(),
+            installed_at,
+        });
+    }
+    let mut store = ProvenanceStore::open(dirs.provenance_file())?;
+    store.forget_manifest(&manifest.info.name);
+    store.record(records);
+    store.save()?;
+}
+
+/// Get the platform to install `manifest` for with `options`.
+///
+/// This is the host platform, with the target architecture of `options` if any; fail if
+/// `manifest` has no downloads for it.
+#[throws]
+fn manifest_platform(manifest: &Manifest, options: &ApplyOptions) -> Platform {
+    let host = Platform::host()?;
+    let platform = Platform {
+        arch: options.target_arch.unwrap_or(host.arch),
+        ..host
+    };
+    manifest.check_platform(platform)?;
+    platform
+}
+
+/// Drop operations which affect the running system if `options` say so.
+fn host_operations<'a>(
+    operations: Vec<Operation<'a>>,
+    options: &ApplyOptions,
+) -> Vec<Operation<'a>> {
+    if options.skip_host_operations {
+        operations::skip_host_operations(operations)
+    } else {
+        operations
+    }
+}
+
+/// Version the binaries of `manifest` in `operations` if `manifest` or `options` say so.
+///
+/// Remove the versions of binaries which are actually installed in `install_dirs`, and not the
+/// version of `manifest`, which differs if the manifest was updated in the meantime.
+fn versioned_operations<'a>(
+    operations: Vec<Operation<'a>>,
+    install_dirs: &InstallDirs,
+    manifest: &Manifest,
+    options: &ApplyOptions,
+) -> Vec<Operation<'a>> {
+    if manifest
+        .info
+        .versioned_binaries
+        .unwrap_or(options.versioned_binaries)
+    {
+        operations::version_binaries(operations, &manifest.info.version.to_string(), |name| {
+            linked_version(install_dirs.bin_dir(), name)
+        })
+    } else {
+        operations
+    }
+}
+
+/// Get the version of the binary `name` in `bin_dir`.
+///
+/// Look for a versioned binary `<name>-<version>` which `name` is a hard link of, and return
+/// its version, or `None` if `name` doesn't exist or links to no versioned binary.
+fn linked_version(bin_dir: &Path, name: &str) -> Option<String> {
+    let binary = std::fs::metadata(bin_dir.join(name)).ok()?;
+    let prefix = format!("{}-", name);
+    std::fs::read_dir(bin_dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .find_map(|entry| {
+            let file_name = entry.file_name().into_string().ok()?;
+            let version = file_name.strip_prefix(&prefix)?;
+            let metadata = entry.metadata().ok()?;
+            if metadata.dev() == binary.dev() && metadata.ino() == binary.ino() {
+                Some(version.to_string())
+            } else {
+                None
+            }
+        })
+}
+
+/// Get all files of `manifest` which already exist but don't belong to `manifest`.
+///
+/// Homebins doesn't record which files belong to which manifest, so if `manifest` is installed
+/// assume that all its files belong to it, and otherwise that none of them does.
+#[throws]
+pub fn conflicting_files(
+    dirs: &InstallDirs,
+    manifest: &Manifest,
+    options: &ApplyOptions,
+) -> Vec<PathBuf> {
+    if installed_manifest_version(dirs, manifest, options)?.is_some() {
+        Vec::new()
+    } else {
+        installed_files(dirs, manifest, options)?
+            .into_iter()
+            .filter(|file| file.exists())
+            .collect()
     }
 }
+
+/// Apply the resolutions of conflicting files in `options` to `operations` for `dirs`.
+///
+/// Move files to back up aside, and drop operations which install skipped files.  Return the
+/// remaining operations along with the backups made, to restore them if installation fails.
+#[throws]
+fn resolve_conflicts<'a>(
+    dirs: &InstallDirs,
+    operations: Vec<Operation<'a>>,
+    options: &ApplyOptions,
+) -> (Vec<Operation<'a>>, Vec<(PathBuf, PathBuf)>) {
+    let mut backups = Vec::new();
+    for (file, resolution) in &options.conflict_resolutions {
+        if *resolution == ConflictResolution::Backup && file.exists() {
+            let backup = backup_path(file);
+            if let Err(error) = std::fs::rename(file, &backup) {
+                restore_backups(&backups);
+                throw!(Error::new(error).context(format!(
+                    "Failed to back up {} to {}",
+                    file.display(),
+                    backup.display()
+                )));
+            }
+            backups.push((file.clone(), backup));
+        }
+    }
+    let operations = operations
+        .into_iter()
+        .filter(|operation| {
+            !operations::operation_destinations(std::iter::once(operation)).any(|destination| {
+                let file = dirs.path(destination.directory()).join(destination.name());
+                options.conflict_resolutions.get(&file) == Some(&ConflictResolution::Skip)
+            })
+        })
+        .collect();
+    (operations, backups)
+}
+
+/// Get a path to back up `file` to which doesn't exist yet.
+///
+/// This is `<file>.bak`, or `<file>.bak.<n>` with the first `n` that doesn't exist, if
+/// there's a backup already, so we never overwrite earlier backups.
+fn backup_path(file: &Path) -> PathBuf {
+    let mut backup = file.as_os_str().to_os_string();
+    backup.push(".bak");
+    let mut candidate = PathBuf::from(&backup);
+    let mut n = 1;
+    while candidate.symlink_metadata().is_ok() {
+        let mut numbered = backup.clone();
+        numbered.push(format!(".{}", n));
+        candidate = PathBuf::from(numbered);
+        n += 1;
+    }
+    candidate
+}
+
+/// Move `backups` of files back to where they came from.
+///
+/// Replace whatever got installed in place of the original file.  Warn about backups we
+/// fail to restore, because this runs while handling another error already.
+fn restore_backups(backups: &[(PathBuf, PathBuf)]) {
+    for (file, backup) in backups {
+        let result = match file.symlink_metadata() {
+            Ok(metadata) if metadata.is_dir() => std::fs::remove_dir_all(file),
+            Ok(_) => std::fs::remove_file(file),
+            Err(_) => Ok(()),
+        }
+        .and_then(|_| std::fs::rename(backup, file));
+        if let Err(error) = result {
+            crate::estatus!(
+                "{}",
+                format!(
+                    "WARNING: Failed to restore {} from {}: {}",
+                    file.display(),
+                    backup.display(),
+                    error
+                )
+                .yellow()
+                .bold()
+            );
+        }
+    }
+}
+
 /// Install a manifest.
 ///
 /// Apply the operations of a `manifest` against the given `install_dirs`; using the given project `dirs` for downloads.
+///
+/// Fail if files of `manifest` already exist, unless `options` resolve the conflicts, force
+/// installation or resume a previous installation.
+///
+/// Record the provenance of all installed files.  Return the number of bytes downloaded.
 pub fn install_manifest(
     dirs: &HomebinProjectDirs,
     install_dirs: &mut InstallDirs,
     manifest: &Manifest,
-) -> Result<()> {
-    apply_operations(
-        dirs,
+    options: &ApplyOptions,
+) -> Result<u64> {
+    if !(options.force || options.resume) {
+        let conflicts: Vec<PathBuf> = conflicting_files(install_dirs, manifest, options)?
+            .into_iter()
+            .filter(|file| !options.conflict_resolutions.contains_key(file))
+            .collect();
+        if !conflicts.is_empty() {
+            let files: Vec<String> = conflicts.iter().map(|f| f.display().to_string()).collect();
+            throw!(anyhow!(
+                "Files of {} already exist: {}",
+                manifest.info.name,
+                files.join(", ")
+            ));
+        }
+    }
+    let (operations, backups) = resolve_conflicts(
         install_dirs,
-        manifest,
-        &operations::install_manifest(manifest),
-    )
+        host_operations(
+            operations::skip_destinations(
+                versioned_operations(
+                    operations::install_manifest(manifest, manifest_platform(manifest, options)?),
+                    install_dirs,
+                    manifest,
+                    options,
+                ),
+                &options.skip_destinations,
+            ),
+            options,
+        ),
+        options,
+    )?;
+    let downloaded = apply_operations(dirs, install_dirs, manifest, &operations, options)
+        .inspect_err(|_| restore_backups(&backups))?;
+    record_provenance(dirs, install_dirs, manifest, &operations, options);
+    Ok(downloaded)
 }
 
 /// Update a manifest
 ///
-/// Apply the update operations of the `manifest` against the given install dirs.
+/// Apply the update operations of the `manifest` against the given install dirs, and record the
+/// provenance of all installed files.  Return the number of bytes downloaded.
 pub fn update_manifest(
     dirs: &HomebinProjectDirs,
     install_dirs: &mut InstallDirs,
     manifest: &Manifest,
-) -> Result<()> {
-    apply_operations(
-        dirs,
-        install_dirs,
-        manifest,
-        &operations::update_manifest(manifest),
-    )
+    options: &ApplyOptions,
+) -> Result<u64> {
+    let operations = host_operations(
+        operations::skip_destinations(
+            versioned_operations(
+                operations::update_manifest(manifest, manifest_platform(manifest, options)?),
+                install_dirs,
+                manifest,
+                options,
+            ),
+            &options.skip_destinations,
+        ),
+        options,
+    );
+    let downloaded = apply_operations(dirs, install_dirs, manifest, &operations, options)?;
+    record_provenance(dirs, install_dirs, manifest, &operations, options);
+    Ok(downloaded)
 }
 
 /// Remove a manifest.
 ///
-/// Apply the remove operations of the `manifest` against the given install dirs.
+/// Apply the remove operations of the `manifest` against the given install dirs, and forget the
+/// provenance of its files.  Return the number of bytes downloaded, e.g. for hooks.
 pub fn remove_manifest(
     dirs: &HomebinProjectDirs,
     install_dirs: &mut InstallDirs,
     manifest: &Manifest,
-) -> Result<()> {
-    apply_operations(
+    options: &ApplyOptions,
+) -> Result<u64> {
+    let downloaded = apply_operations(
         dirs,
         install_dirs,
         manifest,
-        &operations::remove_manifest(manifest),
-    )
+        &host_operations(
+            versioned_operations(
+                operations::remove_manifest(manifest, manifest_platform(manifest, options)?),
+                install_dirs,
+                manifest,
+                options,
+            ),
+            options,
+        ),
+        options,
+    )?;
+    let mut store = ProvenanceStore::open(dirs.provenance_file())?;
+    store.forget_manifest(&manifest.info.name);
+    store.save()?;
+    Ok(downloaded)
 }
 
-/// Get the installed version of the given manifest.
+/// Remove files of `manifest` of the given `kinds` of destinations, e.g. all its completions.
+///
+/// Keep all other files of `manifest` in place, and forget the provenance of removed files.
+/// Return the number of bytes downloaded.
+pub fn remove_manifest_destinations(
+    dirs: &HomebinProjectDirs,
+    install_dirs: &mut InstallDirs,
+    manifest: &Manifest,
+    kinds: &[DestinationKind],
+    options: &ApplyOptions,
+) -> Result<u64> {
+    let operations = host_operations(
+        operations::only_removals_of(
+            versioned_operations(
+                operations::remove_manifest(manifest, manifest_platform(manifest, options)?),
+                install_dirs,
+                manifest,
+                options,
+            ),
+            kinds,
+        ),
+        options,
+    );
+    let downloaded = apply_operations(dirs, install_dirs, manifest, &operations, options)?;
+    let mut store = ProvenanceStore::open(dirs.provenance_file())?;
+    for destination in operations::operation_destinations(operations.iter()) {
+        store.forget_file(
+            &install_dirs
+                .path(destination.directory())
+                .join(destination.name()),
+        );
+    }
+    store.save()?;
+    Ok(downloaded)
+}
+
+/// How often to attempt a version check before giving up.
+const VERSION_CHECK_ATTEMPTS: u32 = 3;
+
+/// How long to wait for a version check if not configured otherwise.
+const DEFAULT_VERSION_CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long to wait before the second attempt of a version check; doubles with every attempt.
+const VERSION_CHECK_BACKOFF: Duration = Duration::from_millis(200);
+
+/// A version check which kept failing after all attempts.
+///
+/// Unlike a missing binary this doesn't tell whether the manifest is installed.
+#[derive(thiserror::Error, Debug)]
+#[error("Version check of {path} failed: {reason}")]
+pub struct VersionCheckFailed {
+    /// The binary or file which failed its version check.
+    pub path: PathBuf,
+    /// How often homebins attempted the version check.
+    pub attempts: u32,
+    /// Why the last attempt failed.
+    pub reason: String,
+}
+
+/// Get the version of the given `binary` with the version `check` of `manifest`.
+///
+/// Attempt to invoke the binary with the version check arguments, and use the pattern to extract
+/// a version number.
 ///
-/// Attempt to invoke the version check denoted in the manifest, i.e. the given binary with the
-/// version check arguments, and use the pattern to extract a version number.
+/// If the binary fails to start or exits with non-zero status without printing a version retry
+/// a few times with increasing delays, and fail with [`VersionCheckFailed`] if it keeps failing.
+/// Kill the binary if it doesn't finish within `timeout`, and fail with [`VersionCheckFailed`]
+/// without retrying.
 ///
-/// Return `None` if the binary doesn't exist or its output doesn't match the pattern;
-/// fail if we cannot invoke it for other reasons or if we fail to parse the version from other.
+/// Return `None` if the output of the binary doesn't match the pattern; fail if we cannot parse
+/// the version from its output.
 #[throws]
-pub fn installed_manifest_version(dirs: &InstallDirs, manifest: &Manifest) -> Option<Versioning> {
-    let args = &manifest.discover.version_check.args;
-    let binary = dirs.bin_dir().join(&manifest.discover.binary);
-    if binary.is_file() {
-        let output = Command::new(&binary).args(args).output().with_context(|| {
-            format!(
-                "Failed to run {} with {:?}",
-                binary.display(),
-                &manifest.discover.version_check.args
-            )
-        })?;
-        let pattern = manifest.discover.version_check.regex().with_context(|| {
-            format!(
-                "Version check for {} failed: Invalid regex {}",
-                manifest.info.name, manifest.discover.version_check.pattern
-            )
-        })?;
-        let output = std::str::from_utf8(&output.stdout).with_context(|| {
-            format!(
-                "Output of command {} with {:?} returned non-utf8 stdout: {:?}",
-                binary.display(),
-                args,
-                output.stdout
-            )
-        })?;
-        let version = pattern
-            .captures(output)
-            .and_then(|c| c.get(1))
-            .map(|m| m.as_str());
-
-        version
-            .map(|s| {
-                Versioning::new(s).ok_or_else(|| {
-                    anyhow!(
-                        "Output of command {} with {:?} returned invalid version {:?}",
-                        binary.display(),
-                        args,
-                        version
-                    )
+fn binary_version(
+    manifest: &Manifest,
+    binary: PathBuf,
+    check: &VersionCheck,
+    timeout: Duration,
+) -> Option<Versioning> {
+    let args = &check.args;
+    let pattern = check.regex().with_context(|| {
+        format!(
+            "Version check for {} failed: Invalid regex {}",
+            manifest.info.name, check.pattern
+        )
+    })?;
+    let mut attempt = 1;
+    let selected = loop {
+        let reason = match Command::new(&binary).args(args).output_timeout(timeout) {
+            // Don't retry binaries which hang, to not block for several times the timeout
+            Err(error) if error.kind() == std::io::ErrorKind::TimedOut => {
+                throw!(VersionCheckFailed {
+                    path: binary,
+                    attempts: attempt,
+                    reason: format!("timed out after {}s", timeout.as_secs_f32())
                 })
+            }
+            Err(error) => error.to_string(),
+            Ok(output) => {
+                let selected = check.select_output(&output);
+                if output.status.success() || pattern.is_match(&String::from_utf8_lossy(&selected))
+                {
+                    break selected;
+                }
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                if stderr.trim().is_empty() {
+                    format!("{}", output.status)
+                } else {
+                    format!("{}: {}", output.status, stderr.trim())
+                }
+            }
+        };
+        if VERSION_CHECK_ATTEMPTS <= attempt {
+            throw!(VersionCheckFailed {
+                path: binary,
+                attempts: attempt,
+                reason
+            });
+        }
+        std::thread::sleep(VERSION_CHECK_BACKOFF * 2u32.pow(attempt - 1));
+        attempt += 1;
+    };
+    let output = std::str::from_utf8(&selected).with_context(|| {
+        format!(
+            "Output of command {} with {:?} returned non-utf8 output: {:?}",
+            binary.display(),
+            args,
+            selected
+        )
+    })?;
+    let version = VersionCheck::extract_version(&pattern, output);
+
+    version
+        .map(|s| {
+            Versioning::new(s).ok_or_else(|| {
+                anyhow!(
+                    "Output of command {} with {:?} returned invalid version {:?}",
+                    binary.display(),
+                    args,
+                    version
+                )
             })
-            .transpose()?
-    } else {
-        None
+        })
+        .transpose()?
+}
+
+/// Get the path of the binary or file `discover` looks at in `dirs`.
+///
+/// For multiple binaries this is the first binary which exists, or the first binary if none
+/// exists.
+fn discovered_path(dirs: &InstallDirs, discover: &Discover) -> PathBuf {
+    match discover {
+        Discover::Binary { binary, .. } => binary
+            .iter()
+            .map(|binary| dirs.bin_dir().join(binary))
+            .find(|path| path.is_file())
+            .unwrap_or_else(|| dirs.bin_dir().join(&binary[0])),
+        other => dirs.data_dir().join(other.name()),
+    }
+}
+
+/// Get all binaries `manifest` discovers which don't exist in `dirs`.
+pub fn missing_binaries<'a>(dirs: &InstallDirs, manifest: &'a Manifest) -> Vec<&'a str> {
+    match &manifest.discover {
+        Discover::Binary { binary, .. } => binary
+            .iter()
+            .filter(|binary| !dirs.bin_dir().join(binary).is_file())
+            .map(|binary| binary.as_str())
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Get the installed version of the given manifest.
+///
+/// Discover the installed version as denoted in the manifest:
+///
+/// - Invoke a binary and extract the version from its output, see [`Discover::Binary`].
+/// - Extract the version from the contents of a file, see [`Discover::VersionFile`].
+/// - Compare the checksum of a file, see [`Discover::Checksum`].  If the checksum matches the
+///   version of the manifest is installed; otherwise fail with [`VersionCheckFailed`] because
+///   the installed version is unknown.
+/// - Check whether a file exists, see [`Discover::File`], and assume that the version of the
+///   manifest is installed if it does.
+///
+/// Return `None` if the binary or file doesn't exist or its output or contents don't match the
+/// pattern.
+#[throws]
+pub fn installed_manifest_version(
+    dirs: &InstallDirs,
+    manifest: &Manifest,
+    options: &ApplyOptions,
+) -> Option<Versioning> {
+    let path = discovered_path(dirs, &manifest.discover);
+    if !path.is_file() {
+        return None;
+    }
+    match &manifest.discover {
+        Discover::Binary { version_check, .. } => binary_version(
+            manifest,
+            path,
+            version_check,
+            options
+                .version_check_timeout
+                .unwrap_or(DEFAULT_VERSION_CHECK_TIMEOUT),
+        )?,
+        Discover::VersionFile { pattern, .. } => {
+            let pattern = Regex::new(pattern).with_context(|| {
+                format!(
+                    "Version check for {} failed: Invalid regex {}",
+                    manifest.info.name, pattern
+                )
+            })?;
+            let contents = std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read version from {}", path.display()))?;
+            let version = VersionCheck::extract_version(&pattern, &contents);
+            version
+                .map(|s| {
+                    Versioning::new(s).ok_or_else(|| {
+                        anyhow!("{} contains invalid version {:?}", path.display(), s)
+                    })
+                })
+                .transpose()?
+        }
+        Discover::Checksum { checksums, .. } => {
+            let mut source =
+                File::open(&path).with_context(|| format!("Failed to open {}", path.display()))?;
+            match checksum::validate_all(checksums, &mut source) {
+                Ok(_) => Some(manifest.info.version.clone()),
+                Err(ValidationError::ChecksumMismatch { .. }) => throw!(VersionCheckFailed {
+                    path,
+                    attempts: 1,
+                    reason: format!("checksum doesn't match version {}", manifest.info.version)
+                }),
+                Err(error) => throw!(Error::new(error)
+                    .context(format!("Failed to validate checksum of {}", path.display()))),
+            }
+        }
+        Discover::File { .. } => Some(manifest.info.version.clone()),
     }
 }
 
 /// Whether the given manifest is outdated and needs updating.
 ///
+/// A manifest is outdated if its installed version is older than the version of the manifest,
+/// or if some of its binaries are missing, see [`missing_binaries`].
+///
 /// Return the installed version if it's outdated, otherwise return None.
 #[throws]
-pub fn outdated_manifest_version(dirs: &InstallDirs, manifest: &Manifest) -> Option<Versioning> {
-    installed_manifest_version(dirs, manifest)?
-        .filter(|installed| installed < &manifest.info.version)
+pub fn outdated_manifest_version(
+    dirs: &InstallDirs,
+    manifest: &Manifest,
+    options: &ApplyOptions,
+) -> Option<Versioning> {
+    installed_manifest_version(dirs, manifest, options)?.filter(|installed| {
+        installed < &manifest.info.version || !missing_binaries(dirs, manifest).is_empty()
+    })
 }
 
-/// Get all files the `manifest` would install to `dirs`.
-pub fn installed_files(dirs: &InstallDirs, manifest: &Manifest) -> Vec<PathBuf> {
-    operations::operation_destinations(operations::install_manifest(manifest).iter())
+/// Get the time the given `manifest` was installed at.
+///
+/// Homebins doesn't record installations, so this is the modification time of the discovered
+/// binary or file of `manifest`.  Return `None` if it doesn't exist.
+#[throws]
+pub fn manifest_installed_at(dirs: &InstallDirs, manifest: &Manifest) -> Option<SystemTime> {
+    let path = discovered_path(dirs, &manifest.discover);
+    if path.is_file() {
+        let modified = path
+            .metadata()
+            .and_then(|m| m.modified())
+            .with_context(|| format!("Failed to get modification time of {}", path.display()))?;
+        Some(modified)
+    } else {
+        None
+    }
+}
+
+/// Get all files the `manifest` would install to `dirs` with `options`.
+#[throws]
+pub fn installed_files(
+    dirs: &InstallDirs,
+    manifest: &Manifest,
+    options: &ApplyOptions,
+) -> Vec<PathBuf> {
+    let operations = operations::skip_destinations(
+        versioned_operations(
+            operations::install_manifest(manifest, manifest_platform(manifest, options)?),
+            dirs,
+            manifest,
+            options,
+        ),
+        &options.skip_destinations,
+    );
+    operations::operation_destinations(operations.iter())
         .map(|destination| dirs.path(destination.directory()).join(destination.name()))
         .collect()
 }
 
-/// Get all files that would be removed when removing `manifest`.
-pub fn files_to_remove(dirs: &InstallDirs, manifest: &Manifest) -> Vec<PathBuf> {
-    operations::operation_destinations(operations::remove_manifest(manifest).iter())
+/// Get all files the `manifest` installs to `dirs` with `options`, with their permissions.
+///
+/// Only include copied files; links have the permissions of the files they link to.
+#[throws]
+pub fn installed_permissions(
+    dirs: &InstallDirs,
+    manifest: &Manifest,
+    options: &ApplyOptions,
+) -> Vec<(PathBuf, operations::Permissions)> {
+    let operations = operations::skip_destinations(
+        versioned_operations(
+            operations::install_manifest(manifest, manifest_platform(manifest, options)?),
+            dirs,
+            manifest,
+            options,
+        ),
+        &options.skip_destinations,
+    );
+    operations::copy_permissions(&operations)
+        .into_iter()
+        .map(|(destination, permissions)| {
+            (
+                dirs.path(destination.directory()).join(destination.name()),
+                permissions,
+            )
+        })
+        .collect()
+}
+
+/// Get all files that would be removed when removing `manifest` with `options`.
+#[throws]
+pub fn files_to_remove(
+    dirs: &InstallDirs,
+    manifest: &Manifest,
+    options: &ApplyOptions,
+) -> Vec<PathBuf> {
+    let operations = versioned_operations(
+        operations::remove_manifest(manifest, manifest_platform(manifest, options)?),
+        dirs,
+        manifest,
+        options,
+    );
+    operations::operation_destinations(operations.iter())
         .map(|destination| dirs.path(destination.directory()).join(destination.name()))
         .collect()
 }
@@ -0,0 +1,370 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Lint manifest files.
+//!
+//! Deserialization already rejects manifests which homebins can't read at all; linting finds
+//! mistakes in manifests which homebins reads fine but which fail later, e.g. when checking the
+//! version of a binary, or which lack information homebins could use.
+//!
+//! Manifests lose their source locations when homebins expands placeholders, so diagnostics
+//! point to the first line which contains the offending value, if any.
+
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+
+use anyhow::{Context, Error};
+use fehler::throws;
+use regex::Regex;
+use url::Url;
+
+use crate::manifest::{
+    Arch, Checksums, Discover, Install, Manifest, Os, Platform, Target, Upstream,
+};
+use crate::operations::{install_manifest, operation_destinations};
+use crate::tools::check_url;
+
+/// The severity of a diagnostic.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Severity {
+    /// A mistake which makes the manifest fail.
+    Error,
+    /// Missing information, which doesn't break the manifest.
+    Warning,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// A diagnostic about a manifest file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// How bad this is.
+    pub severity: Severity,
+    /// The line in the manifest file this diagnostic refers to, starting at 1, if known.
+    pub line: Option<usize>,
+    /// What's wrong.
+    pub message: String,
+}
+
+impl Diagnostic {
+    fn error<S: Into<String>>(line: Option<usize>, message: S) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            line,
+            message: message.into(),
+        }
+    }
+
+    fn warning<S: Into<String>>(line: Option<usize>, message: S) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Warning,
+            line,
+            message: message.into(),
+        }
+    }
+}
+
+/// Find the first line of `contents` which contains any of `needles`.
+fn line_of(contents: &str, needles: &[&str]) -> Option<usize> {
+    contents
+        .lines()
+        .position(|line| needles.iter().any(|needle| line.contains(needle)))
+        .map(|index| index + 1)
+}
+
+/// Find the first line of `contents` which contains the string `value`, raw or escaped.
+fn line_of_value(contents: &str, value: &str) -> Option<usize> {
+    line_of(contents, &[value, &value.replace('\\', "\\\\")])
+}
+
+fn lint_pattern(contents: &str, what: &str, pattern: &str, diagnostics: &mut Vec<Diagnostic>) {
+    if let Err(error) = Regex::new(pattern) {
+        diagnostics.push(Diagnostic::error(
+            line_of_value(contents, pattern),
+            format!("Invalid pattern for {}: {}", what, error),
+        ));
+    }
+}
+
+fn lint_checksums(contents: &str, checksums: &Checksums, diagnostics: &mut Vec<Diagnostic>) {
+    let all = [
+        ("b2", &checksums.b2, 64),
+        ("sha512", &checksums.sha512, 64),
+        ("b3", &checksums.b3, 32),
+        ("sha3_256", &checksums.sha3_256, 32),
+        ("sha256", &checksums.sha256, 32),
+        ("sha1", &checksums.sha1, 20),
+    ];
+    let lowercase = contents.to_lowercase();
+    for (algorithm, checksum, length) in all.iter() {
+        if let Some(checksum) = checksum {
+            if checksum.len() != *length {
+                diagnostics.push(Diagnostic::error(
+                    line_of(&lowercase, &[&hex::encode(checksum)]),
+                    format!(
+                        "{} checksum has {} hex digits, expected {}",
+                        algorithm,
+                        checksum.len() * 2,
+                        length * 2
+                    ),
+                ));
+            }
+        }
+    }
+}
+
+fn lint_target(contents: &str, target: &Target, diagnostics: &mut Vec<Diagnostic>) {
+    if let Target::Manpage { section } = target {
+        if !(1..=9).contains(section) {
+            diagnostics.push(Diagnostic::error(
+                line_of(
+                    contents,
+                    &[
+                        &format!("section = {}", section),
+                        &format!("section: {}", section),
+                        &format!("\"section\": {}", section),
+                    ],
+                ),
+                format!("Manpage section {} is not between 1 and 9", section),
+            ));
+        }
+    }
+}
+
+/// All platforms homebins knows.
+fn all_platforms() -> impl Iterator<Item = Platform> {
+    [Os::Linux, Os::MacOs].iter().flat_map(|os| {
+        [Arch::X86_64, Arch::Aarch64, Arch::Armv7]
+            .iter()
+            .map(move |arch| Platform {
+                os: *os,
+                arch: *arch,
+            })
+    })
+}
+
+fn lint_destinations(manifest: &Manifest, diagnostics: &mut Vec<Diagnostic>) {
+    let mut reported = Vec::new();
+    for platform in all_platforms() {
+        if manifest.downloads(platform).next().is_none() {
+            continue;
+        }
+        let operations = install_manifest(manifest, platform);
+        let mut seen = Vec::new();
+        for destination in operation_destinations(operations.iter()) {
+            let key = (destination.directory(), destination.name().to_string());
+            if seen.contains(&key) && !reported.contains(&key) {
+                diagnostics.push(Diagnostic::error(
+                    None,
+                    format!(
+                        "Multiple files install to {} of {} on {} {}",
+                        key.1,
+                        key.0.kind(),
+                        platform.os,
+                        platform.arch
+                    ),
+                ));
+                reported.push(key.clone());
+            }
+            seen.push(key);
+        }
+    }
+}
+
+fn lint_metadata(contents: &str, manifest: &Manifest, diagnostics: &mut Vec<Diagnostic>) {
+    let info = line_of(contents, &["[info]", "info:", "\"info\""]);
+    if manifest.info.description.is_none() {
+        diagnostics.push(Diagnostic::warning(
+            info,
+            "No description; add description to show in homebins list",
+        ));
+    }
+    if manifest.info.released.is_none() {
+        diagnostics.push(Diagnostic::warning(
+            info,
+            "No release date; add released to show how old a version is",
+        ));
+    }
+    if manifest.upstream.is_none() {
+        diagnostics.push(Diagnostic::warning(
+            info,
+            "No upstream; add [upstream] to check for new versions",
+        ));
+    }
+    for download in &manifest.install {
+        if download.checksums.is_empty() {
+            diagnostics.push(Diagnostic::warning(
+                line_of_value(contents, download.download.as_str()),
+                format!("No checksums for {}", download.download),
+            ));
+        }
+    }
+}
+
+/// Lint a parsed `manifest` whose file has the given `contents`.
+fn lint_manifest(contents: &str, manifest: &Manifest) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    match &manifest.discover {
+        Discover::Binary { version_check, .. } => lint_pattern(
+            contents,
+            "version check",
+            &version_check.pattern,
+            &mut diagnostics,
+        ),
+        Discover::VersionFile { pattern, .. } => {
+            lint_pattern(contents, "version file", pattern, &mut diagnostics)
+        }
+        Discover::Checksum { checksums, .. } => {
+            lint_checksums(contents, checksums, &mut diagnostics)
+        }
+        Discover::File { .. } => {}
+    }
+    match &manifest.upstream {
+        Some(Upstream::Feed { pattern, .. })
+        | Some(Upstream::Scrape { pattern, .. })
+        | Some(Upstream::Command { pattern, .. }) => {
+            lint_pattern(contents, "upstream", pattern, &mut diagnostics)
+        }
+        _ => {}
+    }
+    for download in &manifest.install {
+        lint_checksums(contents, &download.checksums, &mut diagnostics);
+        match &download.install {
+            Install::SingleFile { target, .. } => lint_target(contents, target, &mut diagnostics),
+            Install::FilesFromArchive { files, .. } => {
+                for file in files {
+                    lint_target(contents, &file.target, &mut diagnostics);
+                }
+            }
+        }
+    }
+    for file in &manifest.remove.additional_files {
+        lint_target(contents, &file.target, &mut diagnostics);
+    }
+    lint_destinations(manifest, &mut diagnostics);
+    lint_metadata(contents, manifest, &mut diagnostics);
+    diagnostics
+}
+
+/// Check that all download URLs of `manifest` are reachable.
+fn lint_urls(contents: &str, manifest: &Manifest) -> Vec<Diagnostic> {
+    let mut urls: Vec<&Url> = Vec::new();
+    for download in &manifest.install {
+        urls.push(&download.download);
+        urls.extend(&download.checksums.sha256_url);
+    }
+    urls.into_iter()
+        .filter_map(|url| {
+            check_url(url).err().map(|error| {
+                Diagnostic::error(
+                    line_of_value(contents, url.as_str()),
+                    format!("{} is not reachable: {}", url, error),
+                )
+            })
+        })
+        .collect()
+}
+
+/// Lint the manifest file at `path`.
+///
+/// If `check_urls` also check whether all downloads of the manifest are reachable.  Return all
+/// diagnostics; fail only if the file can't be read.
+#[throws]
+pub fn lint_manifest_file<P: AsRef<Path>>(path: P, check_urls: bool) -> Vec<Diagnostic> {
+    let contents = std::fs::read_to_string(path.as_ref())
+        .with_context(|| format!("Failed to read {}", path.as_ref().display()))?;
+    match Manifest::read_from_path(path.as_ref()) {
+        Err(error) => vec![Diagnostic::error(None, format!("{:#}", error))],
+        Ok(manifest) => {
+            let mut diagnostics = lint_manifest(&contents, &manifest);
+            if check_urls {
+                diagnostics.extend(lint_urls(&contents, &manifest));
+            }
+            diagnostics
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn lint_valid_manifest() {
+        let diagnostics = lint_manifest_file("tests/manifests/ripgrep.toml", false).unwrap();
+        assert!(diagnostics
+            .iter()
+            .all(|diagnostic| diagnostic.severity == Severity::Warning));
+    }
+
+    #[test]
+    fn lint_broken_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("broken.toml");
+        std::fs::write(
+            &path,
+            r#"[info]
+name = "broken"
+version = "1.0.0"
+url = "https://example.com/broken"
+license = "MIT"
+description = "A broken manifest"
+released = 2020-12-21
+
+[discover]
+binary = "broken"
+version_check.args = ["--version"]
+version_check.pattern = "broken ([^ ]+"
+
+[[install]]
+download = "https://example.com/broken-1.0.0.tar.gz"
+checksums.sha256 = "abcd"
+files = [
+    { source = "broken", type = "bin" },
+    { source = "doc/broken", type = "bin" },
+    { source = "broken.1", type = "man", section = 10 },
+]
+
+[upstream]
+provider = "github"
+repo = "example/broken"
+"#,
+        )
+        .unwrap();
+        let diagnostics = lint_manifest_file(&path, false).unwrap();
+        let summary: Vec<(Severity, Option<usize>)> = diagnostics
+            .iter()
+            .map(|diagnostic| (diagnostic.severity, diagnostic.line))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (Severity::Error, Some(12)),
+                (Severity::Error, Some(16)),
+                (Severity::Error, Some(20)),
+                (Severity::Error, None),
+            ],
+            "{:#?}",
+            diagnostics
+        );
+        assert_eq!(
+            diagnostics[1].message,
+            "sha256 checksum has 4 hex digits, expected 64"
+        );
+        assert_eq!(
+            diagnostics[3].message,
+            "Multiple files install to broken of binaries on linux x86_64"
+        );
+    }
+}
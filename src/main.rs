@@ -13,24 +13,181 @@ use colored::*;
 use anyhow::{anyhow, Context, Error, Result};
 use directories::BaseDirs;
 use fehler::{throw, throws};
-use homebins::{HomebinProjectDirs, HomebinRepos, InstallDirs, Manifest};
+use homebins::bench::{self, Baseline, ChecksumWorkload};
+use homebins::checksum::Algorithm;
+use homebins::destinations;
+use homebins::export::{ExportFormat, ManifestFiles};
+use homebins::graph::{Graph, GraphFormat};
+use homebins::lint::Severity;
+use homebins::manifest::{Arch, Os, Platform};
+use homebins::operations::DestinationKind;
+use homebins::outdated::OutdatedCache;
+use homebins::permissions;
+use homebins::pins::{Pin, Pins};
+use homebins::provenance::{KnownManifest, Provenance, ProvenanceStore};
+use homebins::secrets::{LoginHosts, Secret};
+use homebins::summary::Summary;
+use homebins::trust::{check_public_key, TrustStore, TrustedKey};
+use homebins::upstream::UpstreamCache;
+use homebins::{
+    estatus, status, ApplyOptions, Config, ConflictResolution, HomebinProjectDirs, HomebinRepos,
+    InstallDirs, Manifest, ManifestRepo, ManifestStore, MissingDestinations, RepoConfig, StateLock,
+};
+use std::collections::HashMap;
+use std::io::{IsTerminal, Read, Write};
 use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
 
 #[derive(Copy, Clone)]
 enum Installed {
     All,
-    Outdated,
+    Outdated { age: bool, by_release: bool },
 }
 
 #[derive(Copy, Clone)]
 enum List {
-    All,
+    All { long: bool, json: bool },
     Installed(Installed),
 }
 
+/// How to resolve conflicts with existing files when installing.
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum ConflictPolicy {
+    /// Ask for every conflicting file.
+    Prompt,
+    /// Resolve all conflicts the same way.
+    Resolve(ConflictResolution),
+    /// Fail on conflicts.
+    Abort,
+}
+
+impl std::str::FromStr for ConflictPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "prompt" => Ok(ConflictPolicy::Prompt),
+            "overwrite" => Ok(ConflictPolicy::Resolve(ConflictResolution::Overwrite)),
+            "skip" => Ok(ConflictPolicy::Resolve(ConflictResolution::Skip)),
+            "backup" => Ok(ConflictPolicy::Resolve(ConflictResolution::Backup)),
+            "abort" => Ok(ConflictPolicy::Abort),
+            _ => Err(format!("Unknown conflict policy: {:?}", s)),
+        }
+    }
+}
+
+/// Ask how to resolve the conflict with the existing `file`, described by `owner`.
+///
+/// Return `None` to abort.
+fn prompt_conflict_resolution(file: &Path, owner: &str) -> Result<Option<ConflictResolution>> {
+    loop {
+        eprint!(
+            "{} already exists{}. [o]verwrite, [s]kip, [b]ackup, [a]bort? ",
+            file.display().to_string().bold(),
+            owner
+        );
+        std::io::stderr().flush()?;
+        let mut answer = String::new();
+        if std::io::stdin().read_line(&mut answer)? == 0 {
+            return Ok(None);
+        }
+        match answer.trim() {
+            "o" | "overwrite" => return Ok(Some(ConflictResolution::Overwrite)),
+            "s" | "skip" => return Ok(Some(ConflictResolution::Skip)),
+            "b" | "backup" => return Ok(Some(ConflictResolution::Backup)),
+            "a" | "abort" => return Ok(None),
+            _ => {}
+        }
+    }
+}
+
 struct Commands {
     dirs: HomebinProjectDirs,
     install_dirs: InstallDirs,
+    apply_options: ApplyOptions,
+    upstream_cache_ttl: Duration,
+    /// Whether to run commands of command upstreams.
+    allow_upstream_commands: bool,
+    /// How long to use manifest repositories without fetching them again.
+    repo_fetch_ttl: Duration,
+    conflict_policy: ConflictPolicy,
+    missing_destinations: MissingDestinations,
+    summary: Summary,
+    repo_configs: Vec<RepoConfig>,
+    /// A directory of manifests to use instead of all manifest repositories.
+    manifest_dir: Option<PathBuf>,
+    /// The lock of homebins state, held as long as these commands exist.
+    _lock: Option<StateLock>,
+}
+
+/// Format the number of whole days since `time`.
+fn days_since(time: SystemTime) -> String {
+    let days = SystemTime::now()
+        .duration_since(time)
+        .map(|d| d.as_secs() / 86400)
+        .unwrap_or(0);
+    if days == 1 {
+        "1 day".to_string()
+    } else {
+        format!("{} days", days)
+    }
+}
+
+/// Print the `error` of the version check of `manifest` in list output.
+///
+/// Return `true` if the version check itself kept failing, so the installed version is unknown,
+/// and `false` for all other errors.
+fn print_version_error(manifest: &Manifest, error: &Error) -> bool {
+    let unknown = error
+        .chain()
+        .any(|cause| cause.is::<homebins::VersionCheckFailed>());
+    if unknown {
+        println!(
+            "{} = {}",
+            manifest.info.name.bold(),
+            format!("unknown: {:#}", error).yellow()
+        )
+    } else {
+        println!(
+            "{} = {}",
+            manifest.info.name.bold(),
+            format!("failed: {:#}", error).red()
+        )
+    }
+    unknown
+}
+
+/// Describe `pin` for status output.
+fn describe_pin(pin: &Pin) -> String {
+    match &pin.version {
+        Some(version) => format!("pinned at {}", version),
+        None => "pinned".to_string(),
+    }
+}
+
+/// Describe the manifest repository a manifest comes from, if any, for listing manifests.
+fn repo_suffix(repo: Option<&str>) -> String {
+    match repo {
+        Some(repo) => format!(" [{}]", repo).dimmed().to_string(),
+        None => String::new(),
+    }
+}
+
+/// Read pins from the file at `path`, or from stdin if `path` is `-`.
+#[throws]
+fn read_pins_file(path: &Path) -> Vec<Pin> {
+    let contents = if path == Path::new("-") {
+        let mut contents = String::new();
+        std::io::stdin()
+            .read_to_string(&mut contents)
+            .with_context(|| "Failed to read pins from stdin")?;
+        contents
+    } else {
+        std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read pins from {}", path.display()))?
+    };
+    homebins::pins::parse_pins(&contents)
+        .with_context(|| format!("Invalid pins in {}", path.display()))?
 }
 
 fn read_manifests<I: Iterator<Item = R>, R: AsRef<Path>>(filenames: I) -> Result<Vec<Manifest>> {
@@ -38,70 +195,370 @@ fn read_manifests<I: Iterator<Item = R>, R: AsRef<Path>>(filenames: I) -> Result
 }
 
 impl Commands {
+    /// Create commands, and lock homebins state exclusively if `changes_state`.
+    ///
+    /// Commands which only read state take no lock, since homebins writes all state atomically.
     #[throws]
-    fn new() -> Commands {
+    fn new(changes_state: bool) -> Commands {
         let dirs = HomebinProjectDirs::open()?;
-        let install_dirs = InstallDirs::from_base_dirs(
-            &BaseDirs::new()
-                .with_context(|| "Cannot determine base dirs for current user".to_string())?,
-        )?;
+        // Lock before reading any state, so that no other process changes it under our feet
+        let lock = if changes_state {
+            Some(StateLock::acquire(dirs.lock_file())?)
+        } else {
+            None
+        };
+        let install_dirs = InstallDirs::builder()
+            .base_dirs(
+                &BaseDirs::new()
+                    .with_context(|| "Cannot determine base dirs for current user".to_string())?,
+            )
+            .env_overrides()
+            .build()?;
+        let config = Config::read_from_path(dirs.config_file())?;
+        let repo_configs = config.repos.unwrap_or_else(homebins::default_repos);
+        let mut tokens = config.tokens;
+        LoginHosts::open(dirs.login_hosts_file())?.add_to_tokens(&mut tokens);
+        let apply_options = ApplyOptions {
+            keep_workdir: config.keep_workdir,
+            skip_destinations: config.skip_destinations,
+            event_log: config.event_log,
+            version_check_timeout: config.version_check_timeout.map(Duration::from_secs),
+            tokens,
+            versioned_binaries: config.versioned_binaries,
+            trusted_keys: TrustStore::open(dirs.trusted_keys_file())?.keys().to_vec(),
+            require_trusted_keys: config.require_trusted_keys,
+            hook_isolation: config.hook_isolation,
+            jobs: config.jobs,
+            ..ApplyOptions::default()
+        };
+
+        let upstream_cache_ttl = config.upstream_cache_ttl.map_or(
+            homebins::upstream::DEFAULT_UPSTREAM_CACHE_TTL,
+            Duration::from_secs,
+        );
+        let repo_fetch_ttl = config
+            .repo_fetch_ttl
+            .map_or(homebins::DEFAULT_FETCH_TTL, Duration::from_secs);
+
+        Commands {
+            dirs,
+            install_dirs,
+            apply_options,
+            upstream_cache_ttl,
+            allow_upstream_commands: config.allow_upstream_commands,
+            repo_fetch_ttl,
+            conflict_policy: if std::io::stdin().is_terminal() {
+                ConflictPolicy::Prompt
+            } else {
+                ConflictPolicy::Abort
+            },
+            missing_destinations: config.missing_destinations,
+            summary: Summary::start(),
+            repo_configs,
+            manifest_dir: None,
+            _lock: lock,
+        }
+    }
 
-        Commands { dirs, install_dirs }
+    /// Skip destinations which this system doesn't use, unless configured otherwise.
+    ///
+    /// Don't skip anything when installing for another system.
+    #[throws]
+    fn skip_unused_destinations(&mut self) -> () {
+        if self.apply_options.skip_host_operations {
+            return;
+        }
+        let to_skip = destinations::destinations_to_skip(
+            destinations::unused_destinations(),
+            &self.apply_options.skip_destinations,
+            self.missing_destinations,
+            |destination| {
+                if !std::io::stdin().is_terminal() {
+                    return Ok(false);
+                }
+                eprint!(
+                    "{}; install {} anyway? [y/N] ",
+                    destination.reason, destination.kind
+                );
+                std::io::stderr().flush()?;
+                let mut answer = String::new();
+                std::io::stdin().read_line(&mut answer)?;
+                Ok(matches!(answer.trim(), "y" | "yes"))
+            },
+        )?;
+        for destination in to_skip {
+            estatus!(
+                "{}",
+                format!(
+                    "Skipping {} because {}; set missing_destinations = \"create\" in {} to install them anyway",
+                    destination.kind,
+                    destination.reason,
+                    self.dirs.config_file().display()
+                )
+                .yellow()
+            );
+            self.apply_options.skip_destinations.push(destination.kind);
+        }
     }
 
+    /// Get the manifest repositories to use, or just the manifest directory if given.
     fn repos(&self) -> HomebinRepos {
-        HomebinRepos::open(&self.dirs)
+        let mut repos = HomebinRepos::open(&self.dirs);
+        repos.set_trusted_keys(
+            self.apply_options.trusted_keys.clone(),
+            self.apply_options.require_trusted_keys,
+        );
+        repos.set_fetch_ttl(self.repo_fetch_ttl);
+        repos.set_tokens(self.apply_options.tokens.clone());
+        match &self.manifest_dir {
+            Some(directory) => repos.set_repos(vec![RepoConfig {
+                name: "manifest-dir".to_string(),
+                url: directory.display().to_string(),
+                reference: None,
+                priority: 0,
+                enabled: true,
+            }]),
+            None => repos.set_repos(self.repo_configs.clone()),
+        }
+        repos
+    }
+
+    /// Get the manifest store to install from.
+    ///
+    /// Record the revision of the store in the provenance of files installed from it.
+    fn manifest_store(&mut self) -> Result<ManifestStore> {
+        let store = self.repos().manifest_store()?;
+        self.apply_options.manifest_revision = store.revision();
+        Ok(store)
+    }
+
+    fn pins(&self) -> Result<Pins> {
+        Pins::open(self.dirs.pins_file())
     }
 
+    /// Forget that the manifest `name` is outdated, after installing, updating or removing it.
+    ///
+    /// The cache only serves the prompt status, so warn if it fails instead of failing the
+    /// command which already succeeded.
+    fn forget_outdated(&self, name: &str) {
+        let result = OutdatedCache::open(self.dirs.outdated_cache_file()).and_then(|mut cache| {
+            if cache.forget(name) {
+                cache.save()?;
+            }
+            Ok(())
+        });
+        if let Err(error) = result {
+            estatus!(
+                "{}",
+                format!(
+                    "WARNING: Failed to forget that {} was outdated: {:#}",
+                    name, error
+                )
+                .yellow()
+                .bold()
+            );
+        }
+    }
+
+    /// Describe how long `manifest` has been outdated.
+    ///
+    /// Use the first of `repos` which has `manifest` to find out when the current version of
+    /// `manifest` became available.
     #[throws]
-    fn list_manifests<'a, I: Iterator<Item = &'a Manifest>>(&self, manifests: I, mode: List) {
+    fn outdated_age(&self, repos: &[ManifestRepo], manifest: &Manifest) -> String {
+        let mut age = Vec::new();
+        if let Some(released) = manifest.info.released {
+            age.push(format!(
+                "released {} ago",
+                days_since(released.to_system_time())
+            ));
+        }
+        for repo in repos {
+            if let Some(changed_at) = repo.manifest_changed_at(&manifest.info.name)? {
+                age.push(format!("behind for {}", days_since(changed_at)));
+                break;
+            }
+        }
+        if let Some(installed_at) = homebins::manifest_installed_at(&self.install_dirs, manifest)? {
+            age.push(format!("installed {} ago", days_since(installed_at)));
+        }
+        age.join(", ")
+    }
+
+    /// Describe which binaries of `manifest` are missing, if any.
+    fn missing_binaries(&self, manifest: &Manifest) -> String {
+        let missing = homebins::missing_binaries(&self.install_dirs, manifest);
+        if missing.is_empty() {
+            String::new()
+        } else {
+            format!(" (missing {})", missing.join(", "))
+                .yellow()
+                .to_string()
+        }
+    }
+
+    #[throws]
+    fn list_manifests<'a, I: Iterator<Item = &'a Manifest>>(
+        &self,
+        manifests: I,
+        mode: List,
+        repos: &[ManifestRepo],
+        origins: &HashMap<String, String>,
+    ) {
         let mut failed = false;
+        let mut unknown = 0;
+        let mut outdated_names = Vec::new();
+        let mut manifests: Vec<&Manifest> = manifests.collect();
+        let pins = self.pins()?;
+        let provenance = ProvenanceStore::open(self.dirs.provenance_file())?;
+        if let List::All { json: true, long } = mode {
+            let json = if long {
+                serde_json::to_string_pretty(&manifests)?
+            } else {
+                let infos: Vec<_> = manifests.iter().map(|m| &m.info).collect();
+                serde_json::to_string_pretty(&infos)?
+            };
+            println!("{}", json);
+            return;
+        }
+        if let List::Installed(Installed::Outdated {
+            by_release: true, ..
+        }) = mode
+        {
+            // Oldest releases first, and manifests without release date last
+            manifests.sort_by_key(|m| (m.info.released.is_none(), m.info.released));
+        }
         for manifest in manifests {
             match mode {
-                List::All => println!(
-                    "{}: {} – {} ({})",
-                    manifest.info.name.bold(),
-                    manifest.info.version,
-                    manifest.info.url.blue(),
-                    format!("{}", manifest.info.license).italic()
-                ),
-                List::Installed(Installed::All) => {
-                    match homebins::installed_manifest_version(&self.install_dirs, &manifest) {
-                        Ok(Some(version)) => {
-                            println!("{} = {}", manifest.info.name.bold(), version)
+                List::All { long, .. } => {
+                    println!(
+                        "{}: {}{} – {} ({}){}",
+                        manifest.info.name.bold(),
+                        manifest.info.version,
+                        match manifest.info.released {
+                            Some(released) if long => format!(" released {}", released),
+                            _ => String::new(),
+                        },
+                        manifest.info.homepage_url().blue(),
+                        format!("{}", manifest.info.license).italic(),
+                        repo_suffix(origins.get(&manifest.info.name).map(String::as_str))
+                    );
+                    if let Some(description) = &manifest.info.description {
+                        println!("  {}", description);
+                    }
+                    if let Some(deprecation) = manifest.info.deprecation() {
+                        println!("  {}", deprecation.yellow());
+                    }
+                    if long && !manifest.info.keywords.is_empty() {
+                        println!("  keywords: {}", manifest.info.keywords.join(", ").dimmed());
+                    }
+                    match manifest.info.repository_url() {
+                        Some(repository) if long && repository != manifest.info.homepage_url() => {
+                            println!("  repository: {}", repository.blue())
                         }
+                        _ => {}
+                    }
+                }
+                List::Installed(Installed::All) => {
+                    match homebins::installed_manifest_version(
+                        &self.install_dirs,
+                        manifest,
+                        &self.apply_options,
+                    ) {
+                        Ok(Some(version)) => println!(
+                            "{} = {}{}{}",
+                            manifest.info.name.bold(),
+                            version,
+                            self.missing_binaries(manifest),
+                            // Tell the repository homebins installed the binary from, which
+                            // may differ from where it'd install it from now
+                            repo_suffix(if origins.is_empty() {
+                                None
+                            } else {
+                                provenance.repository_of(&manifest.info.name).or_else(|| {
+                                    origins.get(&manifest.info.name).map(String::as_str)
+                                })
+                            })
+                        ),
                         Ok(None) => {}
                         Err(error) => {
-                            failed = true;
-                            println!(
-                                "{} = {}",
-                                manifest.info.name.bold(),
-                                format!("failed: {:#}", error).red()
-                            )
+                            if print_version_error(manifest, &error) {
+                                unknown += 1;
+                            } else {
+                                failed = true;
+                            }
                         }
                     }
                 }
-                List::Installed(Installed::Outdated) => {
-                    match homebins::outdated_manifest_version(&self.install_dirs, &manifest) {
-                        Ok(Some(version)) => println!(
-                            "{} = {} -> {}",
+                List::Installed(Installed::Outdated { age, .. }) => {
+                    let outdated = homebins::outdated_manifest_version(
+                        &self.install_dirs,
+                        manifest,
+                        &self.apply_options,
+                    )
+                    .and_then(|version| match version {
+                        Some(version) if age => self
+                            .outdated_age(repos, manifest)
+                            .map(|age| Some((version, age))),
+                        Some(version) => Ok(Some((version, String::new()))),
+                        None => Ok(None),
+                    });
+                    if let Ok(Some(_)) = outdated {
+                        outdated_names.push(manifest.info.name.clone());
+                    }
+                    match outdated {
+                        Ok(Some((version, age))) => println!(
+                            "{} = {} -> {}{}{}{}{}",
                             manifest.info.name.bold(),
                             format!("{}", version).red(),
-                            format!("{}", manifest.info.version).bold().green()
+                            format!("{}", manifest.info.version).bold().green(),
+                            self.missing_binaries(manifest),
+                            match pins.get(&manifest.info.name) {
+                                Some(pin) => format!(" [{}]", describe_pin(pin))
+                                    .yellow()
+                                    .bold()
+                                    .to_string(),
+                                None => String::new(),
+                            },
+                            match manifest.info.deprecation() {
+                                Some(deprecation) => {
+                                    format!(" [{}]", deprecation).yellow().to_string()
+                                }
+                                None => String::new(),
+                            },
+                            if age.is_empty() {
+                                String::new()
+                            } else {
+                                format!(" ({})", age).dimmed().to_string()
+                            }
                         ),
                         Ok(None) => {}
                         Err(error) => {
-                            failed = true;
-                            println!(
-                                "{} = {}",
-                                manifest.info.name.bold(),
-                                format!("failed: {:#}", error).red()
-                            )
+                            if print_version_error(manifest, &error) {
+                                unknown += 1;
+                            } else {
+                                failed = true;
+                            }
                         }
                     }
                 }
             }
         }
+        if let List::Installed(Installed::Outdated { .. }) = mode {
+            let mut cache = OutdatedCache::open(self.dirs.outdated_cache_file())?;
+            cache.set_outdated(outdated_names);
+            cache.save()?;
+        }
+        if unknown > 0 {
+            eprintln!(
+                "{}",
+                format!(
+                    "WARNING: Version checks of {} manifests kept failing, their versions are unknown",
+                    unknown
+                )
+                .yellow()
+            );
+        }
         if failed {
             throw!(anyhow!("Some version checks failed"));
         }
@@ -110,9 +567,9 @@ impl Commands {
     #[throws]
     fn list_files(&self, manifest: &Manifest, existing: bool, to_remove: bool) -> () {
         let files = if to_remove {
-            homebins::files_to_remove(&self.install_dirs, manifest)
+            homebins::files_to_remove(&self.install_dirs, manifest, &self.apply_options)?
         } else {
-            homebins::installed_files(&self.install_dirs, manifest)
+            homebins::installed_files(&self.install_dirs, manifest, &self.apply_options)?
         };
         for file in files {
             if !existing || file.exists() {
@@ -121,42 +578,259 @@ impl Commands {
         }
     }
 
+    /// Resolve conflicts of `manifest` with existing files according to the conflict policy.
+    ///
+    /// Use `store` to tell which manifests the conflicting files belong to, if given.
+    #[throws]
+    fn resolve_file_conflicts(&mut self, store: Option<&ManifestStore>, manifest: &Manifest) -> () {
+        if self.apply_options.force || self.apply_options.resume {
+            return;
+        }
+        let conflicts =
+            homebins::conflicting_files(&self.install_dirs, manifest, &self.apply_options)?;
+        if conflicts.is_empty() {
+            return;
+        }
+        let mut others = Vec::new();
+        if let Some(store) = store {
+            for other in store.manifests()? {
+                let other = other?;
+                if other.info.name != manifest.info.name {
+                    let files =
+                        homebins::installed_files(&self.install_dirs, &other, &self.apply_options)
+                            .unwrap_or_default();
+                    others.push((other.info.name.clone(), files));
+                }
+            }
+        }
+        for file in conflicts {
+            let owner = match others.iter().find(|(_, files)| files.contains(&file)) {
+                Some((name, _)) => format!(" (from {})", name),
+                None if store.is_some() => " (from no known binary)".to_string(),
+                None => String::new(),
+            };
+            let resolution = match self.conflict_policy {
+                ConflictPolicy::Prompt => prompt_conflict_resolution(&file, &owner)?,
+                ConflictPolicy::Resolve(resolution) => Some(resolution),
+                ConflictPolicy::Abort => None,
+            };
+            match resolution {
+                Some(resolution) => {
+                    self.apply_options
+                        .conflict_resolutions
+                        .insert(file, resolution);
+                }
+                None => throw!(anyhow!(
+                    "{} already exists{}; use --on-conflict or --force to install {} anyway",
+                    file.display(),
+                    owner,
+                    manifest.info.name
+                )),
+            }
+        }
+    }
+
     #[throws]
-    fn install_manifest(&mut self, name: &str, manifest: &Manifest) -> () {
-        println!("Installing {}", name.bold());
-        homebins::install_manifest(&self.dirs, &mut self.install_dirs, manifest)?;
-        println!("{}", format!("{} installed", name).green());
+    fn install_manifest(
+        &mut self,
+        store: Option<&ManifestStore>,
+        name: &str,
+        manifest: &Manifest,
+    ) -> () {
+        self.resolve_file_conflicts(store, manifest)?;
+        self.apply_options.manifest_repository = store
+            .and_then(|store| store.repository_of(&manifest.info.name))
+            .map(str::to_string);
+        status!("Installing {}", name.bold());
+        match homebins::install_manifest(
+            &self.dirs,
+            &mut self.install_dirs,
+            manifest,
+            &self.apply_options,
+        ) {
+            Ok(downloaded) => self.summary.downloaded(downloaded),
+            Err(error) => {
+                self.summary.failed(name);
+                throw!(error);
+            }
+        }
+        self.summary.installed(name);
+        self.forget_outdated(name);
+        status!("{}", format!("{} installed", name).green());
     }
 
     #[throws]
     fn remove_manifest(&mut self, name: &str, manifest: &Manifest) -> () {
-        if homebins::installed_manifest_version(&self.install_dirs, manifest)?.is_some() {
-            println!("Removing {}", name.bold());
-            homebins::remove_manifest(&self.dirs, &mut self.install_dirs, manifest)?;
-            println!("{}", format!("{} removed", name).yellow())
+        if homebins::installed_manifest_version(&self.install_dirs, manifest, &self.apply_options)?
+            .is_some()
+        {
+            status!("Removing {}", name.bold());
+            match homebins::remove_manifest(
+                &self.dirs,
+                &mut self.install_dirs,
+                manifest,
+                &self.apply_options,
+            ) {
+                Ok(downloaded) => self.summary.downloaded(downloaded),
+                Err(error) => {
+                    self.summary.failed(name);
+                    throw!(error);
+                }
+            }
+            self.summary.removed(name);
+            self.forget_outdated(name);
+            status!("{}", format!("{} removed", name).yellow())
         }
     }
 
+    /// Update `manifest` if it's outdated.
+    ///
+    /// After updating check the installed version again, and warn if the binary still doesn't
+    /// report the version of `manifest`.
     #[throws]
     fn update_manifest(&mut self, name: &str, manifest: &Manifest) -> () {
-        if homebins::outdated_manifest_version(&self.install_dirs, manifest)?.is_some() {
-            println!("Updating {}", name.bold());
-            homebins::update_manifest(&self.dirs, &mut self.install_dirs, manifest)?;
-            println!("{}", format!("{} updated", name).green());
+        if let Some(old) =
+            homebins::outdated_manifest_version(&self.install_dirs, manifest, &self.apply_options)?
+        {
+            if let Some(pin) = self.pins()?.get(&manifest.info.name) {
+                if !pin.allows(&manifest.info.version.to_string()) {
+                    estatus!(
+                        "{}",
+                        format!("Not updating {}: {}", name, describe_pin(pin))
+                            .yellow()
+                            .bold()
+                    );
+                    return;
+                }
+            }
+            status!("Updating {}", name.bold());
+            match homebins::update_manifest(
+                &self.dirs,
+                &mut self.install_dirs,
+                manifest,
+                &self.apply_options,
+            ) {
+                Ok(downloaded) => self.summary.downloaded(downloaded),
+                Err(error) => {
+                    self.summary.failed(name);
+                    throw!(error);
+                }
+            }
+            self.summary.updated(name);
+            self.forget_outdated(name);
+            match homebins::installed_manifest_version(
+                &self.install_dirs,
+                manifest,
+                &self.apply_options,
+            )? {
+                Some(new) if new == manifest.info.version => {
+                    status!("{}", format!("{} updated: {} → {}", name, old, new).green())
+                }
+                Some(new) => estatus!(
+                    "{}",
+                    format!(
+                        "WARNING: {} updated to {} but {} still reports {} (was {})",
+                        name,
+                        manifest.info.version,
+                        manifest.discover.name(),
+                        new,
+                        old
+                    )
+                    .yellow()
+                    .bold()
+                ),
+                None => estatus!(
+                    "{}",
+                    format!(
+                        "WARNING: {} updated to {} but no version found for {}",
+                        name,
+                        manifest.info.version,
+                        manifest.discover.name()
+                    )
+                    .yellow()
+                    .bold()
+                ),
+            }
         }
     }
 
+    #[throws]
+    pub fn refresh(&mut self) -> () {
+        let mut repos = self.repos();
+        repos.set_fetch_ttl(Duration::ZERO);
+        repos.manifest_index()?;
+        status!("{}", "Manifests refreshed".green());
+    }
+
     pub fn list(&mut self, mode: List) -> Result<()> {
-        let store = self.repos().manifest_store()?;
+        if let List::All {
+            long: false,
+            json: false,
+        } = mode
+        {
+            // The index has all we need to list manifests briefly, so skip reading manifests
+            let mut repos = self.repos();
+            let index = repos.manifest_index()?;
+            for (repo, entry) in index {
+                let summary = entry.summary;
+                println!(
+                    "{}: {} – {} ({}){}",
+                    summary.name.bold(),
+                    summary.version,
+                    summary.homepage_url().blue(),
+                    summary.license.italic(),
+                    repo_suffix(repos.has_multiple_repos().then_some(repo.as_str()))
+                );
+                if let Some(description) = &summary.description {
+                    println!("  {}", description);
+                }
+                if let Some(deprecation) = summary.deprecation() {
+                    println!("  {}", deprecation.yellow());
+                }
+            }
+            return Ok(());
+        }
+        let mut homebin_repos = self.repos();
+        let repos = homebin_repos.manifest_repos()?;
+        let store = homebin_repos.manifest_store_of(&repos)?;
         // FIXME: Don't unwrap here!  (Still we can safely assume that a store only has valid manifests to some degree)
-        let mut manifests: Vec<Manifest> = store.manifests()?.map(|m| m.unwrap()).collect();
+        let mut manifests: Vec<_> = store.manifests()?.map(|m| m.unwrap()).collect();
         manifests.sort_by_cached_key(|m| m.info.name.to_string());
-        self.list_manifests(manifests.iter(), mode)
+        let origins: HashMap<String, String> = if homebin_repos.has_multiple_repos() {
+            manifests
+                .iter()
+                .filter_map(|m| {
+                    store
+                        .repository_of(&m.info.name)
+                        .map(|repo| (m.info.name.clone(), repo.to_string()))
+                })
+                .collect()
+        } else {
+            HashMap::new()
+        };
+        self.list_manifests(manifests.iter().map(AsRef::as_ref), mode, &repos, &origins)
+    }
+
+    #[throws]
+    pub fn search(&mut self, query: &str) -> () {
+        let index = self.repos().manifest_index()?;
+        let summaries = index.into_iter().map(|(_, entry)| entry.summary);
+        let matches = homebins::search::search(summaries, query);
+        if matches.is_empty() {
+            throw!(anyhow!("No binary matches {}", query));
+        }
+        for summary in matches {
+            println!("{}: {}", summary.name.bold(), summary.version);
+            if let Some(description) = &summary.description {
+                println!("  {}", description);
+            }
+        }
     }
 
     #[throws]
     pub fn files(&mut self, names: Vec<String>, existing: bool, to_remove: bool) -> () {
-        let store = self.repos().manifest_store()?;
+        let store = self.manifest_store()?;
+        let names = self.repos().resolve_names(&store, &names)?;
         for name in names {
             let manifest = store
                 .load_manifest(&name)?
@@ -167,49 +841,1023 @@ impl Commands {
 
     #[throws]
     pub fn install(&mut self, names: Vec<String>) -> () {
-        let store = self.repos().manifest_store()?;
-        for name in names {
-            let manifest = store
-                .load_manifest(&name)?
-                .ok_or_else(|| anyhow!("Binary {} not found", name))?;
-            self.install_manifest(&name, &manifest)?;
+        self.skip_unused_destinations()?;
+        let store = self.manifest_store()?;
+        let names = self.repos().resolve_names(&store, &names)?;
+        for manifest in store.resolve(&names)? {
+            let name = manifest.info.name.clone();
+            let is_requirement = !names.contains(&name);
+            // Install requirements only if they're missing
+            if !is_requirement
+                || homebins::installed_manifest_version(
+                    &self.install_dirs,
+                    &manifest,
+                    &self.apply_options,
+                )?
+                .is_none()
+            {
+                self.check_conflicts(&store, &manifest)?;
+                self.install_manifest(Some(&store), &name, &manifest)?;
+            }
+        }
+    }
+
+    /// Install manifests with the given `names` and their requirements on the SSH `destination`.
+    ///
+    /// Homebins can't tell what's installed on the remote host, so this installs all requirements
+    /// and doesn't check for conflicts.
+    #[throws]
+    pub fn install_over_ssh(&mut self, destination: &str, names: Vec<String>) -> () {
+        let store = self.manifest_store()?;
+        let names = self.repos().resolve_names(&store, &names)?;
+        for manifest in store.resolve(&names)? {
+            let name = &manifest.info.name;
+            status!("Installing {} on {}", name.bold(), destination.bold());
+            if let Err(error) = homebins::install_manifest_over_ssh(
+                &self.dirs,
+                destination,
+                &manifest,
+                &self.apply_options,
+            ) {
+                self.summary.failed(name);
+                throw!(error);
+            }
+            self.summary.installed(name);
+            status!(
+                "{}",
+                format!("{} installed on {}", name, destination).green()
+            );
+        }
+    }
+
+    /// Fail if `manifest` conflicts with any installed manifest in `store`.
+    ///
+    /// Manifests conflict if either declares a conflict with the other.  Don't check anything
+    /// if installation is forced.
+    #[throws]
+    fn check_conflicts(&self, store: &ManifestStore, manifest: &Manifest) -> () {
+        if !self.apply_options.force {
+            for other in store.manifests()? {
+                let other = other?;
+                let conflicting = manifest.info.conflicts.contains(&other.info.name)
+                    || other.info.conflicts.contains(&manifest.info.name);
+                if conflicting
+                    && other.info.name != manifest.info.name
+                    && homebins::installed_manifest_version(
+                        &self.install_dirs,
+                        &other,
+                        &self.apply_options,
+                    )?
+                    .is_some()
+                {
+                    throw!(anyhow!(
+                        "{} conflicts with installed {}; use --force to install anyway",
+                        manifest.info.name,
+                        other.info.name
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Fail if any installed manifest in `store` requires `name`.
+    ///
+    /// Ignore manifests in `removed`, which get removed as well.
+    #[throws]
+    fn check_not_required(&self, store: &ManifestStore, name: &str, removed: &[String]) -> () {
+        for manifest in store.manifests()? {
+            let manifest = manifest?;
+            if manifest.info.requires.iter().any(|r| r == name)
+                && !removed.contains(&manifest.info.name)
+                && homebins::installed_manifest_version(
+                    &self.install_dirs,
+                    &manifest,
+                    &self.apply_options,
+                )?
+                .is_some()
+            {
+                throw!(anyhow!(
+                    "Cannot remove {}: Required by {}",
+                    name,
+                    manifest.info.name
+                ));
+            }
         }
     }
 
     #[throws]
     pub fn remove(&mut self, names: Vec<String>) -> () {
-        let store = self.repos().manifest_store()?;
-        for name in names {
+        let store = self.manifest_store()?;
+        let names = self.repos().resolve_names(&store, &names)?;
+        for name in &names {
             let manifest = store
-                .load_manifest(&name)?
+                .load_manifest(name)?
                 .ok_or_else(|| anyhow!("Binary {} not found", name))?;
-            self.remove_manifest(&name, &manifest)?;
+            self.check_not_required(&store, name, &names)?;
+            self.remove_manifest(name, &manifest)?;
+        }
+    }
+
+    /// Remove files of the given `kinds` of destinations, but keep all other files.
+    ///
+    /// Remove files of all installed manifests, unless `names` are given.
+    #[throws]
+    pub fn remove_destinations(
+        &mut self,
+        names: Option<Vec<String>>,
+        kinds: Vec<DestinationKind>,
+    ) -> () {
+        let store = self.manifest_store()?;
+        let manifests = match names {
+            None => store.manifests()?.collect::<Result<Vec<_>>>()?,
+            Some(names) => self
+                .repos()
+                .resolve_names(&store, &names)?
+                .iter()
+                .map(|name| {
+                    store
+                        .load_manifest(name)?
+                        .ok_or_else(|| anyhow!("Binary {} not found", name))
+                })
+                .collect::<Result<Vec<_>>>()?,
+        };
+        let kind_names: Vec<String> = kinds.iter().map(ToString::to_string).collect();
+        for manifest in manifests {
+            if homebins::installed_manifest_version(
+                &self.install_dirs,
+                &manifest,
+                &self.apply_options,
+            )?
+            .is_some()
+            {
+                status!(
+                    "Removing {} of {}",
+                    kind_names.join(", "),
+                    manifest.info.name.bold()
+                );
+                let downloaded = homebins::remove_manifest_destinations(
+                    &self.dirs,
+                    &mut self.install_dirs,
+                    &manifest,
+                    &kinds,
+                    &self.apply_options,
+                )?;
+                self.summary.downloaded(downloaded);
+            }
+        }
+        estatus!(
+            "{}",
+            format!(
+                "Add {} to skip_destinations in {} to keep update from installing them again",
+                kind_names.join(", "),
+                self.dirs.config_file().display()
+            )
+            .dimmed()
+        );
+    }
+
+    /// Migrate `manifest` to its replacement in `store` if it's installed and deprecated.
+    ///
+    /// Migrate right away if `migrate` is set, otherwise ask if stdin is a terminal, and only
+    /// warn if not.  Migrating installs the replacement and its missing requirements, and then
+    /// removes `manifest`.
+    #[throws]
+    fn migrate_deprecated(
+        &mut self,
+        store: &ManifestStore,
+        manifest: &Manifest,
+        migrate: bool,
+    ) -> () {
+        let replacement = match manifest.info.migration_target() {
+            Some(replacement) => replacement.to_string(),
+            None => return,
+        };
+        if homebins::installed_manifest_version(&self.install_dirs, manifest, &self.apply_options)?
+            .is_none()
+        {
+            return;
+        }
+        let name = &manifest.info.name;
+        estatus!(
+            "{}",
+            format!("{} is deprecated, replaced by {}", name, replacement)
+                .yellow()
+                .bold()
+        );
+        let confirmed = migrate
+            || (std::io::stdin().is_terminal() && {
+                eprint!("Replace {} with {}? [y/N] ", name, replacement);
+                std::io::stderr().flush()?;
+                let mut answer = String::new();
+                std::io::stdin().read_line(&mut answer)?;
+                matches!(answer.trim(), "y" | "yes")
+            });
+        if !confirmed {
+            return;
+        }
+        for other in store.resolve(std::slice::from_ref(&replacement))? {
+            if homebins::installed_manifest_version(
+                &self.install_dirs,
+                &other,
+                &self.apply_options,
+            )?
+            .is_none()
+            {
+                self.check_conflicts(store, &other)?;
+                self.install_manifest(Some(store), &other.info.name, &other)?;
+            }
+        }
+        self.remove_manifest(name, manifest)?;
+    }
+
+    #[throws]
+    pub fn update(&mut self, names: Option<Vec<String>>, migrate: bool) -> () {
+        self.skip_unused_destinations()?;
+        let store = self.manifest_store()?;
+        let manifests = match names {
+            None => store.manifests()?.collect::<Result<Vec<_>>>()?,
+            Some(names) => self
+                .repos()
+                .resolve_names(&store, &names)?
+                .iter()
+                .map(|name| {
+                    store
+                        .load_manifest(name)?
+                        .ok_or_else(|| anyhow!("Binary {} not found", name))
+                })
+                .collect::<Result<Vec<_>>>()?,
+        };
+        for manifest in manifests {
+            let name = &manifest.info.name;
+            self.apply_options.manifest_repository = store.repository_of(name).map(str::to_string);
+            self.update_manifest(name, &manifest)?;
+            self.migrate_deprecated(&store, &manifest, migrate)?;
+        }
+    }
+
+    /// Print the summary of all manifests this command installed, updated or removed.
+    ///
+    /// Print the summary as a single line of JSON if `json` is set.
+    #[throws]
+    fn print_summary(&self, json: bool) -> () {
+        if json {
+            println!("{}", self.summary.to_json()?);
+        } else if !self.summary.is_empty() {
+            let (symbol, summary) = if self.summary.failures().is_empty() {
+                ("✔", self.summary.describe().green().bold())
+            } else {
+                ("✘", self.summary.describe().red().bold())
+            };
+            if homebins::status::is_line_oriented() {
+                status!("{}", summary);
+            } else {
+                status!("{} {}", symbol, summary);
+            }
+        }
+    }
+
+    /// List manifests whose upstream has a newer version than the manifest.
+    ///
+    /// Check all manifests in the store unless `names` are given.  Use cached upstream
+    /// responses unless `refresh` is set; revalidate all cached responses otherwise.  Run
+    /// upstream commands only if `allow_commands` is set or the configuration allows them.
+    #[throws]
+    pub fn upstream_outdated(
+        &mut self,
+        names: Option<Vec<String>>,
+        refresh: bool,
+        allow_commands: bool,
+    ) -> () {
+        let allow_commands = allow_commands || self.allow_upstream_commands;
+        let store = self.manifest_store()?;
+        let manifests = match names {
+            None => store.manifests()?.collect::<Result<Vec<_>>>()?,
+            Some(names) => names
+                .iter()
+                .map(|name| {
+                    store
+                        .load_manifest(name)?
+                        .ok_or_else(|| anyhow!("Binary {} not found", name))
+                })
+                .collect::<Result<Vec<_>>>()?,
+        };
+        let ttl = if refresh {
+            Duration::from_secs(0)
+        } else {
+            self.upstream_cache_ttl
+        };
+        let mut cache = UpstreamCache::open(self.dirs.upstream_cache_file().to_path_buf(), ttl)?;
+        let mut failed = false;
+        let mut checked = 0;
+        for manifest in manifests {
+            let hits = cache.hits();
+            let result =
+                homebins::upstream::upstream_version(&manifest, &mut cache, allow_commands);
+            let cached = if hits < cache.hits() { " (cached)" } else { "" };
+            match result {
+                Ok(Some(upstream)) if manifest.info.version < upstream => println!(
+                    "{} = {} -> {}{}",
+                    manifest.info.name.bold(),
+                    manifest.info.version,
+                    upstream.to_string().yellow(),
+                    cached.dimmed()
+                ),
+                Ok(_) => {}
+                Err(error) => {
+                    failed = true;
+                    eprintln!("{}", format!("{:#}", error).red());
+                }
+            }
+            if manifest.upstream.is_some() {
+                checked += 1;
+            }
+        }
+        cache.save()?;
+        if 0 < cache.hits() {
+            eprintln!(
+                "{}",
+                format!(
+                    "{} of {} upstream checks used cached responses",
+                    cache.hits(),
+                    checked
+                )
+                .dimmed()
+            );
+        }
+        if failed {
+            throw!(anyhow!("Some upstream version checks failed"));
         }
     }
 
+    /// Print the graph of manifests with the given `names` in `format`.
+    ///
+    /// Include all manifests in the store unless `names` are given.
     #[throws]
-    pub fn update(&mut self, names: Option<Vec<String>>) -> () {
+    pub fn graph(&mut self, names: Option<Vec<String>>, format: GraphFormat) -> () {
+        let store = self.manifest_store()?;
+        let manifests = match names {
+            None => store.manifests()?.collect::<Result<Vec<_>>>()?,
+            Some(names) => names
+                .iter()
+                .map(|name| {
+                    store
+                        .load_manifest(name)?
+                        .ok_or_else(|| anyhow!("Binary {} not found", name))
+                })
+                .collect::<Result<Vec<_>>>()?,
+        };
+        let host = Platform::host()?;
+        let platform = Platform {
+            arch: self.apply_options.target_arch.unwrap_or(host.arch),
+            ..host
+        };
+        let graph = Graph::of_manifests(&self.install_dirs, &manifests, platform);
+        print!("{}", graph.render(format)?);
+    }
+
+    /// Print all installed manifests in the given export `format`.
+    #[throws]
+    pub fn export(&mut self, format: ExportFormat) -> () {
         let store = self.repos().manifest_store()?;
-        match names {
-            None => {
-                for manifest in store.manifests()? {
-                    let manifest = manifest?;
-                    self.update_manifest(&manifest.info.name, &manifest)?;
+        let manifests = store.manifests()?.collect::<Result<Vec<_>>>()?;
+        let mut installed = Vec::new();
+        for manifest in &manifests {
+            if let Some(version) = homebins::installed_manifest_version(
+                &self.install_dirs,
+                manifest,
+                &self.apply_options,
+            )? {
+                installed.push((manifest.as_ref(), version));
+            }
+        }
+        let host = Platform::host()?;
+        let platform = Platform {
+            arch: self.apply_options.target_arch.unwrap_or(host.arch),
+            ..host
+        };
+        let mut files = ManifestFiles::default();
+        if format == ExportFormat::Bundle {
+            let outdated = homebins::export::bundle_mismatches(&installed);
+            if !outdated.is_empty() {
+                throw!(anyhow!(
+                    "Installed versions differ from the manifests to bundle, update first: {}",
+                    outdated.join(", ")
+                ));
+            }
+            files.revision = store.revision();
+            // Embed requirements as well, and order manifests to install requirements first
+            let names: Vec<&str> = installed
+                .iter()
+                .map(|(m, _)| m.info.name.as_str())
+                .collect();
+            for manifest in store.resolve(&names)? {
+                let path = store
+                    .manifest_file(&manifest.info.name)
+                    .ok_or_else(|| anyhow!("No manifest file for {}", manifest.info.name))?;
+                let contents = std::fs::read_to_string(&path)
+                    .with_context(|| format!("Failed to read {}", path.display()))?;
+                let file_name = path.file_name().unwrap().to_string_lossy().into_owned();
+                files.files.push((file_name, contents));
+            }
+        }
+        print!(
+            "{}",
+            homebins::export::export(&installed, &files, format, platform)
+        );
+    }
+
+    /// Pin the given `pins` and the pins in `file`, or list all pins if there are none.
+    #[throws]
+    pub fn pin(&mut self, mut pins: Vec<Pin>, file: Option<&Path>) -> () {
+        if let Some(file) = file {
+            pins.extend(read_pins_file(file)?);
+        }
+        let mut all = self.pins()?;
+        if pins.is_empty() {
+            for pin in all.iter() {
+                println!("{} {}", pin.name.bold(), describe_pin(pin));
+            }
+        } else {
+            for pin in pins {
+                println!(
+                    "{}",
+                    format!("{} {}", pin.name, describe_pin(&pin)).yellow()
+                );
+                all.pin(pin);
+            }
+            all.save()?;
+        }
+    }
+
+    /// Unpin manifests with the given `names` and the manifests listed in `file`.
+    #[throws]
+    pub fn unpin(&mut self, mut names: Vec<String>, file: Option<&Path>) -> () {
+        if let Some(file) = file {
+            names.extend(read_pins_file(file)?.into_iter().map(|pin| pin.name));
+        }
+        let mut pins = self.pins()?;
+        for name in names {
+            if pins.unpin(&name) {
+                println!("{}", format!("{} unpinned", name).green());
+            } else {
+                eprintln!(
+                    "{}",
+                    format!("WARNING: {} was not pinned", name).yellow().bold()
+                );
+            }
+        }
+        pins.save()?;
+    }
+
+    /// Store a token for `host` in the keyring.
+    #[throws]
+    pub fn auth_login(&self, host: &str) -> () {
+        if let Some(secret) = self
+            .apply_options
+            .tokens
+            .get(host)
+            .filter(|secret| !matches!(secret, Secret::Login { .. }))
+        {
+            println!(
+                "{}",
+                format!(
+                    "WARNING: The configuration has a token for {} {}, which takes precedence",
+                    host, secret
+                )
+                .yellow()
+            );
+        }
+        homebins::secrets::login(host)?;
+        let mut hosts = LoginHosts::open(self.dirs.login_hosts_file())?;
+        hosts.add(host);
+        hosts.save()?;
+        println!("Stored token for {} in keyring", host.bold());
+    }
+
+    /// Show whether tokens for configured hosts and the given `hosts` resolve.
+    #[throws]
+    pub fn auth_status(&self, hosts: Vec<String>) -> () {
+        let mut all_hosts: Vec<&String> = self.apply_options.tokens.keys().collect();
+        all_hosts.extend(hosts.iter());
+        all_hosts.sort();
+        all_hosts.dedup();
+        if all_hosts.is_empty() {
+            println!("No tokens configured");
+        }
+        for host in all_hosts {
+            match self.apply_options.tokens.get(host) {
+                Some(secret) => match secret.reveal() {
+                    Ok(_) => println!("{}: token {}: {}", host.bold(), secret, "ok".green()),
+                    Err(error) => println!(
+                        "{}: token {}: {}",
+                        host.bold(),
+                        secret,
+                        format!("failed: {:#}", error).red()
+                    ),
+                },
+                None => match homebins::secrets::keyring_token(host) {
+                    Ok(Some(_)) => println!("{}: token in keyring: {}", host.bold(), "ok".green()),
+                    Ok(None) => println!("{}: {}", host.bold(), "no token".yellow()),
+                    Err(error) => println!(
+                        "{}: token in keyring: {}",
+                        host.bold(),
+                        format!("failed: {:#}", error).red()
+                    ),
+                },
+            }
+        }
+    }
+
+    /// Trust the minisign `public_key` under the given `name`.
+    #[throws]
+    pub fn trust_add(&self, public_key: &str, name: &str) -> () {
+        check_public_key(public_key)?;
+        let mut store = TrustStore::open(self.dirs.trusted_keys_file())?;
+        let key = TrustedKey {
+            public_key: public_key.to_string(),
+            name: name.to_string(),
+        };
+        println!("Trusting {}", key.to_string().bold());
+        store.add(key);
+        store.save()?;
+    }
+
+    /// Stop trusting the keys with the given public keys or names.
+    #[throws]
+    pub fn trust_remove(&self, keys_or_names: Vec<String>) -> () {
+        let mut store = TrustStore::open(self.dirs.trusted_keys_file())?;
+        for key_or_name in keys_or_names {
+            let removed = store.remove(&key_or_name);
+            if removed.is_empty() {
+                eprintln!(
+                    "{}",
+                    format!("WARNING: {} was not trusted", key_or_name)
+                        .yellow()
+                        .bold()
+                );
+            }
+            for key in removed {
+                println!("No longer trusting {}", key.to_string().bold());
+            }
+        }
+        store.save()?;
+    }
+
+    /// List trusted keys.
+    #[throws]
+    pub fn trust_list(&self) -> () {
+        let store = TrustStore::open(self.dirs.trusted_keys_file())?;
+        if store.keys().is_empty() {
+            println!("No trusted keys");
+        }
+        for key in store.keys() {
+            println!("{}: {}", key.name.bold(), key.public_key);
+        }
+    }
+
+    /// Add a manifest repository with the given `name` and `priority`, to clone from `url`.
+    ///
+    /// Pin the repository to the branch, tag or commit `reference` if given.  If `url` is an
+    /// existing directory add it as plain directory of manifests, with its absolute path.  Fail
+    /// if a repository of the same name exists, or if `url` is no Git repository.
+    #[throws]
+    pub fn repo_add(
+        &mut self,
+        name: &str,
+        url: &str,
+        reference: Option<&str>,
+        priority: u32,
+    ) -> () {
+        homebins::check_repo_name(name)?;
+        if self.repo_configs.iter().any(|repo| repo.name == name) {
+            throw!(anyhow!("Repository {} already exists", name));
+        }
+        let url = if Path::new(url).is_dir() {
+            if reference.is_some() {
+                throw!(anyhow!("Cannot pin plain directory {}", url));
+            }
+            std::fs::canonicalize(url)
+                .with_context(|| format!("Failed to resolve {}", url))?
+                .display()
+                .to_string()
+        } else {
+            if let (Some(reference), Some(_)) = (reference, homebins::archive_extension(url)) {
+                homebins::check_archive_pin(reference)?;
+            }
+            let token = homebins::repo_token(&self.apply_options.tokens, url);
+            homebins::check_repo_remote(url, token.as_ref())?;
+            url.to_string()
+        };
+        self.repo_configs.push(RepoConfig {
+            name: name.to_string(),
+            url: url.clone(),
+            reference: reference.map(str::to_string),
+            priority,
+            enabled: true,
+        });
+        homebins::write_repo_configs(self.dirs.config_file(), &self.repo_configs)?;
+        println!("Added repository {} from {}", name.bold(), url);
+    }
+
+    /// Remove the manifest repositories with the given `names`.
+    ///
+    /// Keep all binaries installed from these repositories, but forget their manifests.
+    #[throws]
+    pub fn repo_remove(&mut self, names: Vec<String>) -> () {
+        for name in names {
+            match self.repo_configs.iter().position(|repo| repo.name == name) {
+                Some(index) => {
+                    self.repo_configs.remove(index);
+                    self.repos().remove_cached(&name)?;
+                    println!("Removed repository {}", name.bold());
+                }
+                None => eprintln!(
+                    "{}",
+                    format!("WARNING: {} is no repository", name)
+                        .yellow()
+                        .bold()
+                ),
+            }
+        }
+        homebins::write_repo_configs(self.dirs.config_file(), &self.repo_configs)?;
+    }
+
+    /// Enable or disable the manifest repositories with the given `names`.
+    ///
+    /// Keep clones of disabled repositories, but ignore their manifests until enabled again.
+    #[throws]
+    pub fn repo_enable(&mut self, names: Vec<String>, enabled: bool) -> () {
+        for name in &names {
+            if !self.repo_configs.iter().any(|repo| &repo.name == name) {
+                throw!(anyhow!("{} is no repository", name));
+            }
+        }
+        for repo in &mut self.repo_configs {
+            if names.contains(&repo.name) {
+                repo.enabled = enabled;
+            }
+        }
+        homebins::write_repo_configs(self.dirs.config_file(), &self.repo_configs)?;
+        for name in names {
+            if enabled {
+                println!("Enabled repository {}", name.bold());
+            } else {
+                println!("Disabled repository {}", name.bold());
+            }
+        }
+    }
+
+    /// Clone the manifest repositories with the given `names`, or all repositories, from scratch.
+    #[throws]
+    pub fn repo_repair(&mut self, names: Option<Vec<String>>) -> () {
+        let names = names.unwrap_or_else(|| {
+            self.repo_configs
+                .iter()
+                .filter(|repo| repo.directory().is_none())
+                .map(|repo| repo.name.clone())
+                .collect()
+        });
+        let mut repos = self.repos();
+        for name in names {
+            repos.repair(&name)?;
+            println!("Repaired repository {}", name.bold());
+        }
+    }
+
+    /// Fetch all manifest repositories, and tell how far pinned repositories are behind.
+    #[throws]
+    pub fn repo_update(&mut self) -> () {
+        for (name, behind) in self.repos().update()? {
+            let reference = self
+                .repo_configs
+                .iter()
+                .find(|repo| repo.name == name)
+                .and_then(|repo| repo.reference.as_deref());
+            match (reference, behind) {
+                (Some(reference), Some(behind)) if 0 < behind => println!(
+                    "{}: {}",
+                    name.bold(),
+                    format!("pinned to {}, {} commits behind main", reference, behind).yellow()
+                ),
+                (Some(reference), _) => {
+                    println!(
+                        "{}: pinned to {}, up to date with main",
+                        name.bold(),
+                        reference
+                    )
                 }
+                (None, _) => println!("{}: {}", name.bold(), "up to date".green()),
+            }
+        }
+    }
+
+    /// List manifest repositories, with their URL, priority, number of manifests and last fetch.
+    #[throws]
+    pub fn repo_list(&self) -> () {
+        if self.repo_configs.is_empty() {
+            println!("No manifest repositories");
+        }
+        let repos = self.repos();
+        for repo in &self.repo_configs {
+            let mut details = Vec::new();
+            if !repo.enabled {
+                details.push("disabled".yellow().to_string());
+            }
+            if repo.archive_extension().is_some() {
+                details.push("archive".to_string());
+            }
+            if let Some(reference) = &repo.reference {
+                details.push(format!("pinned to {}", reference));
+            }
+            if repo.priority != 0 {
+                details.push(format!("priority {}", repo.priority));
             }
-            Some(names) => {
-                for name in names {
-                    let manifest = store
-                        .load_manifest(&name)?
-                        .ok_or_else(|| anyhow!("Binary {} not found", name))?;
-                    self.update_manifest(&name, &manifest)?;
+            if details.is_empty() {
+                println!("{}: {}", repo.name.bold(), repo.url.blue());
+            } else {
+                println!(
+                    "{}: {} ({})",
+                    repo.name.bold(),
+                    repo.url.blue(),
+                    details.join(", ")
+                );
+            }
+            let manifests = match repos.manifest_count(&repo.name)? {
+                Some(1) => "1 manifest".to_string(),
+                Some(count) => format!("{} manifests", count),
+                None => "no manifests yet".to_string(),
+            };
+            match repos.last_fetched(&repo.name) {
+                _ if repo.directory().is_some() => println!("  {}, local directory", manifests),
+                Some(fetched) => {
+                    println!("  {}, fetched {} ago", manifests, days_since(fetched))
                 }
+                None => println!("  {}, never fetched", manifests),
+            }
+        }
+    }
+
+    /// Check permissions of files of installed manifests with the given `names`, or all.
+    ///
+    /// Report installed files whose permissions differ from the modes homebins installs them
+    /// with, e.g. because of a restrictive umask or extended ACLs, and restore the intended
+    /// permissions if `fix` is set.
+    #[throws]
+    pub fn verify(&mut self, names: Option<Vec<String>>, fix: bool) -> () {
+        let store = self.manifest_store()?;
+        let manifests = match names {
+            None => store.manifests()?.collect::<Result<Vec<_>>>()?,
+            Some(names) => self
+                .repos()
+                .resolve_names(&store, &names)?
+                .iter()
+                .map(|name| {
+                    store
+                        .load_manifest(name)?
+                        .ok_or_else(|| anyhow!("Binary {} not found", name))
+                })
+                .collect::<Result<Vec<_>>>()?,
+        };
+        let umask = permissions::current_umask();
+        let mut problems = Vec::new();
+        for manifest in manifests {
+            if homebins::installed_manifest_version(
+                &self.install_dirs,
+                &manifest,
+                &self.apply_options,
+            )?
+            .is_some()
+            {
+                let files = homebins::installed_permissions(
+                    &self.install_dirs,
+                    &manifest,
+                    &self.apply_options,
+                )?;
+                problems.extend(permissions::permission_problems(&files, umask)?);
+            }
+        }
+        for problem in &problems {
+            if fix {
+                permissions::fix_permissions(problem)?;
+                println!("{} {}", "Fixed".green().bold(), problem);
+            } else {
+                println!("{}", problem);
+            }
+        }
+        if !fix && !problems.is_empty() {
+            throw!(anyhow!(
+                "Found {} installed files with wrong permissions; fix them with homebins verify --fix-perms",
+                problems.len()
+            ));
+        }
+    }
+
+    /// Reconcile provenance records and pins with installed binaries.
+    ///
+    /// Forget records and pins of binaries which are not installed, and records of files which
+    /// no longer exist, unless `dry_run` is set; report records of other versions and files
+    /// without records, which only reinstalling repairs.
+    #[throws]
+    pub fn gc_state(&mut self, dry_run: bool) -> () {
+        let store = self.manifest_store()?;
+        let mut known = Vec::new();
+        for manifest in store.manifests()? {
+            let manifest = manifest?;
+            let installed_version = homebins::installed_manifest_version(
+                &self.install_dirs,
+                &manifest,
+                &self.apply_options,
+            )?;
+            let files = match installed_version {
+                Some(_) => {
+                    homebins::installed_files(&self.install_dirs, &manifest, &self.apply_options)?
+                        .into_iter()
+                        .filter(|file| file.exists())
+                        .collect()
+                }
+                None => Vec::new(),
+            };
+            known.push(KnownManifest {
+                name: manifest.info.name.clone(),
+                installed_version: installed_version.map(|version| version.to_string()),
+                files,
+            });
+        }
+
+        let mut provenance = ProvenanceStore::open(self.dirs.provenance_file())?;
+        let discrepancies = provenance.reconcile(&known);
+        let mut pins = self.pins()?;
+        let stale_pins: Vec<String> = pins
+            .iter()
+            .filter(|pin| {
+                known.iter().any(|manifest| {
+                    manifest.name == pin.name && manifest.installed_version.is_none()
+                })
+            })
+            .map(|pin| pin.name.clone())
+            .collect();
+        let fixed = if dry_run { "Found" } else { "Fixed" };
+        for discrepancy in &discrepancies {
+            if discrepancy.is_repaired() {
+                println!("{} {}", fixed.green().bold(), discrepancy);
+            } else {
+                println!("{}", discrepancy);
+            }
+        }
+        for name in &stale_pins {
+            pins.unpin(name);
+            println!(
+                "{} {}: pinned, but not installed",
+                fixed.green().bold(),
+                name
+            );
+        }
+        if !dry_run {
+            provenance.save()?;
+            pins.save()?;
+        }
+        let unrepaired = discrepancies
+            .iter()
+            .filter(|discrepancy| !discrepancy.is_repaired())
+            .count();
+        if 0 < unrepaired {
+            eprintln!(
+                "{}",
+                format!(
+                    "WARNING: Reinstall binaries to repair the remaining {} discrepancies",
+                    unrepaired
+                )
+                .yellow()
+                .bold()
+            );
+        }
+    }
+
+    /// Measure core paths on the manifests of all repositories.
+    ///
+    /// Run each workload `iterations` times, and print the median and the fastest run of each.
+    /// Save the medians to `save_baseline`, and fail if any workload is slower than the given
+    /// `baseline` by more than the given percentage.
+    #[throws]
+    pub fn bench_selftest(
+        &mut self,
+        iterations: usize,
+        baseline: Option<(&Path, u32)>,
+        save_baseline: Option<&Path>,
+    ) -> () {
+        let store = self.manifest_store()?;
+        let platform = Platform::host()?;
+        let manifests = bench::parse_manifests(&store)?;
+        let checksum = ChecksumWorkload::new(16 * 1024 * 1024, Algorithm::Sha256);
+        let measurements = vec![
+            bench::measure("parse manifests", iterations, || {
+                bench::parse_manifests(&store).map(|_| ())
+            })?,
+            bench::measure("plan installs", iterations, || {
+                bench::plan_installs(&manifests, platform);
+                Ok(())
+            })?,
+            bench::measure("validate sha256 of 16 MiB", iterations, || {
+                checksum.validate()?;
+                Ok(())
+            })?,
+            bench::measure("scan store", iterations, || {
+                bench::scan_store(&store).map(|_| ())
+            })?,
+            bench::measure("index store", iterations, || {
+                bench::index_store(&store).map(|_| ())
+            })?,
+        ];
+        println!(
+            "{} manifests on {} {}",
+            manifests.len(),
+            platform.os,
+            platform.arch
+        );
+        for measurement in &measurements {
+            println!("{}", measurement);
+        }
+        if let Some(path) = save_baseline {
+            Baseline::from_measurements(&measurements).save(path)?;
+        }
+        if let Some((path, max_slowdown)) = baseline {
+            Baseline::read_from_path(path)?.check(&measurements, max_slowdown)?;
+        }
+    }
+
+    /// Print where installed files come from.
+    ///
+    /// `file_or_name` is either an installed file, or the name of a manifest to print the
+    /// provenance of all its files.
+    #[throws]
+    pub fn provenance(&self, file_or_name: &str) -> () {
+        let store = ProvenanceStore::open(self.dirs.provenance_file())?;
+        let file = std::env::current_dir()?.join(file_or_name);
+        let records: Vec<&Provenance> = match store.for_file(&file).or_else(|| {
+            file.canonicalize()
+                .ok()
+                .and_then(|file| store.for_file(&file))
+        }) {
+            Some(record) => vec![record],
+            None => store.for_manifest(file_or_name).collect(),
+        };
+        if records.is_empty() {
+            throw!(anyhow!(
+                "No provenance for {}: Not a file or binary installed by homebins",
+                file_or_name
+            ));
+        }
+        for record in records {
+            println!("{}", record.file.display().to_string().bold());
+            println!("  manifest:  {} {}", record.manifest, record.version);
+            println!("  download:  {}", record.url);
+            println!("  sha256:    {}", record.sha256);
+            if let Some(repository) = &record.repository {
+                println!("  repository: {}", repository);
             }
+            println!(
+                "  revision:  {}",
+                record.revision.as_deref().unwrap_or("unknown")
+            );
+            println!(
+                "  installed: {}",
+                homebins::status::format_timestamp(record.installed_at)
+            );
+        }
+    }
+
+    /// Lint the given manifest files, and fail if any has errors.
+    #[throws]
+    pub fn lint(&self, filenames: Vec<PathBuf>, check_urls: bool) -> () {
+        let mut errors = 0;
+        for filename in filenames {
+            for diagnostic in homebins::lint::lint_manifest_file(&filename, check_urls)? {
+                let location = match diagnostic.line {
+                    Some(line) => format!("{}:{}", filename.display(), line),
+                    None => filename.display().to_string(),
+                };
+                let severity = match diagnostic.severity {
+                    Severity::Error => {
+                        errors += 1;
+                        diagnostic.severity.to_string().red().bold()
+                    }
+                    Severity::Warning => diagnostic.severity.to_string().yellow().bold(),
+                };
+                println!("{}: {}: {}", location.bold(), severity, diagnostic.message);
+            }
+        }
+        if 0 < errors {
+            throw!(anyhow!("Found {} errors in manifests", errors));
         }
     }
 
     pub fn manifest_list(&self, filenames: Vec<PathBuf>, mode: List) -> Result<()> {
-        self.list_manifests(read_manifests(filenames.iter())?.iter(), mode)
+        self.list_manifests(
+            read_manifests(filenames.iter())?.iter(),
+            mode,
+            &[],
+            &HashMap::new(),
+        )
     }
 
     #[throws]
@@ -219,11 +1867,74 @@ impl Commands {
         }
     }
 
+    #[throws]
+    pub fn manifest_render(
+        &self,
+        filename: &Path,
+        os: Option<Os>,
+        arch: Option<Arch>,
+        version: Option<&str>,
+    ) -> () {
+        let host = Platform::host()?;
+        let platform = Platform {
+            os: os.unwrap_or(host.os),
+            arch: arch.unwrap_or(host.arch),
+        };
+        print!(
+            "{}",
+            Manifest::render_from_path(filename, platform, version)?
+        );
+    }
+
     #[throws]
     pub fn manifest_install(&mut self, filenames: Vec<PathBuf>) -> () {
         for filename in filenames {
             let manifest = Manifest::read_from_path(&filename)?;
-            self.install_manifest(&filename.display().to_string(), &manifest)?;
+            self.install_manifest(None, &filename.display().to_string(), &manifest)?;
+        }
+    }
+
+    /// Test the given manifest files end-to-end in a throwaway prefix.
+    ///
+    /// Report the installed files and version of every manifest, and fail if any manifest failed.
+    #[throws]
+    pub fn manifest_test(&mut self, filenames: Vec<PathBuf>) -> () {
+        let mut failed = 0;
+        for filename in &filenames {
+            let manifest = Manifest::read_from_path(filename)?;
+            status!("Testing {}", filename.display().to_string().bold());
+            // Keep downloads and provenance records of the test away from the real ones
+            let root = tempfile::Builder::new()
+                .prefix("homebins-manifest-test")
+                .tempdir()
+                .with_context(|| "Failed to create directory to test manifest in")?;
+            let dirs = HomebinProjectDirs::in_directory(root.path());
+            let report =
+                homebins::manifest_test::test_manifest(&dirs, &manifest, &self.apply_options)
+                    .with_context(|| format!("Testing {} failed", filename.display()))?;
+            println!("{} {}", manifest.info.name.bold(), manifest.info.version);
+            for file in &report.installed_files {
+                println!("  installed {}", file.display());
+            }
+            if let Some(version) = &report.installed_version {
+                println!("  {} reports {}", manifest.discover.name(), version);
+            }
+            for problem in &report.problems {
+                println!("  {}", problem.red());
+            }
+            if report.passed() {
+                println!("  {}", "passed".green().bold());
+            } else {
+                failed += 1;
+                println!("  {}", "failed".red().bold());
+            }
+        }
+        if 0 < failed {
+            throw!(anyhow!(
+                "{} of {} manifests failed",
+                failed,
+                filenames.len()
+            ));
         }
     }
 
@@ -244,39 +1955,377 @@ impl Commands {
     }
 }
 
+/// Arguments to skip kinds of destinations when installing or updating.
+fn skip_destination_args<'a, 'b>() -> [clap::Arg<'a, 'b>; 3] {
+    use clap::Arg;
+    [
+        Arg::with_name("no-completions")
+            .long("no-completions")
+            .help("Do not install shell completions"),
+        Arg::with_name("no-man")
+            .long("no-man")
+            .help("Do not install manpages"),
+        Arg::with_name("binaries-only")
+            .long("binaries-only")
+            .conflicts_with_all(&["no-completions", "no-man"])
+            .help("Only install binaries"),
+    ]
+}
+
+/// Argument to print the summary of a command as JSON.
+fn summary_json_arg<'a, 'b>() -> clap::Arg<'a, 'b> {
+    clap::Arg::with_name("json")
+        .long("json")
+        .help("Print the summary at the end as a single line of JSON, and status to stderr")
+}
+
+/// Get the kinds of destinations to skip from the given `matches`.
+fn skipped_destinations(matches: &clap::ArgMatches) -> Vec<DestinationKind> {
+    if matches.is_present("binaries-only") {
+        DestinationKind::ALL
+            .iter()
+            .copied()
+            .filter(|kind| *kind != DestinationKind::Binaries)
+            .collect()
+    } else {
+        let mut skip = Vec::new();
+        if matches.is_present("no-completions") {
+            skip.push(DestinationKind::Completions);
+        }
+        if matches.is_present("no-man") {
+            skip.push(DestinationKind::Manpages);
+        }
+        skip
+    }
+}
+
+/// Whether the subcommand in `matches` changes installed files, pins, or configuration.
+///
+/// All other subcommands only read state, or replace caches atomically, and take no lock.
+/// Fetching manifest repos takes the lock of the repos instead, see [`HomebinRepos`], so that
+/// `refresh` and `repo update` never wait for a running update.
+fn changes_state(matches: &clap::ArgMatches) -> bool {
+    match matches.subcommand() {
+        ("auth", Some(m)) => m.subcommand_name() == Some("login"),
+        ("trust", Some(m)) => m.subcommand_name() != Some("list"),
+        ("repo", Some(m)) => !matches!(m.subcommand_name(), Some("list") | Some("update")),
+        ("verify", Some(m)) => m.is_present("fix-perms"),
+        ("gc", Some(m)) => !m.is_present("dry-run"),
+        (name, _) => matches!(
+            name,
+            "install"
+                | "remove"
+                | "update"
+                | "pin"
+                | "unpin"
+                | "manifest-install"
+                | "manifest-remove"
+                | "manifest-update"
+        ),
+    }
+}
+
+/// Get the value of the global argument `name` from `matches` or its subcommand.
+fn global_value_of<'a>(matches: &'a clap::ArgMatches, name: &str) -> Option<&'a str> {
+    matches
+        .value_of(name)
+        .or_else(|| matches.subcommand().1.and_then(|m| m.value_of(name)))
+}
+
+/// Whether the global flag `name` is present in `matches` or its subcommand.
+fn global_is_present(matches: &clap::ArgMatches, name: &str) -> bool {
+    matches.is_present(name) || matches.subcommand().1.is_some_and(|m| m.is_present(name))
+}
+
+/// Print how many manifests are outdated, for a shell prompt.
+///
+/// Only read the names of outdated manifests which `homebins outdated` cached, see
+/// [`OutdatedCache`], and skip the configuration, to stay fast enough for prompts.
+#[throws]
+fn prompt_status() -> () {
+    let dirs = HomebinProjectDirs::open()?;
+    let status = OutdatedCache::open(dirs.outdated_cache_file())?.prompt_status();
+    if !status.is_empty() {
+        println!("{}", status);
+    }
+}
+
 #[allow(clippy::cognitive_complexity)]
 fn process_args(matches: &clap::ArgMatches) -> anyhow::Result<()> {
     use clap::*;
 
-    let mut commands = Commands::new()?;
+    if let ("prompt-status", _) = matches.subcommand() {
+        return prompt_status();
+    }
+    let mut commands = Commands::new(changes_state(matches))?;
+    if matches.is_present("keep-workdir")
+        || matches
+            .subcommand()
+            .1
+            .is_some_and(|m| m.is_present("keep-workdir"))
+    {
+        commands.apply_options.keep_workdir = true;
+    }
+    if matches.is_present("verify-all")
+        || matches
+            .subcommand()
+            .1
+            .is_some_and(|m| m.is_present("verify-all"))
+    {
+        commands.apply_options.verify_all = true;
+    }
+    if let Some(jobs) = global_value_of(matches, "jobs") {
+        commands.apply_options.jobs = Some(
+            jobs.parse::<usize>()
+                .ok()
+                .filter(|jobs| 0 < *jobs)
+                .ok_or_else(|| anyhow!("Invalid number of jobs: {}", jobs))?,
+        );
+    }
+    if let Some(prefix) = global_value_of(matches, "prefix") {
+        commands.install_dirs = InstallDirs::in_home(Path::new(prefix));
+        commands.apply_options.skip_host_operations = true;
+    }
+    if global_is_present(matches, "skip-host-operations") {
+        commands.apply_options.skip_host_operations = true;
+    }
+    if let Some(directory) = global_value_of(matches, "manifest-dir") {
+        // Plain directory repositories need absolute paths
+        commands.manifest_dir = Some(
+            std::fs::canonicalize(directory)
+                .with_context(|| format!("Manifest directory {} not found", directory))?,
+        );
+    }
+    if let Some(arch) = global_value_of(matches, "target-arch") {
+        let arch: Arch = arch.parse().map_err(|e: String| anyhow!(e))?;
+        // Binaries for another architecture won't run here
+        commands.apply_options.skip_host_operations |= arch != Platform::host()?.arch;
+        commands.apply_options.target_arch = Some(arch);
+    }
 
     match matches.subcommand() {
-        ("list", _) => commands.list(List::All),
+        ("refresh", _) => commands.refresh(),
+        ("list", m) => commands.list(List::All {
+            long: m.is_some_and(|m| m.is_present("long")),
+            json: m.is_some_and(|m| m.is_present("json")),
+        }),
         ("", _) => commands.list(List::Installed(Installed::All)),
         ("installed", _) => commands.list(List::Installed(Installed::All)),
-        ("outdated", _) => commands.list(List::Installed(Installed::Outdated)),
+        ("outdated", m) => commands.list(List::Installed(Installed::Outdated {
+            age: m.is_some_and(|m| m.is_present("age")),
+            by_release: m.is_some_and(|m| m.is_present("sort-by-release")),
+        })),
+        ("search", Some(m)) => commands.search(
+            &values_t!(m.values_of("query"), String)
+                .unwrap_or_else(|e| e.exit())
+                .join(" "),
+        ),
+        ("provenance", Some(m)) => commands.provenance(m.value_of("file_or_name").unwrap()),
+        ("verify", Some(m)) => commands.verify(
+            if m.is_present("name") {
+                Some(values_t!(m.values_of("name"), String).unwrap_or_else(|e| e.exit()))
+            } else {
+                None
+            },
+            m.is_present("fix-perms"),
+        ),
+        ("gc", Some(m)) => commands.gc_state(m.is_present("dry-run")),
+        ("bench-selftest", Some(m)) => commands.bench_selftest(
+            value_t!(m, "iterations", usize).unwrap_or_else(|e| e.exit()),
+            m.value_of("baseline").map(|baseline| {
+                (
+                    Path::new(baseline),
+                    value_t!(m, "max-slowdown", u32).unwrap_or_else(|e| e.exit()),
+                )
+            }),
+            m.value_of("save-baseline").map(Path::new),
+        ),
         ("files", Some(m)) => commands.files(
             values_t!(m.values_of("name"), String).unwrap_or_else(|e| e.exit()),
             m.is_present("existing"),
             m.is_present("remove"),
         ),
         ("install", Some(m)) => {
-            commands.install(values_t!(m.values_of("name"), String).unwrap_or_else(|e| e.exit()))
+            // Keep stdout for the summary
+            homebins::status::set_stderr(m.is_present("json"));
+            commands.apply_options.resume = m.is_present("resume");
+            commands.apply_options.force = m.is_present("force");
+            if m.is_present("on-conflict") {
+                commands.conflict_policy =
+                    value_t!(m, "on-conflict", ConflictPolicy).unwrap_or_else(|e| e.exit());
+            }
+            commands
+                .apply_options
+                .skip_destinations
+                .extend(skipped_destinations(m));
+            let names = values_t!(m.values_of("name"), String).unwrap_or_else(|e| e.exit());
+            let result = match m.value_of("ssh") {
+                Some(destination) => commands.install_over_ssh(destination, names),
+                None => commands.install(names),
+            };
+            commands.print_summary(m.is_present("json"))?;
+            result
         }
         ("remove", Some(m)) => {
-            commands.remove(values_t!(m.values_of("name"), String).unwrap_or_else(|e| e.exit()))
+            // Keep stdout for the summary
+            homebins::status::set_stderr(m.is_present("json"));
+            let mut kinds = if m.is_present("only") {
+                values_t!(m.values_of("only"), DestinationKind).unwrap_or_else(|e| e.exit())
+            } else {
+                Vec::new()
+            };
+            if m.is_present("unused-completions") {
+                kinds.extend(destinations::unused_completions());
+            }
+            let result = if m.is_present("only") || m.is_present("unused-completions") {
+                let names = if m.is_present("name") {
+                    Some(values_t!(m.values_of("name"), String).unwrap_or_else(|e| e.exit()))
+                } else {
+                    None
+                };
+                if kinds.is_empty() {
+                    status!("All shells with completions are in use");
+                    Ok(())
+                } else {
+                    commands.remove_destinations(names, kinds)
+                }
+            } else {
+                commands.remove(values_t!(m.values_of("name"), String).unwrap_or_else(|e| e.exit()))
+            };
+            commands.print_summary(m.is_present("json"))?;
+            result
         }
         ("update", Some(m)) => {
+            // Keep stdout for the summary
+            homebins::status::set_stderr(m.is_present("json"));
+            commands
+                .apply_options
+                .skip_destinations
+                .extend(skipped_destinations(m));
+            let names = if m.is_present("name") {
+                Some(values_t!(m.values_of("name"), String).unwrap_or_else(|e| e.exit()))
+            } else {
+                None
+            };
+            let result = commands.update(names, m.is_present("migrate_deprecated"));
+            commands.print_summary(m.is_present("json"))?;
+            result
+        }
+        ("upstream-outdated", Some(m)) => {
+            let names = if m.is_present("name") {
+                Some(values_t!(m.values_of("name"), String).unwrap_or_else(|e| e.exit()))
+            } else {
+                None
+            };
+            commands.upstream_outdated(
+                names,
+                m.is_present("refresh"),
+                m.is_present("allow-commands"),
+            )
+        }
+        ("export", Some(m)) => {
+            commands.export(value_t!(m, "format", ExportFormat).unwrap_or_else(|e| e.exit()))
+        }
+        ("graph", Some(m)) => {
             let names = if m.is_present("name") {
                 Some(values_t!(m.values_of("name"), String).unwrap_or_else(|e| e.exit()))
             } else {
                 None
             };
-            commands.update(names)
+            commands.graph(
+                names,
+                value_t!(m, "format", GraphFormat).unwrap_or_else(|e| e.exit()),
+            )
         }
+        ("new", Some(m)) => {
+            let arch = match m.value_of("arch") {
+                Some(_) => value_t!(m, "arch", Arch).unwrap_or_else(|e| e.exit()),
+                None => Arch::host()?,
+            };
+            print!(
+                "{}",
+                homebins::github::new_manifest(m.value_of("url").unwrap(), arch)?
+            );
+            Ok(())
+        }
+        ("import-brew", Some(m)) => {
+            print!(
+                "{}",
+                homebins::brew::import(m.value_of("name").unwrap(), m.is_present("cask"))?
+            );
+            Ok(())
+        }
+        ("pin", Some(m)) => commands.pin(
+            if m.is_present("pin") {
+                values_t!(m.values_of("pin"), Pin).unwrap_or_else(|e| e.exit())
+            } else {
+                Vec::new()
+            },
+            m.value_of("file").map(Path::new),
+        ),
+        ("unpin", Some(m)) => commands.unpin(
+            m.values_of("name")
+                .map_or_else(Vec::new, |names| names.map(String::from).collect()),
+            m.value_of("file").map(Path::new),
+        ),
+        ("lint", Some(m)) => commands.lint(
+            values_t!(m.values_of("manifest-file"), PathBuf).unwrap_or_else(|e| e.exit()),
+            m.is_present("check-urls"),
+        ),
+        ("auth", Some(m)) => match m.subcommand() {
+            ("login", Some(m)) => commands.auth_login(m.value_of("host").unwrap()),
+            ("status", Some(m)) => commands.auth_status(
+                m.values_of("host")
+                    .map_or_else(Vec::new, |hosts| hosts.map(String::from).collect()),
+            ),
+            (other, _) => unreachable!("Unknown auth subcommand: {}", other),
+        },
+        ("trust", Some(m)) => match m.subcommand() {
+            ("add", Some(m)) => {
+                commands.trust_add(m.value_of("key").unwrap(), m.value_of("name").unwrap())
+            }
+            ("remove", Some(m)) => commands.trust_remove(
+                values_t!(m.values_of("key-or-name"), String).unwrap_or_else(|e| e.exit()),
+            ),
+            ("list", _) => commands.trust_list(),
+            (other, _) => unreachable!("Unknown trust subcommand: {}", other),
+        },
+        ("repo", Some(m)) => match m.subcommand() {
+            ("add", Some(m)) => commands.repo_add(
+                m.value_of("name").unwrap(),
+                m.value_of("url").unwrap(),
+                m.value_of("ref"),
+                if m.is_present("priority") {
+                    value_t!(m, "priority", u32).unwrap_or_else(|e| e.exit())
+                } else {
+                    0
+                },
+            ),
+            ("remove", Some(m)) => commands
+                .repo_remove(values_t!(m.values_of("name"), String).unwrap_or_else(|e| e.exit())),
+            ("enable", Some(m)) => commands.repo_enable(
+                values_t!(m.values_of("name"), String).unwrap_or_else(|e| e.exit()),
+                true,
+            ),
+            ("disable", Some(m)) => commands.repo_enable(
+                values_t!(m.values_of("name"), String).unwrap_or_else(|e| e.exit()),
+                false,
+            ),
+            ("list", _) => commands.repo_list(),
+            ("update", _) => commands.repo_update(),
+            ("repair", Some(m)) => commands.repo_repair(if m.is_present("name") {
+                Some(values_t!(m.values_of("name"), String).unwrap_or_else(|e| e.exit()))
+            } else {
+                None
+            }),
+            (other, _) => unreachable!("Unknown repo subcommand: {}", other),
+        },
         ("manifest-list", Some(m)) => commands.manifest_list(
             values_t!(m.values_of("manifest-file"), PathBuf).unwrap_or_else(|e| e.exit()),
-            List::All,
+            List::All {
+                long: m.is_present("long"),
+                json: m.is_present("json"),
+            },
         ),
         ("manifest-installed", Some(m)) => commands.manifest_list(
             values_t!(m.values_of("manifest-file"), PathBuf).unwrap_or_else(|e| e.exit()),
@@ -284,22 +2333,54 @@ fn process_args(matches: &clap::ArgMatches) -> anyhow::Result<()> {
         ),
         ("manifest-outdated", Some(m)) => commands.manifest_list(
             values_t!(m.values_of("manifest-file"), PathBuf).unwrap_or_else(|e| e.exit()),
-            List::Installed(Installed::Outdated),
+            List::Installed(Installed::Outdated {
+                age: m.is_present("age"),
+                by_release: m.is_present("sort-by-release"),
+            }),
         ),
         ("manifest-files", Some(m)) => commands.manifest_files(
             values_t!(m.values_of("manifest-file"), PathBuf).unwrap_or_else(|e| e.exit()),
             m.is_present("existing"),
             m.is_present("remove"),
         ),
-        ("manifest-install", Some(m)) => commands.manifest_install(
-            values_t!(m.values_of("manifest-file"), PathBuf).unwrap_or_else(|e| e.exit()),
+        ("manifest-render", Some(m)) => commands.manifest_render(
+            Path::new(m.value_of("manifest-file").unwrap()),
+            m.value_of("os")
+                .map(|_| value_t!(m, "os", Os).unwrap_or_else(|e| e.exit())),
+            m.value_of("arch")
+                .map(|_| value_t!(m, "arch", Arch).unwrap_or_else(|e| e.exit())),
+            m.value_of("version"),
         ),
-        ("manifest-remove", Some(m)) => commands.manifest_remove(
+        ("manifest-install", Some(m)) => {
+            commands.apply_options.resume = m.is_present("resume");
+            commands.apply_options.force = m.is_present("force");
+            if m.is_present("on-conflict") {
+                commands.conflict_policy =
+                    value_t!(m, "on-conflict", ConflictPolicy).unwrap_or_else(|e| e.exit());
+            }
+            commands
+                .apply_options
+                .skip_destinations
+                .extend(skipped_destinations(m));
+            commands.manifest_install(
+                values_t!(m.values_of("manifest-file"), PathBuf).unwrap_or_else(|e| e.exit()),
+            )
+        }
+        ("manifest-test", Some(m)) => commands.manifest_test(
             values_t!(m.values_of("manifest-file"), PathBuf).unwrap_or_else(|e| e.exit()),
         ),
-        ("manifest-update", Some(m)) => commands.manifest_update(
+        ("manifest-remove", Some(m)) => commands.manifest_remove(
             values_t!(m.values_of("manifest-file"), PathBuf).unwrap_or_else(|e| e.exit()),
         ),
+        ("manifest-update", Some(m)) => {
+            commands
+                .apply_options
+                .skip_destinations
+                .extend(skipped_destinations(m));
+            commands.manifest_update(
+                values_t!(m.values_of("manifest-file"), PathBuf).unwrap_or_else(|e| e.exit()),
+            )
+        }
         (other, _) => Err(anyhow!("Unknown subcommand: {}", other)),
     }
 }
@@ -309,9 +2390,101 @@ fn main() {
     let app = app_from_crate!()
         .setting(AppSettings::DeriveDisplayOrder)
         .setting(AppSettings::ColoredHelp)
-        .subcommand(SubCommand::with_name("list").about("List available binaries"))
+        .arg(
+            Arg::with_name("keep-workdir")
+                .long("keep-workdir")
+                .global(true)
+                .help("Keep the working directory of failed installations for debugging"),
+        )
+        .arg(
+            Arg::with_name("verify-all")
+                .long("verify-all")
+                .global(true)
+                .help("Verify downloads against all checksums, not just the strongest"),
+        )
+        .arg(
+            Arg::with_name("jobs")
+                .short("j")
+                .long("jobs")
+                .global(true)
+                .takes_value(true)
+                .value_name("N")
+                .help("Download up to N files of a manifest at once"),
+        )
+        .arg(
+            Arg::with_name("target-arch")
+                .long("target-arch")
+                .global(true)
+                .takes_value(true)
+                .possible_values(&["x86_64", "aarch64", "armv7"])
+                .help("Install binaries for this architecture instead of the host's"),
+        )
+        .arg(
+            Arg::with_name("prefix")
+                .long("prefix")
+                .global(true)
+                .takes_value(true)
+                .help("Install into the home directory at this path instead of $HOME"),
+        )
+        .arg(
+            Arg::with_name("skip-host-operations")
+                .long("skip-host-operations")
+                .global(true)
+                .help("Skip hooks, systemd units and cache updates, which affect the running system"),
+        )
+        .arg(
+            Arg::with_name("manifest-dir")
+                .long("manifest-dir")
+                .global(true)
+                .takes_value(true)
+                .value_name("DIR")
+                .help("Use manifests in this directory instead of all manifest repositories"),
+        )
+        .subcommand(SubCommand::with_name("refresh").about("Fetch the latest manifests"))
+        .subcommand(
+            SubCommand::with_name("list")
+                .about("List available binaries")
+                .arg(
+                    Arg::with_name("long")
+                        .short("l")
+                        .long("long")
+                        .help("Show release dates, or whole manifests with --json"),
+                )
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Print the info of manifests as JSON"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("search")
+                .about("Search binaries by name, description and keywords")
+                .arg(
+                    Arg::with_name("query")
+                        .required(true)
+                        .multiple(true)
+                        .help("Words to search for"),
+                ),
+        )
         .subcommand(SubCommand::with_name("installed").about("List installed binaries (default)"))
-        .subcommand(SubCommand::with_name("outdated").about("List outdated binaries"))
+        .subcommand(
+            SubCommand::with_name("outdated")
+                .about("List outdated binaries")
+                .arg(
+                    Arg::with_name("age")
+                        .long("age")
+                        .help("Show how long binaries have been outdated"),
+                )
+                .arg(
+                    Arg::with_name("sort-by-release")
+                        .long("sort-by-release")
+                        .help("Sort by release date of the available version, oldest first"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("prompt-status")
+                .about("Print the number of outdated binaries found last, for a shell prompt"),
+        )
         .subcommand(
             SubCommand::with_name("files")
                 .about("List files of binary")
@@ -334,9 +2507,108 @@ fn main() {
                         .help("Binaries to install"),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("verify")
+                .about("Check permissions of installed files")
+                .arg(
+                    Arg::with_name("fix-perms")
+                        .long("fix-perms")
+                        .help("Restore the permissions homebins installs files with"),
+                )
+                .arg(
+                    Arg::with_name("name")
+                        .multiple(true)
+                        .help("Binaries to check (default: all installed binaries)"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("gc")
+                .about("Clean up state of homebins")
+                .arg(
+                    Arg::with_name("state")
+                        .long("state")
+                        .required(true)
+                        .help("Reconcile provenance records and pins with installed binaries"),
+                )
+                .arg(
+                    Arg::with_name("dry-run")
+                        .long("dry-run")
+                        .help("Only report discrepancies, and change nothing"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("bench-selftest")
+                .setting(AppSettings::Hidden)
+                .about("Measure core paths on the manifests of all repositories")
+                .arg(
+                    Arg::with_name("iterations")
+                        .long("iterations")
+                        .value_name("N")
+                        .default_value("10")
+                        .help("How often to run each workload"),
+                )
+                .arg(
+                    Arg::with_name("baseline")
+                        .long("baseline")
+                        .value_name("FILE")
+                        .help("Fail if any workload is slower than in the baseline in FILE"),
+                )
+                .arg(
+                    Arg::with_name("max-slowdown")
+                        .long("max-slowdown")
+                        .value_name("PERCENT")
+                        .default_value("20")
+                        .help("How much slower than the baseline workloads may be"),
+                )
+                .arg(
+                    Arg::with_name("save-baseline")
+                        .long("save-baseline")
+                        .value_name("FILE")
+                        .help("Save the medians of all workloads as baseline to FILE"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("provenance")
+                .about("Show where installed files come from")
+                .arg(
+                    Arg::with_name("file_or_name")
+                        .required(true)
+                        .value_name("FILE-OR-NAME")
+                        .help("An installed file, or a binary to show all files of"),
+                ),
+        )
         .subcommand(
             SubCommand::with_name("install")
                 .about("Install binaries")
+                .args(&skip_destination_args())
+                .arg(summary_json_arg())
+                .arg(
+                    Arg::with_name("resume")
+                        .long("resume")
+                        .help("Skip steps completed by a previous failed installation"),
+                )
+                .arg(
+                    Arg::with_name("force")
+                        .long("force")
+                        .help("Install even if files exist or binaries conflict"),
+                )
+                .arg(
+                    Arg::with_name("on-conflict")
+                        .long("on-conflict")
+                        .takes_value(true)
+                        .value_name("POLICY")
+                        .possible_values(&["prompt", "overwrite", "skip", "backup", "abort"])
+                        .help("Resolve conflicts with existing files; prompts by default on a terminal, and aborts otherwise"),
+                )
+                .arg(
+                    Arg::with_name("ssh")
+                        .long("ssh")
+                        .takes_value(true)
+                        .value_name("DESTINATION")
+                        .help(
+                            "Install to the home directory of this SSH destination, e.g. user@host",
+                        ),
+                )
                 .arg(
                     Arg::with_name("name")
                         .required(true)
@@ -347,25 +2619,330 @@ fn main() {
         .subcommand(
             SubCommand::with_name("remove")
                 .about("Remove binaries")
+                .arg(summary_json_arg())
+                .arg(
+                    Arg::with_name("only")
+                        .long("only")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .value_name("KIND")
+                        .help(
+                            "Only remove files of KIND, e.g. fish_completions or \
+                             systemd_user_units, and keep the binaries",
+                        )
+                        .validator(|kind| {
+                            if kind == "binaries" {
+                                Err("Binaries go with the whole manifest; remove it instead"
+                                    .to_string())
+                            } else {
+                                Ok(())
+                            }
+                        }),
+                )
+                .arg(
+                    Arg::with_name("unused-completions")
+                        .long("unused-completions")
+                        .help("Only remove completions for shells which are not installed"),
+                )
                 .arg(
                     Arg::with_name("name")
-                        .required(true)
+                        .required_unless_one(&["only", "unused-completions"])
                         .multiple(true)
-                        .help("Binaries to remove"),
+                        .help("Binaries to remove (default to all binaries with --only)"),
                 ),
         )
         .subcommand(
             SubCommand::with_name("update")
                 .about("Update binaries")
+                .args(&skip_destination_args())
+                .arg(summary_json_arg())
+                .arg(
+                    Arg::with_name("migrate_deprecated")
+                        .long("migrate-deprecated")
+                        .help("Replace deprecated binaries with their replacements without asking"),
+                )
                 .arg(
                     Arg::with_name("name")
                         .multiple(true)
                         .help("Binaries to update (default to all outdated binaries)"),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("upstream-outdated")
+                .about("List manifests which are behind their upstream")
+                .arg(
+                    Arg::with_name("refresh")
+                        .long("refresh")
+                        .help("Revalidate all cached upstream responses"),
+                )
+                .arg(
+                    Arg::with_name("allow-commands")
+                        .long("allow-commands")
+                        .help("Run the commands of manifests with a command upstream"),
+                )
+                .arg(
+                    Arg::with_name("name")
+                        .multiple(true)
+                        .help("Manifests to check (default to all manifests)"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("export")
+                .about("Export installed binaries for other tools")
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .takes_value(true)
+                        .required(true)
+                        .possible_values(&["brewfile", "tool-versions", "script", "bundle"])
+                        .help("Export as Brewfile, .tool-versions, shell script with curl, or shell script with the manifests for homebins"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("graph")
+                .about("Print the graph of manifests, their files and their relations")
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .takes_value(true)
+                        .default_value("dot")
+                        .possible_values(&["dot", "json"])
+                        .help("Print as Graphviz dot or JSON"),
+                )
+                .arg(
+                    Arg::with_name("name")
+                        .multiple(true)
+                        .help("Manifests to include (default to all manifests)"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("new")
+                .about("Print a draft manifest for the latest release of a GitHub repository")
+                .arg(
+                    Arg::with_name("arch")
+                        .long("arch")
+                        .takes_value(true)
+                        .possible_values(&["x86_64", "aarch64", "armv7"])
+                        .help("The architecture to pick a download for (default to the host)"),
+                )
+                .arg(
+                    Arg::with_name("url")
+                        .required(true)
+                        .help("The URL of the GitHub repository"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("import-brew")
+                .about("Print a draft manifest for a Homebrew formula")
+                .arg(
+                    Arg::with_name("cask")
+                        .long("cask")
+                        .help("Import a cask instead of a formula"),
+                )
+                .arg(
+                    Arg::with_name("name")
+                        .required(true)
+                        .help("The name of the formula or cask"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("pin")
+                .about("Pin binaries to keep update from updating them, or list pins")
+                .arg(
+                    Arg::with_name("file")
+                        .long("file")
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .help(
+                            "Pin all binaries in FILE (- for stdin), one name[=version] per line",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("pin")
+                        .multiple(true)
+                        .value_name("NAME[=VERSION]")
+                        .help("Binaries to pin, at the given version or the installed one"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("unpin")
+                .about("Unpin binaries")
+                .arg(
+                    Arg::with_name("file")
+                        .long("file")
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .help("Unpin all binaries in FILE (- for stdin), one name per line"),
+                )
+                .arg(
+                    Arg::with_name("name")
+                        .multiple(true)
+                        .required_unless("file")
+                        .help("Binaries to unpin"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("lint")
+                .about("Check manifest files for mistakes")
+                .arg(
+                    Arg::with_name("check-urls")
+                        .long("check-urls")
+                        .help("Also check that all downloads are reachable"),
+                )
+                .arg(
+                    Arg::with_name("manifest-file")
+                        .required(true)
+                        .multiple(true)
+                        .help("Manifest files"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("auth")
+                .about("Manage download tokens in the keyring")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("login")
+                        .about("Store a download token for a host in the keyring")
+                        .arg(
+                            Arg::with_name("host")
+                                .required(true)
+                                .help("The host to download with the token from"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("status")
+                        .about("Show whether tokens for configured and given hosts resolve")
+                        .arg(
+                            Arg::with_name("host")
+                                .multiple(true)
+                                .help("Additional hosts to check"),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("trust")
+                .about("Manage trusted keys for signed downloads and manifest repos")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("add")
+                        .about("Trust a minisign public key")
+                        .arg(
+                            Arg::with_name("key")
+                                .required(true)
+                                .help("The base64-encoded minisign public key"),
+                        )
+                        .arg(
+                            Arg::with_name("name")
+                                .required(true)
+                                .help("A name for the key"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("remove")
+                        .about("Stop trusting keys")
+                        .arg(
+                            Arg::with_name("key-or-name")
+                                .required(true)
+                                .multiple(true)
+                                .help("Public keys or names of keys"),
+                        ),
+                )
+                .subcommand(SubCommand::with_name("list").about("List trusted keys")),
+        )
+        .subcommand(
+            SubCommand::with_name("repo")
+                .about("Manage manifest repositories")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("add")
+                        .about("Add a manifest repository")
+                        .arg(
+                            Arg::with_name("name")
+                                .required(true)
+                                .help("A name for the repository, to qualify binaries with"),
+                        )
+                        .arg(
+                            Arg::with_name("url")
+                                .required(true)
+                                .help("The Git URL of the repository, or a directory of manifests"),
+                        )
+                        .arg(
+                            Arg::with_name("ref")
+                                .long("ref")
+                                .takes_value(true)
+                                .value_name("REF")
+                                .help("Pin the repository to this branch, tag or commit instead of the latest commit on main"),
+                        )
+                        .arg(
+                            Arg::with_name("priority")
+                                .long("priority")
+                                .takes_value(true)
+                                .value_name("N")
+                                .help("Prefer manifests of this repository over repositories with lower priority; defaults to 0"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("remove")
+                        .about("Remove manifest repositories")
+                        .arg(
+                            Arg::with_name("name")
+                                .required(true)
+                                .multiple(true)
+                                .help("Names of repositories"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("enable")
+                        .about("Install from disabled manifest repositories again")
+                        .arg(
+                            Arg::with_name("name")
+                                .required(true)
+                                .multiple(true)
+                                .help("Names of repositories"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("disable")
+                        .about("Ignore manifest repositories, but keep their clones")
+                        .arg(
+                            Arg::with_name("name")
+                                .required(true)
+                                .multiple(true)
+                                .help("Names of repositories"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("list").about("List manifest repositories"),
+                )
+                .subcommand(
+                    SubCommand::with_name("update")
+                        .about("Fetch manifest repositories and tell how far pinned repositories are behind"),
+                )
+                .subcommand(
+                    SubCommand::with_name("repair")
+                        .about("Clone manifest repositories again from scratch")
+                        .arg(
+                            Arg::with_name("name")
+                                .multiple(true)
+                                .help("Names of repositories; defaults to all repositories"),
+                        ),
+                ),
+        )
         .subcommand(
             SubCommand::with_name("manifest-list")
                 .about("List info for given manifest files")
+                .arg(
+                    Arg::with_name("long")
+                        .short("l")
+                        .long("long")
+                        .help("Show release dates, or whole manifests with --json"),
+                )
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Print the info of manifests as JSON"),
+                )
                 .arg(
                     Arg::with_name("manifest-file")
                         .required(true)
@@ -386,6 +2963,16 @@ fn main() {
         .subcommand(
             SubCommand::with_name("manifest-outdated")
                 .about("Show outdated versions of given manifest files")
+                .arg(
+                    Arg::with_name("age")
+                        .long("age")
+                        .help("Show how long binaries have been installed"),
+                )
+                .arg(
+                    Arg::with_name("sort-by-release")
+                        .long("sort-by-release")
+                        .help("Sort by release date of the available version, oldest first"),
+                )
                 .arg(
                     Arg::with_name("manifest-file")
                         .required(true)
@@ -415,9 +3002,67 @@ fn main() {
                         .help("Manifest files"),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("manifest-render")
+                .about("Show a manifest file as homebins sees it on a platform")
+                .arg(
+                    Arg::with_name("os")
+                        .long("os")
+                        .takes_value(true)
+                        .possible_values(&["linux", "macos"])
+                        .help("The operating system to render for (default to the host)"),
+                )
+                .arg(
+                    Arg::with_name("arch")
+                        .long("arch")
+                        .takes_value(true)
+                        .possible_values(&["x86_64", "aarch64", "armv7"])
+                        .help("The architecture to render for (default to the host)"),
+                )
+                .arg(
+                    Arg::with_name("version")
+                        .long("version")
+                        .takes_value(true)
+                        .help("Render for this version instead of the version of the manifest"),
+                )
+                .arg(
+                    Arg::with_name("manifest-file")
+                        .required(true)
+                        .help("The manifest file"),
+                ),
+        )
         .subcommand(
             SubCommand::with_name("manifest-install")
                 .about("Install given manifest files")
+                .args(&skip_destination_args())
+                .arg(
+                    Arg::with_name("resume")
+                        .long("resume")
+                        .help("Skip steps completed by a previous failed installation"),
+                )
+                .arg(
+                    Arg::with_name("force")
+                        .long("force")
+                        .help("Install even if files exist or binaries conflict"),
+                )
+                .arg(
+                    Arg::with_name("on-conflict")
+                        .long("on-conflict")
+                        .takes_value(true)
+                        .value_name("POLICY")
+                        .possible_values(&["prompt", "overwrite", "skip", "backup", "abort"])
+                        .help("Resolve conflicts with existing files; prompts by default on a terminal, and aborts otherwise"),
+                )
+                .arg(
+                    Arg::with_name("manifest-file")
+                        .required(true)
+                        .multiple(true)
+                        .help("Manifest files"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("manifest-test")
+                .about("Install, check and remove given manifest files in a throwaway prefix")
                 .arg(
                     Arg::with_name("manifest-file")
                         .required(true)
@@ -438,6 +3083,7 @@ fn main() {
         .subcommand(
             SubCommand::with_name("manifest-update")
                 .about("Update given manifest files")
+                .args(&skip_destination_args())
                 .arg(
                     Arg::with_name("manifest-file")
                         .required(true)
@@ -446,7 +3092,9 @@ fn main() {
                 ),
         );
 
-    if let Err(error) = process_args(&app.get_matches()) {
+    let matches = app.get_matches();
+    homebins::status::detect();
+    if let Err(error) = process_args(&matches) {
         eprintln!("{}", format!("Error: {:#}", error).red().bold());
         std::process::exit(1)
     }
@@ -4,10 +4,18 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
+mod credentials;
+mod index;
+#[cfg(feature = "native-git")]
+mod native_git;
 mod repo;
 mod store;
+mod template;
 mod types;
 
+pub(crate) use credentials::{fetch_error, git_environment};
+pub use credentials::{FetchError, GitToken};
+pub use index::{IndexEntry, ManifestIndex};
 pub use repo::ManifestRepo;
 pub use store::ManifestStore;
 pub use types::*;
@@ -0,0 +1,199 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Credentials for private manifest repositories.
+//!
+//! Homebins fetches HTTPS remotes with the token for the host of the remote, if there is one,
+//! and leaves everything else to Git: its credential helpers for HTTPS remotes, and ssh with its
+//! agent for SSH remotes.  Git never sees tokens on its command line, where other users could
+//! read them, but gets them from a credential helper through its environment.  Neither Git nor
+//! ssh ever prompt for credentials, so fetching fails instead of waiting for a prompt.
+
+use std::fmt::{Debug, Formatter};
+
+/// The environment variable to pass a token to the credential helper with.
+const TOKEN_VARIABLE: &str = "HOMEBINS_GIT_TOKEN";
+
+/// The user name to send along with tokens.
+///
+/// GitHub wants this user name for app tokens, and accepts any user name for personal tokens,
+/// like most other Git hosts.
+pub const TOKEN_USER_NAME: &str = "x-access-token";
+
+/// A token to fetch a manifest repository over HTTPS with.
+#[derive(Clone, PartialEq, Eq)]
+pub struct GitToken(String);
+
+impl GitToken {
+    /// Use `token` to fetch manifest repositories.
+    pub fn new(token: String) -> GitToken {
+        GitToken(token)
+    }
+
+    /// The token itself.
+    pub fn secret(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Debug for GitToken {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("GitToken(<redacted>)")
+    }
+}
+
+/// The environment to run Git in to fetch manifest repositories, with `token` if any.
+///
+/// Disable prompts of Git and ssh.  Replace all credential helpers with a helper which answers
+/// with `token`, if given.
+pub fn git_environment(token: Option<&GitToken>) -> Vec<(&'static str, String)> {
+    let mut environment = vec![("GIT_TERMINAL_PROMPT", "0".to_string())];
+    if std::env::var_os("GIT_SSH_COMMAND").is_none() {
+        environment.push(("GIT_SSH_COMMAND", "ssh -o BatchMode=yes".to_string()));
+    }
+    if let Some(token) = token {
+        environment.extend([
+            ("GIT_CONFIG_COUNT", "2".to_string()),
+            // An empty helper removes all helpers configured before
+            ("GIT_CONFIG_KEY_0", "credential.helper".to_string()),
+            ("GIT_CONFIG_VALUE_0", String::new()),
+            ("GIT_CONFIG_KEY_1", "credential.helper".to_string()),
+            (
+                "GIT_CONFIG_VALUE_1",
+                format!(
+                    "!f() {{ test \"$1\" = get && echo username={} && echo \"password=${}\"; }}; f",
+                    TOKEN_USER_NAME, TOKEN_VARIABLE
+                ),
+            ),
+            (TOKEN_VARIABLE, token.secret().to_string()),
+        ]);
+    }
+    environment
+}
+
+/// Why fetching a manifest repository failed.
+#[derive(Debug, thiserror::Error)]
+pub enum FetchError {
+    /// The remote refused the credentials, or homebins had none to offer.
+    #[error("Authentication failed for {remote}; configure a token for its host, or check your Git credentials or SSH keys: {message}")]
+    Authentication {
+        /// The remote which refused to authenticate.
+        remote: String,
+        /// What Git said.
+        message: String,
+    },
+    /// The remote was not reachable.
+    #[error("Cannot reach {remote}: {message}")]
+    Network {
+        /// The unreachable remote.
+        remote: String,
+        /// What Git said.
+        message: String,
+    },
+    /// Fetching failed for another reason.
+    #[error("Failed to fetch {remote}: {message}")]
+    Other {
+        /// The remote.
+        remote: String,
+        /// What Git said.
+        message: String,
+    },
+}
+
+/// Messages of Git, ssh and libgit2 about failed authentication.
+const AUTHENTICATION_FAILURES: &[&str] = &[
+    "authentication failed",
+    "could not read username",
+    "could not read password",
+    "terminal prompts disabled",
+    "permission denied (publickey",
+    "host key verification failed",
+    "requested url returned error: 401",
+    "requested url returned error: 403",
+    "remote authentication required",
+    "too many redirects or authentication replays",
+];
+
+/// Messages of Git, ssh and libgit2 about unreachable remotes.
+const NETWORK_FAILURES: &[&str] = &[
+    "could not resolve host",
+    "could not resolve hostname",
+    "failed to resolve address",
+    "failed to connect",
+    "connection refused",
+    "connection timed out",
+    "operation timed out",
+    "network is unreachable",
+    "no route to host",
+    "temporary failure in name resolution",
+];
+
+/// Tell why fetching `remote` failed from the `message` of Git.
+pub fn fetch_error(remote: &str, message: &str) -> FetchError {
+    let message = message.trim().to_string();
+    let lowercase = message.to_lowercase();
+    let remote = remote.to_string();
+    if AUTHENTICATION_FAILURES
+        .iter()
+        .any(|failure| lowercase.contains(failure))
+    {
+        FetchError::Authentication { remote, message }
+    } else if NETWORK_FAILURES
+        .iter()
+        .any(|failure| lowercase.contains(failure))
+    {
+        FetchError::Network { remote, message }
+    } else {
+        FetchError::Other { remote, message }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn classify_fetch_errors() {
+        let remote = "https://github.com/example/private";
+        let kind = |message: &str| match fetch_error(remote, message) {
+            FetchError::Authentication { .. } => "authentication",
+            FetchError::Network { .. } => "network",
+            FetchError::Other { .. } => "other",
+        };
+        assert_eq!(
+            kind("fatal: could not read Username for 'https://github.com': terminal prompts disabled"),
+            "authentication"
+        );
+        assert_eq!(
+            kind("git@github.com: Permission denied (publickey).\nfatal: Could not read from remote repository."),
+            "authentication"
+        );
+        assert_eq!(
+            kind("fatal: unable to access 'https://github.com/example/private/': Could not resolve host: github.com"),
+            "network"
+        );
+        assert_eq!(kind("fatal: couldn't find remote ref main"), "other");
+        assert_eq!(
+            fetch_error(remote, "fatal: Authentication failed for 'https://github.com/example/private/'\n").to_string(),
+            "Authentication failed for https://github.com/example/private; configure a token for its host, or check your Git credentials or SSH keys: fatal: Authentication failed for 'https://github.com/example/private/'"
+        );
+    }
+
+    #[test]
+    fn hide_token() {
+        let token = GitToken::new("hunter2".to_string());
+        assert_eq!(format!("{:?}", token), "GitToken(<redacted>)");
+        let environment = git_environment(Some(&token));
+        assert!(environment
+            .iter()
+            .any(|(name, value)| *name == TOKEN_VARIABLE && value == "hunter2"));
+        assert!(environment
+            .iter()
+            .filter(|(name, _)| *name != TOKEN_VARIABLE)
+            .all(|(_, value)| !value.contains("hunter2")));
+    }
+}
@@ -0,0 +1,188 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! An index of the manifests in a repository.
+//!
+//! Reading summaries of all manifests still parses every manifest file, which gets slow as a
+//! repository grows.  The index caches summaries of all manifests of a repository in a JSON
+//! file, along with the Git revision of the repository and the modification time of every
+//! manifest file.  Homebins rebuilds the whole index when the revision changes, and otherwise
+//! only reads manifest files which changed since it last built the index.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::{Context, Error};
+use fehler::throws;
+use serde::{Deserialize, Serialize};
+
+use super::store::ManifestStore;
+use super::types::ManifestSummary;
+use crate::state::{read_state, write_atomically};
+
+/// The format of the index.
+///
+/// Increase whenever the index gets new fields, to rebuild indexes of older versions.
+const INDEX_FORMAT: u32 = 2;
+
+/// A manifest in the index.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct IndexEntry {
+    /// The summary of the manifest.
+    #[serde(flatten)]
+    pub summary: ManifestSummary,
+    /// The manifest file.
+    pub path: PathBuf,
+    /// When the manifest file was last modified.
+    pub mtime: SystemTime,
+}
+
+/// An index of all manifests in a repository.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ManifestIndex {
+    format: u32,
+    revision: String,
+    entries: Vec<IndexEntry>,
+}
+
+impl ManifestIndex {
+    /// Index all manifests in `store` at the given Git `revision` of its repository.
+    ///
+    /// Reuse entries of a `previous` index at the same revision for manifest files which didn't
+    /// change since, and read all other manifest files.
+    #[throws]
+    pub fn build(
+        store: &ManifestStore,
+        revision: &str,
+        previous: Option<&ManifestIndex>,
+    ) -> ManifestIndex {
+        let previous =
+            previous.filter(|index| index.format == INDEX_FORMAT && index.revision == revision);
+        let mut entries = Vec::new();
+        for path in store.manifest_paths()? {
+            let mtime = std::fs::metadata(&path)
+                .and_then(|metadata| metadata.modified())
+                .with_context(|| {
+                    format!("Failed to get modification time of {}", path.display())
+                })?;
+            let cached = previous.and_then(|index| {
+                index
+                    .entries
+                    .iter()
+                    .find(|entry| entry.path == path && entry.mtime == mtime)
+            });
+            let entry = match cached {
+                Some(entry) => entry.clone(),
+                None => IndexEntry {
+                    summary: ManifestSummary::read_from_path(&path)?,
+                    path,
+                    mtime,
+                },
+            };
+            entries.push(entry);
+        }
+        ManifestIndex {
+            format: INDEX_FORMAT,
+            revision: revision.to_string(),
+            entries,
+        }
+    }
+
+    /// Read an index from the file at `path`.
+    ///
+    /// Return `None` if the file doesn't exist, or doesn't contain a valid index, e.g. because an
+    /// older version of homebins wrote it.
+    #[throws]
+    pub fn read_from_path<P: AsRef<Path>>(path: P) -> Option<ManifestIndex> {
+        read_state(path)?
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .filter(|index: &ManifestIndex| index.format == INDEX_FORMAT)
+    }
+
+    /// Write this index to the file at `path`.
+    #[throws]
+    pub fn write_to_path<P: AsRef<Path>>(&self, path: P) -> () {
+        let path = path.as_ref();
+        write_atomically(path, serde_json::to_vec(self)?)?;
+    }
+
+    /// The Git revision of the repository this index describes.
+    pub fn revision(&self) -> &str {
+        &self.revision
+    }
+
+    /// All manifests in this index, ordered by file name.
+    pub fn entries(&self) -> &[IndexEntry] {
+        &self.entries
+    }
+
+    /// The names of manifests by their aliases.
+    ///
+    /// If multiple manifests have the same alias, use the first manifest by name.
+    pub fn aliases(&self) -> HashMap<String, String> {
+        let mut aliases = HashMap::new();
+        for entry in &self.entries {
+            for alias in &entry.summary.aliases {
+                aliases
+                    .entry(alias.clone())
+                    .or_insert_with(|| entry.summary.name.clone());
+            }
+        }
+        aliases
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn build_and_reuse_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifests = dir.path().join("manifests");
+        std::fs::create_dir(&manifests).unwrap();
+        std::fs::copy(
+            "tests/manifests/ripgrep.toml",
+            manifests.join("ripgrep.toml"),
+        )
+        .unwrap();
+        let store = ManifestStore::open(manifests.clone());
+        let index = ManifestIndex::build(&store, "abc", None).unwrap();
+        assert_eq!(index.entries().len(), 1);
+        assert_eq!(index.entries()[0].summary.name, "ripgrep");
+        assert_eq!(index.aliases()["rg"], "ripgrep");
+
+        let file = dir.path().join("index").join("repo.json");
+        index.write_to_path(&file).unwrap();
+        let cached = ManifestIndex::read_from_path(&file).unwrap().unwrap();
+        assert_eq!(cached, index);
+
+        // Pretend ripgrep changed, without touching the file: The index at the same revision
+        // keeps the cached entry, but an index at a different revision reads the file again.
+        let mut stale = cached.clone();
+        stale.entries[0].summary.version = "0.1.0".to_string();
+        assert_eq!(
+            ManifestIndex::build(&store, "abc", Some(&stale))
+                .unwrap()
+                .entries()[0]
+                .summary
+                .version,
+            "0.1.0"
+        );
+        assert_eq!(
+            ManifestIndex::build(&store, "def", Some(&stale)).unwrap(),
+            ManifestIndex {
+                revision: "def".to_string(),
+                ..index
+            }
+        );
+
+        std::fs::write(&file, "not an index").unwrap();
+        assert!(ManifestIndex::read_from_path(&file).unwrap().is_none());
+    }
+}
@@ -0,0 +1,287 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Clone, fetch and inspect manifest repositories with libgit2.
+//!
+//! With the `native-git` feature homebins clones and fetches manifest repositories with libgit2
+//! instead of the `git` binary, and reports progress of fetches.  It also reads revisions and the
+//! history of manifests with libgit2.  libgit2 only supports HTTPS and
+//! local remotes here, because we build it without SSH support.  Unlike the `git` binary libgit2
+//! has no sparse checkouts, so native clones check out all files of manifest repositories.
+//!
+//! Native fetches authenticate with the token for the host of the remote, or Git's credential
+//! helpers, like fetches with the `git` binary, see [`super::credentials`].
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context, Error};
+use fehler::throws;
+use git2::{
+    AutotagOption, Commit, Cred, CredentialType, FetchOptions, Oid, RemoteCallbacks, Repository,
+    ResetType, Sort,
+};
+
+use super::credentials::{fetch_error, FetchError, GitToken, TOKEN_USER_NAME};
+
+/// How often to report progress of a fetch.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(3);
+
+/// The fetch depth which tells libgit2 to fetch all history of a shallow clone.
+const UNSHALLOW_DEPTH: i32 = i32::MAX;
+
+/// Resolve `reference` to a commit in `repo`.
+///
+/// Try a branch of the `homebins` remote first, and then a tag or a commit.
+fn resolve_reference<'r>(repo: &'r Repository, reference: &str) -> Result<Commit<'r>, Error> {
+    for candidate in [
+        format!("refs/remotes/homebins/{}", reference),
+        reference.to_string(),
+    ] {
+        if let Ok(commit) = repo
+            .revparse_single(&candidate)
+            .and_then(|object| object.peel_to_commit())
+        {
+            return Ok(commit);
+        }
+    }
+    Err(anyhow!("No branch, tag or commit {}", reference))
+}
+
+/// Fetch `refspec` from the `homebins` remote of `repo`, which points to `remote`.
+///
+/// Fetch with the given `depth` and all tags if `all_tags`, and authenticate with `token` if given, and with Git's
+/// credential helpers otherwise.  Report progress of slow fetches.
+#[throws]
+fn fetch(
+    repo: &Repository,
+    remote: &str,
+    refspec: &str,
+    depth: Option<i32>,
+    all_tags: bool,
+    token: Option<&GitToken>,
+) -> () {
+    let config = repo.config()?;
+    let mut attempted = false;
+    let mut callbacks = RemoteCallbacks::new();
+    callbacks.credentials(|url, username, allowed| {
+        // libgit2 asks again and again as long as the remote refuses credentials
+        if attempted || !allowed.contains(CredentialType::USER_PASS_PLAINTEXT) {
+            return Err(git2::Error::from_str("authentication required"));
+        }
+        attempted = true;
+        match token {
+            Some(token) => Cred::userpass_plaintext(TOKEN_USER_NAME, token.secret()),
+            None => Cred::credential_helper(&config, url, username),
+        }
+    });
+    let mut last_report = Instant::now();
+    callbacks.transfer_progress(|progress| {
+        if PROGRESS_INTERVAL <= last_report.elapsed() {
+            crate::estatus!(
+                "Fetching {}: {}/{} objects",
+                remote,
+                progress.received_objects(),
+                progress.total_objects()
+            );
+            last_report = Instant::now();
+        }
+        true
+    });
+    let mut options = FetchOptions::new();
+    options.remote_callbacks(callbacks);
+    if let Some(depth) = depth {
+        options.depth(depth);
+    }
+    if all_tags {
+        options.download_tags(AutotagOption::All);
+    }
+    repo.find_remote("homebins")?
+        .fetch(&[refspec], Some(&mut options), None)
+        .map_err(|error| {
+            let remote = remote.to_string();
+            let message = error.message().to_string();
+            match (error.code(), error.class()) {
+                (git2::ErrorCode::Auth, _) => FetchError::Authentication { remote, message },
+                (_, git2::ErrorClass::Net) => FetchError::Network { remote, message },
+                _ => fetch_error(&remote, &message),
+            }
+        })?;
+}
+
+/// Clone `remote` to `target_directory`, or fetch `remote` if `target_directory` exists.
+///
+/// Check out the `main` branch of `remote`, or the given branch, tag or commit `reference`.
+/// Only fetch the latest commit of `main`, but all history if pinned to a `reference`.
+///
+/// Authenticate with `token` if given, and with Git's credential helpers otherwise.
+#[throws]
+pub fn clone_repo(
+    remote: &str,
+    target_directory: &Path,
+    reference: Option<&str>,
+    token: Option<&GitToken>,
+) -> () {
+    let repo = if target_directory.is_dir() {
+        Repository::open(target_directory).with_context(|| {
+            format!(
+                "Directory {} not a Git repository",
+                target_directory.display()
+            )
+        })?
+    } else {
+        Repository::init(target_directory).with_context(|| {
+            format!(
+                "Failed to create git repository in {}",
+                target_directory.display(),
+            )
+        })?
+    };
+    if repo.find_remote("homebins").is_ok() {
+        repo.remote_set_url("homebins", remote)?;
+    } else {
+        repo.remote("homebins", remote)?;
+    }
+
+    // We don't know whether a reference is a branch, a tag, or a commit on any branch, so fetch
+    // all of them.
+    match reference {
+        None => fetch(
+            &repo,
+            remote,
+            "+refs/heads/main:refs/remotes/homebins/main",
+            Some(1),
+            false,
+            token,
+        )?,
+        Some(_) => fetch(
+            &repo,
+            remote,
+            "+refs/heads/*:refs/remotes/homebins/*",
+            Some(UNSHALLOW_DEPTH).filter(|_| repo.is_shallow()),
+            true,
+            token,
+        )?,
+    }
+
+    let commit = match reference {
+        None => repo
+            .find_reference("refs/remotes/homebins/main")
+            .and_then(|main| main.peel_to_commit())
+            .with_context(|| format!("No main branch in {}", remote))?,
+        Some(reference) => resolve_reference(&repo, reference)?,
+    };
+    repo.reset(commit.as_object(), ResetType::Hard, None)
+        .with_context(|| format!("Failed to check out {}", commit.id()))?;
+}
+
+/// Whether all objects in the Git repository at `working_copy` are intact.
+///
+/// Read every object, and resolve the commit `HEAD` points to.
+pub fn objects_intact(working_copy: &Path) -> bool {
+    let repo = match Repository::open(working_copy) {
+        Ok(repo) => repo,
+        Err(_) => return false,
+    };
+    let readable = repo
+        .odb()
+        .and_then(|odb| odb.foreach(|oid| odb.read(*oid).is_ok()))
+        .is_ok();
+    // A repository without commits yet has no HEAD to resolve
+    let head = match repo.head() {
+        Ok(head) => head.peel_to_commit().is_ok(),
+        Err(error) => error.code() == git2::ErrorCode::UnbornBranch,
+    };
+    readable && head
+}
+
+/// Open the Git repository at `working_copy`.
+#[throws]
+fn open(working_copy: &Path) -> Repository {
+    Repository::open(working_copy)
+        .with_context(|| format!("Directory {} not a Git repository", working_copy.display()))?
+}
+
+/// Get the commit `HEAD` of the Git repository at `working_copy` points to.
+#[throws]
+pub fn head_revision(working_copy: &Path) -> String {
+    open(working_copy)?
+        .head()
+        .and_then(|head| head.peel_to_commit())
+        .with_context(|| format!("Failed to get revision of {}", working_copy.display()))?
+        .id()
+        .to_string()
+}
+
+/// Count the commits on the `main` branch of the `homebins` remote which `HEAD` lacks.
+#[throws]
+pub fn commits_behind_main(working_copy: &Path) -> u64 {
+    let repo = open(working_copy)?;
+    let head = repo.head().and_then(|head| head.peel_to_commit());
+    let main = repo
+        .find_reference("refs/remotes/homebins/main")
+        .and_then(|main| main.peel_to_commit());
+    let (_, behind) = head
+        .and_then(|head| main.map(|main| (head, main)))
+        .and_then(|(head, main)| repo.graph_ahead_behind(head.id(), main.id()))
+        .with_context(|| {
+            format!(
+                "Failed to compare {} with main branch",
+                working_copy.display()
+            )
+        })?;
+    behind as u64
+}
+
+/// Fetch the full history of the shallow clone at `working_copy` from `remote`.
+///
+/// Authenticate with `token` if given, see [`clone_repo`].
+#[throws]
+pub fn unshallow(working_copy: &Path, remote: &str, token: Option<&GitToken>) -> () {
+    fetch(
+        &open(working_copy)?,
+        remote,
+        "+refs/heads/*:refs/remotes/homebins/*",
+        Some(UNSHALLOW_DEPTH),
+        false,
+        token,
+    )?;
+}
+
+/// Get the commit time of the last commit in `HEAD` which changed any of the given `paths`.
+///
+/// `paths` are relative to the root of `working_copy`.  Return the commit time in seconds
+/// since the epoch, or `None` if no commit touched any of `paths`.
+#[throws]
+pub fn last_changed_at(working_copy: &Path, paths: &[PathBuf]) -> Option<i64> {
+    let repo = open(working_copy)?;
+    let entry_id = |commit: &Commit<'_>, path: &Path| -> Option<Oid> {
+        commit
+            .tree()
+            .ok()
+            .and_then(|tree| tree.get_path(path).ok())
+            .map(|entry| entry.id())
+    };
+    let mut revwalk = repo.revwalk()?;
+    revwalk.set_sorting(Sort::TIME)?;
+    revwalk.push_head()?;
+    for oid in revwalk {
+        let commit = repo.find_commit(oid?)?;
+        // Like `git log` a commit changes a path if the path differs from all its parents.
+        let changed = paths.iter().any(|path| {
+            let id = entry_id(&commit, path);
+            if commit.parent_count() == 0 {
+                id.is_some()
+            } else {
+                commit.parents().all(|parent| entry_id(&parent, path) != id)
+            }
+        });
+        if changed {
+            return Some(commit.time().seconds());
+        }
+    }
+    None
+}
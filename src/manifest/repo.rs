@@ -6,25 +6,160 @@
 
 //! Manifest repositories.
 
+use std::collections::HashSet;
+use std::fs::File;
 use std::path::{Path, PathBuf};
+#[cfg(not(feature = "native-git"))]
 use std::process::{Command, Stdio};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use anyhow::{Context, Error};
-use fehler::throws;
+use anyhow::{anyhow, Context, Error};
+use colored::Colorize;
+use fehler::{throw, throws};
+use url::Url;
 
-use crate::manifest::ManifestStore;
+use super::credentials::GitToken;
+#[cfg(not(feature = "native-git"))]
+use super::credentials::{fetch_error, git_environment};
+use crate::checksum::{Algorithm, Hasher, Validate};
+use crate::manifest::{Checksums, ManifestIndex, ManifestStore, MANIFEST_EXTENSIONS};
+#[cfg(any(test, not(feature = "native-git")))]
 use crate::process::CommandExt;
+#[cfg(any(test, not(feature = "native-git")))]
 use crate::tools::git;
+use crate::tools::{download, extract, minisign_verify};
+use crate::trust::TrustedKey;
 
-/// A Git repository of manifests.
+#[cfg(feature = "native-git")]
+use super::native_git::{
+    clone_repo, commits_behind_main, head_revision, last_changed_at, objects_intact, unshallow,
+};
+
+/// The name of the file with checksums of all manifests in a repository.
+const MANIFEST_CHECKSUMS_FILE: &str = "MANIFESTS.sha256";
+
+/// The name of the minisign signature of the checksums file.
+const MANIFEST_SIGNATURE_FILE: &str = "MANIFESTS.sha256.minisig";
+
+/// The file in the Git directory which records the remote and the reference of a verified clone.
+const SOURCE_FILE: &str = "homebins-source";
+
+/// The file in extracted archives which records the URL and the SHA256 checksum of the archive.
+const ARCHIVE_SOURCE_FILE: &str = ".homebins-archive";
+
+/// Lock files which Git leaves behind in the Git directory if it's interrupted.
+const GIT_LOCK_FILES: &[&str] = &["index.lock", "HEAD.lock", "config.lock", "shallow.lock"];
+
+/// The kind of a manifest repository.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum RepoKind {
+    /// A clone of a Git repository.
+    Git,
+    /// A plain directory of manifests, without Git.
+    Directory,
+    /// An archive of a repository, downloaded over HTTP and extracted, without Git.
+    Archive,
+}
+
+/// A Git repository of manifests, a plain directory of manifests, or an archive of a repository.
 #[derive(Debug)]
 pub struct ManifestRepo {
     remote: String,
+    /// The branch, tag or commit this repository is pinned to, if any.
+    reference: Option<String>,
     working_copy: PathBuf,
+    signed_by: Option<TrustedKey>,
+    kind: RepoKind,
+    /// The token this repository was fetched with, if any.
+    token: Option<GitToken>,
+}
+
+/// Resolve `reference` to a commit in the Git repository at `target_directory`.
+///
+/// Try a branch of the `homebins` remote first, and then a tag or a commit.
+#[cfg(not(feature = "native-git"))]
+fn resolve_reference(target_directory: &Path, reference: &str) -> Result<String, Error> {
+    for candidate in [
+        format!("refs/remotes/homebins/{}", reference),
+        reference.to_string(),
+    ] {
+        let output = git(target_directory)
+            .args(["rev-parse", "--verify", "--quiet"])
+            .arg(format!("{}^{{commit}}", candidate))
+            .stderr(Stdio::null())
+            .output()?;
+        if output.status.success() {
+            return Ok(String::from_utf8_lossy(&output.stdout).trim().to_string());
+        }
+    }
+    Err(anyhow!("No branch, tag or commit {}", reference))
+}
+
+/// Whether the Git repository at `working_copy` is a shallow clone, without full history.
+fn is_shallow(working_copy: &Path) -> bool {
+    working_copy.join(".git").join("shallow").exists()
 }
 
+/// Run the Git `command` which fetches `remote`, with `token` if any.
+///
+/// Tell authentication failures from network failures, see [`fetch_error`].
+#[cfg(not(feature = "native-git"))]
 #[throws]
-fn clone_repo(remote: &str, target_directory: &Path) -> () {
+fn fetch(command: &mut Command, remote: &str, token: Option<&GitToken>) -> () {
+    let output = command
+        .envs(git_environment(token))
+        .stdout(Stdio::null())
+        .output()
+        .with_context(|| format!("Failed to run git to fetch {}", remote))?;
+    if !output.status.success() {
+        throw!(fetch_error(
+            remote,
+            &String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+}
+
+/// Restrict checkouts of the new Git repository at `working_copy` to manifests.
+///
+/// Check out only the `manifests` directory and files at the root of the repository, e.g. the
+/// checksums file.  Check out everything if Git doesn't support sparse checkouts.
+#[cfg(not(feature = "native-git"))]
+fn sparse_checkout_manifests(working_copy: &Path) {
+    let succeeds = |args: &[&str]| {
+        git(working_copy)
+            .args(args)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .call()
+            .is_ok_and(|status| status.success())
+    };
+    if !(succeeds(&["sparse-checkout", "init", "--cone"])
+        && succeeds(&["sparse-checkout", "set", "manifests"]))
+    {
+        crate::estatus!(
+            "Git doesn't support sparse checkouts, checking out all files of {}",
+            working_copy.display()
+        );
+    }
+}
+
+/// Clone `remote` to `target_directory`, or fetch `remote` if `target_directory` exists.
+///
+/// Check out the `main` branch of `remote`, or the given branch, tag or commit `reference`.
+///
+/// Only fetch the latest commit of `main`, and check out only manifests, to keep clones of
+/// repositories with long histories or many other files small.  Fetch all history if pinned to
+/// a `reference`, because it may refer to any commit.
+///
+/// Fetch with `token` if given, see [`git_environment`].
+#[cfg(not(feature = "native-git"))]
+#[throws]
+fn clone_repo(
+    remote: &str,
+    target_directory: &Path,
+    reference: Option<&str>,
+    token: Option<&GitToken>,
+) -> () {
     if target_directory.is_dir() {
         git(target_directory)
             .stdout(Stdio::null())
@@ -48,6 +183,7 @@ fn clone_repo(remote: &str, target_directory: &Path) -> () {
                     target_directory.display(),
                 )
             })?;
+        sparse_checkout_manifests(target_directory);
     }
 
     let remote_exists = git(&target_directory)
@@ -71,38 +207,1129 @@ fn clone_repo(remote: &str, target_directory: &Path) -> () {
         .arg(&remote)
         .checked_call()?;
 
-    git(target_directory)
-        .args(&["fetch", "--quiet", "homebins", "main"])
-        .checked_call()?;
+    match reference {
+        None => {
+            fetch(
+                git(target_directory)
+                    .args(["fetch", "--quiet", "--depth", "1", "homebins", "main"]),
+                remote,
+                token,
+            )?;
+            git(target_directory)
+                .args(["reset", "--quiet", "--hard", "homebins/main"])
+                .checked_call()?;
+        }
+        Some(reference) => {
+            // We don't know whether the reference is a branch, a tag, or a commit on any branch,
+            // so fetch all of them.
+            let mut fetch = git(target_directory);
+            fetch.args(["fetch", "--quiet", "--tags"]);
+            if is_shallow(target_directory) {
+                fetch.arg("--unshallow");
+            }
+            fetch
+                .arg("homebins")
+                .arg("+refs/heads/*:refs/remotes/homebins/*");
+            self::fetch(&mut fetch, remote, token)?;
+            let commit = resolve_reference(target_directory, reference)?;
+            git(target_directory)
+                .args(["reset", "--quiet", "--hard"])
+                .arg(commit)
+                .checked_call()?;
+        }
+    }
+}
 
-    git(target_directory)
-        .args(&["reset", "--quiet", "--hard", "homebins/main"])
-        .checked_call()?;
+/// Get the commit `HEAD` of the Git repository at `working_copy` points to.
+#[cfg(not(feature = "native-git"))]
+#[throws]
+fn head_revision(working_copy: &Path) -> String {
+    let output = git(working_copy)
+        .args(["rev-parse", "HEAD"])
+        .checked_output()
+        .with_context(|| format!("Failed to get revision of {}", working_copy.display()))?;
+    String::from_utf8_lossy(&output.stdout).trim().to_string()
+}
+
+/// Count the commits on the `main` branch of the `homebins` remote which `HEAD` lacks.
+#[cfg(not(feature = "native-git"))]
+#[throws]
+fn commits_behind_main(working_copy: &Path) -> u64 {
+    let output = git(working_copy)
+        .args(["rev-list", "--count", "HEAD..refs/remotes/homebins/main"])
+        .checked_output()
+        .with_context(|| {
+            format!(
+                "Failed to compare {} with main branch",
+                working_copy.display()
+            )
+        })?;
+    let count = String::from_utf8_lossy(&output.stdout);
+    count
+        .trim()
+        .parse()
+        .with_context(|| format!("Invalid commit count: {}", count.trim()))?
+}
+
+/// Fetch the full history of the shallow clone at `working_copy` from `remote`.
+///
+/// Fetch with `token` if given, see [`git_environment`].
+#[cfg(not(feature = "native-git"))]
+#[throws]
+fn unshallow(working_copy: &Path, remote: &str, token: Option<&GitToken>) -> () {
+    fetch(
+        git(working_copy).args(["fetch", "--quiet", "--unshallow", "homebins"]),
+        remote,
+        token,
+    )?;
+}
+
+/// Get the commit time of the last commit in `HEAD` which changed any of the given `paths`.
+///
+/// `paths` are relative to the root of `working_copy`.  Return the commit time in seconds
+/// since the epoch, or `None` if no commit touched any of `paths`.
+#[cfg(not(feature = "native-git"))]
+#[throws]
+fn last_changed_at(working_copy: &Path, paths: &[PathBuf]) -> Option<i64> {
+    let output = git(working_copy)
+        .args(["log", "-1", "--format=%ct", "--"])
+        .args(paths)
+        .checked_output()?;
+    let timestamp = String::from_utf8_lossy(&output.stdout);
+    let timestamp = timestamp.trim();
+    if timestamp.is_empty() {
+        None
+    } else {
+        Some(
+            timestamp
+                .parse::<i64>()
+                .with_context(|| format!("Invalid commit timestamp: {}", timestamp))?,
+        )
+    }
+}
+
+/// Whether all objects in the Git repository at `working_copy` are present and connected.
+#[cfg(not(feature = "native-git"))]
+fn objects_intact(working_copy: &Path) -> bool {
+    git(working_copy)
+        .args(["fsck", "--connectivity-only", "--no-progress"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .call()
+        .is_ok_and(|status| status.success())
+}
+
+/// Find out what's broken in the Git repository at `working_copy`, if anything.
+///
+/// Look for lock files of interrupted Git commands, and check that all objects are intact, to
+/// catch interrupted fetches and corrupt objects.
+fn find_damage(working_copy: &Path) -> Option<String> {
+    let git_dir = working_copy.join(".git");
+    if !git_dir.is_dir() {
+        return Some("not a Git repository".to_string());
+    }
+    if let Some(lock) = GIT_LOCK_FILES
+        .iter()
+        .find(|lock| git_dir.join(lock).exists())
+    {
+        return Some(format!("stale lock file {}", lock));
+    }
+    if objects_intact(working_copy) {
+        None
+    } else {
+        Some("missing or corrupt objects".to_string())
+    }
+}
+
+/// Find the root of the repository in the extracted archive at `directory`.
+///
+/// This is `directory` itself if it has a `manifests` directory, or otherwise its only
+/// subdirectory, if that has a `manifests` directory, as in archives of GitHub repositories.
+#[throws]
+fn archive_root(directory: &Path) -> PathBuf {
+    if directory.join("manifests").is_dir() {
+        return directory.to_path_buf();
+    }
+    let entries = std::fs::read_dir(directory)
+        .with_context(|| format!("Failed to read {}", directory.display()))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    match entries.as_slice() {
+        [root] if root.join("manifests").is_dir() => root.clone(),
+        _ => throw!(anyhow!("No manifests directory in archive")),
+    }
+}
+
+/// Describe the source of a clone of `remote` at `reference`, for the source file of the clone.
+fn source(remote: &str, reference: Option<&str>) -> String {
+    format!("{}\n{}\n", remote, reference.unwrap_or_default())
+}
+
+/// Parse a line of `sha256sum` output into the checksum and the file name.
+fn parse_checksum_line(line: &str) -> Option<(&str, &str)> {
+    let mut parts = line.splitn(2, ' ');
+    let checksum = parts.next()?;
+    // sha256sum separates checksum and name with a space and a space or asterisk, for text or
+    // binary mode respectively.
+    let name = parts.next()?.trim_start_matches(&[' ', '*'][..]);
+    if checksum.is_empty() || name.is_empty() {
+        None
+    } else {
+        Some((checksum, name))
+    }
+}
+
+/// Verify the manifests in `working_copy` against a checksums file, if the repository has one.
+///
+/// The checksums file must be in `sha256sum` format, with file names relative to the repository
+/// root, and must include all manifests in the `manifests/` subdirectory.
+#[throws]
+fn verify_manifest_checksums(working_copy: &Path) -> () {
+    let checksums_file = working_copy.join(MANIFEST_CHECKSUMS_FILE);
+    if checksums_file.is_file() {
+        let contents = std::fs::read_to_string(&checksums_file)
+            .with_context(|| format!("Failed to read {}", checksums_file.display()))?;
+        let mut verified = HashSet::new();
+        for (lineno, line) in contents.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let (checksum, name) = parse_checksum_line(line).ok_or_else(|| {
+                anyhow!(
+                    "{}:{}: Invalid checksum line: {:?}",
+                    checksums_file.display(),
+                    lineno + 1,
+                    line
+                )
+            })?;
+            let checksums = Checksums {
+                sha256: Some(hex::decode(checksum).with_context(|| {
+                    format!(
+                        "{}:{}: Invalid checksum {}",
+                        checksums_file.display(),
+                        lineno + 1,
+                        checksum
+                    )
+                })?),
+                ..Checksums::default()
+            };
+            let file = working_copy.join(name);
+            let mut source = File::open(&file).with_context(|| {
+                format!("Failed to open {} for checksum validation", file.display())
+            })?;
+            checksums
+                .validate(&mut source)
+                .with_context(|| format!("Failed to validate {}", file.display()))?;
+            verified.insert(file);
+        }
+
+        let manifest_dir = working_copy.join("manifests");
+        let mut paths = manifest_dir
+            .read_dir()
+            .with_context(|| format!("Failed to read {}", manifest_dir.display()))?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        // Sort to report the same missing manifest on every run
+        paths.sort();
+        for path in paths {
+            if !verified.contains(&path) {
+                throw!(anyhow!(
+                    "Manifest {} missing in {}",
+                    path.display(),
+                    checksums_file.display()
+                ));
+            }
+        }
+    }
+}
+
+/// Verify the signature of the checksums file in `working_copy` with `trusted_keys`.
+///
+/// If the repository signs its checksums file with minisign, return the trusted key which
+/// verified the signature, and fail if no trusted key verifies it.  Return `None` if the
+/// repository isn't signed, unless `require_signature` is set, in which case fail, so that
+/// removing the signature doesn't downgrade a signed repository to an unsigned one.
+#[throws]
+fn verify_manifest_signature(
+    working_copy: &Path,
+    trusted_keys: &[TrustedKey],
+    require_signature: bool,
+) -> Option<TrustedKey> {
+    let signature = working_copy.join(MANIFEST_SIGNATURE_FILE);
+    if signature.is_file() {
+        let checksums_file = working_copy.join(MANIFEST_CHECKSUMS_FILE);
+        if !checksums_file.is_file() {
+            throw!(anyhow!(
+                "{} without {}",
+                MANIFEST_SIGNATURE_FILE,
+                MANIFEST_CHECKSUMS_FILE
+            ));
+        }
+        let key = trusted_keys
+            .iter()
+            .find(|key| minisign_verify(&key.public_key, &checksums_file, &signature).is_ok())
+            .ok_or_else(|| {
+                anyhow!(
+                    "No trusted key verifies {}; trust the key of the repository with: homebins trust add <key> <name>",
+                    signature.display()
+                )
+            })?;
+        Some(key.clone())
+    } else if require_signature {
+        throw!(anyhow!(
+            "Missing signature {}; with trusted keys homebins only uses signed manifest repositories",
+            signature.display()
+        ));
+    } else {
+        None
+    }
 }
 
 impl ManifestRepo {
     /// Create a manifest repo cloned from the given remote.
     ///
-    /// If `target_directory` exists check that it is a Git repository and has a
+    /// If `target_directory` exists check that it is a Git repository and fetch the remote.
+    /// Check out the `main` branch, or the given branch, tag or commit `reference`.  Fetch only
+    /// the latest commit of `main`, and check out only the `manifests` directory and files at the
+    /// root of the repository, unless pinned to a `reference`.
+    ///
+    /// If cloning fails because the existing clone in `target_directory` is broken, see
+    /// [`find_damage`], remove the clone and clone again.
+    ///
+    /// If the repository contains a `MANIFESTS.sha256` file verify all manifests against the
+    /// checksums in this file after fetching, and fail if any manifest doesn't match or is not
+    /// listed in the checksums file.  If the repository also has a minisign signature of this
+    /// file in `MANIFESTS.sha256.minisig` verify the signature with `trusted_keys` first.  If
+    /// `require_signature` is set fail if the repository has no signature.
+    ///
+    /// Fetch HTTPS remotes with `token` if given, and Git credentials otherwise.  If fetching
+    /// fails the error tells authentication failures from network failures, see [`FetchError`].
+    ///
+    /// [`FetchError`]: super::FetchError
     #[throws]
-    pub fn cloned(remote: String, target_directory: PathBuf) -> ManifestRepo {
-        clone_repo(&remote, &target_directory).with_context(|| {
+    pub fn cloned(
+        remote: String,
+        target_directory: PathBuf,
+        reference: Option<String>,
+        trusted_keys: &[TrustedKey],
+        require_signature: bool,
+        token: Option<GitToken>,
+    ) -> ManifestRepo {
+        let reference_ref = reference.as_deref();
+        let cloned = clone_repo(&remote, &target_directory, reference_ref, token.as_ref()).or_else(
+            |error| match Some(&target_directory)
+                .filter(|directory| directory.exists())
+                .and_then(|directory| find_damage(directory))
+            {
+                Some(damage) => {
+                    crate::estatus!(
+                        "{}",
+                        format!(
+                            "Cloning {} again, because {} is broken: {}",
+                            remote,
+                            target_directory.display(),
+                            damage
+                        )
+                        .yellow()
+                    );
+                    std::fs::remove_dir_all(&target_directory).with_context(|| {
+                        format!("Failed to remove {}", target_directory.display())
+                    })?;
+                    clone_repo(&remote, &target_directory, reference_ref, token.as_ref())
+                }
+                None => Err(error),
+            },
+        );
+        cloned.with_context(|| {
             format!(
                 "Failed to clone {} to {}",
                 remote,
                 target_directory.display()
             )
         })?;
+        // Forget the source of the clone until we verified the new checkout, so that we never
+        // reuse a clone which failed verification, see ManifestRepo::fresh
+        let source_file = target_directory.join(".git").join(SOURCE_FILE);
+        if source_file.exists() {
+            std::fs::remove_file(&source_file)
+                .with_context(|| format!("Failed to remove {}", source_file.display()))?;
+        }
+        let signed_by =
+            verify_manifest_signature(&target_directory, trusted_keys, require_signature)
+                .and_then(|key| verify_manifest_checksums(&target_directory).map(|_| key))
+                .with_context(|| {
+                    format!(
+                        "Failed to verify manifests of {} in {}",
+                        remote,
+                        target_directory.display()
+                    )
+                })?;
+        std::fs::write(&source_file, source(&remote, reference_ref))
+            .with_context(|| format!("Failed to write {}", source_file.display()))?;
         ManifestRepo {
             remote,
+            reference,
             working_copy: target_directory,
+            signed_by,
+            kind: RepoKind::Git,
+            token,
         }
     }
 
+    /// Use the existing clone of `remote` in `target_directory` without fetching, if it's fresh.
+    ///
+    /// A clone is fresh if homebins cloned and verified it from `remote` at `reference`, see
+    /// [`ManifestRepo::cloned`], and last fetched it less than `ttl` ago.  Return `None` if the
+    /// clone isn't fresh.
+    ///
+    /// Verify the manifests of a fresh clone with `trusted_keys` again, like
+    /// [`ManifestRepo::cloned`], and fail if the clone changed since homebins fetched it.
+    #[throws]
+    pub fn fresh(
+        remote: String,
+        target_directory: PathBuf,
+        reference: Option<String>,
+        trusted_keys: &[TrustedKey],
+        require_signature: bool,
+        ttl: Duration,
+    ) -> Option<ManifestRepo> {
+        let git_dir = target_directory.join(".git");
+        let fetched = match std::fs::metadata(git_dir.join("FETCH_HEAD"))
+            .and_then(|metadata| metadata.modified())
+        {
+            Ok(fetched) => fetched,
+            Err(_) => return None,
+        };
+        let recent = fetched.elapsed().is_ok_and(|elapsed| elapsed < ttl);
+        let same_source = std::fs::read_to_string(git_dir.join(SOURCE_FILE))
+            .is_ok_and(|contents| contents == source(&remote, reference.as_deref()));
+        if recent && same_source {
+            let signed_by =
+                verify_manifest_signature(&target_directory, trusted_keys, require_signature)
+                    .and_then(|key| verify_manifest_checksums(&target_directory).map(|_| key))
+                    .with_context(|| {
+                        format!(
+                            "Failed to verify manifests of {} in {}",
+                            remote,
+                            target_directory.display()
+                        )
+                    })?;
+            Some(ManifestRepo {
+                remote,
+                reference,
+                working_copy: target_directory,
+                signed_by,
+                kind: RepoKind::Git,
+                token: None,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Create a manifest repo from the archive at `url`, extracted to `target_directory`.
+    ///
+    /// Download the archive, and fail if it doesn't match the `sha256` checksum, if given.  Take
+    /// the type of the archive from `extension`, e.g. `.tar.gz`.  Extract the archive, and use
+    /// the only directory in the archive as repository if the archive has no `manifests`
+    /// directory at its root.  Then replace `target_directory` with the repository.
+    ///
+    /// Verify manifests like [`ManifestRepo::cloned`] before replacing `target_directory`.
+    /// Download with `token`, if given.
+    #[throws]
+    pub fn archive(
+        url: String,
+        extension: &str,
+        target_directory: PathBuf,
+        sha256: Option<&str>,
+        trusted_keys: &[TrustedKey],
+        require_signature: bool,
+        token: Option<&GitToken>,
+    ) -> ManifestRepo {
+        let parent = target_directory
+            .parent()
+            .with_context(|| format!("{} has no parent directory", target_directory.display()))?;
+        let temp = tempfile::tempdir_in(parent)
+            .with_context(|| format!("Failed to create directory in {}", parent.display()))?;
+        let archive_name = format!("archive{}", extension);
+        let archive = temp.path().join(&archive_name);
+        let parsed = Url::parse(&url).with_context(|| format!("Invalid URL {}", url))?;
+        crate::estatus!("Downloading manifests from {}", url);
+        download(&parsed, &archive, token.map(GitToken::secret))
+            .with_context(|| format!("Failed to download {}", url))?;
+
+        let mut hasher = Hasher::new(&[Algorithm::Sha256]);
+        File::open(&archive)
+            .and_then(|mut source| hasher.read_from(&mut source))
+            .with_context(|| format!("Failed to read {}", archive.display()))?;
+        let checksum = hex::encode(&hasher.finalize()[0].1);
+        if let Some(expected) = sha256 {
+            if !checksum.eq_ignore_ascii_case(expected) {
+                throw!(anyhow!(
+                    "SHA256 checksum of {} is {}, expected {}",
+                    url,
+                    checksum,
+                    expected
+                ));
+            }
+        }
+
+        let contents = temp.path().join("contents");
+        std::fs::create_dir(&contents)?;
+        extract(&archive, &archive_name, &contents, 0)
+            .with_context(|| format!("Failed to extract {}", url))?;
+        let root = archive_root(&contents).with_context(|| format!("Invalid archive {}", url))?;
+        let signed_by = verify_manifest_signature(&root, trusted_keys, require_signature)
+            .and_then(|key| verify_manifest_checksums(&root).map(|_| key))
+            .with_context(|| format!("Failed to verify manifests of {}", url))?;
+        let source_file = root.join(ARCHIVE_SOURCE_FILE);
+        std::fs::write(&source_file, source(&url, Some(&checksum)))
+            .with_context(|| format!("Failed to write {}", source_file.display()))?;
+
+        if target_directory.exists() {
+            std::fs::remove_dir_all(&target_directory)
+                .with_context(|| format!("Failed to remove {}", target_directory.display()))?;
+        }
+        std::fs::rename(&root, &target_directory).with_context(|| {
+            format!(
+                "Failed to move manifests of {} to {}",
+                url,
+                target_directory.display()
+            )
+        })?;
+        ManifestRepo {
+            remote: url,
+            reference: sha256.map(|sha256| format!("sha256:{}", sha256)),
+            working_copy: target_directory,
+            signed_by,
+            kind: RepoKind::Archive,
+            token: None,
+        }
+    }
+
+    /// Use the existing archive of `url` extracted to `target_directory`, if it's fresh.
+    ///
+    /// An extracted archive is fresh if homebins downloaded and verified it from `url` less
+    /// than `ttl` ago, see [`ManifestRepo::archive`], and it matches the `sha256` checksum, if
+    /// given.  Return `None` if it isn't fresh.
+    ///
+    /// Verify the manifests of a fresh archive with `trusted_keys` again, like
+    /// [`ManifestRepo::archive`], and fail if the manifests changed since homebins extracted them.
+    #[throws]
+    pub fn fresh_archive(
+        url: String,
+        target_directory: PathBuf,
+        sha256: Option<&str>,
+        trusted_keys: &[TrustedKey],
+        require_signature: bool,
+        ttl: Duration,
+    ) -> Option<ManifestRepo> {
+        let source_file = target_directory.join(ARCHIVE_SOURCE_FILE);
+        let (fetched, contents) = match std::fs::metadata(&source_file)
+            .and_then(|metadata| metadata.modified())
+            .and_then(|fetched| Ok((fetched, std::fs::read_to_string(&source_file)?)))
+        {
+            Ok(source) => source,
+            Err(_) => return None,
+        };
+        let mut lines = contents.lines();
+        let same_source = lines.next() == Some(url.as_str())
+            && sha256.is_none_or(|sha256| {
+                lines
+                    .next()
+                    .is_some_and(|checksum| checksum.eq_ignore_ascii_case(sha256))
+            });
+        if same_source && fetched.elapsed().is_ok_and(|elapsed| elapsed < ttl) {
+            let signed_by =
+                verify_manifest_signature(&target_directory, trusted_keys, require_signature)
+                    .and_then(|key| verify_manifest_checksums(&target_directory).map(|_| key))
+                    .with_context(|| format!("Failed to verify manifests of {}", url))?;
+            Some(ManifestRepo {
+                remote: url,
+                reference: sha256.map(|sha256| format!("sha256:{}", sha256)),
+                working_copy: target_directory,
+                signed_by,
+                kind: RepoKind::Archive,
+                token: None,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Use the plain `directory` of manifests as a manifest repo.
+    ///
+    /// Use manifests in `directory` directly, without cloning, Git or checksums, e.g. for
+    /// private manifests in a dotfiles repository.  Fail if `directory` doesn't exist.
+    #[throws]
+    pub fn directory(directory: PathBuf) -> ManifestRepo {
+        if !directory.is_dir() {
+            throw!(anyhow!(
+                "Manifest directory {} does not exist",
+                directory.display()
+            ));
+        }
+        ManifestRepo {
+            remote: directory.display().to_string(),
+            reference: None,
+            working_copy: directory,
+            signed_by: None,
+            kind: RepoKind::Directory,
+            token: None,
+        }
+    }
+
+    /// When homebins last fetched the repository cloned or extracted to `working_copy`.
+    ///
+    /// Return `None` if homebins never fetched the repository.
+    pub fn fetched_at(working_copy: &Path) -> Option<SystemTime> {
+        [
+            working_copy.join(".git").join("FETCH_HEAD"),
+            working_copy.join(ARCHIVE_SOURCE_FILE),
+        ]
+        .iter()
+        .find_map(|file| {
+            std::fs::metadata(file)
+                .and_then(|metadata| metadata.modified())
+                .ok()
+        })
+    }
+
+    /// Whether this repository is a plain directory of manifests, without Git.
+    pub fn is_directory(&self) -> bool {
+        self.kind == RepoKind::Directory
+    }
+
+    /// The remote this repository was cloned from.
+    pub fn remote(&self) -> &str {
+        &self.remote
+    }
+
+    /// The branch, tag or commit this repository is pinned to, if any.
+    pub fn reference(&self) -> Option<&str> {
+        self.reference.as_deref()
+    }
+
+    /// How many commits the pinned reference of this repository is behind the `main` branch.
+    ///
+    /// Return `None` if this repository isn't pinned, or isn't a Git repository.
+    #[throws]
+    pub fn commits_behind(&self) -> Option<u64> {
+        if self.reference.is_none() || self.kind != RepoKind::Git {
+            return None;
+        }
+        Some(commits_behind_main(&self.working_copy)?)
+    }
+
+    /// The trusted key which verified the signature of this repository, if it's signed.
+    pub fn signed_by(&self) -> Option<&TrustedKey> {
+        self.signed_by.as_ref()
+    }
+
+    /// Get the time the manifest with the given `name` was last changed in this repository.
+    ///
+    /// This is the time of the last commit which touched the manifest file, i.e. usually the
+    /// time the current version of the manifest became available, or the modification time of
+    /// the manifest file in plain directories and archives.  Return `None` if the repository has
+    /// no manifest with the given `name`.
+    ///
+    /// Fetch the full history of shallow clones first, see [`ManifestRepo::cloned`].
+    #[throws]
+    pub fn manifest_changed_at(&self, name: &str) -> Option<SystemTime> {
+        if self.kind != RepoKind::Git {
+            return match self.store().manifest_file(name) {
+                Some(path) => Some(
+                    std::fs::metadata(&path)
+                        .and_then(|metadata| metadata.modified())
+                        .with_context(|| {
+                            format!("Failed to get modification time of {}", path.display())
+                        })?,
+                ),
+                None => None,
+            };
+        }
+        if is_shallow(&self.working_copy) {
+            unshallow(&self.working_copy, &self.remote, self.token.as_ref())
+                .with_context(|| format!("Failed to fetch history of {}", self.remote))?;
+        }
+        let paths: Vec<PathBuf> = MANIFEST_EXTENSIONS
+            .iter()
+            .map(|extension| Path::new("manifests").join(name).with_extension(extension))
+            .collect();
+        last_changed_at(&self.working_copy, &paths)
+            .with_context(|| format!("Failed to get history of manifest {}", name))?
+            .map(|seconds| UNIX_EPOCH + Duration::from_secs(seconds as u64))
+    }
+
+    /// Get the Git revision of this repository.
+    ///
+    /// Plain directories have no revision, so return an empty string for these.  For archives
+    /// return the SHA256 checksum of the archive.
+    #[throws]
+    pub fn revision(&self) -> String {
+        match self.kind {
+            RepoKind::Git => {}
+            RepoKind::Directory => return String::new(),
+            RepoKind::Archive => {
+                let source_file = self.working_copy.join(ARCHIVE_SOURCE_FILE);
+                let contents = std::fs::read_to_string(&source_file)
+                    .with_context(|| format!("Failed to read {}", source_file.display()))?;
+                return contents.lines().nth(1).unwrap_or_default().to_string();
+            }
+        }
+        head_revision(&self.working_copy)?
+    }
+
+    /// Get the index of the manifests in this repository, cached in the `index_file`.
+    ///
+    /// Update the index in `index_file` if the repository changed, see [`ManifestIndex`].
+    #[throws]
+    pub fn index(&self, index_file: &Path) -> ManifestIndex {
+        let previous = ManifestIndex::read_from_path(index_file)?;
+        let index = ManifestIndex::build(&self.store(), &self.revision()?, previous.as_ref())?;
+        if previous.as_ref() != Some(&index) {
+            index.write_to_path(index_file)?;
+        }
+        index
+    }
+
     /// Get the store this repository has cloned.
     ///
-    /// The store must be in the `manifests/` subdirectory of the repository.
+    /// The store must be in the `manifests/` subdirectory of Git repositories and archives;
+    /// plain directories are stores themselves.
     pub fn store(&self) -> ManifestStore {
-        ManifestStore::open(self.working_copy.join("manifests"))
+        if self.kind == RepoKind::Directory {
+            ManifestStore::open(self.working_copy.clone())
+        } else {
+            ManifestStore::open(self.working_copy.join("manifests"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::process::Stdio;
+
+    // sha256 of "foo\n"
+    const FOO_SHA256: &str = "b5bb9d8014a0f9b1d61e21e796d78dccdf1352f23cd32812f4850b878ae4944c";
+
+    fn working_copy_with_manifest() -> tempfile::TempDir {
+        let working_copy = tempfile::tempdir().unwrap();
+        std::fs::create_dir(working_copy.path().join("manifests")).unwrap();
+        std::fs::write(working_copy.path().join("manifests/foo.toml"), "foo\n").unwrap();
+        working_copy
+    }
+
+    #[test]
+    fn parse_checksum_lines() {
+        assert_eq!(
+            parse_checksum_line("abcd  manifests/foo.toml"),
+            Some(("abcd", "manifests/foo.toml"))
+        );
+        assert_eq!(
+            parse_checksum_line("abcd *manifests/foo.toml"),
+            Some(("abcd", "manifests/foo.toml"))
+        );
+        assert_eq!(parse_checksum_line("abcd"), None);
+    }
+
+    #[test]
+    fn verify_without_checksums_file() {
+        let working_copy = working_copy_with_manifest();
+        assert!(verify_manifest_checksums(working_copy.path()).is_ok());
+    }
+
+    #[test]
+    fn verify_matching_checksums() {
+        let working_copy = working_copy_with_manifest();
+        std::fs::write(
+            working_copy.path().join(MANIFEST_CHECKSUMS_FILE),
+            format!("{}  manifests/foo.toml\n", FOO_SHA256),
+        )
+        .unwrap();
+        assert!(verify_manifest_checksums(working_copy.path()).is_ok());
+    }
+
+    #[test]
+    fn verify_tampered_manifest() {
+        let working_copy = working_copy_with_manifest();
+        std::fs::write(
+            working_copy.path().join(MANIFEST_CHECKSUMS_FILE),
+            format!("{}  manifests/foo.toml\n", FOO_SHA256),
+        )
+        .unwrap();
+        std::fs::write(working_copy.path().join("manifests/foo.toml"), "bar\n").unwrap();
+        assert!(verify_manifest_checksums(working_copy.path()).is_err());
+    }
+
+    #[test]
+    fn verify_unlisted_manifest() {
+        let working_copy = working_copy_with_manifest();
+        std::fs::write(working_copy.path().join(MANIFEST_CHECKSUMS_FILE), "").unwrap();
+        let error = verify_manifest_checksums(working_copy.path()).unwrap_err();
+        assert!(error.to_string().contains("missing in"));
+    }
+
+    #[test]
+    fn verify_signature_without_trusted_keys() {
+        let working_copy = working_copy_with_manifest();
+        assert_eq!(
+            verify_manifest_signature(working_copy.path(), &[], false).unwrap(),
+            None
+        );
+        std::fs::write(
+            working_copy.path().join(MANIFEST_CHECKSUMS_FILE),
+            format!("{}  manifests/foo.toml\n", FOO_SHA256),
+        )
+        .unwrap();
+        std::fs::write(working_copy.path().join(MANIFEST_SIGNATURE_FILE), "").unwrap();
+        let error = verify_manifest_signature(working_copy.path(), &[], false).unwrap_err();
+        assert!(error.to_string().starts_with("No trusted key verifies"));
+    }
+
+    #[test]
+    fn verify_missing_signature_when_required() {
+        let working_copy = working_copy_with_manifest();
+        let error = verify_manifest_signature(working_copy.path(), &[], true).unwrap_err();
+        assert!(error.to_string().starts_with("Missing signature"));
+        // Removing the signature but keeping the checksums doesn't help either
+        std::fs::write(
+            working_copy.path().join(MANIFEST_CHECKSUMS_FILE),
+            format!("{}  manifests/foo.toml\n", FOO_SHA256),
+        )
+        .unwrap();
+        let error = verify_manifest_signature(working_copy.path(), &[], true).unwrap_err();
+        assert!(error.to_string().starts_with("Missing signature"));
+    }
+
+    /// Commit all files in the Git repository at `path`.
+    fn commit_all(path: &Path, message: &str) {
+        git(path).args(["add", "--all"]).checked_call().unwrap();
+        git(path)
+            .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+            .args(["commit", "--quiet", "-m", message])
+            .checked_call()
+            .unwrap();
+    }
+
+    /// Create a Git repository with a manifest on the `main` branch.
+    fn remote_with_manifest() -> tempfile::TempDir {
+        let remote = working_copy_with_manifest();
+        git(remote.path())
+            .args(["init", "--quiet"])
+            .checked_call()
+            .unwrap();
+        git(remote.path())
+            .args(["checkout", "--quiet", "-b", "main"])
+            .checked_call()
+            .unwrap();
+        commit_all(remote.path(), "Add foo");
+        remote
+    }
+
+    #[test]
+    fn reclone_broken_clone() {
+        let remote = remote_with_manifest();
+        let url = remote.path().display().to_string();
+        let clones = tempfile::tempdir().unwrap();
+        let target = clones.path().join("repo");
+
+        ManifestRepo::cloned(url.clone(), target.clone(), None, &[], false, None).unwrap();
+        assert_eq!(find_damage(&target), None);
+        std::fs::write(target.join(".git").join("index.lock"), "").unwrap();
+        assert_eq!(
+            find_damage(&target),
+            Some("stale lock file index.lock".to_string())
+        );
+        let repo = ManifestRepo::cloned(url, target.clone(), None, &[], false, None).unwrap();
+        assert_eq!(find_damage(&target), None);
+        assert!(repo.store().contains("foo"));
+        assert_eq!(
+            find_damage(clones.path()),
+            Some("not a Git repository".to_string())
+        );
+    }
+
+    #[test]
+    fn reuse_fresh_clone() {
+        let remote = remote_with_manifest();
+        let url = remote.path().display().to_string();
+        let clones = tempfile::tempdir().unwrap();
+        let target = clones.path().join("repo");
+        let hour = Duration::from_secs(3600);
+        let fresh = |url: &str, reference: Option<&str>, ttl| {
+            ManifestRepo::fresh(
+                url.to_string(),
+                target.clone(),
+                reference.map(str::to_string),
+                &[],
+                false,
+                ttl,
+            )
+            .unwrap()
+            .is_some()
+        };
+
+        assert!(!fresh(&url, None, hour));
+        ManifestRepo::cloned(url.clone(), target.clone(), None, &[], false, None).unwrap();
+        assert!(fresh(&url, None, hour));
+        assert!(!fresh(&url, None, Duration::from_secs(0)));
+        assert!(!fresh(&url, Some("main"), hour));
+        assert!(!fresh("https://example.com/other", None, hour));
+
+        // A clone which fails verification is never fresh
+        std::fs::write(remote.path().join(MANIFEST_CHECKSUMS_FILE), "").unwrap();
+        commit_all(remote.path(), "Add checksums");
+        assert!(ManifestRepo::cloned(url.clone(), target.clone(), None, &[], false, None).is_err());
+        assert!(!fresh(&url, None, hour));
+    }
+
+    #[test]
+    fn verify_fresh_clone_again() {
+        let remote = remote_with_manifest();
+        std::fs::write(
+            remote.path().join(MANIFEST_CHECKSUMS_FILE),
+            format!("{}  manifests/foo.toml\n", FOO_SHA256),
+        )
+        .unwrap();
+        commit_all(remote.path(), "Add checksums");
+        let url = remote.path().display().to_string();
+        let clones = tempfile::tempdir().unwrap();
+        let target = clones.path().join("repo");
+        let fresh = || {
+            ManifestRepo::fresh(
+                url.clone(),
+                target.clone(),
+                None,
+                &[],
+                false,
+                Duration::from_secs(3600),
+            )
+        };
+
+        ManifestRepo::cloned(url.clone(), target.clone(), None, &[], false, None).unwrap();
+        assert!(fresh().unwrap().is_some());
+
+        std::fs::write(target.join("manifests/foo.toml"), "bar\n").unwrap();
+        let error = fresh().unwrap_err();
+        assert!(format!("{:#}", error).starts_with("Failed to verify manifests of"));
+        std::fs::write(target.join("manifests/foo.toml"), "foo\n").unwrap();
+        assert!(fresh().unwrap().is_some());
+        // A clone verified without signature isn't fresh once homebins requires signatures
+        assert!(ManifestRepo::fresh(
+            url.clone(),
+            target.clone(),
+            None,
+            &[],
+            true,
+            Duration::from_secs(3600)
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn shallow_sparse_clone() {
+        let remote = remote_with_manifest();
+        std::fs::create_dir(remote.path().join("docs")).unwrap();
+        std::fs::write(remote.path().join("docs").join("README"), "docs").unwrap();
+        commit_all(remote.path(), "Add docs");
+        let url = remote.path().display().to_string();
+        let clones = tempfile::tempdir().unwrap();
+        let target = clones.path().join("repo");
+
+        let repo = ManifestRepo::cloned(url, target.clone(), None, &[], false, None).unwrap();
+        assert!(target.join("manifests").join("foo.toml").is_file());
+        // libgit2 has no sparse checkouts, and no shallow clones from local remotes
+        if cfg!(not(feature = "native-git")) {
+            assert!(!target.join("docs").exists());
+            assert!(is_shallow(&target));
+        }
+        // The age of manifests needs the full history
+        assert!(repo.manifest_changed_at("foo").unwrap().is_some());
+        assert!(!is_shallow(&target));
+    }
+
+    #[test]
+    fn pin_repo_to_reference() {
+        let remote = remote_with_manifest();
+        git(remote.path())
+            .args(["tag", "v1"])
+            .checked_call()
+            .unwrap();
+        std::fs::write(remote.path().join("manifests/bar.toml"), "bar\n").unwrap();
+        commit_all(remote.path(), "Add bar");
+        let url = remote.path().display().to_string();
+        let clones = tempfile::tempdir().unwrap();
+        let target = clones.path().join("repo");
+
+        let repo = ManifestRepo::cloned(
+            url.clone(),
+            target.clone(),
+            Some("v1".into()),
+            &[],
+            false,
+            None,
+        )
+        .unwrap();
+        assert_eq!(repo.reference(), Some("v1"));
+        assert!(repo.store().contains("foo"));
+        assert!(!repo.store().contains("bar"));
+        assert_eq!(repo.commits_behind().unwrap(), Some(1));
+        let tag = git(remote.path())
+            .args(["rev-parse", "v1^{commit}"])
+            .checked_output()
+            .unwrap();
+        assert_eq!(
+            repo.revision().unwrap(),
+            String::from_utf8_lossy(&tag.stdout).trim()
+        );
+
+        let repo =
+            ManifestRepo::cloned(url.clone(), target.clone(), None, &[], false, None).unwrap();
+        assert!(repo.store().contains("bar"));
+        assert_eq!(repo.commits_behind().unwrap(), None);
+
+        let error =
+            ManifestRepo::cloned(url, target, Some("v2".into()), &[], false, None).unwrap_err();
+        assert_eq!(
+            format!("{:#}", error).split(": ").last(),
+            Some("No branch, tag or commit v2")
+        );
+    }
+
+    #[test]
+    fn plain_directory_repo() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::copy(
+            "tests/manifests/ripgrep.toml",
+            dir.path().join("ripgrep.toml"),
+        )
+        .unwrap();
+        let repo = ManifestRepo::directory(dir.path().to_path_buf()).unwrap();
+        assert!(repo.is_directory());
+        assert_eq!(repo.revision().unwrap(), "");
+        assert!(repo.store().contains("ripgrep"));
+        assert_eq!(
+            repo.manifest_changed_at("ripgrep").unwrap(),
+            Some(
+                std::fs::metadata(dir.path().join("ripgrep.toml"))
+                    .unwrap()
+                    .modified()
+                    .unwrap()
+            )
+        );
+        assert_eq!(repo.manifest_changed_at("fd").unwrap(), None);
+        let index_dir = tempfile::tempdir().unwrap();
+        let index = repo.index(&index_dir.path().join("repo.json")).unwrap();
+        assert_eq!(index.entries()[0].summary.name, "ripgrep");
+        assert!(ManifestRepo::directory(dir.path().join("missing")).is_err());
+    }
+
+    #[test]
+    fn archive_repo() {
+        let source = tempfile::tempdir().unwrap();
+        let top = source.path().join("manifests-main");
+        std::fs::create_dir_all(top.join("manifests")).unwrap();
+        std::fs::write(top.join("manifests/foo.toml"), "foo\n").unwrap();
+        let archive = source.path().join("manifests.tar.gz");
+        let status = std::process::Command::new("tar")
+            .arg("-czf")
+            .arg(&archive)
+            .arg("-C")
+            .arg(source.path())
+            .arg("manifests-main")
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let url = Url::from_file_path(&archive).unwrap().to_string();
+        let mut hasher = Hasher::new(&[Algorithm::Sha256]);
+        hasher
+            .read_from(&mut File::open(&archive).unwrap())
+            .unwrap();
+        let checksum = hex::encode(&hasher.finalize()[0].1);
+
+        let repos = tempfile::tempdir().unwrap();
+        let target = repos.path().join("archive");
+        let error = ManifestRepo::archive(
+            url.clone(),
+            ".tar.gz",
+            target.clone(),
+            Some(FOO_SHA256),
+            &[],
+            false,
+            None,
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "SHA256 checksum of {} is {}, expected {}",
+                url, checksum, FOO_SHA256
+            )
+        );
+        assert!(!target.exists());
+
+        let repo = ManifestRepo::archive(
+            url.clone(),
+            ".tar.gz",
+            target.clone(),
+            Some(&checksum),
+            &[],
+            false,
+            None,
+        )
+        .unwrap();
+        assert!(!repo.is_directory());
+        assert_eq!(repo.revision().unwrap(), checksum);
+        assert!(target.join("manifests/foo.toml").is_file());
+        assert!(ManifestRepo::fetched_at(&target).is_some());
+        assert_eq!(repo.commits_behind().unwrap(), None);
+
+        let fresh = |sha256: Option<&str>, ttl| {
+            ManifestRepo::fresh_archive(url.clone(), target.clone(), sha256, &[], false, ttl)
+                .unwrap()
+        };
+        let minute = Duration::from_secs(60);
+        assert_eq!(
+            fresh(Some(&checksum), minute).unwrap().revision().unwrap(),
+            checksum
+        );
+        assert!(fresh(Some(FOO_SHA256), minute).is_none());
+        assert!(fresh(None, Duration::ZERO).is_none());
+    }
+
+    #[test]
+    fn manifest_changed_at_commit_time() {
+        let working_copy = working_copy_with_manifest();
+        git(working_copy.path())
+            .arg("init")
+            .stdout(Stdio::null())
+            .checked_call()
+            .unwrap();
+        git(working_copy.path())
+            .args(["add", "manifests/foo.toml"])
+            .checked_call()
+            .unwrap();
+        git(working_copy.path())
+            .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+            .args(["commit", "--quiet", "-m", "Add foo"])
+            .env("GIT_COMMITTER_DATE", "@1600000000 +0000")
+            .checked_call()
+            .unwrap();
+        let repo = ManifestRepo {
+            remote: "https://example.com".to_string(),
+            reference: None,
+            working_copy: working_copy.path().to_path_buf(),
+            signed_by: None,
+            kind: RepoKind::Git,
+            token: None,
+        };
+        assert_eq!(
+            repo.manifest_changed_at("foo").unwrap(),
+            Some(UNIX_EPOCH + Duration::from_secs(1_600_000_000))
+        );
+        assert_eq!(repo.manifest_changed_at("bar").unwrap(), None);
     }
 }
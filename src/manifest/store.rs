@@ -4,17 +4,115 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-use std::path::PathBuf;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
 use anyhow::{anyhow, Context, Error, Result};
-use fehler::throws;
+use colored::Colorize;
+use fehler::{throw, throws};
+use rayon::prelude::*;
+use tempfile::TempDir;
 
-use super::types::Manifest;
+use super::types::{Manifest, ManifestSummary, MANIFEST_EXTENSIONS};
+
+/// A directory of manifests in a store.
+#[derive(Debug)]
+struct StoreDir {
+    /// The name of the repository of this directory, if any.
+    repo: Option<String>,
+    path: PathBuf,
+    /// The Git revision of the repository of this directory, if known.
+    revision: Option<String>,
+}
+
+impl StoreDir {
+    /// Whether this directory has a manifest file for the manifest with the given `name`.
+    fn contains(&self, name: &str) -> bool {
+        MANIFEST_EXTENSIONS
+            .iter()
+            .any(|extension| self.path.join(name).with_extension(extension).is_file())
+    }
+
+    /// The manifest file for the manifest with the given `name` in this directory, if any.
+    ///
+    /// Fail if the manifest exists in several formats, e.g. as `.toml` and `.yaml` file, because
+    /// homebins can't tell which one is meant.
+    #[throws]
+    fn manifest_file(&self, name: &str) -> Option<PathBuf> {
+        let mut files = MANIFEST_EXTENSIONS
+            .iter()
+            .map(|extension| self.path.join(name).with_extension(extension))
+            .filter(|path| path.is_file());
+        let file = files.next();
+        if let (Some(file), Some(other)) = (&file, files.next()) {
+            throw!(ambiguous_manifest(file, &other));
+        }
+        file
+    }
+
+    /// All manifest files in this directory.
+    #[throws]
+    fn manifest_paths(&self) -> Vec<PathBuf> {
+        let mut paths = self
+            .path
+            .read_dir()
+            .with_context(|| format!("Failed to open manifest store at {}", self.path.display()))?
+            .map(|item| item.map(|entry| entry.path()))
+            .collect::<std::io::Result<Vec<_>>>()
+            .map_err(Error::new)?;
+        paths.retain(|path| {
+            path.extension()
+                .and_then(|extension| extension.to_str())
+                .is_some_and(|extension| MANIFEST_EXTENSIONS.contains(&extension))
+        });
+        paths.sort();
+        let mut stems = HashMap::new();
+        for path in &paths {
+            if let Some(other) = stems.insert(path.file_stem(), path) {
+                throw!(ambiguous_manifest(other, path));
+            }
+        }
+        paths
+    }
+}
+
+/// The error for a manifest which exists as `file` and as `other`, in different formats.
+fn ambiguous_manifest(file: &Path, other: &Path) -> Error {
+    anyhow!(
+        "Manifest {} exists as both {} and {}",
+        file.file_stem().unwrap_or_default().to_string_lossy(),
+        file.display(),
+        other.display()
+    )
+}
 
 /// A store of manifests.
+///
+/// A store has manifests of one directory, or combines the directories of several manifest
+/// repositories, see [`ManifestStore::combined`].
 #[derive(Debug)]
 pub struct ManifestStore {
-    base_dir: PathBuf,
+    /// The directories of manifests, in the order to search them.
+    dirs: Vec<StoreDir>,
+    /// The directories holding a snapshot of manifests, if this store is a snapshot.
+    ///
+    /// We keep them here to delete them when the store goes away.
+    _snapshot_dirs: Vec<TempDir>,
+    /// Manifests this store already read, by path.
+    ///
+    /// A single command often loads the same manifest many times, e.g. to resolve requirements
+    /// and check conflicts, so we parse every manifest file only once, and share it instead of
+    /// copying it.
+    cache: RefCell<HashMap<PathBuf, Rc<Manifest>>>,
+    /// The names of manifests by their aliases, if known.
+    aliases: RefCell<Option<HashMap<String, String>>>,
+    /// The repository to load a manifest from, by the name of the manifest.
+    ///
+    /// Without a repository for a manifest, load the manifest from the first directory which
+    /// has it.
+    chosen: RefCell<HashMap<String, String>>,
 }
 
 impl ManifestStore {
@@ -22,47 +120,422 @@ impl ManifestStore {
     ///
     /// Does not fail because this method doesn't attempt to access `base_dir` just yet.
     pub fn open(base_dir: PathBuf) -> ManifestStore {
-        ManifestStore { base_dir }
+        ManifestStore {
+            dirs: vec![StoreDir {
+                repo: None,
+                path: base_dir,
+                revision: None,
+            }],
+            _snapshot_dirs: Vec::new(),
+            cache: RefCell::new(HashMap::new()),
+            aliases: RefCell::new(None),
+            chosen: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Combine `stores`, by the names of their repositories, into a single store.
+    ///
+    /// The combined store searches all `stores` in the given order, and loads a manifest from
+    /// the first store which has it, unless told otherwise with
+    /// [`ManifestStore::choose_repository`].  It only knows aliases if all `stores` do.
+    pub fn combined(stores: Vec<(String, ManifestStore)>) -> ManifestStore {
+        let mut dirs = Vec::new();
+        let mut snapshot_dirs = Vec::new();
+        let mut aliases = Some(HashMap::new());
+        for (repo, store) in stores {
+            aliases = match (aliases, store.aliases.into_inner()) {
+                (Some(mut aliases), Some(more)) => {
+                    for (alias, name) in more {
+                        aliases.entry(alias).or_insert(name);
+                    }
+                    Some(aliases)
+                }
+                _ => None,
+            };
+            dirs.extend(store.dirs.into_iter().map(|dir| StoreDir {
+                repo: Some(repo.clone()),
+                ..dir
+            }));
+            snapshot_dirs.extend(store._snapshot_dirs);
+        }
+        ManifestStore {
+            dirs,
+            _snapshot_dirs: snapshot_dirs,
+            cache: RefCell::new(HashMap::new()),
+            aliases: RefCell::new(aliases),
+            chosen: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Take a snapshot of this store.
+    ///
+    /// Copy all manifests of this store to a temporary directory, and return a store for this
+    /// directory.  The snapshot doesn't change when the manifests of this store change, e.g.
+    /// because the underlying repo gets refreshed while a long update is running.
+    #[throws]
+    pub fn snapshot(&self) -> ManifestStore {
+        let snapshot_dir = tempfile::Builder::new()
+            .prefix("homebins-manifests")
+            .tempdir()
+            .with_context(|| "Failed to create directory for manifest snapshot")?;
+        let mut dirs = Vec::with_capacity(self.dirs.len());
+        for (index, dir) in self.dirs.iter().enumerate() {
+            let target_dir = snapshot_dir.path().join(index.to_string());
+            std::fs::create_dir(&target_dir)
+                .with_context(|| format!("Failed to create {}", target_dir.display()))?;
+            for entry in dir.path.read_dir().with_context(|| {
+                format!("Failed to open manifest store at {}", dir.path.display())
+            })? {
+                let source = entry?.path();
+                if source.is_file() {
+                    // Read the whole file at once to get a consistent copy of each manifest
+                    let contents = std::fs::read(&source)
+                        .with_context(|| format!("Failed to read {}", source.display()))?;
+                    let target = target_dir.join(source.file_name().unwrap());
+                    std::fs::write(&target, contents)
+                        .with_context(|| format!("Failed to write {}", target.display()))?;
+                }
+            }
+            dirs.push(StoreDir {
+                repo: dir.repo.clone(),
+                path: target_dir,
+                revision: dir.revision.clone(),
+            });
+        }
+        ManifestStore {
+            dirs,
+            _snapshot_dirs: vec![snapshot_dir],
+            cache: RefCell::new(HashMap::new()),
+            aliases: RefCell::new(self.aliases.borrow().clone()),
+            chosen: RefCell::new(self.chosen.borrow().clone()),
+        }
+    }
+
+    /// Set the names of manifests by their aliases, e.g. from an index of this store.
+    ///
+    /// Without aliases this store reads summaries of all manifests to resolve aliases, see
+    /// [`ManifestStore::resolve_alias`].
+    pub fn set_aliases(&mut self, aliases: HashMap<String, String>) {
+        self.aliases = RefCell::new(Some(aliases));
+    }
+
+    /// Set the Git revision of the repository of this store.
+    pub fn set_revision(&mut self, revision: String) {
+        for dir in &mut self.dirs {
+            dir.revision = Some(revision.clone());
+        }
+    }
+
+    /// The Git revision of the repository of this store, if known.
+    ///
+    /// If this store combines multiple repositories, list the revisions of all repositories as
+    /// `repo@revision`, separated by spaces.
+    pub fn revision(&self) -> Option<String> {
+        match self.dirs.as_slice() {
+            [dir] => dir.revision.clone(),
+            dirs => {
+                let revisions: Vec<String> = dirs
+                    .iter()
+                    .filter_map(|dir| match (&dir.repo, &dir.revision) {
+                        (Some(repo), Some(revision)) => Some(format!("{}@{}", repo, revision)),
+                        _ => None,
+                    })
+                    .collect();
+                Some(revisions.join(" ")).filter(|revisions| !revisions.is_empty())
+            }
+        }
+    }
+
+    /// The names of all repositories of this store, in the order this store searches them.
+    pub fn repositories(&self) -> Vec<&str> {
+        self.dirs
+            .iter()
+            .filter_map(|dir| dir.repo.as_deref())
+            .collect()
+    }
+
+    /// Whether the repository `repo` of this store has a manifest with the given `name`.
+    pub fn repository_contains(&self, repo: &str, name: &str) -> bool {
+        self.dirs
+            .iter()
+            .any(|dir| dir.repo.as_deref() == Some(repo) && dir.contains(name))
+    }
+
+    /// The repository this store loads the manifest with the given `name` from, if any.
+    ///
+    /// Return `None` if no repository has the manifest, or if this store has no repositories.
+    pub fn repository_of(&self, name: &str) -> Option<&str> {
+        let chosen = self.chosen.borrow().get(name).cloned();
+        self.dirs
+            .iter()
+            .filter(|dir| chosen.is_none() || dir.repo == chosen)
+            .find(|dir| dir.contains(name))
+            .and_then(|dir| dir.repo.as_deref())
+    }
+
+    /// The file this store loads the manifest with the given `name` from, if any.
+    ///
+    /// Ignore aliases.
+    pub fn manifest_file(&self, name: &str) -> Option<PathBuf> {
+        let chosen = self.chosen.borrow().get(name).cloned();
+        self.dirs
+            .iter()
+            .filter(|dir| chosen.is_none() || dir.repo == chosen)
+            .flat_map(|dir| {
+                MANIFEST_EXTENSIONS
+                    .iter()
+                    .map(move |extension| dir.path.join(name).with_extension(extension))
+            })
+            .find(|path| path.is_file())
+    }
+
+    /// Load the manifest with the given `name` from the repository `repo`.
+    ///
+    /// Ignore manifests of the same name in all other repositories.
+    pub fn choose_repository(&self, name: &str, repo: &str) {
+        self.chosen
+            .borrow_mut()
+            .insert(name.to_string(), repo.to_string());
+    }
+
+    /// Get the name of the manifest with the given `alias`, if any.
+    ///
+    /// Return `None` if no manifest has `alias`.  If multiple manifests have the same alias, use
+    /// the first manifest by name.
+    ///
+    /// Without aliases from an index, read summaries of all manifests once, and skip manifests
+    /// which fail to parse with a warning, so that one broken manifest doesn't break all lookups.
+    #[throws]
+    pub fn resolve_alias(&self, alias: &str) -> Option<String> {
+        if self.aliases.borrow().is_none() {
+            let mut aliases = HashMap::new();
+            // A store which doesn't exist has no manifests and thus no aliases either
+            let summaries = if self.dirs.iter().all(|dir| dir.path.is_dir()) {
+                self.summaries()?.collect()
+            } else {
+                Vec::new()
+            };
+            for summary in summaries {
+                let summary = match summary {
+                    Ok(summary) => summary,
+                    Err(error) => {
+                        crate::estatus!(
+                            "{}",
+                            format!("WARNING: Skipping manifest for aliases: {:#}", error)
+                                .yellow()
+                                .bold()
+                        );
+                        continue;
+                    }
+                };
+                for alias in &summary.aliases {
+                    aliases
+                        .entry(alias.clone())
+                        .or_insert_with(|| summary.name.clone());
+                }
+            }
+            self.aliases.replace(Some(aliases));
+        }
+        self.aliases
+            .borrow()
+            .as_ref()
+            .and_then(|aliases| aliases.get(alias).cloned())
+    }
+
+    /// Read the manifest at `path`, or get it from the cache if this store already read it.
+    fn read_manifest(&self, path: &Path) -> Result<Rc<Manifest>> {
+        if let Some(manifest) = self.cache.borrow().get(path) {
+            return Ok(Rc::clone(manifest));
+        }
+        let manifest = Rc::new(Manifest::read_from_path(path)?);
+        self.cache
+            .borrow_mut()
+            .insert(path.to_path_buf(), Rc::clone(&manifest));
+        Ok(manifest)
     }
 
     /// Load a manifest from this store.
     ///
+    /// Look for a TOML manifest first, then for YAML and JSON manifests, see
+    /// [`MANIFEST_EXTENSIONS`].  If the store has no manifest with the given `name` look for a
+    /// manifest which has `name` as alias, see [`ManifestStore::resolve_alias`].
+    ///
     /// Return the manifest if it exists or None if the store has no manifest with the given name.
     /// Fail if the store doesn't exist or isn't readable.
-    pub fn load_manifest<S: AsRef<str>>(&self, name: S) -> Result<Option<Manifest>> {
-        let manifest_file = self.base_dir.join(name.as_ref()).with_extension("toml");
-        if name.as_ref().is_empty()
-            || manifest_file.file_stem().unwrap_or_default() != name.as_ref()
+    pub fn load_manifest<S: AsRef<str>>(&self, name: S) -> Result<Option<Rc<Manifest>>> {
+        match self.load_manifest_file(name.as_ref())? {
+            Some(manifest) => Ok(Some(manifest)),
+            None => match self.resolve_alias(name.as_ref())? {
+                Some(name) => self.load_manifest_file(&name),
+                None => Ok(None),
+            },
+        }
+    }
+
+    /// Load the manifest file for the manifest with the given `name`, ignoring aliases.
+    fn load_manifest_file(&self, name: &str) -> Result<Option<Rc<Manifest>>> {
+        if name.is_empty()
+            || Path::new(name)
+                .with_extension("toml")
+                .file_stem()
+                .unwrap_or_default()
+                != name
         {
             // If the stem of the manifest isn't the name we got a name with a path separator
             // which we definitely don't accept.
-            Err(anyhow!("Invalid manifest name: {}", name.as_ref()))
-        } else {
-            Manifest::read_from_path(manifest_file)
-                .map(Some)
-                .or_else(|error| match error.downcast_ref::<std::io::Error>() {
-                    Some(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(None),
-                    _ => Err(error),
-                })
+            return Err(anyhow!("Invalid manifest name: {}", name));
+        }
+        let chosen = self.chosen.borrow().get(name).cloned();
+        for dir in &self.dirs {
+            if chosen.is_some() && dir.repo != chosen {
+                continue;
+            }
+            if let Some(file) = dir.manifest_file(name)? {
+                return self.read_manifest(&file).map(Some);
+            }
+        }
+        Ok(None)
+    }
+
+    /// Whether this store has a manifest with the given `name`.
+    ///
+    /// Only look for a manifest file, without reading it.
+    pub fn contains<S: AsRef<str>>(&self, name: S) -> bool {
+        let name = name.as_ref();
+        !name.is_empty() && !name.contains('/') && self.dirs.iter().any(|dir| dir.contains(name))
+    }
+
+    /// Load the manifests with the given `names` along with all their requirements.
+    ///
+    /// Return manifests in the order to install them in, i.e. every manifest comes after all
+    /// manifests it requires.  Fail if a manifest doesn't exist or if manifests require each
+    /// other in a cycle.
+    #[throws]
+    pub fn resolve<S: AsRef<str>>(&self, names: &[S]) -> Vec<Rc<Manifest>> {
+        let mut resolved = Vec::new();
+        let mut path = Vec::new();
+        for name in names {
+            self.resolve_manifest(name.as_ref(), &mut path, &mut resolved)?;
+        }
+        resolved
+    }
+
+    /// Resolve `name` and its requirements into `resolved`.
+    ///
+    /// `path` holds the chain of manifests which required `name`, to detect cycles.
+    #[throws]
+    fn resolve_manifest(
+        &self,
+        name: &str,
+        path: &mut Vec<String>,
+        resolved: &mut Vec<Rc<Manifest>>,
+    ) {
+        if resolved.iter().any(|m| m.info.name == name) {
+            return;
+        }
+        if let Some(start) = path.iter().position(|n| n == name) {
+            let mut cycle = path[start..].to_vec();
+            cycle.push(name.to_string());
+            throw!(anyhow!("Dependency cycle: {}", cycle.join(" -> ")));
         }
+        let manifest = self.load_manifest(name)?.ok_or_else(|| match path.last() {
+            Some(parent) => anyhow!("{} requires {}, which does not exist", parent, name),
+            None => anyhow!("Binary {} not found", name),
+        })?;
+        path.push(name.to_string());
+        for requirement in &manifest.info.requires {
+            self.resolve_manifest(requirement, path, resolved)?;
+        }
+        path.pop();
+        resolved.push(manifest);
+    }
+
+    /// All manifest files in this store, ordered by file name.
+    ///
+    /// Skip files without a manifest extension, see [`MANIFEST_EXTENSIONS`].  If multiple
+    /// directories have a manifest of the same name only take the manifest which this store
+    /// loads, see [`ManifestStore::load_manifest`].
+    #[throws]
+    pub fn manifest_paths(&self) -> Vec<PathBuf> {
+        let chosen = self.chosen.borrow();
+        let mut seen = HashSet::new();
+        let mut paths = Vec::new();
+        for dir in &self.dirs {
+            let mut dir_paths = dir.manifest_paths()?;
+            dir_paths.retain(|path| {
+                let name = path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .unwrap_or_default();
+                match chosen.get(name) {
+                    Some(repo) => dir.repo.as_ref() == Some(repo),
+                    None => !seen.contains(name),
+                }
+            });
+            seen.extend(dir_paths.iter().filter_map(|path| {
+                path.file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .map(String::from)
+            }));
+            paths.extend(dir_paths);
+        }
+        paths.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
+        paths
     }
 
     /// Iterate over all manifests in this store.
+    ///
+    /// Manifests are ordered by file name, i.e. by the name of the manifest, regardless of the
+    /// order the file system lists them in.  Skip files without a manifest extension, see
+    /// [`MANIFEST_EXTENSIONS`].
+    ///
+    /// Read all manifests which this store didn't read yet up front, in parallel.
     #[throws]
-    pub fn manifests(&self) -> impl Iterator<Item = Result<Manifest>> {
-        self.base_dir
-            .read_dir()
-            .with_context(|| {
-                format!(
-                    "Failed to open manifest store at {}",
-                    self.base_dir.display()
-                )
-            })?
-            .map(|item| match item {
-                Ok(entry) => Manifest::read_from_path(entry.path()),
-                Err(err) => Err(Error::new(err)),
+    pub fn manifests(&self) -> impl Iterator<Item = Result<Rc<Manifest>>> + '_ {
+        let paths = self.manifest_paths()?;
+        let unread: Vec<&PathBuf> = {
+            let cache = self.cache.borrow();
+            paths
+                .iter()
+                .filter(|path| !cache.contains_key(*path))
+                .collect()
+        };
+        let read: Vec<(PathBuf, Result<Manifest>)> = unread
+            .into_par_iter()
+            .map(|path| (path.clone(), Manifest::read_from_path(path)))
+            .collect();
+        let mut errors = HashMap::new();
+        {
+            let mut cache = self.cache.borrow_mut();
+            for (path, result) in read {
+                match result {
+                    Ok(manifest) => {
+                        cache.insert(path, Rc::new(manifest));
+                    }
+                    Err(error) => {
+                        errors.insert(path, error);
+                    }
+                }
+            }
+        }
+        paths
+            .into_iter()
+            .map(move |path| match errors.remove(&path) {
+                Some(error) => Err(error),
+                None => self.read_manifest(&path),
             })
     }
+
+    /// Iterate over summaries of all manifests in this store.
+    ///
+    /// Like [`ManifestStore::manifests`] but only read what's necessary to list and search
+    /// manifests, see [`ManifestSummary::read_from_path`].
+    #[throws]
+    pub fn summaries(&self) -> impl Iterator<Item = Result<ManifestSummary>> {
+        self.manifest_paths()?
+            .into_iter()
+            .map(ManifestSummary::read_from_path)
+    }
 }
 
 #[cfg(test)]
@@ -78,6 +551,56 @@ mod tests {
         assert_eq!(manifest.info.name, "ripgrep");
     }
 
+    #[test]
+    fn load_manifest_by_alias() {
+        let store = ManifestStore::open(Path::new("tests/manifests/").to_path_buf());
+        assert_eq!(
+            store.resolve_alias("rg").unwrap(),
+            Some("ripgrep".to_string())
+        );
+        assert_eq!(store.resolve_alias("ripgrep").unwrap(), None);
+        let manifest = store.load_manifest("rg").unwrap().unwrap();
+        assert_eq!(manifest.info.name, "ripgrep");
+
+        let mut store = ManifestStore::open(Path::new("tests/manifests/").to_path_buf());
+        let mut aliases = HashMap::new();
+        aliases.insert("format-shell".to_string(), "shfmt".to_string());
+        store.set_aliases(aliases);
+        let manifest = store.load_manifest("format-shell").unwrap().unwrap();
+        assert_eq!(manifest.info.name, "shfmt");
+        assert!(store.load_manifest("rg").unwrap().is_none());
+    }
+
+    #[test]
+    fn resolve_alias_skips_broken_manifests() {
+        let base_dir = tempfile::tempdir().unwrap();
+        std::fs::copy(
+            "tests/manifests/ripgrep.toml",
+            base_dir.path().join("ripgrep.toml"),
+        )
+        .unwrap();
+        std::fs::write(base_dir.path().join("broken.toml"), "[info\n").unwrap();
+        let store = ManifestStore::open(base_dir.path().to_path_buf());
+        assert_eq!(
+            store.resolve_alias("rg").unwrap(),
+            Some("ripgrep".to_string())
+        );
+    }
+
+    #[test]
+    fn snapshot_keeps_aliases() {
+        let mut store = ManifestStore::open(Path::new("tests/manifests/").to_path_buf());
+        let mut aliases = HashMap::new();
+        aliases.insert("format-shell".to_string(), "shfmt".to_string());
+        store.set_aliases(aliases);
+        let snapshot = store.snapshot().unwrap();
+        assert_eq!(
+            snapshot.resolve_alias("format-shell").unwrap(),
+            Some("shfmt".to_string())
+        );
+        assert_eq!(snapshot.resolve_alias("rg").unwrap(), None);
+    }
+
     #[test]
     fn load_empty_name() {
         let store = ManifestStore::open(Path::new("manifests/").to_path_buf());
@@ -103,4 +626,310 @@ mod tests {
         let store = ManifestStore::open(Path::new("manifests/").to_path_buf());
         assert!(store.load_manifest("non-existing").unwrap().is_none())
     }
+
+    #[test]
+    fn read_manifests_once() {
+        let base_dir = tempfile::tempdir().unwrap();
+        let path = base_dir.path().join("shfmt.toml");
+        std::fs::copy("tests/manifests/shfmt.toml", &path).unwrap();
+        let store = ManifestStore::open(base_dir.path().to_path_buf());
+        let manifest = store.load_manifest("shfmt").unwrap().unwrap();
+
+        std::fs::write(&path, "Not a manifest").unwrap();
+        assert_eq!(store.load_manifest("shfmt").unwrap().unwrap(), manifest);
+        assert_eq!(
+            store.manifests().unwrap().next().unwrap().unwrap(),
+            manifest
+        );
+        let fresh = ManifestStore::open(base_dir.path().to_path_buf());
+        assert!(fresh.load_manifest("shfmt").is_err());
+    }
+
+    #[test]
+    fn combine_stores() {
+        let main = tempfile::tempdir().unwrap();
+        let extra = tempfile::tempdir().unwrap();
+        std::fs::copy(
+            "tests/manifests/ripgrep.toml",
+            main.path().join("ripgrep.toml"),
+        )
+        .unwrap();
+        std::fs::write(
+            extra.path().join("ripgrep.toml"),
+            std::fs::read_to_string("tests/manifests/ripgrep.toml")
+                .unwrap()
+                .replacen("version = \"12.1.1\"", "version = \"13.0.0\"", 1),
+        )
+        .unwrap();
+        std::fs::copy(
+            "tests/manifests/shfmt.toml",
+            extra.path().join("shfmt.toml"),
+        )
+        .unwrap();
+        let mut main_store = ManifestStore::open(main.path().to_path_buf());
+        main_store.set_revision("abc".to_string());
+        let store = ManifestStore::combined(vec![
+            ("main".to_string(), main_store),
+            (
+                "extra".to_string(),
+                ManifestStore::open(extra.path().to_path_buf()),
+            ),
+        ]);
+        assert_eq!(store.repositories(), vec!["main", "extra"]);
+        assert_eq!(store.revision(), Some("main@abc".to_string()));
+        assert!(store.contains("shfmt"));
+        assert!(!store.repository_contains("main", "shfmt"));
+        let version = |name: &str| {
+            store
+                .load_manifest(name)
+                .unwrap()
+                .unwrap()
+                .info
+                .version
+                .to_string()
+        };
+        assert_eq!(version("ripgrep"), "12.1.1");
+        assert_eq!(version("shfmt"), "3.1.1");
+        assert_eq!(store.repository_of("ripgrep"), Some("main"));
+        assert_eq!(store.repository_of("shfmt"), Some("extra"));
+        assert_eq!(store.repository_of("fd"), None);
+        assert_eq!(
+            store.manifest_file("shfmt"),
+            Some(extra.path().join("shfmt.toml"))
+        );
+        assert_eq!(store.manifest_file("fd"), None);
+        let versions = |store: &ManifestStore| -> Vec<String> {
+            store
+                .manifests()
+                .unwrap()
+                .map(|m| m.unwrap().info.version.to_string())
+                .collect()
+        };
+        assert_eq!(versions(&store), vec!["12.1.1", "3.1.1"]);
+
+        store.choose_repository("ripgrep", "extra");
+        assert_eq!(version("ripgrep"), "13.0.0");
+        assert_eq!(store.repository_of("ripgrep"), Some("extra"));
+        assert_eq!(versions(&store), vec!["13.0.0", "3.1.1"]);
+        assert_eq!(
+            versions(&store.snapshot().unwrap()),
+            vec!["13.0.0", "3.1.1"]
+        );
+    }
+
+    /// Copy all test manifests `copies` times into a new directory.
+    fn many_manifests(copies: usize) -> tempfile::TempDir {
+        let base_dir = tempfile::tempdir().unwrap();
+        for entry in std::fs::read_dir("tests/manifests").unwrap() {
+            let source = entry.unwrap().path();
+            let name = source.file_stem().unwrap().to_str().unwrap().to_string();
+            for copy in 0..copies {
+                std::fs::copy(
+                    &source,
+                    base_dir.path().join(format!("{}-{:03}.toml", name, copy)),
+                )
+                .unwrap();
+            }
+        }
+        base_dir
+    }
+
+    #[test]
+    fn read_many_manifests_in_order() {
+        let base_dir = many_manifests(20);
+        std::fs::write(base_dir.path().join("hello-005.toml"), "Not a manifest").unwrap();
+        let store = ManifestStore::open(base_dir.path().to_path_buf());
+        let results: Vec<Result<Rc<Manifest>>> = store.manifests().unwrap().collect();
+        let paths = store.manifest_paths().unwrap();
+        assert_eq!(results.len(), paths.len());
+        for (path, result) in paths.iter().zip(&results) {
+            if path.ends_with("hello-005.toml") {
+                assert!(result.is_err());
+            } else {
+                assert_eq!(
+                    result.as_deref().unwrap(),
+                    &Manifest::read_from_path(path).unwrap()
+                );
+            }
+        }
+    }
+
+    /// Compare reading many manifests one by one and in parallel.
+    ///
+    /// Run with `cargo test --release -- --ignored --nocapture bench_read_many_manifests`.
+    #[test]
+    #[ignore]
+    fn bench_read_many_manifests() {
+        let base_dir = many_manifests(100);
+        let store = ManifestStore::open(base_dir.path().to_path_buf());
+        let paths = store.manifest_paths().unwrap();
+
+        let start = std::time::Instant::now();
+        for path in &paths {
+            Manifest::read_from_path(path).unwrap();
+        }
+        let sequential = start.elapsed();
+
+        let start = std::time::Instant::now();
+        assert_eq!(store.manifests().unwrap().count(), paths.len());
+        let parallel = start.elapsed();
+
+        println!(
+            "Read {} manifests: {:?} one by one, {:?} in parallel",
+            paths.len(),
+            sequential,
+            parallel
+        );
+    }
+
+    #[test]
+    fn contains_manifest() {
+        let store = ManifestStore::open(Path::new("tests/manifests/").to_path_buf());
+        assert!(store.contains("ripgrep"));
+        assert!(!store.contains("non-existing"));
+        assert!(!store.contains("manifests/ripgrep"));
+        assert!(!store.contains(""));
+    }
+
+    #[test]
+    fn load_yaml_manifests() {
+        let base_dir = tempfile::tempdir().unwrap();
+        let value: toml::Value =
+            toml::from_str(&std::fs::read_to_string("tests/manifests/shfmt.toml").unwrap())
+                .unwrap();
+        let yaml = serde_yaml::to_string(&value).unwrap();
+        std::fs::write(base_dir.path().join("shfmt.yaml"), &yaml).unwrap();
+        std::fs::write(base_dir.path().join("shfmt-yml.yml"), &yaml).unwrap();
+        std::fs::write(base_dir.path().join("README.md"), "Not a manifest").unwrap();
+        let store = ManifestStore::open(base_dir.path().to_path_buf());
+
+        assert_eq!(
+            *store.load_manifest("shfmt").unwrap().unwrap(),
+            Manifest::read_from_path("tests/manifests/shfmt.toml").unwrap()
+        );
+        assert!(store.load_manifest("shfmt-yml").unwrap().is_some());
+        assert_eq!(store.manifests().unwrap().count(), 2);
+
+        std::fs::write(base_dir.path().join("shfmt.toml"), "").unwrap();
+        let message = format!(
+            "Manifest shfmt exists as both {} and {}",
+            base_dir.path().join("shfmt.toml").display(),
+            base_dir.path().join("shfmt.yaml").display()
+        );
+        assert_eq!(
+            store.load_manifest("shfmt").unwrap_err().to_string(),
+            message
+        );
+        assert_eq!(store.manifest_paths().unwrap_err().to_string(), message);
+    }
+
+    #[test]
+    fn manifests_ordered_by_name() {
+        let store = ManifestStore::open(Path::new("tests/manifests/").to_path_buf());
+        let names: Vec<String> = store
+            .manifests()
+            .unwrap()
+            .map(|m| m.unwrap().info.name.clone())
+            .collect();
+        let mut sorted = names.clone();
+        sorted.sort();
+        assert_eq!(names, sorted);
+        assert!(1 < names.len(), "{:?}", names);
+    }
+
+    #[test]
+    fn summaries_of_manifests() {
+        let store = ManifestStore::open(Path::new("tests/manifests/").to_path_buf());
+        let summaries: Vec<ManifestSummary> =
+            store.summaries().unwrap().map(|s| s.unwrap()).collect();
+        let names: Vec<String> = store
+            .manifests()
+            .unwrap()
+            .map(|m| m.unwrap().info.name.clone())
+            .collect();
+        assert_eq!(
+            summaries.iter().map(|s| s.name.clone()).collect::<Vec<_>>(),
+            names
+        );
+        let ripgrep = summaries.iter().find(|s| s.name == "ripgrep").unwrap();
+        assert_eq!(
+            ripgrep.description.as_deref(),
+            Some("Recursively search directories for a regex pattern")
+        );
+        assert_eq!(ripgrep.keywords, vec!["search", "grep"]);
+    }
+
+    fn store_with_requirements(requirements: &[(&str, &[&str])]) -> (TempDir, ManifestStore) {
+        let base_dir = tempfile::tempdir().unwrap();
+        let template = std::fs::read_to_string("tests/manifests/shfmt.toml").unwrap();
+        for (name, requires) in requirements {
+            let requires: Vec<String> = requires.iter().map(|r| format!("{:?}", r)).collect();
+            let manifest = template.replacen(
+                "name = \"shfmt\"\n",
+                &format!(
+                    "name = \"{}\"\nrequires = [{}]\n",
+                    name,
+                    requires.join(", ")
+                ),
+                1,
+            );
+            std::fs::write(base_dir.path().join(format!("{}.toml", name)), manifest).unwrap();
+        }
+        let store = ManifestStore::open(base_dir.path().to_path_buf());
+        (base_dir, store)
+    }
+
+    #[test]
+    fn resolve_requirements_in_install_order() {
+        let (_dir, store) =
+            store_with_requirements(&[("app", &["lib", "tool"]), ("tool", &["lib"]), ("lib", &[])]);
+        let names: Vec<String> = store
+            .resolve(&["app"])
+            .unwrap()
+            .into_iter()
+            .map(|m| m.info.name.clone())
+            .collect();
+        assert_eq!(names, vec!["lib", "tool", "app"]);
+    }
+
+    #[test]
+    fn resolve_missing_requirement() {
+        let (_dir, store) = store_with_requirements(&[("app", &["lib"])]);
+        assert_eq!(
+            store.resolve(&["app"]).unwrap_err().to_string(),
+            "app requires lib, which does not exist"
+        );
+    }
+
+    #[test]
+    fn resolve_cycle() {
+        let (_dir, store) = store_with_requirements(&[("a", &["b"]), ("b", &["c"]), ("c", &["b"])]);
+        assert_eq!(
+            store.resolve(&["a"]).unwrap_err().to_string(),
+            "Dependency cycle: b -> c -> b"
+        );
+    }
+
+    #[test]
+    fn snapshot_is_isolated_from_store() {
+        let base_dir = tempfile::tempdir().unwrap();
+        std::fs::copy(
+            "tests/manifests/shfmt.toml",
+            base_dir.path().join("shfmt.toml"),
+        )
+        .unwrap();
+        let store = ManifestStore::open(base_dir.path().to_path_buf());
+        let snapshot = store.snapshot().unwrap();
+        std::fs::remove_file(base_dir.path().join("shfmt.toml")).unwrap();
+        std::fs::copy(
+            "tests/manifests/ripgrep.toml",
+            base_dir.path().join("ripgrep.toml"),
+        )
+        .unwrap();
+
+        assert!(store.load_manifest("shfmt").unwrap().is_none());
+        assert!(snapshot.load_manifest("ripgrep").unwrap().is_none());
+        let manifest = snapshot.load_manifest("shfmt").unwrap().unwrap();
+        assert_eq!(manifest.info.name, "shfmt");
+    }
 }
@@ -0,0 +1,273 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Placeholders in manifests.
+//!
+//! Manifests can use `{version}` in downloads, signatures, sources and names of files to refer to the
+//! version in `info.version`, and `{arch}` to refer to the architecture of the download.
+//!
+//! Manifests can also define their own variables in a `[vars]` table.  The values of these
+//! variables can use `{version}` and `{arch}` but not other variables from `[vars]`.
+
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Context, Error};
+use fehler::{throw, throws};
+use regex::{Captures, Regex};
+use toml::Value;
+
+/// Names of variables homebins defines for every manifest.
+const BUILTIN_VARIABLES: [&str; 2] = ["version", "arch"];
+
+fn placeholder() -> Regex {
+    Regex::new(r"\{([a-z_][a-z0-9_]*)\}").unwrap()
+}
+
+/// Expand all placeholders in `s` with `vars` from the manifest and builtin `variables`.
+///
+/// First replace all `vars` with their values, then expand builtin `variables` in the result.
+/// Fail if `s` refers to an unknown variable.
+#[throws]
+fn expand(s: &str, variables: &HashMap<&str, String>, vars: &HashMap<String, String>) -> String {
+    let placeholder = placeholder();
+    let with_vars = placeholder.replace_all(s, |caps: &Captures| match vars.get(&caps[1]) {
+        Some(value) => value.clone(),
+        None => caps[0].to_string(),
+    });
+    let mut unknown = None;
+    let expanded = placeholder.replace_all(&with_vars, |caps: &Captures| {
+        let name = &caps[1];
+        match variables.get(name) {
+            Some(value) => value.clone(),
+            None => {
+                unknown.get_or_insert_with(|| name.to_string());
+                caps[0].to_string()
+            }
+        }
+    });
+    if let Some(name) = unknown {
+        throw!(anyhow!("Unknown placeholder {{{}}} in {:?}", name, s));
+    }
+    expanded.into_owned()
+}
+
+/// Expand placeholders in the string at `key` in `table` if any.
+#[throws]
+fn expand_key(
+    table: &mut toml::value::Table,
+    key: &str,
+    variables: &HashMap<&str, String>,
+    vars: &HashMap<String, String>,
+) {
+    if let Some(Value::String(s)) = table.get_mut(key) {
+        *s = expand(s, variables, vars)?;
+    }
+}
+
+/// Get the user-defined variables from the `[vars]` table of a manifest `value`.
+///
+/// Fail if a variable isn't a string, has an invalid name or overrides a builtin variable.
+#[throws]
+fn user_vars(value: &Value) -> HashMap<String, String> {
+    let mut vars = HashMap::new();
+    if let Some(table) = value.get("vars") {
+        let table = table
+            .as_table()
+            .ok_or_else(|| anyhow!("vars must be a table"))?;
+        let name_pattern = Regex::new(r"\A[a-z_][a-z0-9_]*\z").unwrap();
+        for (name, value) in table {
+            if !name_pattern.is_match(name) {
+                throw!(anyhow!("Invalid variable name: {:?}", name));
+            }
+            if BUILTIN_VARIABLES.contains(&name.as_str()) {
+                throw!(anyhow!("Variable {} overrides builtin variable", name));
+            }
+            let value = value
+                .as_str()
+                .ok_or_else(|| anyhow!("Variable {} must be a string", name))?;
+            vars.insert(name.clone(), value.to_string());
+        }
+    }
+    vars
+}
+
+/// Expand placeholders in the manifest `value`.
+///
+/// Expand placeholders in `download`, `name`, `minisign.signature` and `checksums.sha256_url` of
+/// every install step, and in `source` and `name` of every file of an archive.  Leave the manifest alone if it's malformed, to let
+/// deserialization report a proper error.
+#[throws]
+pub fn expand_placeholders(value: &mut Value) {
+    let version = value
+        .get("info")
+        .and_then(|info| info.get("version"))
+        .and_then(|version| version.as_str())
+        .map(|version| version.to_string());
+    let vars = user_vars(value).with_context(|| "Invalid [vars]")?;
+    let mut variables = HashMap::new();
+    if let Some(version) = version {
+        variables.insert("version", version);
+    }
+    if let Some(Value::Array(downloads)) = value.get_mut("install") {
+        for (index, download) in downloads.iter_mut().enumerate() {
+            if let Value::Table(download) = download {
+                let mut variables = variables.clone();
+                if let Some(arch) = download.get("arch").and_then(|a| a.as_str()) {
+                    variables.insert("arch", arch.to_string());
+                }
+                let context = || format!("Failed to expand placeholders in install[{}]", index);
+                expand_key(download, "download", &variables, &vars).with_context(context)?;
+                expand_key(download, "name", &variables, &vars).with_context(context)?;
+                if let Some(Value::Table(minisign)) = download.get_mut("minisign") {
+                    expand_key(minisign, "signature", &variables, &vars).with_context(context)?;
+                }
+                if let Some(Value::Table(checksums)) = download.get_mut("checksums") {
+                    expand_key(checksums, "sha256_url", &variables, &vars).with_context(context)?;
+                }
+                if let Some(Value::Array(files)) = download.get_mut("files") {
+                    for file in files.iter_mut() {
+                        if let Value::Table(file) = file {
+                            expand_key(file, "source", &variables, &vars).with_context(context)?;
+                            expand_key(file, "name", &variables, &vars).with_context(context)?;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn expand_variables() {
+        let mut variables = HashMap::new();
+        variables.insert("version", "1.2.3".to_string());
+        variables.insert("arch", "x86_64".to_string());
+        let vars = HashMap::new();
+        assert_eq!(
+            expand("foo-{version}-{arch}/foo-{version}", &variables, &vars).unwrap(),
+            "foo-1.2.3-x86_64/foo-1.2.3"
+        );
+        assert_eq!(expand("foo", &variables, &vars).unwrap(), "foo");
+    }
+
+    #[test]
+    fn expand_unknown_variable() {
+        let variables = HashMap::new();
+        assert_eq!(
+            expand("foo-{arch}", &variables, &HashMap::new())
+                .unwrap_err()
+                .to_string(),
+            "Unknown placeholder {arch} in \"foo-{arch}\""
+        );
+    }
+
+    #[test]
+    fn expand_placeholders_in_manifest() {
+        let mut value: Value = toml::from_str(
+            r#"
+[info]
+version = "1.2.3"
+
+[[install]]
+download = "https://example.com/{version}/foo-{arch}.tar.gz"
+arch = "aarch64"
+files = [{ source = "foo-{version}/foo", name = "foo-{version}" }]
+
+[[install]]
+download = "https://example.com/{version}/foo-{arch}.tar.gz"
+"#,
+        )
+        .unwrap();
+        let error = expand_placeholders(&mut value).unwrap_err();
+        assert_eq!(
+            format!("{:#}", error),
+            "Failed to expand placeholders in install[1]: \
+             Unknown placeholder {arch} in \"https://example.com/{version}/foo-{arch}.tar.gz\""
+        );
+        let download = &value["install"][0];
+        assert_eq!(
+            download["download"].as_str(),
+            Some("https://example.com/1.2.3/foo-aarch64.tar.gz")
+        );
+        assert_eq!(
+            download["files"][0]["source"].as_str(),
+            Some("foo-1.2.3/foo")
+        );
+        assert_eq!(download["files"][0]["name"].as_str(), Some("foo-1.2.3"));
+    }
+
+    #[test]
+    fn expand_user_vars() {
+        let mut variables = HashMap::new();
+        variables.insert("version", "1.2.3".to_string());
+        let mut vars = HashMap::new();
+        vars.insert("release".to_string(), "foo-{version}-linux".to_string());
+        vars.insert("nested".to_string(), "{release}".to_string());
+        assert_eq!(
+            expand("{release}/foo", &variables, &vars).unwrap(),
+            "foo-1.2.3-linux/foo"
+        );
+        assert_eq!(
+            expand("{nested}", &variables, &vars)
+                .unwrap_err()
+                .to_string(),
+            "Unknown placeholder {release} in \"{nested}\""
+        );
+    }
+
+    #[test]
+    fn expand_vars_in_manifest() {
+        let mut value: Value = toml::from_str(
+            r#"
+[info]
+version = "1.2.3"
+
+[vars]
+release_name = "foo-{version}-{arch}-linux"
+
+[[install]]
+download = "https://example.com/{version}/{release_name}.tar.gz"
+arch = "x86_64"
+files = [{ source = "{release_name}/foo" }]
+"#,
+        )
+        .unwrap();
+        expand_placeholders(&mut value).unwrap();
+        let download = &value["install"][0];
+        assert_eq!(
+            download["download"].as_str(),
+            Some("https://example.com/1.2.3/foo-1.2.3-x86_64-linux.tar.gz")
+        );
+        assert_eq!(
+            download["files"][0]["source"].as_str(),
+            Some("foo-1.2.3-x86_64-linux/foo")
+        );
+    }
+
+    #[test]
+    fn reject_invalid_user_vars() {
+        let value: Value = toml::from_str("[vars]\nversion = \"1\"").unwrap();
+        assert_eq!(
+            user_vars(&value).unwrap_err().to_string(),
+            "Variable version overrides builtin variable"
+        );
+        let value: Value = toml::from_str("[vars]\nfoo = 1").unwrap();
+        assert_eq!(
+            user_vars(&value).unwrap_err().to_string(),
+            "Variable foo must be a string"
+        );
+        let value: Value = toml::from_str("[vars]\n\"foo-bar\" = \"1\"").unwrap();
+        assert_eq!(
+            user_vars(&value).unwrap_err().to_string(),
+            "Invalid variable name: \"foo-bar\""
+        );
+    }
+}
@@ -6,13 +6,38 @@
 
 //! Manifest types.
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Error, Result};
 use regex::Regex;
-use serde::{Deserialize, Deserializer};
-use std::path::Path;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::Digest;
+use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::fmt::{Display, Formatter};
+use std::path::{Component, Path};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use url::Url;
 use versions::Versioning;
 
+use super::template::expand_placeholders;
+
+fn serialize_display<T: Display, S: Serializer>(
+    value: &T,
+    s: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    s.collect_str(value)
+}
+
+fn serialize_optional_display<T: Display, S: Serializer>(
+    value: &Option<T>,
+    s: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    match value {
+        Some(value) => s.collect_str(value),
+        None => s.serialize_none(),
+    }
+}
+
 fn deserialize_versioning<'de, D>(d: D) -> std::result::Result<Versioning, D::Error>
 where
     D: Deserializer<'de>,
@@ -31,30 +56,263 @@ where
         .and_then(|s| spdx::Expression::parse(&s).map_err(serde::de::Error::custom))
 }
 
+/// The date of a release.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone)]
+pub struct ReleaseDate {
+    year: u16,
+    month: u8,
+    day: u8,
+}
+
+impl ReleaseDate {
+    /// The number of days between the Unix epoch and this date.
+    fn days_since_epoch(&self) -> i64 {
+        // See http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+        let month = i64::from(self.month);
+        let year = i64::from(self.year) - if month <= 2 { 1 } else { 0 };
+        let era = year.div_euclid(400);
+        let year_of_era = year - era * 400;
+        let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + i64::from(self.day) - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        era * 146_097 + day_of_era - 719_468
+    }
+
+    /// The date of the given number of `days` since the Unix epoch.
+    fn from_days_since_epoch(days: i64) -> ReleaseDate {
+        // See http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+        let days = days + 719_468;
+        let era = days.div_euclid(146_097);
+        let day_of_era = days - era * 146_097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let shifted_month = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+        let month = if shifted_month < 10 {
+            shifted_month + 3
+        } else {
+            shifted_month - 9
+        };
+        let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+        ReleaseDate {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+        }
+    }
+
+    /// The date of `time` in UTC.
+    ///
+    /// Times before the Unix epoch fall on the epoch.
+    pub fn from_system_time(time: SystemTime) -> ReleaseDate {
+        let seconds = time
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        ReleaseDate::from_days_since_epoch((seconds / 86400) as i64)
+    }
+
+    /// The start of this date in UTC as system time.
+    pub fn to_system_time(&self) -> SystemTime {
+        let seconds = self.days_since_epoch() * 86400;
+        if seconds < 0 {
+            UNIX_EPOCH - Duration::from_secs(seconds.unsigned_abs())
+        } else {
+            UNIX_EPOCH + Duration::from_secs(seconds.unsigned_abs())
+        }
+    }
+}
+
+impl Display for ReleaseDate {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+impl FromStr for ReleaseDate {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut parts = s.splitn(3, '-');
+        let year = parts.next().and_then(|y| y.parse::<u16>().ok());
+        let month = parts.next().and_then(|m| m.parse::<u8>().ok());
+        let day = parts.next().and_then(|d| d.parse::<u8>().ok());
+        match (year, month, day) {
+            (Some(year), Some(month), Some(day))
+                if (1..=12).contains(&month) && (1..=31).contains(&day) =>
+            {
+                Ok(ReleaseDate { year, month, day })
+            }
+            _ => Err(format!("Invalid release date: {:?}", s)),
+        }
+    }
+}
+
+fn deserialize_release_date<'de, D>(d: D) -> std::result::Result<Option<ReleaseDate>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    // Accept native TOML dates as well as strings
+    let date = match toml::Value::deserialize(d)? {
+        toml::Value::Datetime(datetime) => datetime.to_string(),
+        toml::Value::String(s) => s,
+        other => {
+            return Err(serde::de::Error::custom(format!(
+                "Invalid release date: {}",
+                other
+            )))
+        }
+    };
+    date.parse().map(Some).map_err(serde::de::Error::custom)
+}
+
 /// Information about the binary in this manifest.
-#[derive(Debug, PartialEq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct Info {
     /// The name of the binary.
     pub name: String,
+    /// Other names to find this binary by, e.g. the name of its executable.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
     /// The version of the binary this manifest describes.
-    #[serde(deserialize_with = "deserialize_versioning")]
+    #[serde(
+        deserialize_with = "deserialize_versioning",
+        serialize_with = "serialize_display"
+    )]
     pub version: Versioning,
     /// An URL for this binary, i.e. its website.
     pub url: String,
-    #[serde(deserialize_with = "deserialize_spdx", alias = "licence")]
+    /// A short description of this binary.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Keywords to find this binary by.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keywords: Vec<String>,
+    /// The website of this binary, if `url` is not the website.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub homepage: Option<String>,
+    /// The source repository of this binary, if `url` is not the repository.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repository: Option<String>,
+    #[serde(
+        deserialize_with = "deserialize_spdx",
+        serialize_with = "serialize_display",
+        alias = "licence"
+    )]
     /// The license of this binary.
     ///
     /// This is an SPDX expression describing the licenses this binary is distributed under.
     pub license: spdx::Expression,
+    /// When `version` was released.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_release_date",
+        serialize_with = "serialize_optional_display",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub released: Option<ReleaseDate>,
+    /// Names of other manifests this binary requires.
+    #[serde(default)]
+    pub requires: Vec<String>,
+    /// Names of other manifests this binary conflicts with.
+    #[serde(default)]
+    pub conflicts: Vec<String>,
+    /// Whether to install binaries with versioned names and link the unversioned names to them.
+    ///
+    /// If `None` follow the configuration.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub versioned_binaries: Option<bool>,
+    /// Whether this binary is deprecated, e.g. because upstream abandoned it.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub deprecated: bool,
+    /// The name of the manifest which replaces this binary.
+    ///
+    /// A binary with a replacement is deprecated, regardless of `deprecated`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replaced_by: Option<String>,
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+/// Describe the deprecation of a binary, if `deprecated` or `replaced_by` another binary.
+fn describe_deprecation(deprecated: bool, replaced_by: Option<&str>) -> Option<String> {
+    match replaced_by {
+        Some(replacement) => Some(format!("deprecated, replaced by {}", replacement)),
+        None if deprecated => Some("deprecated".to_string()),
+        None => None,
+    }
+}
+
+/// Hosts whose URLs point to source repositories.
+const REPOSITORY_HOSTS: [&str; 3] = ["github.com", "gitlab.com", "codeberg.org"];
+
+impl Info {
+    /// Describe whether this binary is deprecated and what replaces it.
+    ///
+    /// Return `None` if this binary is not deprecated.
+    pub fn deprecation(&self) -> Option<String> {
+        describe_deprecation(self.deprecated, self.replaced_by.as_deref())
+    }
+
+    /// The manifest to migrate installations of this binary to, if any.
+    ///
+    /// This is `replaced_by`, whether or not `deprecated` is set, because a replacement implies
+    /// deprecation.
+    pub fn migration_target(&self) -> Option<&str> {
+        self.replaced_by.as_deref()
+    }
+
+    /// The website of this binary.
+    ///
+    /// This is `homepage` if given, and `url` otherwise.
+    pub fn homepage_url(&self) -> &str {
+        self.homepage.as_deref().unwrap_or(&self.url)
+    }
+
+    /// The source repository of this binary, if known.
+    ///
+    /// This is `repository` if given, and otherwise `url` if it points to a known code hosting
+    /// service like GitHub.
+    pub fn repository_url(&self) -> Option<&str> {
+        self.repository.as_deref().or_else(|| {
+            Url::parse(&self.url)
+                .ok()
+                .filter(|url| {
+                    url.host_str()
+                        .is_some_and(|host| REPOSITORY_HOSTS.contains(&host))
+                })
+                .map(|_| self.url.as_str())
+        })
+    }
+}
+
+/// The output stream a binary prints its version to.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VersionStream {
+    /// Standard output.
+    #[default]
+    Stdout,
+    /// Standard error, e.g. for `java -version`.
+    Stderr,
+    /// Standard output followed by standard error.
+    Both,
 }
 
 /// How to check the version of a binary.
-#[derive(Debug, PartialEq, Eq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub struct VersionCheck {
     /// The arguments to pass to the binary to make it output its version.
     pub args: Vec<String>,
     /// A regular expression to extract the version from the binary invoked with `args`.
+    ///
+    /// The version is the `version` group if the pattern has one, otherwise the first group.
     pub pattern: String,
+    /// The output stream to extract the version from.
+    #[serde(default)]
+    pub stream: VersionStream,
 }
 
 impl VersionCheck {
@@ -62,17 +320,186 @@ impl VersionCheck {
     pub fn regex(&self) -> std::result::Result<Regex, regex::Error> {
         Regex::new(&self.pattern)
     }
+
+    /// Get the output of a version check from `output`, according to `stream`.
+    pub fn select_output(&self, output: &std::process::Output) -> Vec<u8> {
+        match self.stream {
+            VersionStream::Stdout => output.stdout.clone(),
+            VersionStream::Stderr => output.stderr.clone(),
+            VersionStream::Both => [output.stdout.as_slice(), output.stderr.as_slice()].concat(),
+        }
+    }
+
+    /// Extract the version from `text` with `pattern`.
+    ///
+    /// Prefer the `version` group of `pattern` over its first group.
+    pub fn extract_version<'a>(pattern: &Regex, text: &'a str) -> Option<&'a str> {
+        pattern
+            .captures(text)
+            .and_then(|c| c.name("version").or_else(|| c.get(1)))
+            .map(|m| m.as_str())
+    }
+}
+
+fn deserialize_binaries<'de, D>(d: D) -> std::result::Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    match OneOrMany::deserialize(d)? {
+        OneOrMany::One(binary) => Ok(vec![binary]),
+        OneOrMany::Many(binaries) if binaries.is_empty() => Err(serde::de::Error::custom(
+            "Binaries to discover must not be empty",
+        )),
+        OneOrMany::Many(binaries) => Ok(binaries),
+    }
+}
+
+/// Deserialize an optional list of binaries, see [`deserialize_binaries`].
+fn deserialize_some_binaries<'de, D>(d: D) -> std::result::Result<Option<Vec<String>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_binaries(d).map(Some)
+}
+
+/// Deserialize an optional relative path, see [`deserialize_relative_path`].
+fn deserialize_some_relative_path<'de, D>(d: D) -> std::result::Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_relative_path(d).map(Some)
+}
+
+/// How manifests describe [`Discover`].
+///
+/// Tell the kind of discovery by its keys, instead of trying one kind after another, so that
+/// invalid values fail instead of silently picking another kind, e.g. a file with invalid
+/// checksums.
+#[derive(Deserialize)]
+struct RawDiscover {
+    #[serde(default, deserialize_with = "deserialize_some_binaries")]
+    binary: Option<Vec<String>>,
+    version_check: Option<VersionCheck>,
+    #[serde(default, deserialize_with = "deserialize_some_relative_path")]
+    version_file: Option<String>,
+    pattern: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_some_relative_path")]
+    file: Option<String>,
+    checksums: Option<Checksums>,
+}
+
+impl TryFrom<RawDiscover> for Discover {
+    type Error = String;
+
+    fn try_from(raw: RawDiscover) -> std::result::Result<Self, Self::Error> {
+        match raw {
+            RawDiscover {
+                binary: Some(binary),
+                version_check: Some(version_check),
+                version_file: None,
+                pattern: None,
+                file: None,
+                checksums: None,
+            } => Ok(Discover::Binary {
+                binary,
+                version_check,
+            }),
+            RawDiscover {
+                binary: None,
+                version_check: None,
+                version_file: Some(version_file),
+                pattern: Some(pattern),
+                file: None,
+                checksums: None,
+            } => Ok(Discover::VersionFile {
+                version_file,
+                pattern,
+            }),
+            RawDiscover {
+                binary: None,
+                version_check: None,
+                version_file: None,
+                pattern: None,
+                file: Some(file),
+                checksums,
+            } => Ok(match checksums {
+                Some(checksums) => Discover::Checksum { file, checksums },
+                None => Discover::File { file },
+            }),
+            _ => Err(
+                "Discover needs either binary and version_check, version_file and pattern, \
+                 or file and optionally checksums"
+                    .to_string(),
+            ),
+        }
+    }
+}
+
+/// How to check whether a manifest is installed, and which version.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(untagged, try_from = "RawDiscover")]
+pub enum Discover {
+    /// Run a binary to check its version.
+    Binary {
+        /// The names of the binaries to look for.
+        ///
+        /// Just the file names in `$HOME/.local/bin`.  Manifests can give a single name or a list;
+        /// homebins checks the version of the first binary which exists, and considers the
+        /// manifest outdated if any binary is missing.
+        binary: Vec<String>,
+        /// How to check the version of this binary.
+        version_check: VersionCheck,
+    },
+    /// Read the version out of an installed file.
+    VersionFile {
+        /// The file to read, relative to `$XDG_DATA_HOME`.
+        version_file: String,
+        /// A regular expression to extract the version from the file, like
+        /// [`VersionCheck::pattern`].
+        pattern: String,
+    },
+    /// Compare the checksum of an installed file.
+    ///
+    /// If the checksum matches the installed version is the version of the manifest.
+    Checksum {
+        /// The file to check, relative to `$XDG_DATA_HOME`.
+        file: String,
+        /// The checksums of the file in the version of the manifest.
+        checksums: Checksums,
+    },
+    /// Check whether an installed file exists.
+    ///
+    /// If the file exists the installed version is the version of the manifest.
+    File {
+        /// The file to look for, relative to `$XDG_DATA_HOME`.
+        file: String,
+    },
 }
 
-/// How to check whether a binary exists.
-#[derive(Debug, PartialEq, Eq, Deserialize)]
-pub struct Discover {
-    /// The name of the binary to look for.
+impl Discover {
+    /// The name of the binary or the file this discovers.
     ///
-    /// Just the file name in `$HOME/.local/bin`.
-    pub binary: String,
-    /// How to check the version of this binary.
-    pub version_check: VersionCheck,
+    /// For multiple binaries this is the first binary.
+    pub fn name(&self) -> &str {
+        match self {
+            Discover::Binary { binary, .. } => &binary[0],
+            Discover::VersionFile { version_file, .. } => version_file,
+            Discover::Checksum { file, .. } | Discover::File { file } => file,
+        }
+    }
+}
+
+fn serialize_hex<S: Serializer>(
+    value: &Option<Vec<u8>>,
+    s: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    value.as_ref().map(hex::encode).serialize(s)
 }
 
 fn deserialize_hex<'de, D>(d: D) -> std::result::Result<Option<Vec<u8>>, D::Error>
@@ -86,20 +513,67 @@ where
 }
 
 /// Checksums for validation of downloads.
-#[derive(Debug, Default, PartialEq, Eq, Deserialize, Clone)]
+#[derive(Debug, Default, PartialEq, Eq, Deserialize, Serialize, Clone)]
 pub struct Checksums {
     /// A Blake2 checksum.
-    #[serde(deserialize_with = "deserialize_hex", default)]
+    #[serde(
+        deserialize_with = "deserialize_hex",
+        serialize_with = "serialize_hex",
+        skip_serializing_if = "Option::is_none",
+        default
+    )]
     pub b2: Option<Vec<u8>>,
     /// A SHA512 checksum.
-    #[serde(deserialize_with = "deserialize_hex", default)]
+    #[serde(
+        deserialize_with = "deserialize_hex",
+        serialize_with = "serialize_hex",
+        skip_serializing_if = "Option::is_none",
+        default
+    )]
     pub sha512: Option<Vec<u8>>,
+    /// A BLAKE3 checksum.
+    #[serde(
+        deserialize_with = "deserialize_hex",
+        serialize_with = "serialize_hex",
+        skip_serializing_if = "Option::is_none",
+        default
+    )]
+    pub b3: Option<Vec<u8>>,
+    /// A SHA3-256 checksum.
+    #[serde(
+        deserialize_with = "deserialize_hex",
+        serialize_with = "serialize_hex",
+        skip_serializing_if = "Option::is_none",
+        default
+    )]
+    pub sha3_256: Option<Vec<u8>>,
     /// A SHA256 checksum.
-    #[serde(deserialize_with = "deserialize_hex", default)]
+    #[serde(
+        deserialize_with = "deserialize_hex",
+        serialize_with = "serialize_hex",
+        skip_serializing_if = "Option::is_none",
+        default
+    )]
     pub sha256: Option<Vec<u8>>,
     /// A SHA1 checksum.
-    #[serde(deserialize_with = "deserialize_hex", default)]
+    #[serde(
+        deserialize_with = "deserialize_hex",
+        serialize_with = "serialize_hex",
+        skip_serializing_if = "Option::is_none",
+        default
+    )]
     pub sha1: Option<Vec<u8>>,
+    /// The URL of a file with SHA256 checksums, in the format of `sha256sum`.
+    ///
+    /// Homebins looks up the SHA256 checksum of a download in this file, if the manifest has no
+    /// explicit SHA256 checksum.
+    #[serde(
+        deserialize_with = "deserialize_optional_url",
+        serialize_with = "serialize_optional_display",
+        skip_serializing_if = "Option::is_none",
+        default
+    )]
+    pub sha256_url: Option<Url>,
 }
 
 impl Checksums {
@@ -110,23 +584,239 @@ impl Checksums {
             Checksums {
                 b2: None,
                 sha512: None,
+                b3: None,
+                sha3_256: None,
                 sha256: None,
                 sha1: None,
+                sha256_url: None,
             }
         )
     }
 }
 
+/// A minisign signature of a download.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Minisign {
+    /// The base64-encoded minisign public key to verify the signature with.
+    pub public_key: String,
+    /// The URL of the signature.
+    ///
+    /// If absent homebins looks for the signature at the URL of the download with `.minisig`
+    /// appended.
+    #[serde(
+        deserialize_with = "deserialize_optional_url",
+        serialize_with = "serialize_optional_display",
+        skip_serializing_if = "Option::is_none",
+        default
+    )]
+    pub signature: Option<Url>,
+}
+
 /// Known shells.
-#[derive(Debug, PartialEq, Eq, Deserialize, Copy, Clone)]
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize, Copy, Clone)]
 pub enum Shell {
     /// The Fish shell.
     #[serde(rename = "fish")]
     Fish,
 }
 
+/// Known CPU architectures.
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize, Copy, Clone)]
+pub enum Arch {
+    /// 64-bit x86.
+    #[serde(rename = "x86_64")]
+    X86_64,
+    /// 64-bit ARM.
+    #[serde(rename = "aarch64")]
+    Aarch64,
+    /// 32-bit ARMv7.
+    #[serde(rename = "armv7")]
+    Armv7,
+}
+
+impl Arch {
+    /// The architecture of the current host.
+    ///
+    /// Fail if homebins doesn't know the host architecture.
+    pub fn host() -> Result<Arch> {
+        match std::env::consts::ARCH {
+            "x86_64" => Ok(Arch::X86_64),
+            "aarch64" => Ok(Arch::Aarch64),
+            "arm" => Ok(Arch::Armv7),
+            other => Err(anyhow!("Unsupported host architecture: {}", other)),
+        }
+    }
+}
+
+impl FromStr for Arch {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "x86_64" => Ok(Arch::X86_64),
+            "aarch64" => Ok(Arch::Aarch64),
+            "armv7" => Ok(Arch::Armv7),
+            _ => Err(format!("Unknown architecture: {:?}", s)),
+        }
+    }
+}
+
+impl Display for Arch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Arch::X86_64 => write!(f, "x86_64"),
+            Arch::Aarch64 => write!(f, "aarch64"),
+            Arch::Armv7 => write!(f, "armv7"),
+        }
+    }
+}
+
+/// Known operating systems.
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize, Copy, Clone)]
+pub enum Os {
+    /// Linux.
+    #[serde(rename = "linux")]
+    Linux,
+    /// macOS.
+    #[serde(rename = "macos")]
+    MacOs,
+}
+
+impl Os {
+    /// The operating system of the current host.
+    ///
+    /// Fail if homebins doesn't know the host operating system.
+    pub fn host() -> Result<Os> {
+        match std::env::consts::OS {
+            "linux" => Ok(Os::Linux),
+            "macos" => Ok(Os::MacOs),
+            other => Err(anyhow!("Unsupported host operating system: {}", other)),
+        }
+    }
+}
+
+impl FromStr for Os {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "linux" => Ok(Os::Linux),
+            "macos" => Ok(Os::MacOs),
+            _ => Err(format!("Unknown operating system: {:?}", s)),
+        }
+    }
+}
+
+impl Display for Os {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Os::Linux => write!(f, "linux"),
+            Os::MacOs => write!(f, "macos"),
+        }
+    }
+}
+
+/// A platform to install binaries for.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct Platform {
+    /// The operating system.
+    pub os: Os,
+    /// The CPU architecture.
+    pub arch: Arch,
+}
+
+impl Platform {
+    /// The platform of the current host.
+    pub fn host() -> Result<Platform> {
+        Ok(Platform {
+            os: Os::host()?,
+            arch: Arch::host()?,
+        })
+    }
+}
+
+/// Known icon themes.
+#[derive(Debug, Default, PartialEq, Eq, Deserialize, Serialize, Copy, Clone)]
+pub enum IconTheme {
+    /// The hicolor theme, which all other icon themes inherit from.
+    #[default]
+    #[serde(rename = "hicolor")]
+    Hicolor,
+}
+
+impl Display for IconTheme {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IconTheme::Hicolor => write!(f, "hicolor"),
+        }
+    }
+}
+
+/// The size of an icon.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum IconSize {
+    /// A bitmap icon with the given width and height in pixels.
+    Fixed(u16),
+    /// A scalable vector icon.
+    Scalable,
+}
+
+impl Display for IconSize {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IconSize::Fixed(size) => write!(f, "{0}x{0}", size),
+            IconSize::Scalable => write!(f, "scalable"),
+        }
+    }
+}
+
+impl FromStr for IconSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if s == "scalable" {
+            Ok(IconSize::Scalable)
+        } else {
+            let mut parts = s.splitn(2, 'x');
+            let width = parts.next().and_then(|w| w.parse::<u16>().ok());
+            let height = parts.next().and_then(|h| h.parse::<u16>().ok());
+            match (width, height) {
+                (Some(width), Some(height)) if width == height => Ok(IconSize::Fixed(width)),
+                _ => Err(format!("Invalid icon size: {:?}", s)),
+            }
+        }
+    }
+}
+
+fn deserialize_icon_size<'de, D>(d: D) -> std::result::Result<IconSize, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    String::deserialize(d).and_then(|s| s.parse().map_err(serde::de::Error::custom))
+}
+
+fn deserialize_relative_path<'de, D>(d: D) -> std::result::Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    String::deserialize(d).and_then(|s| {
+        let is_relative = !s.is_empty()
+            && Path::new(&s)
+                .components()
+                .all(|c| matches!(c, Component::Normal(_)));
+        if is_relative {
+            Ok(s)
+        } else {
+            Err(serde::de::Error::custom(format!(
+                "Path must be relative and must not contain . or ..: {:?}",
+                s
+            )))
+        }
+    })
+}
+
 /// The kind of installation target.
-#[derive(Debug, PartialEq, Eq, Deserialize, Clone)]
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize, Clone)]
 #[serde(tag = "type")]
 pub enum Target {
     /// A binary to install to `$HOME/.local/bin` as executable.
@@ -144,27 +834,75 @@ pub enum Target {
     },
     /// A systemd user unit file.
     #[serde(rename = "systemd_user_unit")]
-    SystemdUserUnit,
+    SystemdUserUnit {
+        /// Whether to enable and start this unit after installation.
+        ///
+        /// If set homebins also disables and stops the unit before removing it.
+        #[serde(default)]
+        enable: bool,
+    },
     /// An tab completion helper for a shell.
     #[serde(rename = "completion")]
     Completion {
         /// The shell to install this completion file for.
         shell: Shell,
     },
+    /// A desktop entry to install to `$XDG_DATA_HOME/applications`.
+    #[serde(rename = "desktop_entry", alias = "desktop")]
+    DesktopEntry,
+    /// An application icon to install to `$XDG_DATA_HOME/icons/<theme>/<size>/apps`.
+    #[serde(rename = "icon")]
+    Icon {
+        /// The size of the icon, either `NxN` or `scalable`.
+        #[serde(
+            deserialize_with = "deserialize_icon_size",
+            serialize_with = "serialize_display"
+        )]
+        size: IconSize,
+        /// The icon theme to install to.
+        #[serde(default)]
+        theme: IconTheme,
+    },
+    /// A font to install to `$XDG_DATA_HOME/fonts`.
+    #[serde(rename = "font")]
+    Font,
+    /// A Texinfo page to install to `$XDG_DATA_HOME/info`.
+    #[serde(rename = "info_page", alias = "info")]
+    InfoPage,
+    /// A data file to install to a directory in `$XDG_DATA_HOME`.
+    #[serde(rename = "data")]
+    Data {
+        /// The directory to install the file to, relative to `$XDG_DATA_HOME`.
+        ///
+        /// Must be a relative path and must not contain `.` or `..`.
+        #[serde(deserialize_with = "deserialize_relative_path")]
+        path: String,
+    },
+    /// A license text to install to `$XDG_DATA_HOME/licenses/<name>`, where `<name>` is the name
+    /// of the manifest.
+    #[serde(rename = "license", alias = "licence")]
+    License,
 }
 
 /// A file to install to $HOME.
-#[derive(Debug, PartialEq, Eq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub struct InstallFile {
     /// The path of this file within the containing download.
     pub source: String,
     /// An explicit file name to install as.
     ///
     /// If absent use the file name of `source`.
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub name: Option<String>,
     /// The target to install the file as.
     #[serde(flatten)]
     pub target: Target,
+    /// Whether to skip this file with a warning if the download doesn't contain it.
+    ///
+    /// Use this for minor files which upstream may drop or rename between releases, like
+    /// completions or manpages.
+    #[serde(default)]
+    pub optional: bool,
 }
 
 fn deserialize_url<'de, D>(d: D) -> std::result::Result<Url, D::Error>
@@ -174,8 +912,15 @@ where
     String::deserialize(d).and_then(|s| Url::parse(&s).map_err(serde::de::Error::custom))
 }
 
+fn deserialize_optional_url<'de, D>(d: D) -> std::result::Result<Option<Url>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_url(d).map(Some)
+}
+
 /// What to install from a download.
-#[derive(Debug, PartialEq, Eq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 #[serde(untagged)]
 pub enum Install {
     /// Install the downloaded file directly as a single file.
@@ -183,6 +928,7 @@ pub enum Install {
         /// An explicit file name to install as.
         ///
         /// If absent use the file name of the download.
+        #[serde(skip_serializing_if = "Option::is_none")]
         name: Option<String>,
         /// The target to install the file as.
         #[serde(flatten)]
@@ -192,6 +938,12 @@ pub enum Install {
     FilesFromArchive {
         /// A list of files to install.
         files: Vec<InstallFile>,
+        /// The number of leading directories to strip from all paths in the archive.
+        ///
+        /// Like `--strip-components` of tar; with `strip_prefix = 1` sources needn't repeat
+        /// the versioned top-level directory of the archive.
+        #[serde(default)]
+        strip_prefix: usize,
     },
 }
 
@@ -208,8 +960,31 @@ where
     })
 }
 
+/// A command to run while installing or removing a manifest.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Hook {
+    /// The command and its arguments.
+    ///
+    /// Homebins looks up the command in the bin directory first.
+    #[serde(deserialize_with = "deserialize_command")]
+    pub command: Vec<String>,
+}
+
+fn deserialize_command<'de, D>(d: D) -> std::result::Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<String>::deserialize(d).and_then(|command| {
+        if command.is_empty() {
+            Err(serde::de::Error::custom("Empty command"))
+        } else {
+            Ok(command)
+        }
+    })
+}
+
 /// An extra file to remove when uninstalling.
-#[derive(Debug, PartialEq, Eq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub struct AdditionalFileToRemove {
     /// The name of the file to remove
     pub name: String,
@@ -219,43 +994,196 @@ pub struct AdditionalFileToRemove {
 }
 
 /// Instructions for manifest removal.
-#[derive(Default, Debug, PartialEq, Eq, Deserialize)]
+#[derive(Default, Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub struct Remove {
     /// A list of additional files to remove during manifest removal.
+    #[serde(default)]
     pub additional_files: Vec<AdditionalFileToRemove>,
+    /// Commands to run before removing any files of the manifest.
+    #[serde(default)]
+    pub pre: Vec<Hook>,
 }
 
 /// An installation definition.
 ///
 /// A URL to download, extract if required, and install to $HOME.
-#[derive(Debug, PartialEq, Eq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub struct InstallDownload {
     /// The URL to download from.
-    #[serde(deserialize_with = "deserialize_url")]
+    #[serde(
+        deserialize_with = "deserialize_url",
+        serialize_with = "serialize_display"
+    )]
     pub download: Url,
+    /// The file name to save the download under.
+    ///
+    /// If absent use the final segment of the path of `download`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filename: Option<String>,
+    /// The architecture this download is for.
+    ///
+    /// If absent the download applies to all architectures.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arch: Option<Arch>,
+    /// The operating system this download is for.
+    ///
+    /// If absent the download applies to all operating systems.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub os: Option<Os>,
     /// Checksums to verify the download with.
     #[serde(deserialize_with = "deserialize_and_validate_checksums")]
     pub checksums: Checksums,
+    /// A minisign signature to verify the download with, in addition to checksums.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub minisign: Option<Minisign>,
     /// Files to install from this download.
     #[serde(flatten)]
     pub install: Install,
+    /// Commands to run after installing all files of the manifest.
+    #[serde(default)]
+    pub post: Vec<Hook>,
 }
 
 impl InstallDownload {
-    /// The file name of the URL, that is, the final segment of the path of `download`.
-    pub fn filename(&self) -> &str {
-        self.download
-            .path_segments()
-            // TODO: Check this during manifest deserialization
-            .expect("Expected path segments in URL")
-            // If there's a path there's also a last segment
-            .last()
-            .unwrap()
+    /// The URL of the minisign signature of this download, if any.
+    pub fn minisign_signature(&self) -> Option<Url> {
+        self.minisign.as_ref().map(|minisign| {
+            minisign.signature.clone().unwrap_or_else(|| {
+                let mut url = self.download.clone();
+                url.set_path(&format!("{}.minisig", self.download.path()));
+                url
+            })
+        })
+    }
+
+    /// The file name of this download.
+    ///
+    /// Use the explicit `filename` if any, otherwise the final non-empty segment of the path of
+    /// `download`.  If the URL has no such segment, e.g. `https://example.com/download?id=42`,
+    /// derive a name from a hash of the whole URL, so that different downloads of a manifest never
+    /// end up in the same file.
+    pub fn filename(&self) -> Cow<'_, str> {
+        match &self.filename {
+            Some(filename) => Cow::Borrowed(filename),
+            None => match self
+                .download
+                .path_segments()
+                .and_then(|mut segments| segments.rfind(|s| !s.is_empty()))
+            {
+                Some(segment) => Cow::Borrowed(segment),
+                None => {
+                    let hash = sha2::Sha256::digest(self.download.as_str().as_bytes());
+                    Cow::Owned(format!("download-{}", &hex::encode(hash)[..16]))
+                }
+            },
+        }
+    }
+
+    /// Whether this download applies to the given `platform`.
+    pub fn applies_to(&self, platform: Platform) -> bool {
+        self.os.is_none_or(|os| os == platform.os)
+            && self.arch.is_none_or(|arch| arch == platform.arch)
+    }
+}
+
+fn default_version_pattern() -> String {
+    r"(\d+(?:\.\d+)+)".to_string()
+}
+
+/// Where to look for new upstream versions of a binary.
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize, Clone)]
+#[serde(tag = "provider", rename_all = "snake_case")]
+pub enum Upstream {
+    /// The latest release of a GitHub repository.
+    #[serde(rename = "github")]
+    GitHub {
+        /// The repository, as `owner/name`.
+        repo: String,
+    },
+    /// The latest release of a GitLab project on gitlab.com.
+    #[serde(rename = "gitlab")]
+    GitLab {
+        /// The project, as `namespace/name`.
+        project: String,
+    },
+    /// The newest entry of an RSS or Atom feed.
+    Feed {
+        /// The URL of the feed.
+        url: String,
+        /// A regular expression to extract the version from the title of the entry.
+        ///
+        /// Must have a single capturing group containing only the version number.
+        #[serde(default = "default_version_pattern")]
+        pattern: String,
+    },
+    /// The highest version on a web page.
+    Scrape {
+        /// The URL of the page.
+        url: String,
+        /// A regular expression to extract versions from the page.
+        ///
+        /// Must have a single capturing group containing only the version number.
+        pattern: String,
+    },
+    /// The highest version in the output of a command.
+    Command {
+        /// The command and its arguments.
+        command: Vec<String>,
+        /// A regular expression to extract versions from the output of the command.
+        ///
+        /// Must have a single capturing group containing only the version number.
+        #[serde(default = "default_version_pattern")]
+        pattern: String,
+    },
+}
+
+/// File extensions of manifests, in the order homebins looks for manifests.
+pub const MANIFEST_EXTENSIONS: [&str; 4] = ["toml", "yaml", "yml", "json"];
+
+/// Parse the `contents` of the manifest file at `path`.
+///
+/// Parse `.yaml` and `.yml` files as YAML, `.json` files as JSON and all other files as TOML, but
+/// always return a TOML value, to expand placeholders the same way regardless of the format.
+///
+/// TOML has no null, so treat keys with null values in YAML and JSON as absent.
+fn parse_manifest_value(path: &Path, contents: &str) -> Result<toml::Value> {
+    let value: serde_json::Value = match path.extension().and_then(|extension| extension.to_str()) {
+        Some("yaml") | Some("yml") => serde_yaml::from_str(contents)?,
+        Some("json") => serde_json::from_str(contents)?,
+        _ => return toml::from_str(contents).map_err(Error::new),
+    };
+    toml::Value::try_from(without_nulls(value)?).map_err(Error::new)
+}
+
+/// Remove all keys with null values from `value`, recursively.
+///
+/// Fail if a list contains null, because there's no sensible way to drop it.
+fn without_nulls(value: serde_json::Value) -> Result<serde_json::Value> {
+    use serde_json::Value;
+    match value {
+        Value::Object(map) => map
+            .into_iter()
+            .filter(|(_, value)| !value.is_null())
+            .map(|(key, value)| Ok((key, without_nulls(value)?)))
+            .collect::<Result<_>>()
+            .map(Value::Object),
+        Value::Array(items) => items
+            .into_iter()
+            .map(|item| {
+                if item.is_null() {
+                    Err(anyhow!("Lists must not contain null"))
+                } else {
+                    without_nulls(item)
+                }
+            })
+            .collect::<Result<_>>()
+            .map(Value::Array),
+        value => Ok(value),
     }
 }
 
 /// A manifest describing an installable binary.
-#[derive(Debug, PartialEq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct Manifest {
     /// Information about this binary.
     pub info: Info,
@@ -266,12 +1194,200 @@ pub struct Manifest {
     /// Extra files to remove upon uninstalling
     #[serde(default)]
     pub remove: Remove,
+    /// Where to look for new upstream versions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upstream: Option<Upstream>,
 }
 
 impl Manifest {
     /// Read a manifest from the file denoted by the given `path`.
+    ///
+    /// Read YAML manifests from files with a `.yaml` or `.yml` extension, JSON manifests from
+    /// files with a `.json` extension, and TOML manifests from all other files.  Expand
+    /// placeholders such as `{version}` in the manifest, see [`expand_placeholders`].
     pub fn read_from_path<P: AsRef<Path>>(path: P) -> Result<Manifest> {
-        toml::from_str(&std::fs::read_to_string(path.as_ref())?)
+        let contents = std::fs::read_to_string(path.as_ref())?;
+        parse_manifest_value(path.as_ref(), &contents)
+            .and_then(|mut value| {
+                expand_placeholders(&mut value)?;
+                value.try_into().map_err(Error::new)
+            })
+            .with_context(|| format!("File {} is no valid manifest", path.as_ref().display()))
+    }
+
+    /// Render the manifest in the file at `path` as homebins sees it on `platform`.
+    ///
+    /// Override the version of the manifest with `version` if given, then expand all
+    /// placeholders, drop `[vars]` and all downloads which don't apply to `platform`, and return
+    /// the result as TOML.  Fail if the manifest isn't valid or doesn't support `platform`.
+    pub fn render_from_path<P: AsRef<Path>>(
+        path: P,
+        platform: Platform,
+        version: Option<&str>,
+    ) -> Result<String> {
+        let contents = std::fs::read_to_string(path.as_ref())?;
+        let mut value = parse_manifest_value(path.as_ref(), &contents)
+            .with_context(|| format!("File {} is no valid manifest", path.as_ref().display()))?;
+        if let (Some(version), Some(info)) = (
+            version,
+            value.get_mut("info").and_then(|i| i.as_table_mut()),
+        ) {
+            info.insert(
+                "version".to_string(),
+                toml::Value::String(version.to_string()),
+            );
+        }
+        expand_placeholders(&mut value)
+            .and_then(|_| value.clone().try_into::<Manifest>().map_err(Error::new))
+            .with_context(|| format!("File {} is no valid manifest", path.as_ref().display()))?
+            .check_platform(platform)?;
+        if let Some(table) = value.as_table_mut() {
+            table.remove("vars");
+        }
+        if let Some(toml::Value::Array(downloads)) = value.get_mut("install") {
+            let (os, arch) = (platform.os.to_string(), platform.arch.to_string());
+            downloads.retain(|download| {
+                let applies = |key: &str, expected: &str| {
+                    download
+                        .get(key)
+                        .and_then(|v| v.as_str())
+                        .is_none_or(|v| v == expected)
+                };
+                applies("os", &os) && applies("arch", &arch)
+            });
+        }
+        // Render tables in the order of the README instead of alphabetically
+        let mut table = match value {
+            toml::Value::Table(table) => table,
+            _ => unreachable!("A valid manifest is always a table"),
+        };
+        let mut sections = Vec::with_capacity(table.len());
+        for key in &["info", "discover", "install", "remove", "upstream"] {
+            if let Some(value) = table.remove(*key) {
+                sections.push((key.to_string(), value));
+            }
+        }
+        sections.extend(table);
+        sections
+            .into_iter()
+            .map(|(key, value)| {
+                let mut section = toml::value::Table::new();
+                section.insert(key, value);
+                toml::to_string(&section)
+            })
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map(|sections| sections.join("\n"))
+            .map_err(Error::new)
+    }
+
+    /// All downloads of this manifest which apply to the given `platform`.
+    pub fn downloads(&self, platform: Platform) -> impl Iterator<Item = &InstallDownload> {
+        self.install.iter().filter(move |d| d.applies_to(platform))
+    }
+
+    /// Check that this manifest supports the given `platform`.
+    ///
+    /// Fail if the manifest has downloads but none of these applies to `platform`.
+    pub fn check_platform(&self, platform: Platform) -> Result<()> {
+        if self.install.is_empty() || self.downloads(platform).next().is_some() {
+            return Ok(());
+        }
+        let for_os: Vec<&InstallDownload> = self
+            .install
+            .iter()
+            .filter(|d| d.os.is_none_or(|os| os == platform.os))
+            .collect();
+        let (what, supported) = if for_os.is_empty() {
+            (
+                format!("operating system {}", platform.os),
+                self.install
+                    .iter()
+                    .filter_map(|d| d.os.map(|os| os.to_string()))
+                    .collect::<Vec<_>>(),
+            )
+        } else {
+            (
+                format!("architecture {}", platform.arch),
+                for_os
+                    .iter()
+                    .filter_map(|d| d.arch.map(|arch| arch.to_string()))
+                    .collect::<Vec<_>>(),
+            )
+        };
+        let mut unique = Vec::with_capacity(supported.len());
+        for item in supported {
+            if !unique.contains(&item) {
+                unique.push(item);
+            }
+        }
+        Err(anyhow!(
+            "{} has no download for {}, only for {}",
+            self.info.name,
+            what,
+            unique.join(", ")
+        ))
+    }
+}
+
+/// A summary of a manifest, for listing and searching manifests.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ManifestSummary {
+    /// The name of the binary.
+    pub name: String,
+    /// Other names of the binary.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+    /// The version of the binary.
+    pub version: String,
+    /// An URL for the binary, i.e. its website.
+    pub url: String,
+    /// The website of the binary, if `url` is not the website.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub homepage: Option<String>,
+    /// The license of the binary, as SPDX expression.
+    #[serde(alias = "licence")]
+    pub license: String,
+    /// A short description of the binary.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Keywords to find the binary by.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keywords: Vec<String>,
+    /// Whether the binary is deprecated.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub deprecated: bool,
+    /// The name of the manifest which replaces the binary.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replaced_by: Option<String>,
+}
+
+impl ManifestSummary {
+    /// Describe whether the binary is deprecated, see [`Info::deprecation`].
+    pub fn deprecation(&self) -> Option<String> {
+        describe_deprecation(self.deprecated, self.replaced_by.as_deref())
+    }
+
+    /// The website of the binary, see [`Info::homepage_url`].
+    pub fn homepage_url(&self) -> &str {
+        self.homepage.as_deref().unwrap_or(&self.url)
+    }
+
+    /// Read the summary of the manifest in the file denoted by `path`.
+    ///
+    /// Only deserialize the fields of `[info]` this summary has, and don't expand placeholders,
+    /// which makes this a lot faster than [`Manifest::read_from_path`].  Consequently this
+    /// doesn't validate the manifest.
+    pub fn read_from_path<P: AsRef<Path>>(path: P) -> Result<ManifestSummary> {
+        let contents = std::fs::read_to_string(path.as_ref())?;
+        parse_manifest_value(path.as_ref(), &contents)
+            .and_then(|value| {
+                value
+                    .get("info")
+                    .cloned()
+                    .ok_or_else(|| anyhow!("Missing [info]"))?
+                    .try_into()
+                    .map_err(Error::new)
+            })
             .with_context(|| format!("File {} is no valid manifest", path.as_ref().display()))
     }
 }
@@ -281,65 +1397,179 @@ mod tests {
     use super::*;
     use pretty_assertions::assert_eq;
 
+    #[test]
+    fn homepage_and_repository_urls() {
+        let mut info = Manifest::read_from_path("tests/manifests/ripgrep.toml")
+            .unwrap()
+            .info;
+        assert_eq!(info.homepage_url(), "https://github.com/BurntSushi/ripgrep");
+        assert_eq!(
+            info.repository_url(),
+            Some("https://github.com/BurntSushi/ripgrep")
+        );
+        info.url = "https://example.com/ripgrep".to_string();
+        assert_eq!(info.repository_url(), None);
+        info.homepage = Some("https://example.com/rg".to_string());
+        info.repository = Some("https://example.com/rg.git".to_string());
+        assert_eq!(info.homepage_url(), "https://example.com/rg");
+        assert_eq!(info.repository_url(), Some("https://example.com/rg.git"));
+    }
+
+    #[test]
+    fn describe_deprecations() {
+        let mut info = Manifest::read_from_path("tests/manifests/ripgrep.toml")
+            .unwrap()
+            .info;
+        assert_eq!(info.deprecation(), None);
+        info.deprecated = true;
+        assert_eq!(info.deprecation(), Some("deprecated".to_string()));
+        assert_eq!(info.migration_target(), None);
+        info.deprecated = false;
+        info.replaced_by = Some("ugrep".to_string());
+        assert_eq!(
+            info.deprecation(),
+            Some("deprecated, replaced by ugrep".to_string())
+        );
+        assert_eq!(info.migration_target(), Some("ugrep"));
+    }
+
     #[test]
     fn deserialize_manifest_with_files() {
         let manifest = Manifest::read_from_path("tests/manifests/ripgrep.toml").unwrap();
         assert_eq!(manifest, Manifest {
             info: Info {
                 name: "ripgrep".to_string(),
+                aliases: vec!["rg".to_string()],
                 version: Versioning::new("12.1.1").unwrap(),
                 url: "https://github.com/BurntSushi/ripgrep".to_string(),
+                description: Some(
+                    "Recursively search directories for a regex pattern".to_string()
+                ),
+                keywords: vec!["search".to_string(), "grep".to_string()],
+                homepage: None,
+                repository: None,
                 license: spdx::Expression::parse("Unlicense OR MIT").unwrap(),
+                released: None,
+                requires: Vec::new(),
+                conflicts: Vec::new(),
+                versioned_binaries: None,
+                deprecated: false,
+                replaced_by: None,
             },
-            discover: Discover {
-                binary: "rg".to_string(),
+            discover: Discover::Binary {
+                binary: vec!["rg".to_string()],
                 version_check: VersionCheck {
                     args: vec!["--version".to_string()],
                     pattern: "ripgrep ([^ ]+)".to_string(),
+                    stream: VersionStream::Stdout,
                 },
             },
             install: vec![
                 InstallDownload {
                     download: Url::parse("https://github.com/BurntSushi/ripgrep/releases/download/12.1.1/ripgrep-12.1.1-x86_64-unknown-linux-musl.tar.gz").unwrap(),
+                    filename: None,
+                    arch: None,
+                    os: None,
                     checksums: Checksums {
                         b2: Some(hex::decode("1c97a37e109f818bce8e974eb3a29eb8d1ca488e048caff658696211e8cad23728a767a2d6b97fed365d24f9545f1bc49a3e2687ab437eb4189993ad5fe30663").unwrap()),
                         ..Checksums::default()
                     },
+                    minisign: None,
                     install: Install::FilesFromArchive {
                         files: vec![
                             InstallFile {
                                 source: "ripgrep-12.1.1-x86_64-unknown-linux-musl/rg".to_string(),
                                 name: None,
                                 target: Target::Binary { links: vec!["ripgrep".to_string()] },
+                                optional: false,
                             },
                             InstallFile {
                                 source: "ripgrep-12.1.1-x86_64-unknown-linux-musl/doc/rg.1".to_string(),
                                 name: None,
                                 target: Target::Manpage { section: 1 },
+                                optional: false,
                             },
                             InstallFile {
                                 source: "ripgrep-12.1.1-x86_64-unknown-linux-musl/complete/rg.fish".to_string(),
                                 name: None,
                                 target: Target::Completion { shell: Shell::Fish },
+                                optional: false,
                             },
                             InstallFile {
                                 source: "ripgrep-12.1.1-x86_64-unknown-linux-musl/rg.unit".to_string(),
                                 name: None,
-                                target: Target::SystemdUserUnit
+                                target: Target::SystemdUserUnit { enable: false },
+                                optional: false,
                             }
                         ],
+                        strip_prefix: 0,
                     },
+                    post: Vec::new(),
                 }
             ],
             remove: Remove {
                 additional_files: vec![AdditionalFileToRemove {
                     name: "rg.old".to_string(),
                     target: Target::Binary { links: Vec::new() },
-                }]
-            }
+                }],
+                pre: Vec::new(),
+            },
+            upstream: Some(Upstream::GitHub {
+                repo: "BurntSushi/ripgrep".to_string()
+            }),
         })
     }
 
+    #[test]
+    fn parse_icon_size() {
+        assert_eq!("48x48".parse(), Ok(IconSize::Fixed(48)));
+        assert_eq!("scalable".parse(), Ok(IconSize::Scalable));
+        assert!("48x32".parse::<IconSize>().is_err());
+        assert!("48".parse::<IconSize>().is_err());
+        assert_eq!(IconSize::Fixed(256).to_string(), "256x256");
+    }
+
+    #[test]
+    fn deserialize_data_target() {
+        let file: InstallFile =
+            toml::from_str("source = \"foo.so\"\ntype = \"data\"\npath = \"foo/grammars\"")
+                .unwrap();
+        assert_eq!(
+            file.target,
+            Target::Data {
+                path: "foo/grammars".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn deserialize_strip_prefix() {
+        let download: InstallDownload = toml::from_str(
+            r#"
+download = "https://example.com/foo-1.0.tar.gz"
+checksums.sha256 = "abcd"
+strip_prefix = 1
+files = [{ source = "foo", type = "bin" }]
+"#,
+        )
+        .unwrap();
+        match download.install {
+            Install::FilesFromArchive { strip_prefix, .. } => assert_eq!(strip_prefix, 1),
+            other => panic!("Unexpected install: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn deserialize_data_target_rejects_escaping_paths() {
+        for path in &["/etc", "../foo", "foo/../../bar", "./foo", ""] {
+            let result = toml::from_str::<InstallFile>(&format!(
+                "source = \"foo.so\"\ntype = \"data\"\npath = {:?}",
+                path
+            ));
+            assert!(result.is_err(), "Path {:?} accepted", path);
+        }
+    }
+
     #[test]
     fn deserialize_manifest_with_single_file() {
         let manifest = Manifest::read_from_path("tests/manifests/shfmt.toml").unwrap();
@@ -348,30 +1578,423 @@ mod tests {
             Manifest {
                 info: Info {
                     name: "shfmt".to_string(),
+                    aliases: Vec::new(),
                     version: Versioning::new("3.1.1").unwrap(),
                     url: "https://github.com/mvdan/sh".to_string(),
-                    license: spdx::Expression::parse("BSD-3-Clause").unwrap()
+                    description: None,
+                    keywords: Vec::new(),
+                    homepage: None,
+                    repository: None,
+                    license: spdx::Expression::parse("BSD-3-Clause").unwrap(),
+                    released: None,
+                    requires: Vec::new(),
+                    conflicts: Vec::new(),
+                    versioned_binaries: None,
+                    deprecated: false,
+                    replaced_by: None,
                 },
-                discover: Discover {
-                    binary: "shfmt".to_string(),
+                discover: Discover::Binary {
+                    binary: vec!["shfmt".to_string()],
                     version_check: VersionCheck {
                         args: vec!["-version".to_string()],
-                        pattern: "v(\\d\\S+)".to_string()
+                        pattern: "v(\\d\\S+)".to_string(),
+                        stream: VersionStream::Stdout,
                     }
                 },
                 install: vec![InstallDownload {
                     download: Url::parse("https://github.com/mvdan/sh/releases/download/v3.1.1/shfmt_v3.1.1_linux_amd64").unwrap(),
+                    filename: None,
+                    arch: None,
+                    os: None,
                     checksums: Checksums {
                         b2: Some(hex::decode("15b203be254ca46b25d35654ceaae91b7e9200f49cd81e103eae7dd80d9e73ab4455c33e6f20073ba2b45f93b06e94e46556c1ab619812718185e071576cf48c").unwrap()),
                         ..Checksums::default()
                     },
+                    minisign: None,
                     install: Install::SingleFile {
                         name: Some("shfmt".to_string()),
                         target: Target::Binary { links: Vec::new() }
                     },
+                    post: Vec::new(),
                 }],
                 remove: Default::default(),
+                upstream: None,
             }
         )
     }
+
+    const X86_64_LINUX: Platform = Platform {
+        os: Os::Linux,
+        arch: Arch::X86_64,
+    };
+
+    #[test]
+    fn deserialize_hooks() {
+        let manifest = Manifest::read_from_path("tests/manifests/tealdeer.toml").unwrap();
+        assert_eq!(
+            manifest.install[0].post,
+            vec![Hook {
+                command: vec!["tldr".to_string(), "--update".to_string()]
+            }]
+        );
+        assert_eq!(
+            manifest.remove.pre,
+            vec![Hook {
+                command: vec!["tldr".to_string(), "--clear-cache".to_string()]
+            }]
+        );
+        let result = toml::from_str::<Hook>("command = []");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn yaml_manifests_with_nulls() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut value: serde_json::Value =
+            serde_json::to_value(Manifest::read_from_path("tests/manifests/shfmt.toml").unwrap())
+                .unwrap();
+        value["info"]["description"] = serde_json::Value::Null;
+        value["upstream"] = serde_json::Value::Null;
+        let path = dir.path().join("shfmt.yaml");
+        std::fs::write(&path, serde_yaml::to_string(&value).unwrap()).unwrap();
+        let manifest = Manifest::read_from_path(&path).unwrap();
+        assert_eq!(manifest.info.description, None);
+        assert_eq!(manifest.upstream, None);
+
+        value["info"]["keywords"] = serde_json::json!(["shell", null]);
+        std::fs::write(&path, serde_yaml::to_string(&value).unwrap()).unwrap();
+        assert_eq!(
+            format!("{:#}", Manifest::read_from_path(&path).unwrap_err()),
+            format!(
+                "File {} is no valid manifest: Lists must not contain null",
+                path.display()
+            )
+        );
+    }
+
+    #[test]
+    fn json_manifests_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        for entry in std::fs::read_dir("tests/manifests").unwrap() {
+            let path = entry.unwrap().path();
+            let manifest = Manifest::read_from_path(&path).unwrap();
+            let json = dir
+                .path()
+                .join(path.file_name().unwrap())
+                .with_extension("json");
+            std::fs::write(&json, serde_json::to_string(&manifest).unwrap()).unwrap();
+            assert_eq!(Manifest::read_from_path(&json).unwrap(), manifest);
+        }
+    }
+
+    #[test]
+    fn render_manifest_for_platform() {
+        let aarch64_linux = Platform {
+            os: Os::Linux,
+            arch: Arch::Aarch64,
+        };
+        let rendered =
+            Manifest::render_from_path("tests/manifests/fd.toml", aarch64_linux, Some("8.3.0"))
+                .unwrap();
+        let manifest: Manifest = toml::from_str(&rendered).unwrap();
+        assert_eq!(manifest.info.version, Versioning::new("8.3.0").unwrap());
+        let downloads: Vec<&str> = manifest
+            .install
+            .iter()
+            .map(|d| d.download.as_str())
+            .collect();
+        assert_eq!(
+            downloads,
+            vec![
+                "https://github.com/sharkdp/fd/releases/download/v8.3.0/fd-v8.3.0-aarch64-unknown-linux-gnu.tar.gz",
+                "https://raw.githubusercontent.com/sharkdp/fd/v8.3.0/doc/fd.1",
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_platform() {
+        assert_eq!("aarch64".parse(), Ok(Arch::Aarch64));
+        assert_eq!("macos".parse(), Ok(Os::MacOs));
+        assert!("arm64".parse::<Arch>().is_err());
+    }
+
+    #[test]
+    fn minisign_signature_url() {
+        let manifest = Manifest::read_from_path("tests/manifests/zig.toml").unwrap();
+        assert_eq!(
+            manifest.install[0].minisign_signature().unwrap().as_str(),
+            "https://ziglang.org/download/0.7.1/zig-linux-x86_64-0.7.1.tar.xz.minisig"
+        );
+        let download: InstallDownload = toml::from_str(
+            r#"
+download = "https://example.com/foo"
+checksums.sha256 = "abcd"
+minisign = { public_key = "RWQ", signature = "https://example.com/foo.sig" }
+type = "bin"
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            download.minisign_signature().unwrap().as_str(),
+            "https://example.com/foo.sig"
+        );
+        let shfmt = Manifest::read_from_path("tests/manifests/shfmt.toml").unwrap();
+        assert!(shfmt.install[0].minisign_signature().is_none());
+    }
+
+    #[test]
+    fn download_filename() {
+        let download = |toml: &str| -> InstallDownload {
+            toml::from_str(&format!(
+                "{}\nchecksums.sha256 = \"abcd\"\ntype = \"bin\"\n",
+                toml
+            ))
+            .unwrap()
+        };
+        assert_eq!(
+            download(r#"download = "https://example.com/v1/foo.tar.gz""#).filename(),
+            "foo.tar.gz"
+        );
+        assert_eq!(
+            download(r#"download = "https://example.com/latest/""#).filename(),
+            "latest"
+        );
+        let first = download(r#"download = "https://example.com/?id=42""#);
+        let second = download(r#"download = "https://example.com/?id=43""#);
+        assert!(first.filename().starts_with("download-"));
+        assert_ne!(first.filename(), second.filename());
+        assert_eq!(
+            download(
+                r#"download = "https://example.com/get?id=42"
+filename = "foo.tar.gz""#
+            )
+            .filename(),
+            "foo.tar.gz"
+        );
+    }
+
+    #[test]
+    fn deserialize_download_platform() {
+        let manifest = Manifest::read_from_path("tests/manifests/fd.toml").unwrap();
+        let platforms: Vec<(Option<Os>, Option<Arch>)> =
+            manifest.install.iter().map(|d| (d.os, d.arch)).collect();
+        assert_eq!(
+            platforms,
+            vec![
+                (Some(Os::Linux), Some(Arch::X86_64)),
+                (Some(Os::Linux), Some(Arch::Aarch64)),
+                (Some(Os::MacOs), Some(Arch::X86_64)),
+                (None, None)
+            ]
+        );
+    }
+
+    #[test]
+    fn manifest_downloads_for_platform() {
+        let manifest = Manifest::read_from_path("tests/manifests/fd.toml").unwrap();
+        let downloads: Vec<Cow<str>> = manifest
+            .downloads(X86_64_LINUX)
+            .map(|d| d.filename())
+            .collect();
+        assert_eq!(
+            downloads,
+            vec!["fd-v8.2.1-x86_64-unknown-linux-musl.tar.gz", "fd.1"]
+        );
+        let downloads: Vec<Cow<str>> = manifest
+            .downloads(Platform {
+                os: Os::MacOs,
+                arch: Arch::X86_64,
+            })
+            .map(|d| d.filename())
+            .collect();
+        assert_eq!(
+            downloads,
+            vec!["fd-v8.2.1-x86_64-apple-darwin.tar.gz", "fd.1"]
+        );
+    }
+
+    #[test]
+    fn manifest_check_platform() {
+        let manifest = Manifest::read_from_path("tests/manifests/fd.toml").unwrap();
+        assert!(manifest.check_platform(X86_64_LINUX).is_ok());
+        let shfmt = Manifest::read_from_path("tests/manifests/shfmt.toml").unwrap();
+        assert!(shfmt
+            .check_platform(Platform {
+                os: Os::MacOs,
+                arch: Arch::Armv7
+            })
+            .is_ok());
+    }
+
+    #[test]
+    fn manifest_check_platform_without_matching_arch() {
+        let mut manifest = Manifest::read_from_path("tests/manifests/fd.toml").unwrap();
+        manifest.install.pop();
+        assert_eq!(
+            manifest
+                .check_platform(Platform {
+                    os: Os::Linux,
+                    arch: Arch::Armv7
+                })
+                .unwrap_err()
+                .to_string(),
+            "fd has no download for architecture armv7, only for x86_64, aarch64"
+        );
+    }
+
+    #[test]
+    fn manifest_check_platform_without_matching_os() {
+        let mut manifest = Manifest::read_from_path("tests/manifests/fd.toml").unwrap();
+        manifest.install.pop();
+        manifest.install.remove(2);
+        assert_eq!(
+            manifest
+                .check_platform(Platform {
+                    os: Os::MacOs,
+                    arch: Arch::X86_64
+                })
+                .unwrap_err()
+                .to_string(),
+            "fd has no download for operating system macos, only for linux"
+        );
+    }
+
+    #[test]
+    fn release_date_from_str() {
+        let date: ReleaseDate = "2020-12-21".parse().unwrap();
+        assert_eq!(date.to_string(), "2020-12-21");
+        assert!("2020-13-01".parse::<ReleaseDate>().is_err());
+        assert!("2020-12".parse::<ReleaseDate>().is_err());
+        assert!("yesterday".parse::<ReleaseDate>().is_err());
+    }
+
+    #[test]
+    fn release_date_to_system_time() {
+        let date: ReleaseDate = "1970-01-02".parse().unwrap();
+        assert_eq!(
+            date.to_system_time(),
+            UNIX_EPOCH + Duration::from_secs(86400)
+        );
+        let date: ReleaseDate = "2020-12-21".parse().unwrap();
+        assert_eq!(
+            date.to_system_time(),
+            UNIX_EPOCH + Duration::from_secs(1_608_508_800)
+        );
+    }
+
+    #[test]
+    fn release_date_from_system_time() {
+        for date in [
+            "1970-01-01",
+            "2000-02-29",
+            "2000-03-01",
+            "2020-12-21",
+            "2100-12-31",
+        ] {
+            let date: ReleaseDate = date.parse().unwrap();
+            assert_eq!(ReleaseDate::from_system_time(date.to_system_time()), date);
+            assert_eq!(
+                ReleaseDate::from_system_time(date.to_system_time() + Duration::from_secs(86399)),
+                date
+            );
+        }
+        assert_eq!(
+            ReleaseDate::from_system_time(UNIX_EPOCH - Duration::from_secs(86400)).to_string(),
+            "1970-01-01"
+        );
+    }
+
+    #[test]
+    fn deserialize_info_released() {
+        let manifest = Manifest::read_from_path("tests/manifests/fd.toml").unwrap();
+        assert_eq!(
+            manifest.info.released,
+            Some(ReleaseDate {
+                year: 2020,
+                month: 12,
+                day: 21
+            })
+        );
+    }
+
+    #[test]
+    fn version_check_stream_and_named_group() {
+        let check: VersionCheck = toml::from_str(
+            r#"
+args = ["-version"]
+pattern = '(\d+) version "(?P<version>[^"]+)"'
+stream = "stderr"
+"#,
+        )
+        .unwrap();
+        assert_eq!(check.stream, VersionStream::Stderr);
+        let output = std::process::Command::new("sh")
+            .args(["-c", "echo 'java' && echo '1 version \"11.0.2\"' >&2"])
+            .output()
+            .unwrap();
+        let selected = String::from_utf8(check.select_output(&output)).unwrap();
+        assert_eq!(selected, "1 version \"11.0.2\"\n");
+        assert_eq!(
+            VersionCheck::extract_version(&check.regex().unwrap(), &selected),
+            Some("11.0.2")
+        );
+        let first_group = Regex::new(r"java (\S+)").unwrap();
+        assert_eq!(
+            VersionCheck::extract_version(&first_group, "java 1.8"),
+            Some("1.8")
+        );
+    }
+
+    #[test]
+    fn deserialize_discover_strategies() {
+        let discover: Discover =
+            toml::from_str("version_file = \"fonts/foo/VERSION\"\npattern = \"(.+)\"").unwrap();
+        assert_eq!(
+            discover,
+            Discover::VersionFile {
+                version_file: "fonts/foo/VERSION".to_string(),
+                pattern: "(.+)".to_string()
+            }
+        );
+        let discover: Discover =
+            toml::from_str("file = \"fonts/foo.ttf\"\nchecksums.sha256 = \"abcd\"").unwrap();
+        assert_eq!(
+            discover,
+            Discover::Checksum {
+                file: "fonts/foo.ttf".to_string(),
+                checksums: Checksums {
+                    sha256: Some(vec![0xab, 0xcd]),
+                    ..Checksums::default()
+                }
+            }
+        );
+        let discover: Discover = toml::from_str(
+            "binary = [\"fzf\", \"fzf-tmux\"]\nversion_check = { args = [], pattern = \"(.+)\" }",
+        )
+        .unwrap();
+        assert_eq!(discover.name(), "fzf");
+        assert!(matches!(discover, Discover::Binary { binary, .. } if binary.len() == 2));
+        assert!(toml::from_str::<Discover>(
+            "binary = []\nversion_check = { args = [], pattern = \"(.+)\" }"
+        )
+        .is_err());
+        let discover: Discover = toml::from_str("file = \"fonts/foo.ttf\"").unwrap();
+        assert_eq!(discover.name(), "fonts/foo.ttf");
+        assert!(toml::from_str::<Discover>("file = \"../foo.ttf\"").is_err());
+    }
+
+    #[test]
+    fn reject_invalid_discover_strategies() {
+        // Invalid checksums must not fall back to discovering just the file
+        assert!(
+            toml::from_str::<Discover>("file = \"fonts/foo.ttf\"\nchecksums.sha256 = \"xyz\"")
+                .is_err()
+        );
+        assert!(toml::from_str::<Discover>(
+            "file = \"fonts/foo.ttf\"\nversion_file = \"foo/VERSION\"\npattern = \"(.+)\""
+        )
+        .is_err());
+        assert!(toml::from_str::<Discover>("binary = \"foo\"").is_err());
+    }
 }
@@ -0,0 +1,159 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Test manifests end-to-end.
+//!
+//! Install a manifest into a throwaway prefix, check the version of the installed binary, and
+//! remove the manifest again, to find out whether the manifest works before publishing it.
+//! Skip hooks and systemd units, because these affect the running system.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Error};
+use fehler::throws;
+
+use crate::{ApplyOptions, HomebinProjectDirs, InstallDirs, Manifest};
+
+/// The result of testing a manifest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestReport {
+    /// The files the manifest installed, relative to the prefix.
+    pub installed_files: Vec<PathBuf>,
+    /// The version the installed binary reports, if any.
+    pub installed_version: Option<String>,
+    /// Everything that went wrong.
+    pub problems: Vec<String>,
+}
+
+impl TestReport {
+    /// Whether the manifest passed the test.
+    pub fn passed(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Find all files below `directory`, relative to `root`.
+#[throws]
+fn files_below(root: &Path, directory: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(directory)
+        .with_context(|| format!("Failed to read {}", directory.display()))?
+    {
+        let path = entry?.path();
+        if path.is_dir() {
+            files.extend(files_below(root, &path)?);
+        } else {
+            files.push(path.strip_prefix(root)?.to_path_buf());
+        }
+    }
+    files.sort();
+    files
+}
+
+/// Test `manifest` in a throwaway prefix, with downloads in `dirs`.
+///
+/// Fail if installing or removing `manifest` fails; report all other problems.
+#[throws]
+pub fn test_manifest(
+    dirs: &HomebinProjectDirs,
+    manifest: &Manifest,
+    options: &ApplyOptions,
+) -> TestReport {
+    let prefix = tempfile::Builder::new()
+        .prefix("homebins-manifest-test")
+        .tempdir()
+        .with_context(|| "Failed to create prefix to test manifest in")?;
+    let mut install_dirs = InstallDirs::in_directory(prefix.path());
+    let options = ApplyOptions {
+        skip_host_operations: true,
+        ..options.clone()
+    };
+    let mut problems = Vec::new();
+
+    crate::install_manifest(dirs, &mut install_dirs, manifest, &options)?;
+    let installed_files = files_below(prefix.path(), prefix.path())?;
+    let installed_version =
+        match crate::installed_manifest_version(&install_dirs, manifest, &options) {
+            Ok(Some(version)) => {
+                if version != manifest.info.version {
+                    problems.push(format!(
+                        "{} reports version {} instead of {}",
+                        manifest.discover.name(),
+                        version,
+                        manifest.info.version
+                    ));
+                }
+                Some(version.to_string())
+            }
+            Ok(None) => {
+                problems.push(format!("No version found for {}", manifest.discover.name()));
+                None
+            }
+            Err(error) => {
+                problems.push(format!("{:#}", error));
+                None
+            }
+        };
+
+    crate::remove_manifest(dirs, &mut install_dirs, manifest, &options)?;
+    let leftovers = files_below(prefix.path(), prefix.path())?;
+    if !leftovers.is_empty() {
+        let files: Vec<String> = leftovers.iter().map(|f| f.display().to_string()).collect();
+        problems.push(format!("Removing left files behind: {}", files.join(", ")));
+    }
+
+    TestReport {
+        installed_files,
+        installed_version,
+        problems,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::{Discover, Target};
+    use crate::testing::{ManifestBuilder, Sandbox};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_working_manifest() {
+        let sandbox = Sandbox::new().unwrap();
+        let fixture = ManifestBuilder::new("hello", "1.0.0")
+            .file("hello.1", Target::Manpage { section: 1 }, ".TH HELLO 1\n")
+            .build();
+        sandbox.put_downloads(&fixture).unwrap();
+        let report =
+            test_manifest(sandbox.dirs(), &fixture.manifest, &ApplyOptions::default()).unwrap();
+        assert_eq!(
+            report,
+            TestReport {
+                installed_files: vec![
+                    PathBuf::from("bin/hello"),
+                    PathBuf::from("share/man/man1/hello.1")
+                ],
+                installed_version: Some("1.0.0".to_string()),
+                problems: Vec::new(),
+            }
+        );
+        assert!(report.passed());
+    }
+
+    #[test]
+    fn test_manifest_with_broken_version_check() {
+        let sandbox = Sandbox::new().unwrap();
+        let mut fixture = ManifestBuilder::new("hello", "1.0.0").build();
+        sandbox.put_downloads(&fixture).unwrap();
+        if let Discover::Binary { version_check, .. } = &mut fixture.manifest.discover {
+            version_check.pattern = "goodbye (\\S+)".to_string();
+        }
+        let report =
+            test_manifest(sandbox.dirs(), &fixture.manifest, &ApplyOptions::default()).unwrap();
+        assert_eq!(report.installed_files, vec![PathBuf::from("bin/hello")]);
+        assert_eq!(report.problems, vec!["No version found for hello"]);
+        assert!(!report.passed());
+    }
+}
@@ -5,14 +5,18 @@
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
 pub use apply::ApplyOperation;
+pub use filter::{only_removals_of, skip_destinations, skip_host_operations, version_binaries};
 pub use install::install_manifest;
+pub use privileges::{privileged_operations, PrivilegedOperation};
 pub use remove::remove_manifest;
 pub use types::*;
 pub use update::update_manifest;
-pub use util::operation_destinations;
+pub use util::{copy_permissions, download_destinations, escaping_names, operation_destinations};
 
 mod apply;
+mod filter;
 mod install;
+mod privileges;
 mod remove;
 mod types;
 mod update;
@@ -4,17 +4,45 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
+use std::borrow::Cow;
+use std::ffi::OsString;
 use std::fs::File;
-use std::os::unix::fs::PermissionsExt;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
 
-use anyhow::{Context, Error};
+use anyhow::{anyhow, Context, Error};
 use colored::Colorize;
-use fehler::throws;
+use fehler::{throw, throws};
+use url::Url;
 
-use crate::checksum::Validate;
+use crate::checksum::{find_checksum, validate_all, Algorithm, Validate, ValidationError};
+use crate::manifest::Checksums;
 use crate::operations::Operation;
-use crate::tools::{curl, extract};
-use crate::ManifestOperationDirs;
+use crate::process::CommandExt;
+use crate::progress::Spinner;
+use crate::tools::{
+    download, extract, install_info, minisign_verify, network_isolation_available, systemctl_user,
+    unshare_without_network, update_desktop_database, update_font_cache,
+};
+use crate::{HookIsolation, ManifestOperationDirs};
+
+/// Environment variables which isolated hooks get to see.
+const HOOK_ENVIRONMENT: &[&str] = &[
+    "HOME", "USER", "LOGNAME", "LANG", "LANGUAGE", "TERM", "TZ", "TMPDIR",
+];
+
+/// Select the variables of `vars` which isolated hooks get to see.
+///
+/// Keep only a few basic variables, and locale settings.
+fn hook_environment<I: IntoIterator<Item = (OsString, OsString)>>(
+    vars: I,
+) -> impl Iterator<Item = (OsString, OsString)> {
+    vars.into_iter().filter(|(name, _)| {
+        name.to_str()
+            .is_some_and(|name| HOOK_ENVIRONMENT.contains(&name) || name.starts_with("LC_"))
+    })
+}
 
 /// Define application of operations.
 pub trait ApplyOperation {
@@ -23,6 +51,91 @@ pub trait ApplyOperation {
 
     /// Apply this operation to the given manifest directories.
     fn apply_operation<'a>(&self, dirs: &ManifestOperationDirs<'a>) -> Result<(), Self::Error>;
+
+    /// Whether this operation was already applied to the given manifest directories.
+    ///
+    /// Return `true` only if applying this operation again would not change anything.
+    fn is_applied<'a>(&self, dirs: &ManifestOperationDirs<'a>) -> Result<bool, Self::Error>;
+}
+
+/// Whether the files at `a` and `b` have the same contents.
+fn same_contents(a: &Path, b: &Path) -> std::io::Result<bool> {
+    if a.metadata()?.len() == b.metadata()?.len() {
+        Ok(std::fs::read(a)? == std::fs::read(b)?)
+    } else {
+        Ok(false)
+    }
+}
+
+/// The path of the cached checksum file for the download `name`.
+fn sums_file(dirs: &ManifestOperationDirs<'_>, name: &str) -> PathBuf {
+    dirs.download_dir().join(format!("{}.sha256sums", name))
+}
+
+/// The path of the file with the name the server gave the download `name`, if it gave any.
+fn remote_name_file(dirs: &ManifestOperationDirs<'_>, name: &str) -> PathBuf {
+    dirs.download_dir().join(format!("{}.name", name))
+}
+
+/// Validate the download at `dest` against `checksums`.
+///
+/// Validate against all `checksums` if `dirs` says so, and against the strongest otherwise.
+fn validate_download(
+    dest: &Path,
+    checksums: &Checksums,
+    dirs: &ManifestOperationDirs<'_>,
+) -> Result<Algorithm, ValidationError> {
+    let mut source = File::open(dest)?;
+    if dirs.verify_all_checksums() {
+        validate_all(checksums, &mut source)
+    } else {
+        checksums.validate(&mut source)
+    }
+}
+
+/// Download `url` to `dest`, as the download `name`.
+///
+/// Resume a partial download at `dest`, see [`download`].  Remember the name the server gave
+/// the download, to tell the type of archive for URLs without a proper file name, and count
+/// the downloaded bytes.
+#[throws]
+fn fetch_download(url: &Url, name: &str, dest: &Path, dirs: &ManifestOperationDirs<'_>) -> () {
+    let size = |file: &Path| file.metadata().map_or(0, |metadata| metadata.len());
+    let before = size(dest);
+    let remote_name = download(url, dest, dirs.token_for(url)?.as_deref())
+        .with_context(|| format!("Failed to download {}", url))?;
+    if let Some(remote_name) = remote_name.filter(|n| n != name) {
+        let file = remote_name_file(dirs, name);
+        std::fs::write(&file, remote_name)
+            .with_context(|| format!("Failed to write {}", file.display()))?;
+    }
+    dirs.record_download(size(dest).saturating_sub(before));
+}
+
+/// Resolve `checksums` of the download `name`.
+///
+/// If `checksums` have no SHA256 checksum but a `sha256_url`, look up the SHA256 checksum of
+/// `name` in the cached checksum file.
+#[throws]
+fn resolve_checksums<'c>(
+    checksums: &'c Checksums,
+    name: &str,
+    dirs: &ManifestOperationDirs<'_>,
+) -> Cow<'c, Checksums> {
+    match &checksums.sha256_url {
+        Some(url) if checksums.sha256.is_none() => {
+            let sums = sums_file(dirs, name);
+            let contents = std::fs::read_to_string(&sums)
+                .with_context(|| format!("Failed to read checksums from {}", sums.display()))?;
+            let sha256 = find_checksum(&contents, name)
+                .ok_or_else(|| anyhow!("No checksum for {} in {}", name, url))?;
+            Cow::Owned(Checksums {
+                sha256: Some(sha256),
+                ..checksums.clone()
+            })
+        }
+        _ => Cow::Borrowed(checksums),
+    }
 }
 
 impl<'a> ApplyOperation for Operation<'a> {
@@ -33,38 +146,105 @@ impl<'a> ApplyOperation for Operation<'a> {
         use Operation::*;
         match self {
             Download(url, name, checksums) => {
-                println!("Downloading {}", url.as_str().bold());
                 let dest = dirs.download_dir().join(name.as_ref());
-                // FIXME: Don't check for file, instead handle 416 errors from curl as indicator for completeness
-                if !dest.exists() {
-                    curl(&url, &dest)?;
+                if let Some(sums_url) = &checksums.sha256_url {
+                    let sums = sums_file(dirs, name);
+                    if checksums.sha256.is_none() && !sums.exists() {
+                        crate::status!("Downloading {}", sums_url.as_str().bold());
+                        download(sums_url, &sums, dirs.token_for(sums_url)?.as_deref())?;
+                    }
+                }
+                let checksums = resolve_checksums(checksums, name, dirs)?;
+                // Reuse a complete download from an earlier attempt as it is, but resume an
+                // incomplete download, and download a corrupt one again.
+                if dest.is_file() && validate_download(&dest, &checksums, dirs).is_ok() {
+                    crate::status!("Using downloaded {}", url.as_str().bold());
+                    return;
+                }
+                crate::status!("Downloading {}", url.as_str().bold());
+                fetch_download(url, name, &dest, dirs)?;
+                match validate_download(&dest, &checksums, dirs) {
+                    Err(ValidationError::ChecksumMismatch { .. }) => {
+                        crate::estatus!(
+                            "{} didn't match its checksum, downloading it again",
+                            name.as_ref().yellow()
+                        );
+                        std::fs::remove_file(&dest)
+                            .with_context(|| format!("Failed to remove {}", dest.display()))?;
+                        fetch_download(url, name, &dest, dirs)?;
+                        validate_download(&dest, &checksums, dirs)
+                    }
+                    result => result,
+                }
+                .with_context(|| format!("Failed to validate {}", dest.display()))?;
+            }
+            VerifyMinisign(url, name, public_key) => {
+                let file = dirs.download_dir().join(name.as_ref());
+                let signature = dirs.download_dir().join(format!("{}.minisig", name));
+                if dirs.require_trusted_keys() && dirs.trusted_key(public_key).is_none() {
+                    throw!(anyhow!(
+                        "Refusing to verify {} with untrusted key {}; trust it with: homebins trust add {} <name>",
+                        name,
+                        public_key,
+                        public_key
+                    ));
+                }
+                crate::status!("Verifying {} with {}", name, url.as_str().bold());
+                if !signature.exists() {
+                    download(url, &signature, dirs.token_for(url)?.as_deref())?;
+                }
+                minisign_verify(public_key, &file, &signature)
+                    .with_context(|| format!("Failed to verify signature of {}", file.display()))?;
+                match dirs.trusted_key(public_key) {
+                    Some(key) => {
+                        crate::status!("Verified {} with trusted key {}", name, key.name.green())
+                    }
+                    None => crate::estatus!(
+                        "{}",
+                        format!(
+                            "WARNING: Verified {} with untrusted key {} from the manifest",
+                            name, public_key
+                        )
+                        .yellow()
+                    ),
                 }
-                let mut source = &mut File::open(&dest).with_context(|| {
-                    format!("Failed to open {} for checksum validation", dest.display())
-                })?;
-                checksums
-                    .validate(&mut source)
-                    .with_context(|| format!("Failed to validate {}", dest.display()))?;
             }
-            Extract(name) => {
-                extract(&dirs.download_dir().join(name.as_ref()), dirs.work_dir())?;
+            Extract(name, strip) => {
+                let remote_name = std::fs::read_to_string(remote_name_file(dirs, name)).ok();
+                let _spinner = Spinner::start(format!("Extracting {}", name));
+                extract(
+                    &dirs.download_dir().join(name.as_ref()),
+                    remote_name.as_deref().unwrap_or(name.as_ref()),
+                    dirs.work_dir(),
+                    *strip,
+                )?;
             }
             Copy(source, destination, permissions) => {
                 let fs_permissions = permissions.to_unix_permissions();
                 let mode = fs_permissions.mode();
                 let source_path = dirs.path(source.directory()).join(source.name());
-                let target_dir = dirs.install_dirs().path(destination.directory());
-                let target = target_dir.join(destination.name());
-                println!(
+                let target = dirs
+                    .install_dirs()
+                    .path(destination.directory())
+                    .join(destination.name());
+                // The destination name may contain directories, so take the parent of the
+                // target file instead of the destination directory.
+                let target_dir = target.parent().with_context(|| {
+                    format!("Target {} has no parent directory", target.display())
+                })?;
+                let target_name = target
+                    .file_name()
+                    .with_context(|| format!("Target {} has no file name", target.display()))?;
+                crate::status!(
                     "install -m{:o} {} {}",
                     mode,
                     source.name(),
                     target.display()
                 );
-                std::fs::create_dir_all(&target_dir)?;
+                std::fs::create_dir_all(target_dir)?;
                 let mut temp_target = tempfile::Builder::new()
-                    .prefix(destination.name())
-                    .tempfile_in(&target_dir)
+                    .prefix(target_name)
+                    .tempfile_in(target_dir)
                     .with_context(|| {
                         format!(
                             "Failed to create temporary target file in {}",
@@ -91,10 +271,27 @@ impl<'a> ApplyOperation for Operation<'a> {
                     )
                 })?;
             }
+            CopyOptional(source, destination, permissions) => {
+                let source_path = dirs.path(source.directory()).join(source.name());
+                if source_path.exists() {
+                    Copy(source.clone(), destination.clone(), *permissions)
+                        .apply_operation(dirs)?;
+                } else {
+                    crate::estatus!(
+                        "{}",
+                        format!(
+                            "WARNING: Skipping optional file {} which doesn't exist",
+                            source.name()
+                        )
+                        .yellow()
+                        .bold()
+                    );
+                }
+            }
             Hardlink(source, target) => {
                 let src = dirs.install_dirs().bin_dir().join(source.as_ref());
                 let dst = dirs.install_dirs().bin_dir().join(target.as_ref());
-                println!("ln -f {} {}", src.display(), dst.display());
+                crate::status!("ln -f {} {}", src.display(), dst.display());
                 if dst.exists() {
                     std::fs::remove_file(&dst)
                         .with_context(|| format!("Failed to override {}", dst.display()))?;
@@ -105,12 +302,237 @@ impl<'a> ApplyOperation for Operation<'a> {
             }
             Remove(directory, name) => {
                 let file = dirs.install_dirs().path(*directory).join(name.as_ref());
-                println!("rm -f {}", file.display());
+                crate::status!("rm -f {}", file.display());
                 if file.exists() {
                     std::fs::remove_file(&file)
                         .with_context(|| format!("Failed to remove {}", file.display()))?;
                 }
             }
+            UpdateDesktopDatabase => {
+                let directory = dirs.install_dirs().desktop_entry_dir();
+                crate::status!("update-desktop-database {}", directory.display());
+                // Desktop entries still work without the cache, so don't fail if the tool's missing
+                if let Err(error) = update_desktop_database(directory) {
+                    crate::estatus!(
+                        "{}",
+                        format!("WARNING: Failed to update desktop database: {}", error)
+                            .yellow()
+                            .bold()
+                    );
+                }
+            }
+            UpdateFontCache => {
+                let directory = dirs.install_dirs().font_dir();
+                crate::status!("fc-cache -f {}", directory.display());
+                // Fonts still get picked up eventually without cache, so don't fail if fc-cache is missing
+                if let Err(error) = update_font_cache(directory) {
+                    crate::estatus!(
+                        "{}",
+                        format!("WARNING: Failed to update font cache: {}", error)
+                            .yellow()
+                            .bold()
+                    );
+                }
+            }
+            SystemdDaemonReload => {
+                crate::status!("systemctl --user daemon-reload");
+                // Not every system runs systemd, so only warn if we can't reload
+                if let Err(error) = systemctl_user().arg("daemon-reload").checked_call() {
+                    crate::estatus!(
+                        "{}",
+                        format!("WARNING: Failed to reload systemd user manager: {}", error)
+                            .yellow()
+                            .bold()
+                    );
+                }
+            }
+            SystemdEnable(unit) => {
+                crate::status!("systemctl --user enable --now {}", unit);
+                systemctl_user()
+                    .arg("enable")
+                    .arg("--now")
+                    .arg(unit.as_ref())
+                    .checked_call()
+                    .with_context(|| format!("Failed to enable systemd user unit {}", unit))?;
+            }
+            InstallInfo(name) => {
+                let info_dir = dirs.install_dirs().info_dir();
+                let page = info_dir.join(name.as_ref());
+                crate::status!(
+                    "install-info --info-dir={} {}",
+                    info_dir.display(),
+                    page.display()
+                );
+                // The info page is still readable without a dir entry
+                if let Err(error) = install_info(info_dir).arg(&page).checked_call() {
+                    crate::estatus!(
+                        "{}",
+                        format!("WARNING: Failed to add {} to info dir: {}", name, error)
+                            .yellow()
+                            .bold()
+                    );
+                }
+            }
+            RemoveInfo(name) => {
+                let info_dir = dirs.install_dirs().info_dir();
+                let page = info_dir.join(name.as_ref());
+                crate::status!(
+                    "install-info --delete --info-dir={} {}",
+                    info_dir.display(),
+                    page.display()
+                );
+                if page.exists() {
+                    if let Err(error) = install_info(info_dir)
+                        .arg("--delete")
+                        .arg(&page)
+                        .checked_call()
+                    {
+                        crate::estatus!(
+                            "{}",
+                            format!(
+                                "WARNING: Failed to remove {} from info dir: {}",
+                                name, error
+                            )
+                            .yellow()
+                            .bold()
+                        );
+                    }
+                }
+            }
+            Run(command) => {
+                crate::status!("{}", command.join(" "));
+                let (program, args) = command
+                    .split_first()
+                    .with_context(|| "Cannot run empty command")?;
+                // Let commands find the binaries we just installed
+                let path = std::env::var_os("PATH").unwrap_or_default();
+                let path = std::env::join_paths(
+                    std::iter::once(dirs.install_dirs().bin_dir().to_path_buf())
+                        .chain(std::env::split_paths(&path)),
+                )?;
+                let isolation = dirs.hook_isolation();
+                let mut process = match isolation {
+                    HookIsolation::Off | HookIsolation::Clean => Command::new(program),
+                    HookIsolation::NoNetwork | HookIsolation::Strict => {
+                        if network_isolation_available() {
+                            let mut process = unshare_without_network();
+                            process.arg(program);
+                            process
+                        } else if isolation == HookIsolation::Strict {
+                            throw!(anyhow!(
+                                "Refusing to run {} with network access: unshare cannot create user namespaces",
+                                command.join(" ")
+                            ));
+                        } else {
+                            crate::estatus!(
+                                "{}",
+                                "WARNING: unshare cannot create user namespaces; running hook with network access"
+                                    .yellow()
+                                    .bold()
+                            );
+                            Command::new(program)
+                        }
+                    }
+                };
+                if isolation != HookIsolation::Off {
+                    process
+                        .env_clear()
+                        .envs(hook_environment(std::env::vars_os()))
+                        .stdin(Stdio::null());
+                }
+                let output = process
+                    .args(args)
+                    .current_dir(dirs.work_dir())
+                    .env("PATH", path)
+                    .checked_output()
+                    .with_context(|| format!("Failed to run {}", command.join(" ")))?;
+                let stdout = String::from_utf8_lossy(&output.stdout);
+                if !stdout.trim().is_empty() {
+                    crate::status!("{}", stdout.trim_end().dimmed());
+                }
+            }
+            SystemdDisable(unit) => {
+                crate::status!("systemctl --user disable --now {}", unit);
+                // The unit may already be gone or disabled, which shouldn't block its removal
+                if let Err(error) = systemctl_user()
+                    .arg("disable")
+                    .arg("--now")
+                    .arg(unit.as_ref())
+                    .checked_call()
+                {
+                    crate::estatus!(
+                        "{}",
+                        format!(
+                            "WARNING: Failed to disable systemd user unit {}: {}",
+                            unit, error
+                        )
+                        .yellow()
+                        .bold()
+                    );
+                }
+            }
+        }
+    }
+
+    #[throws]
+    fn is_applied<'b>(&self, dirs: &ManifestOperationDirs<'b>) -> bool {
+        use Operation::*;
+        match self {
+            Download(_, name, checksums) => {
+                let dest = dirs.download_dir().join(name.as_ref());
+                let needs_sums = checksums.sha256.is_none() && checksums.sha256_url.is_some();
+                dest.is_file() && (!needs_sums || sums_file(dirs, name).is_file()) && {
+                    let checksums = resolve_checksums(checksums, name, dirs)?;
+                    validate_download(&dest, &checksums, dirs).is_ok()
+                }
+            }
+            // Verifying is cheap, and must check the key against the trusted keys every time
+            VerifyMinisign(_, _, _) => false,
+            // The work dir is always empty before applying operations, so we must always extract
+            Extract(_, _) => false,
+            Copy(source, destination, permissions) => {
+                let source_path = dirs.path(source.directory()).join(source.name());
+                let target = dirs
+                    .install_dirs()
+                    .path(destination.directory())
+                    .join(destination.name());
+                target.is_file()
+                    && source_path.is_file()
+                    && target.metadata()?.mode() & 0o7777
+                        == permissions.to_unix_permissions().mode()
+                    && same_contents(&source_path, &target).with_context(|| {
+                        format!(
+                            "Failed to compare {} with {}",
+                            source_path.display(),
+                            target.display()
+                        )
+                    })?
+            }
+            // If the source is missing applying just warns again, which is cheap
+            CopyOptional(source, destination, permissions) => {
+                Copy(source.clone(), destination.clone(), *permissions).is_applied(dirs)?
+            }
+            Hardlink(source, target) => {
+                let src = dirs.install_dirs().bin_dir().join(source.as_ref());
+                let dst = dirs.install_dirs().bin_dir().join(target.as_ref());
+                match (src.metadata(), dst.metadata()) {
+                    (Ok(src), Ok(dst)) => src.dev() == dst.dev() && src.ino() == dst.ino(),
+                    _ => false,
+                }
+            }
+            Remove(directory, name) => !dirs
+                .install_dirs()
+                .path(*directory)
+                .join(name.as_ref())
+                .exists(),
+            // Refreshing caches is cheap, and we can't tell whether caches are up to date anyway
+            UpdateDesktopDatabase | UpdateFontCache | SystemdDaemonReload => false,
+            // Enabling and disabling is idempotent, so just do it again
+            SystemdEnable(_) | SystemdDisable(_) => false,
+            InstallInfo(_) => false,
+            // We can't tell what a command did, so always run it again
+            Run(_) => false,
+            RemoveInfo(name) => !dirs.install_dirs().info_dir().join(name.as_ref()).exists(),
         }
     }
 }
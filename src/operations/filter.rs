@@ -0,0 +1,353 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::borrow::Cow;
+
+use super::types::*;
+
+/// Whether to keep `operation` if installing to any destination of `skip` is not desired.
+fn keep_operation(operation: &Operation<'_>, skip: &[DestinationKind]) -> bool {
+    use DestinationDirectory::*;
+    use Operation::*;
+    let directory = match operation {
+        Copy(_, destination, _) | CopyOptional(_, destination, _) => destination.directory(),
+        Hardlink(_, _) => BinDir,
+        UpdateDesktopDatabase => DesktopEntryDir,
+        UpdateFontCache => FontDir,
+        SystemdDaemonReload | SystemdEnable(_) | SystemdDisable(_) => SystemdUserUnitDir,
+        InstallInfo(_) | RemoveInfo(_) => InfoDir,
+        // Always keep downloads and extractions, removals of obsolete files, and hooks
+        Download(_, _, _) | VerifyMinisign(_, _, _) | Extract(_, _) | Remove(_, _) | Run(_) => {
+            return true
+        }
+    };
+    !skip.iter().any(|kind| kind.includes(directory))
+}
+
+/// Remove all operations from `operations` which install to a destination of any kind in `skip`.
+///
+/// Also removes operations which depend on skipped files, e.g. cache updates.
+pub fn skip_destinations<'a>(
+    operations: Vec<Operation<'a>>,
+    skip: &[DestinationKind],
+) -> Vec<Operation<'a>> {
+    if skip.is_empty() {
+        operations
+    } else {
+        operations
+            .into_iter()
+            .filter(|operation| keep_operation(operation, skip))
+            .collect()
+    }
+}
+
+/// Keep only operations in `operations` which remove files of any kind in `only`.
+///
+/// Also keep operations which stop services or update caches for these files, but drop hooks and
+/// removals of all other files.  Never remove binaries, even if `only` includes them; removing
+/// binaries means removing the whole manifest.
+pub fn only_removals_of<'a>(
+    operations: Vec<Operation<'a>>,
+    only: &[DestinationKind],
+) -> Vec<Operation<'a>> {
+    use DestinationDirectory::*;
+    use Operation::*;
+    operations
+        .into_iter()
+        .filter(|operation| {
+            let directory = match operation {
+                Remove(BinDir, _) => return false,
+                Remove(directory, _) => *directory,
+                UpdateDesktopDatabase => DesktopEntryDir,
+                UpdateFontCache => FontDir,
+                SystemdDaemonReload | SystemdDisable(_) => SystemdUserUnitDir,
+                RemoveInfo(_) => InfoDir,
+                _ => return false,
+            };
+            only.iter().any(|kind| kind.includes(directory))
+        })
+        .collect()
+}
+
+/// Remove all operations from `operations` which affect the running system.
+///
+/// This removes hooks, which may run binaries for another system, all operations on systemd
+/// units, which would affect the systemd user manager of the running system, and all updates of
+/// caches and indexes with tools of the running system, i.e. the font cache, the desktop database
+/// and the info directory.
+pub fn skip_host_operations(operations: Vec<Operation<'_>>) -> Vec<Operation<'_>> {
+    use Operation::*;
+    operations
+        .into_iter()
+        .filter(|operation| {
+            !matches!(
+                operation,
+                Run(_)
+                    | SystemdDaemonReload
+                    | SystemdEnable(_)
+                    | SystemdDisable(_)
+                    | UpdateFontCache
+                    | UpdateDesktopDatabase
+                    | InstallInfo(_)
+                    | RemoveInfo(_)
+            )
+        })
+        .collect()
+}
+
+/// Install binaries in `operations` with names versioned by `version`.
+///
+/// Install every binary as `<name>-<version>` and link `<name>` to it.  Remove the versioned
+/// binary `<name>` actually links to along with `<name>`, as told by `installed_version`, and fall
+/// back to `version` if `installed_version` doesn't know.  Binaries of other versions stay in
+/// place, so versions can coexist.  Leave optional binaries alone because there'd be nothing to
+/// link to.
+pub fn version_binaries<'a, F>(
+    operations: Vec<Operation<'a>>,
+    version: &str,
+    installed_version: F,
+) -> Vec<Operation<'a>>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    use DestinationDirectory::BinDir;
+    use Operation::*;
+    let mut versioned = Vec::with_capacity(operations.len());
+    for operation in operations {
+        match operation {
+            Copy(source, destination, permissions) if destination.directory() == BinDir => {
+                let name = destination.name().to_string();
+                let versioned_name = format!("{}-{}", name, version);
+                versioned.push(Copy(
+                    source,
+                    Destination::new(BinDir, Cow::Owned(versioned_name.clone())),
+                    permissions,
+                ));
+                versioned.push(Hardlink(Cow::Owned(versioned_name), Cow::Owned(name)));
+            }
+            Remove(BinDir, name) => {
+                let installed = installed_version(&name).unwrap_or_else(|| version.to_string());
+                let versioned_name = format!("{}-{}", name, installed);
+                versioned.push(Remove(BinDir, name));
+                versioned.push(Remove(BinDir, Cow::Owned(versioned_name)));
+            }
+            operation => versioned.push(operation),
+        }
+    }
+    versioned
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::{Arch, Os, Platform, Shell};
+    use crate::operations::DestinationDirectory::*;
+    use crate::operations::SourceDirectory::*;
+    use crate::operations::{install_manifest, remove_manifest};
+    use crate::Manifest;
+    use pretty_assertions::assert_eq;
+    use std::borrow::Cow;
+
+    const X86_64_LINUX: Platform = Platform {
+        os: Os::Linux,
+        arch: Arch::X86_64,
+    };
+
+    #[test]
+    fn skip_nothing() {
+        let manifest = Manifest::read_from_path("tests/manifests/ripgrep.toml").unwrap();
+        assert_eq!(
+            skip_destinations(install_manifest(&manifest, X86_64_LINUX), &[]),
+            install_manifest(&manifest, X86_64_LINUX)
+        );
+    }
+
+    #[test]
+    fn skip_completions_and_manpages() {
+        let manifest = Manifest::read_from_path("tests/manifests/ripgrep.toml").unwrap();
+        let operations = skip_destinations(
+            install_manifest(&manifest, X86_64_LINUX),
+            &[DestinationKind::Completions, DestinationKind::Manpages],
+        );
+        assert!(!operations.iter().any(|operation| matches!(
+            operation,
+            Operation::Copy(_, destination, _)
+                if matches!(destination.directory(), CompletionDir(_) | ManDir(_))
+        )));
+        assert_eq!(
+            operations.len(),
+            install_manifest(&manifest, X86_64_LINUX).len() - 2
+        );
+    }
+
+    #[test]
+    fn skip_shell_completions() {
+        let manifest = Manifest::read_from_path("tests/manifests/ripgrep.toml").unwrap();
+        let operations = skip_destinations(
+            install_manifest(&manifest, X86_64_LINUX),
+            &[DestinationKind::ShellCompletions(Shell::Fish)],
+        );
+        assert_eq!(
+            operations.len(),
+            install_manifest(&manifest, X86_64_LINUX).len() - 1
+        );
+    }
+
+    #[test]
+    fn only_remove_systemd_units() {
+        let manifest = Manifest::read_from_path("tests/manifests/syncthing.toml").unwrap();
+        assert_eq!(
+            only_removals_of(
+                remove_manifest(&manifest, X86_64_LINUX),
+                &[DestinationKind::SystemdUserUnits]
+            ),
+            vec![
+                Operation::SystemdDisable(Cow::from("syncthing.service")),
+                Operation::Remove(SystemdUserUnitDir, Cow::from("syncthing.service")),
+                Operation::SystemdDaemonReload,
+            ]
+        );
+    }
+
+    #[test]
+    fn only_removals_keep_binaries() {
+        let manifest = Manifest::read_from_path("tests/manifests/ripgrep.toml").unwrap();
+        let operations = only_removals_of(
+            remove_manifest(&manifest, X86_64_LINUX),
+            &[DestinationKind::Binaries, DestinationKind::Manpages],
+        );
+        assert!(!operations.is_empty());
+        assert!(operations
+            .iter()
+            .all(|operation| matches!(operation, Operation::Remove(ManDir(_), _))));
+    }
+
+    #[test]
+    fn skip_host_operations_of_service() {
+        let manifest = Manifest::read_from_path("tests/manifests/syncthing.toml").unwrap();
+        let operations = skip_host_operations(install_manifest(&manifest, X86_64_LINUX));
+        assert_eq!(
+            &operations[operations.len() - 1..],
+            &[Operation::Copy(
+                Source::new(
+                    WorkDir,
+                    Cow::from(
+                        "syncthing-linux-amd64-v1.12.1/etc/linux-systemd/user/syncthing.service"
+                    )
+                ),
+                Destination::new(SystemdUserUnitDir, Cow::from("syncthing.service")),
+                Permissions::Regular
+            )]
+        );
+    }
+
+    #[test]
+    fn skip_host_operations_of_caches_and_indexes() {
+        let updates_host = |operation: &Operation<'_>| {
+            matches!(
+                operation,
+                Operation::UpdateFontCache
+                    | Operation::UpdateDesktopDatabase
+                    | Operation::InstallInfo(_)
+                    | Operation::RemoveInfo(_)
+            )
+        };
+        for name in ["fira-code", "neovide", "hello"] {
+            let manifest =
+                Manifest::read_from_path(format!("tests/manifests/{}.toml", name)).unwrap();
+            for operations in [
+                install_manifest(&manifest, X86_64_LINUX),
+                remove_manifest(&manifest, X86_64_LINUX),
+            ] {
+                assert!(operations.iter().any(updates_host));
+                assert!(!skip_host_operations(operations).iter().any(updates_host));
+            }
+        }
+    }
+
+    #[test]
+    fn binaries_only() {
+        let manifest = Manifest::read_from_path("tests/manifests/ripgrep.toml").unwrap();
+        let skip: Vec<DestinationKind> = DestinationKind::ALL
+            .iter()
+            .copied()
+            .filter(|kind| *kind != DestinationKind::Binaries)
+            .collect();
+        assert_eq!(
+            skip_destinations(install_manifest(&manifest, X86_64_LINUX), &skip),
+            vec![
+                Operation::Download(
+                    Cow::Borrowed(&manifest.install[0].download),
+                    Cow::Borrowed("ripgrep-12.1.1-x86_64-unknown-linux-musl.tar.gz"),
+                    Cow::Borrowed(&manifest.install[0].checksums),
+                ),
+                Operation::Extract(
+                    Cow::from("ripgrep-12.1.1-x86_64-unknown-linux-musl.tar.gz"),
+                    0
+                ),
+                Operation::Copy(
+                    Source::new(
+                        WorkDir,
+                        Cow::from("ripgrep-12.1.1-x86_64-unknown-linux-musl/rg")
+                    ),
+                    Destination::new(BinDir, Cow::from("rg")),
+                    Permissions::Executable
+                ),
+                Operation::Hardlink(Cow::Borrowed("rg"), Cow::from("ripgrep")),
+            ]
+        );
+    }
+
+    #[test]
+    fn versioned_binaries() {
+        let manifest = Manifest::read_from_path("tests/manifests/ripgrep.toml").unwrap();
+        let operations = version_binaries(
+            skip_destinations(
+                install_manifest(&manifest, X86_64_LINUX),
+                &[DestinationKind::Completions, DestinationKind::Manpages],
+            ),
+            "12.1.1",
+            |_| None,
+        );
+        assert_eq!(
+            &operations[2..5],
+            &[
+                Operation::Copy(
+                    Source::new(
+                        WorkDir,
+                        Cow::from("ripgrep-12.1.1-x86_64-unknown-linux-musl/rg")
+                    ),
+                    Destination::new(BinDir, Cow::from("rg-12.1.1")),
+                    Permissions::Executable
+                ),
+                Operation::Hardlink(Cow::from("rg-12.1.1"), Cow::from("rg")),
+                Operation::Hardlink(Cow::Borrowed("rg"), Cow::from("ripgrep")),
+            ]
+        );
+        assert_eq!(
+            version_binaries(
+                vec![Operation::Remove(BinDir, Cow::from("rg"))],
+                "12.1.1",
+                |_| None
+            ),
+            vec![
+                Operation::Remove(BinDir, Cow::from("rg")),
+                Operation::Remove(BinDir, Cow::from("rg-12.1.1")),
+            ]
+        );
+        assert_eq!(
+            version_binaries(
+                vec![Operation::Remove(BinDir, Cow::from("rg"))],
+                "13.0.0",
+                |name| Some(format!("{}-version", name))
+            ),
+            vec![
+                Operation::Remove(BinDir, Cow::from("rg")),
+                Operation::Remove(BinDir, Cow::from("rg-rg-version")),
+            ]
+        );
+    }
+}
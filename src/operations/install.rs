@@ -4,7 +4,7 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-use crate::manifest::{Install, InstallDownload, Manifest, Target};
+use crate::manifest::{Install, InstallDownload, Manifest, Platform, Target};
 use std::borrow::Cow;
 use std::borrow::Cow::Borrowed;
 
@@ -19,6 +19,7 @@ impl NumberOfInstallOperations for Target {
     fn number_of_install_operations(&self) -> usize {
         match self {
             Target::Binary { links } => links.len() + 1,
+            Target::InfoPage => 2,
             _ => 1,
         }
     }
@@ -28,7 +29,7 @@ impl NumberOfInstallOperations for InstallDownload {
     fn number_of_install_operations(&self) -> usize {
         match &self.install {
             Install::SingleFile { target, .. } => target.number_of_install_operations(),
-            Install::FilesFromArchive { files } => files
+            Install::FilesFromArchive { files, .. } => files
                 .iter()
                 .map(|f| f.target.number_of_install_operations())
                 .sum(),
@@ -45,82 +46,125 @@ impl NumberOfInstallOperations for Manifest {
     }
 }
 
-fn copy<'a>(source: Source<'a>, target: &Target, name: Cow<'a, str>) -> Operation<'a> {
-    use Operation::Copy;
+fn copy<'a>(
+    manifest: &Manifest,
+    source: Source<'a>,
+    target: &Target,
+    name: Cow<'a, str>,
+    optional: bool,
+) -> Operation<'a> {
+    use Operation::{Copy, CopyOptional};
     let (dir, permissions) = dir_and_permissions(target);
-    Copy(source, Destination::new(dir, name), permissions)
+    let destination = Destination::new(dir, destination_name(manifest, target, name));
+    if optional {
+        CopyOptional(source, destination, permissions)
+    } else {
+        Copy(source, destination, permissions)
+    }
 }
 
-fn push_links<'a>(target: &'a Target, target_name: &'a str, operations: &mut Vec<Operation<'a>>) {
-    if let Target::Binary { links } = target {
-        for link in links {
-            operations.push(Operation::Hardlink(Cow::from(target_name), Cow::from(link)))
+fn push_links<'a>(
+    target: &'a Target,
+    target_name: Cow<'a, str>,
+    operations: &mut Vec<Operation<'a>>,
+) {
+    match target {
+        Target::Binary { links } => {
+            for link in links {
+                operations.push(Operation::Hardlink(target_name.clone(), Cow::from(link)))
+            }
         }
+        Target::InfoPage => operations.push(Operation::InstallInfo(target_name)),
+        _ => {}
     }
 }
 
-/// Add install operations of a given `download` to `operations`.
+/// Add install operations of a given `download` of `manifest` to `operations`.
 pub fn push_download_install<'a>(
+    manifest: &'a Manifest,
     download: &'a InstallDownload,
     operations: &mut Vec<Operation<'a>>,
 ) {
     let filename = download.filename();
     match &download.install {
         Install::SingleFile { name, target } => {
-            let target_name = name.as_deref().unwrap_or(filename);
+            let target_name = name
+                .as_deref()
+                .map_or_else(|| filename.clone(), Cow::Borrowed);
             operations.push(copy(
-                Source::new(SourceDirectory::Download, Cow::from(filename)),
+                manifest,
+                Source::new(SourceDirectory::Download, filename),
                 target,
-                Cow::Borrowed(target_name),
+                target_name.clone(),
+                false,
             ));
             push_links(target, target_name, operations);
         }
-        Install::FilesFromArchive { files } => {
-            operations.push(Operation::Extract(Borrowed(filename)));
+        Install::FilesFromArchive {
+            files,
+            strip_prefix,
+        } => {
+            operations.push(Operation::Extract(filename, *strip_prefix));
             for file in files {
-                let name = file.name.as_deref().unwrap_or_else(|| {
-                    file.source
-                        .split('/')
-                        .last()
-                        .expect("rsplit should always be non-empty!")
-                });
+                let name = install_file_name(file);
                 operations.push(copy(
+                    manifest,
                     Source::new(SourceDirectory::WorkDir, Cow::from(file.source.as_str())),
                     &file.target,
                     Cow::from(name),
+                    file.optional,
                 ));
-                push_links(&file.target, name, operations);
+                push_links(&file.target, Cow::from(name), operations);
             }
         }
     }
 }
 
 /// Add the download operation of `download` to `operations`.
+///
+/// If `download` has a signature, also add an operation to verify the signature.
 pub fn push_download<'a>(download: &'a InstallDownload, operations: &mut Vec<Operation<'a>>) {
     operations.push(Operation::Download(
         Borrowed(&download.download),
-        Borrowed(download.filename()),
+        download.filename(),
         Borrowed(&download.checksums),
     ));
+    if let (Some(minisign), Some(signature)) = (&download.minisign, download.minisign_signature()) {
+        operations.push(Operation::VerifyMinisign(
+            Cow::Owned(signature),
+            download.filename(),
+            Borrowed(&minisign.public_key),
+        ));
+    }
 }
 
-/// Create a list of operations necessary to install `manifest`.
-pub fn install_manifest(manifest: &Manifest) -> Vec<Operation<'_>> {
+/// Create a list of operations necessary to install `manifest` on `platform`.
+///
+/// The order of operations is stable: First all downloads in the order of the manifest, then
+/// the install operations of each download in the same order, then cache updates, then
+/// post-install hooks, and finally operations to enable systemd units.
+pub fn install_manifest(manifest: &Manifest, platform: Platform) -> Vec<Operation<'_>> {
     let mut operations = Vec::with_capacity(manifest.number_of_install_operations());
     // First download all artifacts…
-    for download in &manifest.install {
+    for download in manifest.downloads(platform) {
         push_download(download, &mut operations);
     }
-    // …then install.
-    for download in &manifest.install {
-        push_download_install(download, &mut operations);
+    // …then install…
+    for download in manifest.downloads(platform) {
+        push_download_install(manifest, download, &mut operations);
     }
+    // …refresh caches…
+    push_cache_updates(&mut operations);
+    // …run hooks…
+    push_post_install(manifest, platform, &mut operations);
+    // …and finally start services.
+    push_systemd_enable(manifest, platform, &mut operations);
     operations
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::manifest::Shell;
+    use crate::manifest::{Arch, IconSize, IconTheme, Install, Os, Platform, Shell};
     use crate::operations::DestinationDirectory::*;
     use crate::operations::SourceDirectory::*;
     use crate::operations::*;
@@ -128,18 +172,26 @@ mod tests {
     use pretty_assertions::assert_eq;
     use std::borrow::Cow;
 
+    const X86_64_LINUX: Platform = Platform {
+        os: Os::Linux,
+        arch: Arch::X86_64,
+    };
+
     #[test]
     fn install_manifest_multiple_files() {
         let manifest = Manifest::read_from_path("tests/manifests/ripgrep.toml").unwrap();
         assert_eq!(
-            install_manifest(&manifest),
+            install_manifest(&manifest, X86_64_LINUX),
             vec![
                 Operation::Download(
                     Cow::Borrowed(&manifest.install[0].download),
                     Cow::Borrowed("ripgrep-12.1.1-x86_64-unknown-linux-musl.tar.gz"),
                     Cow::Borrowed(&manifest.install[0].checksums),
                 ),
-                Operation::Extract(Cow::from("ripgrep-12.1.1-x86_64-unknown-linux-musl.tar.gz")),
+                Operation::Extract(
+                    Cow::from("ripgrep-12.1.1-x86_64-unknown-linux-musl.tar.gz"),
+                    0
+                ),
                 Operation::Copy(
                     Source::new(
                         WorkDir,
@@ -172,7 +224,8 @@ mod tests {
                     ),
                     Destination::new(SystemdUserUnitDir, Cow::from("rg.unit")),
                     Permissions::Regular
-                )
+                ),
+                Operation::SystemdDaemonReload,
             ]
         );
     }
@@ -181,7 +234,7 @@ mod tests {
     fn install_manifest_single_file() {
         let manifest = Manifest::read_from_path("tests/manifests/shfmt.toml").unwrap();
         assert_eq!(
-            install_manifest(&manifest),
+            install_manifest(&manifest, X86_64_LINUX),
             vec![
                 Operation::Download(
                     Cow::Borrowed(&manifest.install[0].download),
@@ -196,4 +249,275 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn install_manifest_desktop_entry() {
+        let manifest = Manifest::read_from_path("tests/manifests/neovide.toml").unwrap();
+        assert_eq!(
+            install_manifest(&manifest, X86_64_LINUX),
+            vec![
+                Operation::Download(
+                    Cow::Borrowed(&manifest.install[0].download),
+                    Cow::from("neovide-linux.tar.gz"),
+                    Cow::Borrowed(&manifest.install[0].checksums),
+                ),
+                Operation::Extract(Cow::from("neovide-linux.tar.gz"), 0),
+                Operation::Copy(
+                    Source::new(WorkDir, Cow::from("neovide/neovide")),
+                    Destination::new(BinDir, Cow::from("neovide")),
+                    Permissions::Executable
+                ),
+                Operation::Copy(
+                    Source::new(WorkDir, Cow::from("neovide/neovide.desktop")),
+                    Destination::new(DesktopEntryDir, Cow::from("neovide.desktop")),
+                    Permissions::Regular
+                ),
+                Operation::Copy(
+                    Source::new(WorkDir, Cow::from("neovide/neovide.svg")),
+                    Destination::new(
+                        IconDir(IconTheme::Hicolor, IconSize::Scalable),
+                        Cow::from("neovide.svg")
+                    ),
+                    Permissions::Regular
+                ),
+                Operation::UpdateDesktopDatabase,
+            ]
+        );
+    }
+
+    #[test]
+    fn install_manifest_fonts() {
+        let manifest = Manifest::read_from_path("tests/manifests/fira-code.toml").unwrap();
+        assert_eq!(
+            install_manifest(&manifest, X86_64_LINUX),
+            vec![
+                Operation::Download(
+                    Cow::Borrowed(&manifest.install[0].download),
+                    Cow::from("Fira_Code_v5.2.zip"),
+                    Cow::Borrowed(&manifest.install[0].checksums),
+                ),
+                Operation::Extract(Cow::from("Fira_Code_v5.2.zip"), 0),
+                Operation::Copy(
+                    Source::new(WorkDir, Cow::from("ttf/FiraCode-Regular.ttf")),
+                    Destination::new(FontDir, Cow::from("FiraCode-Regular.ttf")),
+                    Permissions::Regular
+                ),
+                Operation::Copy(
+                    Source::new(WorkDir, Cow::from("ttf/FiraCode-Bold.ttf")),
+                    Destination::new(FontDir, Cow::from("FiraCode-Bold.ttf")),
+                    Permissions::Regular
+                ),
+                Operation::UpdateFontCache,
+            ]
+        );
+    }
+
+    #[test]
+    fn install_manifest_enable_systemd_unit() {
+        let manifest = Manifest::read_from_path("tests/manifests/syncthing.toml").unwrap();
+        let operations = install_manifest(&manifest, X86_64_LINUX);
+        assert_eq!(
+            &operations[operations.len() - 3..],
+            &[
+                Operation::Copy(
+                    Source::new(
+                        WorkDir,
+                        Cow::from(
+                            "syncthing-linux-amd64-v1.12.1/etc/linux-systemd/user/syncthing.service"
+                        )
+                    ),
+                    Destination::new(SystemdUserUnitDir, Cow::from("syncthing.service")),
+                    Permissions::Regular
+                ),
+                Operation::SystemdDaemonReload,
+                Operation::SystemdEnable(Cow::from("syncthing.service")),
+            ]
+        );
+    }
+
+    #[test]
+    fn remove_manifest_disable_systemd_unit() {
+        let manifest = Manifest::read_from_path("tests/manifests/syncthing.toml").unwrap();
+        assert_eq!(
+            remove_manifest(&manifest, X86_64_LINUX),
+            vec![
+                Operation::SystemdDisable(Cow::from("syncthing.service")),
+                Operation::Remove(BinDir, Cow::from("syncthing")),
+                Operation::Remove(SystemdUserUnitDir, Cow::from("syncthing.service")),
+                Operation::SystemdDaemonReload,
+            ]
+        );
+    }
+
+    #[test]
+    fn install_and_remove_info_page() {
+        let manifest = Manifest::read_from_path("tests/manifests/hello.toml").unwrap();
+        let operations = install_manifest(&manifest, X86_64_LINUX);
+        assert_eq!(
+            &operations[3..5],
+            &[
+                Operation::Copy(
+                    Source::new(WorkDir, Cow::from("hello-2.10/hello.info")),
+                    Destination::new(InfoDir, Cow::from("hello.info")),
+                    Permissions::Regular
+                ),
+                Operation::InstallInfo(Cow::from("hello.info")),
+            ]
+        );
+        assert_eq!(
+            &remove_manifest(&manifest, X86_64_LINUX)[..3],
+            &[
+                Operation::RemoveInfo(Cow::from("hello.info")),
+                Operation::Remove(BinDir, Cow::from("hello")),
+                Operation::Remove(InfoDir, Cow::from("hello.info")),
+            ]
+        );
+    }
+
+    #[test]
+    fn install_data_file() {
+        let manifest = Manifest::read_from_path("tests/manifests/hello.toml").unwrap();
+        let operations = install_manifest(&manifest, X86_64_LINUX);
+        assert_eq!(
+            operations[5],
+            Operation::Copy(
+                Source::new(WorkDir, Cow::from("hello-2.10/greetings.txt")),
+                Destination::new(DataDir, Cow::from("hello/greetings/greetings.txt")),
+                Permissions::Regular
+            )
+        );
+    }
+
+    #[test]
+    fn install_optional_file() {
+        let mut manifest = Manifest::read_from_path("tests/manifests/hello.toml").unwrap();
+        if let Install::FilesFromArchive { files, .. } = &mut manifest.install[0].install {
+            files[2].optional = true;
+        }
+        let operations = install_manifest(&manifest, X86_64_LINUX);
+        assert_eq!(
+            operations[5],
+            Operation::CopyOptional(
+                Source::new(WorkDir, Cow::from("hello-2.10/greetings.txt")),
+                Destination::new(DataDir, Cow::from("hello/greetings/greetings.txt")),
+                Permissions::Regular
+            )
+        );
+    }
+
+    #[test]
+    fn install_and_remove_license_file() {
+        let manifest = Manifest::read_from_path("tests/manifests/hello.toml").unwrap();
+        assert_eq!(
+            install_manifest(&manifest, X86_64_LINUX).last(),
+            Some(&Operation::Copy(
+                Source::new(WorkDir, Cow::from("hello-2.10/COPYING")),
+                Destination::new(LicenseDir, Cow::from("hello/COPYING")),
+                Permissions::Regular
+            ))
+        );
+        assert_eq!(
+            remove_manifest(&manifest, X86_64_LINUX).last(),
+            Some(&Operation::Remove(LicenseDir, Cow::from("hello/COPYING")))
+        );
+    }
+
+    #[test]
+    fn install_manifest_post_install_hook() {
+        let manifest = Manifest::read_from_path("tests/manifests/tealdeer.toml").unwrap();
+        assert_eq!(
+            install_manifest(&manifest, X86_64_LINUX),
+            vec![
+                Operation::Download(
+                    Cow::Borrowed(&manifest.install[0].download),
+                    Cow::from("tldr-linux-x86_64-musl"),
+                    Cow::Borrowed(&manifest.install[0].checksums),
+                ),
+                Operation::Copy(
+                    Source::new(Download, Cow::from("tldr-linux-x86_64-musl")),
+                    Destination::new(BinDir, Cow::from("tldr")),
+                    Permissions::Executable
+                ),
+                Operation::Run(Cow::Owned(vec!["tldr".to_string(), "--update".to_string()])),
+            ]
+        );
+    }
+
+    #[test]
+    fn remove_manifest_pre_remove_hook() {
+        let manifest = Manifest::read_from_path("tests/manifests/tealdeer.toml").unwrap();
+        assert_eq!(
+            remove_manifest(&manifest, X86_64_LINUX),
+            vec![
+                Operation::Run(Cow::Owned(vec![
+                    "tldr".to_string(),
+                    "--clear-cache".to_string()
+                ])),
+                Operation::Remove(BinDir, Cow::from("tldr")),
+            ]
+        );
+    }
+
+    #[test]
+    fn install_manifest_verify_minisign() {
+        let manifest = Manifest::read_from_path("tests/manifests/zig.toml").unwrap();
+        assert_eq!(
+            &install_manifest(&manifest, X86_64_LINUX)[..3],
+            &[
+                Operation::Download(
+                    Cow::Borrowed(&manifest.install[0].download),
+                    Cow::from("zig-linux-x86_64-0.7.1.tar.xz"),
+                    Cow::Borrowed(&manifest.install[0].checksums),
+                ),
+                Operation::VerifyMinisign(
+                    Cow::Owned(
+                        url::Url::parse(
+                            "https://ziglang.org/download/0.7.1/zig-linux-x86_64-0.7.1.tar.xz.minisig"
+                        )
+                        .unwrap()
+                    ),
+                    Cow::from("zig-linux-x86_64-0.7.1.tar.xz"),
+                    Cow::from("RWSGOq2NVecA2UPNdBUZykf1CCb147pkmdtYxgb3Ti+JO/wCYvhbAb/U"),
+                ),
+                Operation::Extract(Cow::from("zig-linux-x86_64-0.7.1.tar.xz"), 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn install_manifest_selects_platform() {
+        let manifest = Manifest::read_from_path("tests/manifests/fd.toml").unwrap();
+        assert_eq!(
+            install_manifest(
+                &manifest,
+                Platform {
+                    os: Os::Linux,
+                    arch: Arch::Aarch64
+                }
+            ),
+            vec![
+                Operation::Download(
+                    Cow::Borrowed(&manifest.install[1].download),
+                    Cow::Borrowed("fd-v8.2.1-aarch64-unknown-linux-gnu.tar.gz"),
+                    Cow::Borrowed(&manifest.install[1].checksums),
+                ),
+                Operation::Download(
+                    Cow::Borrowed(&manifest.install[3].download),
+                    Cow::Borrowed("fd.1"),
+                    Cow::Borrowed(&manifest.install[3].checksums),
+                ),
+                Operation::Extract(Cow::from("fd-v8.2.1-aarch64-unknown-linux-gnu.tar.gz"), 0),
+                Operation::Copy(
+                    Source::new(WorkDir, Cow::from("fd-v8.2.1-aarch64-unknown-linux-gnu/fd")),
+                    Destination::new(BinDir, Cow::from("fd")),
+                    Permissions::Executable
+                ),
+                Operation::Copy(
+                    Source::new(Download, Cow::from("fd.1")),
+                    Destination::new(ManDir(1), Cow::from("fd.1")),
+                    Permissions::Regular
+                ),
+            ]
+        );
+    }
 }
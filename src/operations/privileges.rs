@@ -0,0 +1,240 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::fmt::{Display, Formatter};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use super::types::*;
+use super::util::operation_destinations;
+use crate::tools::shell_quote;
+use crate::InstallDirs;
+
+/// Commands which only root can run successfully.
+const PRIVILEGED_COMMANDS: [&str; 9] = [
+    "setcap", "chown", "chgrp", "mount", "modprobe", "sysctl", "useradd", "groupadd", "usermod",
+];
+
+/// Commands which elevate privileges of the command they run.
+const ELEVATING_COMMANDS: [&str; 3] = ["sudo", "doas", "pkexec"];
+
+/// An operation which needs elevated privileges.
+#[derive(Debug, PartialEq, Eq)]
+pub enum PrivilegedOperation {
+    /// An operation writes files of the given kind to a directory which homebins can't write to,
+    /// because the nearest existing ancestor isn't writable.
+    UnwritableDirectory {
+        /// The kind of files in the directory.
+        kind: DestinationKind,
+        /// The directory homebins needs to write to.
+        directory: PathBuf,
+        /// The nearest existing ancestor of `directory`, which isn't writable.
+        ancestor: PathBuf,
+    },
+    /// A hook runs a command which only root can run, and which will fail.
+    PrivilegedCommand(Vec<String>),
+    /// A hook elevates privileges itself, e.g. with sudo, and will prompt for a password.
+    ElevatingCommand(Vec<String>),
+}
+
+impl PrivilegedOperation {
+    /// Whether this operation fails without elevated privileges.
+    ///
+    /// Elevating commands don't fail, but ask for a password.
+    pub fn fails(&self) -> bool {
+        !matches!(self, PrivilegedOperation::ElevatingCommand(_))
+    }
+
+    /// Suggest how to avoid elevated privileges, or the privileged command to run instead.
+    pub fn suggestion(&self) -> String {
+        match self {
+            PrivilegedOperation::UnwritableDirectory { kind, ancestor, .. } => format!(
+                "skip these files with skip_destinations = [\"{}\"] in the configuration, or take ownership with: sudo chown -R \"$USER\" {}",
+                kind,
+                shell_quote(&ancestor.to_string_lossy())
+            ),
+            PrivilegedOperation::PrivilegedCommand(command) => format!(
+                "skip hooks with --skip-host-operations and run yourself: sudo {}",
+                quote_command(command)
+            ),
+            PrivilegedOperation::ElevatingCommand(_) => {
+                "skip hooks with --skip-host-operations to install without a password prompt"
+                    .to_string()
+            }
+        }
+    }
+}
+
+impl Display for PrivilegedOperation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PrivilegedOperation::UnwritableDirectory {
+                directory,
+                ancestor,
+                ..
+            } => write!(
+                f,
+                "Cannot write to {}: {} is not writable",
+                directory.display(),
+                ancestor.display()
+            ),
+            PrivilegedOperation::PrivilegedCommand(command) => {
+                write!(f, "Hook {} needs root privileges", quote_command(command))
+            }
+            PrivilegedOperation::ElevatingCommand(command) => write!(
+                f,
+                "Hook {} asks for elevated privileges",
+                quote_command(command)
+            ),
+        }
+    }
+}
+
+fn quote_command(command: &[String]) -> String {
+    let words: Vec<String> = command.iter().map(|s| shell_quote(s)).collect();
+    words.join(" ")
+}
+
+/// Whether homebins runs as root.
+fn is_root() -> bool {
+    std::fs::metadata("/proc/self")
+        .map(|metadata| metadata.uid() == 0)
+        .unwrap_or(false)
+}
+
+/// Get the nearest ancestor of `path` which exists, including `path` itself.
+fn nearest_existing_ancestor(path: &Path) -> Option<&Path> {
+    path.ancestors().find(|ancestor| ancestor.exists())
+}
+
+/// Whether we can create files in `directory`.
+fn is_writable(directory: &Path) -> bool {
+    tempfile::tempfile_in(directory).is_ok()
+}
+
+/// Get the program of `command`, without leading directories.
+fn program(command: &[String]) -> Option<&str> {
+    command
+        .first()
+        .map(|program| program.rsplit('/').next().unwrap_or(program))
+}
+
+/// Find operations among `operations` which need elevated privileges with `dirs`, unless `root`.
+fn privileged_operations_as(
+    operations: &[Operation<'_>],
+    dirs: &InstallDirs,
+    root: bool,
+) -> Vec<PrivilegedOperation> {
+    let mut privileged = Vec::new();
+    let mut directories = Vec::new();
+    for destination in operation_destinations(operations.iter()) {
+        let directory = dirs.path(destination.directory()).join(destination.name());
+        let directory = directory.parent().unwrap_or(&directory).to_path_buf();
+        if !directories.contains(&directory) {
+            if let Some(ancestor) = nearest_existing_ancestor(&directory) {
+                if !is_writable(ancestor) {
+                    privileged.push(PrivilegedOperation::UnwritableDirectory {
+                        kind: destination.directory().kind(),
+                        ancestor: ancestor.to_path_buf(),
+                        directory: directory.clone(),
+                    });
+                }
+            }
+            directories.push(directory);
+        }
+    }
+    for operation in operations {
+        if let Operation::Run(command) = operation {
+            match program(command) {
+                Some(program) if ELEVATING_COMMANDS.contains(&program) => {
+                    privileged.push(PrivilegedOperation::ElevatingCommand(command.to_vec()))
+                }
+                Some(program) if !root && PRIVILEGED_COMMANDS.contains(&program) => {
+                    privileged.push(PrivilegedOperation::PrivilegedCommand(command.to_vec()))
+                }
+                _ => {}
+            }
+        }
+    }
+    privileged
+}
+
+/// Find operations among `operations` which need elevated privileges to apply them to `dirs`.
+///
+/// This detects files which homebins can't write and hooks which need root before applying any
+/// operation, instead of failing half-way through.
+pub fn privileged_operations(
+    operations: &[Operation<'_>],
+    dirs: &InstallDirs,
+) -> Vec<PrivilegedOperation> {
+    privileged_operations_as(operations, dirs, is_root())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::borrow::Cow;
+
+    fn command(words: &[&str]) -> Vec<String> {
+        words.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn privileged_hooks() {
+        let dir = tempfile::tempdir().unwrap();
+        let dirs = InstallDirs::in_directory(dir.path());
+        let operations = vec![
+            Operation::Run(Cow::Owned(command(&["rg", "--version"]))),
+            Operation::Run(Cow::Owned(command(&[
+                "/usr/sbin/setcap",
+                "cap_net_raw+ep",
+                "bin/ping",
+            ]))),
+            Operation::Run(Cow::Owned(command(&["sudo", "sysctl", "-w", "foo=1"]))),
+        ];
+        let privileged = privileged_operations_as(&operations, &dirs, false);
+        assert_eq!(
+            privileged,
+            vec![
+                PrivilegedOperation::PrivilegedCommand(command(&[
+                    "/usr/sbin/setcap",
+                    "cap_net_raw+ep",
+                    "bin/ping"
+                ])),
+                PrivilegedOperation::ElevatingCommand(command(&["sudo", "sysctl", "-w", "foo=1"])),
+            ]
+        );
+        assert!(privileged[0].fails());
+        assert!(!privileged[1].fails());
+        assert_eq!(
+            privileged[0].suggestion(),
+            "skip hooks with --skip-host-operations and run yourself: sudo '/usr/sbin/setcap' 'cap_net_raw+ep' 'bin/ping'"
+        );
+        assert_eq!(
+            privileged_operations_as(&operations, &dirs, true),
+            vec![PrivilegedOperation::ElevatingCommand(command(&[
+                "sudo", "sysctl", "-w", "foo=1"
+            ]))]
+        );
+    }
+
+    #[test]
+    fn writable_destinations() {
+        let dir = tempfile::tempdir().unwrap();
+        let dirs = InstallDirs::in_directory(&dir.path().join("home"));
+        let operations = vec![Operation::Copy(
+            Source::new(SourceDirectory::WorkDir, Cow::from("foo")),
+            Destination::new(DestinationDirectory::BinDir, Cow::from("foo")),
+            Permissions::Executable,
+        )];
+        assert_eq!(
+            nearest_existing_ancestor(&dirs.bin_dir().join("foo")),
+            Some(dir.path())
+        );
+        assert_eq!(privileged_operations_as(&operations, &dirs, false), vec![]);
+    }
+}
@@ -4,30 +4,50 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
+use std::borrow::Cow;
+
 use super::install::install_manifest;
 use super::types::*;
 use super::util::*;
-use crate::manifest::Remove;
+use crate::manifest::Platform;
 use crate::Manifest;
 
-pub fn push_additional_remove<'a>(remove: &'a Remove, operations: &mut Vec<Operation<'a>>) {
-    for to_remove in &remove.additional_files {
+pub fn push_additional_remove<'a>(manifest: &'a Manifest, operations: &mut Vec<Operation<'a>>) {
+    for to_remove in &manifest.remove.additional_files {
         let (dir, _) = dir_and_permissions(&to_remove.target);
-        operations.push(Operation::Remove(dir, (&to_remove.name).into()))
+        operations.push(Operation::Remove(
+            dir,
+            destination_name(manifest, &to_remove.target, (&to_remove.name).into()),
+        ))
     }
 }
 
-/// Create a list of operations necessary to remove `manifest`.
-pub fn remove_manifest(manifest: &Manifest) -> Vec<Operation<'_>> {
-    let install_ops = install_manifest(manifest);
+/// Create a list of operations necessary to remove `manifest` installed on `platform`.
+pub fn remove_manifest(manifest: &Manifest, platform: Platform) -> Vec<Operation<'_>> {
+    let install_ops = install_manifest(manifest, platform);
     let mut remove_ops =
         Vec::with_capacity(install_ops.len() + manifest.remove.additional_files.len());
+    // Run hooks while all files are still in place
+    for hook in &manifest.remove.pre {
+        remove_ops.push(Operation::Run(Cow::Borrowed(&hook.command)));
+    }
+    // Stop services before removing their unit files
+    for unit in systemd_units_to_enable(manifest, platform) {
+        remove_ops.push(Operation::SystemdDisable(unit));
+    }
+    // Remove info pages from the info directory before deleting them
+    for destination in operation_destinations(install_ops.iter()) {
+        if destination.directory() == DestinationDirectory::InfoDir {
+            remove_ops.push(Operation::RemoveInfo(destination.name().to_string().into()));
+        }
+    }
     for destination in operation_destinations(install_ops.iter()) {
         remove_ops.push(Operation::Remove(
             destination.directory(),
             destination.name().to_string().into(),
         ));
     }
-    push_additional_remove(&manifest.remove, &mut remove_ops);
+    push_additional_remove(manifest, &mut remove_ops);
+    push_cache_updates(&mut remove_ops);
     remove_ops
 }
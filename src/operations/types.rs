@@ -4,9 +4,12 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-use crate::manifest::{Checksums, Shell};
+use crate::manifest::{Checksums, IconSize, IconTheme, Shell};
+use serde::{Deserialize, Deserializer};
 use std::borrow::Cow;
+use std::fmt::{Display, Formatter};
 use std::ops::Deref;
+use std::str::FromStr;
 use url::Url;
 
 /// A source directory for manifest installation.
@@ -31,6 +34,148 @@ pub enum DestinationDirectory {
     SystemdUserUnitDir,
     /// The directory for completion files for the given shell.
     CompletionDir(Shell),
+    /// The directory for desktop entries.
+    DesktopEntryDir,
+    /// The directory for application icons of the given theme and size.
+    IconDir(IconTheme, IconSize),
+    /// The directory for fonts.
+    FontDir,
+    /// The directory for info pages.
+    InfoDir,
+    /// The base directory for data files.
+    ///
+    /// Destinations in this directory may have names with subdirectories.
+    DataDir,
+    /// The base directory for license files.
+    ///
+    /// Destinations in this directory have the manifest name as subdirectory.
+    LicenseDir,
+}
+
+impl DestinationDirectory {
+    /// The kind of this destination.
+    pub fn kind(self) -> DestinationKind {
+        use DestinationDirectory::*;
+        match self {
+            BinDir => DestinationKind::Binaries,
+            ManDir(_) => DestinationKind::Manpages,
+            SystemdUserUnitDir => DestinationKind::SystemdUserUnits,
+            CompletionDir(_) => DestinationKind::Completions,
+            DesktopEntryDir => DestinationKind::DesktopEntries,
+            IconDir(_, _) => DestinationKind::Icons,
+            FontDir => DestinationKind::Fonts,
+            InfoDir => DestinationKind::InfoPages,
+            DataDir => DestinationKind::Data,
+            LicenseDir => DestinationKind::Licenses,
+        }
+    }
+}
+
+/// The kind of a destination directory.
+///
+/// Groups destination directories into categories of installed files, e.g. to skip all
+/// completions regardless of the shell.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DestinationKind {
+    /// Binaries.
+    Binaries,
+    /// Manpages of all sections.
+    Manpages,
+    /// Systemd user units.
+    SystemdUserUnits,
+    /// Completions for all shells.
+    Completions,
+    /// Completions for the given shell.
+    ShellCompletions(Shell),
+    /// Desktop entries.
+    DesktopEntries,
+    /// Icons of all themes and sizes.
+    Icons,
+    /// Fonts.
+    Fonts,
+    /// Info pages.
+    InfoPages,
+    /// Data files.
+    Data,
+    /// License files.
+    Licenses,
+}
+
+impl DestinationKind {
+    /// All kinds of destinations.
+    pub const ALL: [DestinationKind; 10] = [
+        DestinationKind::Binaries,
+        DestinationKind::Manpages,
+        DestinationKind::SystemdUserUnits,
+        DestinationKind::Completions,
+        DestinationKind::DesktopEntries,
+        DestinationKind::Icons,
+        DestinationKind::Fonts,
+        DestinationKind::InfoPages,
+        DestinationKind::Data,
+        DestinationKind::Licenses,
+    ];
+
+    /// Whether this kind includes the given `directory`.
+    pub fn includes(self, directory: DestinationDirectory) -> bool {
+        match (self, directory) {
+            (DestinationKind::ShellCompletions(shell), DestinationDirectory::CompletionDir(d)) => {
+                shell == d
+            }
+            (kind, directory) => kind == directory.kind(),
+        }
+    }
+}
+
+impl FromStr for DestinationKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use DestinationKind::*;
+        match s {
+            "binaries" => Ok(Binaries),
+            "manpages" => Ok(Manpages),
+            "systemd_user_units" => Ok(SystemdUserUnits),
+            "completions" => Ok(Completions),
+            "fish_completions" => Ok(ShellCompletions(Shell::Fish)),
+            "desktop_entries" => Ok(DesktopEntries),
+            "icons" => Ok(Icons),
+            "fonts" => Ok(Fonts),
+            "info_pages" => Ok(InfoPages),
+            "data" => Ok(Data),
+            "licenses" => Ok(Licenses),
+            _ => Err(format!("Unknown kind of destination: {:?}", s)),
+        }
+    }
+}
+
+impl Display for DestinationKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        use DestinationKind::*;
+        let name = match self {
+            Binaries => "binaries",
+            Manpages => "manpages",
+            SystemdUserUnits => "systemd_user_units",
+            Completions => "completions",
+            ShellCompletions(Shell::Fish) => "fish_completions",
+            DesktopEntries => "desktop_entries",
+            Icons => "icons",
+            Fonts => "fonts",
+            InfoPages => "info_pages",
+            Data => "data",
+            Licenses => "licenses",
+        };
+        f.write_str(name)
+    }
+}
+
+impl<'de> Deserialize<'de> for DestinationKind {
+    fn deserialize<D>(d: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        String::deserialize(d).and_then(|s| s.parse().map_err(serde::de::Error::custom))
+    }
 }
 
 /// Permissions for the target of a copy operation.
@@ -44,7 +189,7 @@ pub enum Permissions {
 
 impl Permissions {
     /// Convert permissions to a Unix file mode.
-    fn to_mode(self) -> u32 {
+    pub fn to_mode(self) -> u32 {
         use Permissions::*;
         match self {
             Regular => 0o644,
@@ -96,16 +241,39 @@ pub type Source<'a> = CopyOperand<'a, SourceDirectory>;
 pub type Destination<'a> = CopyOperand<'a, DestinationDirectory>;
 
 /// Operations to apply a manifest to a home directory.
+// Downloads are much larger than other operations, but we only ever have a few operations, and
+// they mostly borrow from the manifest anyway.
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Operation<'a> {
     /// Download a to the given filename in the manifest download directory and validate against checksums.
     Download(Cow<'a, Url>, Cow<'a, str>, Cow<'a, Checksums>),
-    /// Extract the given filename from the manifest download directory into the manifest work directory.
-    Extract(Cow<'a, str>),
+    /// Download the minisign signature from the given URL and verify the given filename in the manifest download directory with it and the given public key.
+    VerifyMinisign(Cow<'a, Url>, Cow<'a, str>, Cow<'a, str>),
+    /// Extract the given filename from the manifest download directory into the manifest work directory, stripping the given number of leading directories.
+    Extract(Cow<'a, str>, usize),
     /// Copy the given source file to the given destination, with the given permissions on target.
     Copy(Source<'a>, Destination<'a>, Permissions),
+    /// Like `Copy`, but skip with a warning if the source file doesn't exist.
+    CopyOptional(Source<'a>, Destination<'a>, Permissions),
     /// Create a hard link, from the first to the second item.
     Hardlink(Cow<'a, str>, Cow<'a, str>),
     /// Delete a file with the given name from the given destination directory.
     Remove(DestinationDirectory, Cow<'a, str>),
+    /// Update the cache database of MIME types handled by desktop entries.
+    UpdateDesktopDatabase,
+    /// Rebuild the font cache.
+    UpdateFontCache,
+    /// Reload the systemd user manager to pick up changed unit files.
+    SystemdDaemonReload,
+    /// Enable and start the systemd user unit with the given name.
+    SystemdEnable(Cow<'a, str>),
+    /// Disable and stop the systemd user unit with the given name.
+    SystemdDisable(Cow<'a, str>),
+    /// Add the info page with the given name to the directory of info pages.
+    InstallInfo(Cow<'a, str>),
+    /// Delete the info page with the given name from the directory of info pages.
+    RemoveInfo(Cow<'a, str>),
+    /// Run the given command with arguments in the manifest work directory.
+    Run(Cow<'a, [String]>),
 }
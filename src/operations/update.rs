@@ -7,22 +7,28 @@
 use super::install::*;
 use super::remove::*;
 use super::types::*;
+use super::util::*;
+use crate::manifest::Platform;
 use crate::Manifest;
 
-/// Create operations to update the given manifest to a newer version.
-pub fn update_manifest(manifest: &Manifest) -> Vec<Operation<'_>> {
+/// Create operations to update the given manifest to a newer version on `platform`.
+pub fn update_manifest(manifest: &Manifest, platform: Platform) -> Vec<Operation<'_>> {
     let mut operations = Vec::with_capacity(
         manifest.number_of_install_operations() + manifest.remove.additional_files.len(),
     );
     // Download all new artifacts first.
-    for download in &manifest.install {
+    for download in manifest.downloads(platform) {
         push_download(download, &mut operations);
     }
     // Then remove legacy files.
-    push_additional_remove(&manifest.remove, &mut operations);
+    push_additional_remove(manifest, &mut operations);
     // Then install all files again, which overwrites those form the previous release
-    for download in &manifest.install {
-        push_download_install(download, &mut operations);
+    for download in manifest.downloads(platform) {
+        push_download_install(manifest, download, &mut operations);
     }
+    // Finally refresh caches for the new files, run hooks, and enable services.
+    push_cache_updates(&mut operations);
+    push_post_install(manifest, platform, &mut operations);
+    push_systemd_enable(manifest, platform, &mut operations);
     operations
 }
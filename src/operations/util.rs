@@ -4,8 +4,15 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::path::Path;
+
+use url::Url;
+
 use super::types::*;
-use crate::manifest::Target;
+use crate::manifest::{Install, InstallFile, Manifest, Platform, Target};
+use crate::tools::is_contained_path;
 
 pub fn dir_and_permissions(target: &Target) -> (DestinationDirectory, Permissions) {
     match target {
@@ -13,7 +20,7 @@ pub fn dir_and_permissions(target: &Target) -> (DestinationDirectory, Permission
         Target::Manpage { section } => {
             (DestinationDirectory::ManDir(*section), Permissions::Regular)
         }
-        Target::SystemdUserUnit => (
+        Target::SystemdUserUnit { .. } => (
             DestinationDirectory::SystemdUserUnitDir,
             Permissions::Regular,
         ),
@@ -21,6 +28,117 @@ pub fn dir_and_permissions(target: &Target) -> (DestinationDirectory, Permission
             DestinationDirectory::CompletionDir(*shell),
             Permissions::Regular,
         ),
+        Target::DesktopEntry => (DestinationDirectory::DesktopEntryDir, Permissions::Regular),
+        Target::Icon { size, theme } => (
+            DestinationDirectory::IconDir(*theme, *size),
+            Permissions::Regular,
+        ),
+        Target::Font => (DestinationDirectory::FontDir, Permissions::Regular),
+        Target::InfoPage => (DestinationDirectory::InfoDir, Permissions::Regular),
+        Target::Data { .. } => (DestinationDirectory::DataDir, Permissions::Regular),
+        Target::License => (DestinationDirectory::LicenseDir, Permissions::Regular),
+    }
+}
+
+/// The name of a file with the given `name` for the given `target` of `manifest` in its
+/// destination directory.
+///
+/// For data files this prepends the path of the target, and for license files the name of the
+/// manifest; for all other targets this is just the name.
+pub fn destination_name<'a>(
+    manifest: &Manifest,
+    target: &Target,
+    name: Cow<'a, str>,
+) -> Cow<'a, str> {
+    match target {
+        Target::Data { path } => Cow::Owned(format!("{}/{}", path, name)),
+        Target::License => Cow::Owned(format!("{}/{}", manifest.info.name, name)),
+        _ => name,
+    }
+}
+
+/// The name to install the given archive `file` as.
+///
+/// This is the explicit name of `file` if any, or the file name of its source.
+pub fn install_file_name(file: &InstallFile) -> &str {
+    file.name.as_deref().unwrap_or_else(|| {
+        file.source
+            .rsplit('/')
+            .next()
+            .expect("rsplit should always be non-empty!")
+    })
+}
+
+/// Get the names of all systemd user units of `manifest` to enable after installation on `platform`.
+pub fn systemd_units_to_enable(manifest: &Manifest, platform: Platform) -> Vec<Cow<'_, str>> {
+    let mut units = Vec::new();
+    for download in manifest.downloads(platform) {
+        match &download.install {
+            Install::SingleFile {
+                name,
+                target: Target::SystemdUserUnit { enable: true },
+            } => units.push(
+                name.as_deref()
+                    .map_or_else(|| download.filename(), Cow::Borrowed),
+            ),
+            Install::SingleFile { .. } => {}
+            Install::FilesFromArchive { files, .. } => {
+                for file in files {
+                    if let Target::SystemdUserUnit { enable: true } = file.target {
+                        units.push(Cow::Borrowed(install_file_name(file)));
+                    }
+                }
+            }
+        }
+    }
+    units
+}
+
+/// Add operations to enable and start all systemd user units to enable from `manifest`.
+pub fn push_systemd_enable<'a>(
+    manifest: &'a Manifest,
+    platform: Platform,
+    operations: &mut Vec<Operation<'a>>,
+) {
+    for unit in systemd_units_to_enable(manifest, platform) {
+        operations.push(Operation::SystemdEnable(unit));
+    }
+}
+
+/// Add operations to run the post-install hooks of `manifest` on `platform`.
+pub fn push_post_install<'a>(
+    manifest: &'a Manifest,
+    platform: Platform,
+    operations: &mut Vec<Operation<'a>>,
+) {
+    for download in manifest.downloads(platform) {
+        for hook in &download.post {
+            operations.push(Operation::Run(Cow::Borrowed(&hook.command)));
+        }
+    }
+}
+
+/// Whether any of `operations` copies to or removes from the given `directory`.
+pub fn touches_directory(operations: &[Operation<'_>], directory: DestinationDirectory) -> bool {
+    operations.iter().any(|operation| match operation {
+        Operation::Copy(_, destination, _) | Operation::CopyOptional(_, destination, _) => {
+            destination.directory() == directory
+        }
+        Operation::Remove(dir, _) => *dir == directory,
+        _ => false,
+    })
+}
+
+/// Add operations to refresh caches affected by `operations` to `operations`.
+pub fn push_cache_updates(operations: &mut Vec<Operation<'_>>) {
+    if touches_directory(operations, DestinationDirectory::DesktopEntryDir) {
+        operations.push(Operation::UpdateDesktopDatabase);
+    }
+    if touches_directory(operations, DestinationDirectory::FontDir) {
+        operations.push(Operation::UpdateFontCache);
+    }
+    if touches_directory(operations, DestinationDirectory::SystemdUserUnitDir) {
+        operations.push(Operation::SystemdDaemonReload);
     }
 }
 
@@ -32,10 +150,12 @@ where
     operations.filter_map(|operation| {
         match operation {
             // TODO: Don't clone but always borrowed out of contained cows
-            Operation::Copy(_, destination, _) => Some(Destination::new(
-                destination.directory(),
-                destination.name().into(),
-            )),
+            Operation::Copy(_, destination, _) | Operation::CopyOptional(_, destination, _) => {
+                Some(Destination::new(
+                    destination.directory(),
+                    destination.name().into(),
+                ))
+            }
             Operation::Hardlink(_, target) => Some(Destination::new(
                 DestinationDirectory::BinDir,
                 target.as_ref().into(),
@@ -44,16 +164,139 @@ where
                 Some(Destination::new(*directory, name.as_ref().into()))
             }
             Operation::Download(_, _, _) => None,
-            Operation::Extract(_) => None,
+            Operation::VerifyMinisign(_, _, _) => None,
+            Operation::Extract(_, _) => None,
+            Operation::UpdateDesktopDatabase => None,
+            Operation::UpdateFontCache => None,
+            Operation::SystemdDaemonReload => None,
+            Operation::SystemdEnable(_) => None,
+            Operation::SystemdDisable(_) => None,
+            Operation::InstallInfo(_) => None,
+            Operation::RemoveInfo(_) => None,
+            Operation::Run(_) => None,
         }
     })
 }
 
-#[cfg(tests)]
+/// Get all destinations of copy operations within `operations`, with the permissions of copies.
+pub fn copy_permissions<'b>(
+    operations: &'b [Operation<'_>],
+) -> Vec<(Destination<'b>, Permissions)> {
+    operations
+        .iter()
+        .filter_map(|operation| match operation {
+            Operation::Copy(_, destination, permissions)
+            | Operation::CopyOptional(_, destination, permissions) => Some((
+                Destination::new(destination.directory(), destination.name().into()),
+                *permissions,
+            )),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Get all file names in `operations` which escape the directory they're relative to.
+///
+/// File names of operations are relative to the download, work or install directories, so they
+/// must be neither absolute nor contain `..`, see [`is_contained_path`].
+pub fn escaping_names<'b>(operations: &'b [Operation<'_>]) -> Vec<&'b str> {
+    let mut names: Vec<&str> = Vec::new();
+    for operation in operations {
+        match operation {
+            Operation::Download(_, name, _)
+            | Operation::VerifyMinisign(_, name, _)
+            | Operation::Extract(name, _)
+            | Operation::Remove(_, name)
+            | Operation::SystemdEnable(name)
+            | Operation::SystemdDisable(name)
+            | Operation::InstallInfo(name)
+            | Operation::RemoveInfo(name) => names.push(name),
+            Operation::Copy(source, destination, _)
+            | Operation::CopyOptional(source, destination, _) => {
+                names.push(source.name());
+                names.push(destination.name());
+            }
+            Operation::Hardlink(source, target) => {
+                names.push(source);
+                names.push(target);
+            }
+            Operation::UpdateDesktopDatabase
+            | Operation::UpdateFontCache
+            | Operation::SystemdDaemonReload
+            | Operation::Run(_) => {}
+        }
+    }
+    names.retain(|name| !is_contained_path(Path::new(name)));
+    names
+}
+
+/// Get all installation destinations within `operations` along with the download they come from.
+///
+/// Return the destination, and the URL and file name of the download.  Files copied from the
+/// working directory come from the archive extracted last, and links come from the file they
+/// link to.
+pub fn download_destinations<'b, 'a: 'b>(
+    operations: &'b [Operation<'a>],
+) -> Vec<(Destination<'b>, &'b Url, &'b str)> {
+    let mut downloads: HashMap<&str, &Url> = HashMap::new();
+    let mut archive = None;
+    let mut binaries: HashMap<&str, (&Url, &str)> = HashMap::new();
+    let mut destinations = Vec::new();
+    for operation in operations {
+        match operation {
+            Operation::Download(url, name, _) => {
+                downloads.insert(name, url);
+            }
+            Operation::Extract(name, _) => {
+                archive = downloads
+                    .get(name.as_ref())
+                    .map(|url| (*url, name.as_ref()));
+            }
+            Operation::Copy(source, destination, _)
+            | Operation::CopyOptional(source, destination, _) => {
+                let origin = match source.directory() {
+                    SourceDirectory::Download => downloads
+                        .get(source.name())
+                        .map(|url| (*url, source.name())),
+                    SourceDirectory::WorkDir => archive,
+                };
+                if let Some((url, name)) = origin {
+                    if destination.directory() == DestinationDirectory::BinDir {
+                        binaries.insert(destination.name(), (url, name));
+                    }
+                    destinations.push((
+                        Destination::new(destination.directory(), destination.name().into()),
+                        url,
+                        name,
+                    ));
+                }
+            }
+            Operation::Hardlink(target, link) => {
+                if let Some((url, name)) = binaries.get(target.as_ref()) {
+                    destinations.push((
+                        Destination::new(DestinationDirectory::BinDir, link.as_ref().into()),
+                        url,
+                        name,
+                    ));
+                }
+            }
+            _ => {}
+        }
+    }
+    destinations
+}
+
+#[cfg(test)]
 mod tests {
+    use super::*;
+    use crate::manifest::{Checksums, Shell};
+    use crate::operations::DestinationDirectory::*;
+    use crate::operations::SourceDirectory::*;
+    use pretty_assertions::assert_eq;
+
     #[test]
     fn install_destinations_all() {
-        let operations = vec![
+        let operations = [
             Operation::Download(
                 Cow::Owned(Url::parse("https://example.com/file.tar.gz").unwrap()),
                 "file.tar.gz".into(),
@@ -86,4 +329,61 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn download_destinations_of_archives_and_links() {
+        let archive = Url::parse("https://example.com/file.tar.gz").unwrap();
+        let binary = Url::parse("https://example.com/bar").unwrap();
+        let operations = vec![
+            Operation::Download(
+                Cow::Borrowed(&archive),
+                "file.tar.gz".into(),
+                Cow::Owned(Checksums::default()),
+            ),
+            Operation::Download(
+                Cow::Borrowed(&binary),
+                "bar".into(),
+                Cow::Owned(Checksums::default()),
+            ),
+            Operation::Extract("file.tar.gz".into(), 0),
+            Operation::Copy(
+                Source::new(WorkDir, "spam".into()),
+                Destination::new(BinDir, "spam".into()),
+                Permissions::Executable,
+            ),
+            Operation::Hardlink("spam".into(), "eggs".into()),
+            Operation::CopyOptional(
+                Source::new(WorkDir, "spam.1".into()),
+                Destination::new(ManDir(1), "spam.1".into()),
+                Permissions::Regular,
+            ),
+            Operation::Copy(
+                Source::new(Download, "bar".into()),
+                Destination::new(BinDir, "bar".into()),
+                Permissions::Executable,
+            ),
+            Operation::Hardlink("unknown".into(), "link".into()),
+        ];
+        assert_eq!(
+            download_destinations(&operations),
+            vec![
+                (
+                    Destination::new(BinDir, "spam".into()),
+                    &archive,
+                    "file.tar.gz"
+                ),
+                (
+                    Destination::new(BinDir, "eggs".into()),
+                    &archive,
+                    "file.tar.gz"
+                ),
+                (
+                    Destination::new(ManDir(1), "spam.1".into()),
+                    &archive,
+                    "file.tar.gz"
+                ),
+                (Destination::new(BinDir, "bar".into()), &binary, "bar"),
+            ]
+        );
+    }
 }
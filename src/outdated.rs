@@ -0,0 +1,105 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! A cache of outdated manifests, for shell prompts.
+//!
+//! Finding outdated manifests means running the version check of every installed binary, which
+//! is much too slow for a shell prompt.  `homebins outdated` remembers the names of all outdated
+//! manifests instead, installing, updating and removing manifests keeps these names current,
+//! and `homebins prompt-status` only reads the cache, without fetching repositories or checking
+//! versions.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Error;
+use fehler::throws;
+
+use crate::state::{read_state, write_atomically};
+
+/// The cached names of outdated manifests.
+#[derive(Debug)]
+pub struct OutdatedCache {
+    path: PathBuf,
+    names: Vec<String>,
+}
+
+impl OutdatedCache {
+    /// Open the cache in the file at `path`.
+    ///
+    /// Start with an empty cache if `path` doesn't exist or has invalid contents, because the
+    /// next `homebins outdated` fills the cache anyway.
+    #[throws]
+    pub fn open<P: AsRef<Path>>(path: P) -> OutdatedCache {
+        let path = path.as_ref().to_path_buf();
+        let names = read_state(&path)?
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+        OutdatedCache { path, names }
+    }
+
+    /// The names of all outdated manifests.
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Replace all names with the `names` of outdated manifests.
+    pub fn set_outdated<I: IntoIterator<Item = String>>(&mut self, names: I) {
+        self.names = names.into_iter().collect();
+        self.names.sort();
+    }
+
+    /// Forget the manifest with the given `name`, e.g. because homebins just updated it.
+    ///
+    /// Return whether the manifest was outdated.
+    pub fn forget(&mut self, name: &str) -> bool {
+        let count = self.names.len();
+        self.names.retain(|outdated| outdated != name);
+        count != self.names.len()
+    }
+
+    /// Describe the outdated manifests for a shell prompt, e.g. `3⬆`.
+    ///
+    /// Return an empty string if nothing is outdated.
+    pub fn prompt_status(&self) -> String {
+        match self.names.len() {
+            0 => String::new(),
+            count => format!("{}⬆", count),
+        }
+    }
+
+    /// Write the cache back to its file.
+    #[throws]
+    pub fn save(&self) -> () {
+        write_atomically(&self.path, serde_json::to_vec(&self.names)?)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn cache_outdated_manifests() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("cache").join("outdated.json");
+        let mut cache = OutdatedCache::open(&path).unwrap();
+        assert_eq!(cache.prompt_status(), "");
+
+        cache.set_outdated(vec!["rg".to_string(), "fd".to_string(), "bat".to_string()]);
+        cache.save().unwrap();
+        let mut cache = OutdatedCache::open(&path).unwrap();
+        assert_eq!(cache.names(), ["bat", "fd", "rg"]);
+        assert_eq!(cache.prompt_status(), "3⬆");
+
+        assert!(cache.forget("fd"));
+        assert!(!cache.forget("jq"));
+        assert_eq!(cache.prompt_status(), "2⬆");
+
+        std::fs::write(&path, "garbage").unwrap();
+        assert_eq!(OutdatedCache::open(&path).unwrap().prompt_status(), "");
+    }
+}
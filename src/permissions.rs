@@ -0,0 +1,221 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Check permissions of installed files.
+//!
+//! Homebins installs files with fixed modes, see [`Permissions`], but tools which rewrite
+//! installed files under a restrictive umask, or default ACLs of install directories, can leave
+//! installed files with different effective permissions, e.g. binaries which other users can't
+//! run.  Find these files, tell why their permissions differ, and restore the intended modes.
+
+use std::fmt::{Display, Formatter};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{Context, Error};
+use fehler::throws;
+
+use crate::operations::Permissions;
+use crate::process::CommandExt;
+
+/// Why an installed file has different permissions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PermissionCause {
+    /// The umask of the process which wrote the file removed permissions.
+    Umask(u32),
+    /// The file has extended ACL entries, e.g. inherited from a default ACL of its directory.
+    Acl,
+}
+
+/// An installed file whose permissions differ from the permissions homebins installs it with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PermissionProblem {
+    /// The installed file.
+    pub path: PathBuf,
+    /// The mode of the installed file.
+    pub mode: u32,
+    /// The permissions homebins installs the file with.
+    pub expected: Permissions,
+    /// Why the permissions differ, if homebins can tell.
+    pub cause: Option<PermissionCause>,
+}
+
+impl Display for PermissionProblem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: mode {:03o} instead of {:03o}",
+            self.path.display(),
+            self.mode,
+            self.expected.to_mode()
+        )?;
+        match self.cause {
+            Some(PermissionCause::Umask(umask)) => write!(f, " (umask {:03o})", umask),
+            Some(PermissionCause::Acl) => write!(f, " (extended ACL)"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The umask of this process.
+///
+/// Read the umask from `/proc`, because reading it with `umask(2)` means changing it.  Return
+/// `None` if `/proc` isn't available.
+pub fn current_umask() -> Option<u32> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("Umask:"))
+        .and_then(|umask| u32::from_str_radix(umask.trim(), 8).ok())
+}
+
+/// Find all of `files` with extended ACL entries.
+///
+/// Ask `getfacl` to only print files with extended ACL entries.  Assume no ACLs at all if
+/// `getfacl` isn't installed.
+fn files_with_acls(files: &[&Path]) -> Vec<PathBuf> {
+    if files.is_empty() {
+        return Vec::new();
+    }
+    // getfacl fails if it can't read some ACL, but still prints all others
+    match Command::new("getfacl")
+        .args(["--skip-base", "--absolute-names", "--"])
+        .args(files)
+        .output()
+    {
+        Ok(output) => String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| line.strip_prefix("# file: "))
+            .map(PathBuf::from)
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Find all installed `files` whose permissions differ from their intended permissions.
+///
+/// Skip files which don't exist.  Compare the permission bits of each file with its intended
+/// mode, and report extended ACLs, which grant or deny permissions regardless of the mode.
+/// Blame `umask` if the mode only lacks permissions which `umask` removes.
+#[throws]
+pub fn permission_problems(
+    files: &[(PathBuf, Permissions)],
+    umask: Option<u32>,
+) -> Vec<PermissionProblem> {
+    let mut existing = Vec::with_capacity(files.len());
+    for (path, expected) in files {
+        match std::fs::symlink_metadata(path) {
+            Ok(metadata) if metadata.is_file() => {
+                existing.push((path, metadata.permissions().mode() & 0o7777, *expected))
+            }
+            Ok(_) => {}
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {}
+            Err(error) => {
+                Err(error).with_context(|| format!("Failed to read {}", path.display()))?
+            }
+        }
+    }
+    let paths: Vec<&Path> = existing.iter().map(|(path, _, _)| path.as_path()).collect();
+    let acls = files_with_acls(&paths);
+    existing
+        .into_iter()
+        .filter_map(|(path, mode, expected)| {
+            let cause = if acls.contains(path) {
+                Some(PermissionCause::Acl)
+            } else if mode == expected.to_mode() {
+                return None;
+            } else {
+                umask
+                    .filter(|umask| mode == expected.to_mode() & !umask)
+                    .map(PermissionCause::Umask)
+            };
+            Some(PermissionProblem {
+                path: path.clone(),
+                mode,
+                expected,
+                cause,
+            })
+        })
+        .collect()
+}
+
+/// Restore the intended permissions of the file of `problem`.
+///
+/// Remove all extended ACL entries, and then set the intended mode.
+#[throws]
+pub fn fix_permissions(problem: &PermissionProblem) -> () {
+    if problem.cause == Some(PermissionCause::Acl) {
+        Command::new("setfacl")
+            .arg("--remove-all")
+            .arg("--")
+            .arg(&problem.path)
+            .checked_call()
+            .with_context(|| format!("Failed to remove ACL of {}", problem.path.display()))?;
+    }
+    std::fs::set_permissions(&problem.path, problem.expected.to_unix_permissions()).with_context(
+        || {
+            format!(
+                "Failed to set mode {:03o} on {}",
+                problem.expected.to_mode(),
+                problem.path.display()
+            )
+        },
+    )?;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn find_and_fix_permission_problems() {
+        let dir = tempfile::tempdir().unwrap();
+        let mode = |name: &str, mode: u32| {
+            let path = dir.path().join(name);
+            std::fs::write(&path, name).unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode)).unwrap();
+            path
+        };
+        let files = vec![
+            (mode("ok", 0o755), Permissions::Executable),
+            (mode("umask", 0o700), Permissions::Executable),
+            (mode("odd", 0o664), Permissions::Regular),
+            (dir.path().join("missing"), Permissions::Regular),
+        ];
+        let problems = permission_problems(&files, Some(0o077)).unwrap();
+        assert_eq!(
+            problems,
+            vec![
+                PermissionProblem {
+                    path: files[1].0.clone(),
+                    mode: 0o700,
+                    expected: Permissions::Executable,
+                    cause: Some(PermissionCause::Umask(0o077)),
+                },
+                PermissionProblem {
+                    path: files[2].0.clone(),
+                    mode: 0o664,
+                    expected: Permissions::Regular,
+                    cause: None,
+                }
+            ]
+        );
+        assert_eq!(
+            problems[0].to_string(),
+            format!(
+                "{}: mode 700 instead of 755 (umask 077)",
+                files[1].0.display()
+            )
+        );
+
+        for problem in &problems {
+            fix_permissions(problem).unwrap();
+        }
+        assert_eq!(permission_problems(&files, Some(0o077)).unwrap(), vec![]);
+    }
+}
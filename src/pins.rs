@@ -0,0 +1,200 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Pinned manifests.
+//!
+//! Homebins never updates a pinned manifest, unless the manifest has the pinned version.  Pins
+//! live in a plain text file with one `name` or `name=version` per line, the same format
+//! `homebins pin --file` reads, so teams can distribute a blessed set of versions as a file.
+
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context, Error};
+use fehler::throws;
+
+use crate::state::{read_state, write_atomically};
+
+/// A pinned manifest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pin {
+    /// The name of the manifest.
+    pub name: String,
+    /// The version to pin the manifest at.
+    ///
+    /// If `None` pin the manifest at whatever version is installed.
+    pub version: Option<String>,
+}
+
+impl Pin {
+    /// Whether this pin allows to install `version` of its manifest.
+    pub fn allows(&self, version: &str) -> bool {
+        self.version.as_deref() == Some(version)
+    }
+}
+
+impl FromStr for Pin {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, version) = match s.split_once('=') {
+            Some((name, version)) => (name.trim(), Some(version.trim())),
+            None => (s.trim(), None),
+        };
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            Err(format!("Invalid pin: {:?}", s))
+        } else if version.is_some_and(|v| v.is_empty() || v.contains(char::is_whitespace)) {
+            Err(format!("Invalid version in pin: {:?}", s))
+        } else {
+            Ok(Pin {
+                name: name.to_string(),
+                version: version.map(String::from),
+            })
+        }
+    }
+}
+
+impl Display for Pin {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.version {
+            Some(version) => write!(f, "{}={}", self.name, version),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+/// Parse pins from `text`, with one pin per line.
+///
+/// Ignore empty lines and comments starting with `#`.
+#[throws]
+pub fn parse_pins(text: &str) -> Vec<Pin> {
+    let mut pins = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if !line.is_empty() {
+            pins.push(
+                line.parse()
+                    .map_err(|error: String| anyhow!("Line {}: {}", index + 1, error))?,
+            );
+        }
+    }
+    pins
+}
+
+/// The pins in a pins file.
+#[derive(Debug)]
+pub struct Pins {
+    path: PathBuf,
+    pins: Vec<Pin>,
+}
+
+impl Pins {
+    /// Open the pins file at `path`.
+    ///
+    /// Start without pins if `path` does not exist.
+    #[throws]
+    pub fn open<P: AsRef<Path>>(path: P) -> Pins {
+        let path = path.as_ref().to_path_buf();
+        let entries = match read_state(&path)? {
+            Some(contents) => parse_pins(&contents)
+                .with_context(|| format!("Invalid pins in {}", path.display()))?,
+            None => Vec::new(),
+        };
+        let mut pins = Pins {
+            path,
+            pins: Vec::new(),
+        };
+        for pin in entries {
+            pins.pin(pin);
+        }
+        pins
+    }
+
+    /// Get the pin of the manifest with the given `name`, if any.
+    pub fn get(&self, name: &str) -> Option<&Pin> {
+        self.pins.iter().find(|pin| pin.name == name)
+    }
+
+    /// All pins, ordered by name.
+    pub fn iter(&self) -> impl Iterator<Item = &Pin> {
+        self.pins.iter()
+    }
+
+    /// Add `pin`, replacing any existing pin of the same manifest.
+    pub fn pin(&mut self, pin: Pin) {
+        self.pins.retain(|existing| existing.name != pin.name);
+        self.pins.push(pin);
+        self.pins.sort_by(|a, b| a.name.cmp(&b.name));
+    }
+
+    /// Remove the pin of the manifest with the given `name`.
+    ///
+    /// Return whether the manifest was pinned.
+    pub fn unpin(&mut self, name: &str) -> bool {
+        let count = self.pins.len();
+        self.pins.retain(|pin| pin.name != name);
+        self.pins.len() != count
+    }
+
+    /// Write all pins back to the pins file.
+    #[throws]
+    pub fn save(&self) -> () {
+        let contents: String = self.pins.iter().map(|pin| format!("{}\n", pin)).collect();
+        write_atomically(&self.path, contents)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn parse_pin_list() {
+        let pins =
+            parse_pins("# Blessed versions\nripgrep=12.1.1\n\n  fd  # any version\n").unwrap();
+        assert_eq!(
+            pins,
+            vec![
+                Pin {
+                    name: "ripgrep".to_string(),
+                    version: Some("12.1.1".to_string())
+                },
+                Pin {
+                    name: "fd".to_string(),
+                    version: None
+                },
+            ]
+        );
+        assert_eq!(
+            parse_pins("fd\nripgrep=\n").unwrap_err().to_string(),
+            "Line 2: Invalid version in pin: \"ripgrep=\""
+        );
+    }
+
+    #[test]
+    fn pin_and_unpin() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config").join("pins");
+        let mut pins = Pins::open(&path).unwrap();
+        pins.pin("ripgrep=12.0.0".parse().unwrap());
+        pins.pin("fd".parse().unwrap());
+        pins.pin("ripgrep=12.1.1".parse().unwrap());
+        pins.save().unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "fd\nripgrep=12.1.1\n"
+        );
+
+        let mut pins = Pins::open(&path).unwrap();
+        assert!(pins.get("ripgrep").unwrap().allows("12.1.1"));
+        assert!(!pins.get("fd").unwrap().allows("8.0.0"));
+        assert!(pins.unpin("fd"));
+        assert!(!pins.unpin("fd"));
+        assert_eq!(pins.iter().count(), 1);
+    }
+}
@@ -6,8 +6,10 @@
 
 //! Command extensions
 
-use std::io::{Error, ErrorKind, Result};
-use std::process::{Command, ExitStatus, Output};
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::process::{Command, ExitStatus, Output, Stdio};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 
 pub trait CommandExt {
     /// Spawn and wait for this command.
@@ -16,8 +18,32 @@ pub trait CommandExt {
     /// Spawn and wait for this command and return an error if the exit code is non-zero.
     fn checked_call(&mut self) -> Result<()>;
 
+    /// Spawn this command, write `input` to its stdin, and wait for it.
+    ///
+    /// Return an error if the exit code is non-zero.
+    // Nothing passes input to commands at the moment, since curl reads tokens while we poll it
+    #[allow(dead_code)]
+    fn checked_call_with_input(&mut self, input: &[u8]) -> Result<()>;
+
     /// Wait for the output of this command and return an error if the exit code is non-zero.
     fn checked_output(&mut self) -> Result<Output>;
+
+    /// Wait for the output of this command for at most `timeout`.
+    ///
+    /// Kill the command and return an error of kind `TimedOut` if it doesn't finish in time.
+    fn output_timeout(&mut self, timeout: Duration) -> Result<Output>;
+}
+
+/// Read all of `source` in a background thread.
+fn read_in_background<R: Read + Send + 'static>(source: Option<R>) -> JoinHandle<Vec<u8>> {
+    std::thread::spawn(move || {
+        let mut buffer = Vec::new();
+        if let Some(mut source) = source {
+            // Return what we got so far if reading fails
+            let _ = source.read_to_end(&mut buffer);
+        }
+        buffer
+    })
 }
 
 impl CommandExt for Command {
@@ -38,6 +64,22 @@ impl CommandExt for Command {
         })
     }
 
+    fn checked_call_with_input(&mut self, input: &[u8]) -> Result<()> {
+        let mut child = self.stdin(Stdio::piped()).spawn()?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(input)?;
+        }
+        let status = child.wait()?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(Error::other(format!(
+                "{:?} failed with exit code {}",
+                self, status
+            )))
+        }
+    }
+
     fn checked_output(&mut self) -> Result<Output> {
         self.output().and_then(|output| {
             if output.status.success() {
@@ -55,4 +97,35 @@ impl CommandExt for Command {
             }
         })
     }
+
+    fn output_timeout(&mut self, timeout: Duration) -> Result<Output> {
+        let mut child = self
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let stdout = read_in_background(child.stdout.take());
+        let stderr = read_in_background(child.stderr.take());
+        let deadline = Instant::now() + timeout;
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if deadline <= Instant::now() {
+                // The command may have exited just now, so ignore errors from kill
+                let _ = child.kill();
+                child.wait()?;
+                return Err(Error::new(
+                    ErrorKind::TimedOut,
+                    format!("{:?} timed out after {}s", self, timeout.as_secs_f32()),
+                ));
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        };
+        Ok(Output {
+            status,
+            stdout: stdout.join().unwrap_or_default(),
+            stderr: stderr.join().unwrap_or_default(),
+        })
+    }
 }
@@ -0,0 +1,250 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Progress of downloads and extraction.
+//!
+//! On a terminal draw a bar with size, speed and ETA for every running download, and a spinner
+//! while extracting an archive.  For line-oriented output, see [`crate::status`], report the
+//! progress of downloads in separate lines every few seconds instead.  All progress goes to
+//! stderr, to keep stdout clean for output of commands like `homebins new`.
+
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use url::Url;
+
+/// How often to report download progress for line-oriented output.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often to redraw spinners.
+const TICK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The template of downloads of known size.
+const DOWNLOAD_TEMPLATE: &str =
+    "{msg} [{bar:30}] {bytes}/{total_bytes} ({bytes_per_sec}, ETA {eta})";
+
+/// The template of downloads of unknown size.
+const DOWNLOAD_SPINNER_TEMPLATE: &str = "{spinner} {msg} {bytes} ({bytes_per_sec})";
+
+/// The template of spinners for other steps.
+const SPINNER_TEMPLATE: &str = "{spinner} {msg}";
+
+static BARS: OnceLock<MultiProgress> = OnceLock::new();
+
+/// All progress bars, drawn together below other output.
+fn bars() -> &'static MultiProgress {
+    BARS.get_or_init(|| MultiProgress::with_draw_target(ProgressDrawTarget::stderr()))
+}
+
+/// Hide all progress bars while running `f`, to print output above them.
+pub(crate) fn suspend<F: FnOnce() -> R, R>(f: F) -> R {
+    match BARS.get() {
+        Some(bars) => bars.suspend(f),
+        None => f(),
+    }
+}
+
+/// Create a progress style from `template`.
+fn style(template: &str) -> ProgressStyle {
+    // Our templates are static and known to be valid
+    ProgressStyle::with_template(template)
+        .unwrap()
+        .progress_chars("=> ")
+}
+
+/// Add a spinner with the given style `template` to all progress bars.
+fn spinner(template: &str) -> ProgressBar {
+    let bar = bars().add(ProgressBar::new_spinner().with_style(style(template)));
+    bar.enable_steady_tick(TICK_INTERVAL);
+    bar
+}
+
+/// Describe `received` of `total` bytes for line-oriented output.
+fn describe(received: u64, total: Option<u64>) -> String {
+    match total {
+        Some(total) if 0 < total => format!("{:.1}%", (received as f64 / total as f64) * 100.0),
+        _ => format!("{} bytes", received),
+    }
+}
+
+/// The progress of a download.
+#[derive(Debug)]
+pub(crate) struct DownloadProgress<'a> {
+    url: &'a Url,
+    bar: Option<ProgressBar>,
+    total: Option<u64>,
+    received: u64,
+    last_report: Instant,
+}
+
+impl<'a> DownloadProgress<'a> {
+    /// Start to report progress of downloading `url`.
+    pub(crate) fn start(url: &'a Url) -> DownloadProgress<'a> {
+        let bar = if crate::status::is_line_oriented() {
+            None
+        } else {
+            let name = url
+                .path_segments()
+                .and_then(|mut segments| segments.next_back())
+                .filter(|name| !name.is_empty())
+                .map_or_else(|| url.to_string(), ToString::to_string);
+            let bar = spinner(DOWNLOAD_SPINNER_TEMPLATE);
+            bar.set_message(name);
+            Some(bar)
+        };
+        DownloadProgress {
+            url,
+            bar,
+            total: None,
+            received: 0,
+            last_report: Instant::now(),
+        }
+    }
+
+    /// Set the `total` size of the download, if known.
+    pub(crate) fn set_total(&mut self, total: Option<u64>) {
+        if self.total != total {
+            self.total = total;
+            if let (Some(bar), Some(total)) = (&self.bar, total) {
+                bar.set_length(total);
+                bar.set_style(style(DOWNLOAD_TEMPLATE));
+            }
+        }
+    }
+
+    /// Update the number of `received` bytes.
+    pub(crate) fn set_received(&mut self, received: u64) {
+        self.received = received;
+        match &self.bar {
+            Some(bar) => bar.set_position(received),
+            None => {
+                if self.report_due(Instant::now()) {
+                    crate::estatus!("{}", self.describe());
+                }
+            }
+        }
+    }
+
+    /// Finish the download.
+    pub(crate) fn finish(mut self) {
+        match self.bar.take() {
+            Some(bar) => bar.finish_and_clear(),
+            None => crate::estatus!("{}", self.describe()),
+        }
+    }
+
+    /// Whether to report progress for line-oriented output at `now`.
+    ///
+    /// Report at most once every [`PROGRESS_INTERVAL`].
+    fn report_due(&mut self, now: Instant) -> bool {
+        let due = PROGRESS_INTERVAL <= now.saturating_duration_since(self.last_report);
+        if due {
+            self.last_report = now;
+        }
+        due
+    }
+
+    /// Describe the progress of this download for line-oriented output.
+    fn describe(&self) -> String {
+        format!(
+            "Downloading {}: {}",
+            self.url,
+            describe(self.received, self.total)
+        )
+    }
+}
+
+impl Drop for DownloadProgress<'_> {
+    fn drop(&mut self) {
+        // Remove the bar of a failed download
+        if let Some(bar) = self.bar.take() {
+            bar.finish_and_clear();
+        }
+    }
+}
+
+/// A spinner for a long-running step, e.g. extracting an archive.
+#[derive(Debug)]
+pub(crate) struct Spinner {
+    bar: Option<ProgressBar>,
+}
+
+impl Spinner {
+    /// Show a spinner with `message`.
+    ///
+    /// For line-oriented output print `message` as status line instead.
+    pub(crate) fn start(message: String) -> Spinner {
+        if crate::status::is_line_oriented() {
+            crate::estatus!("{}", message);
+            Spinner { bar: None }
+        } else {
+            let bar = spinner(SPINNER_TEMPLATE);
+            bar.set_message(message);
+            Spinner { bar: Some(bar) }
+        }
+    }
+}
+
+impl Drop for Spinner {
+    fn drop(&mut self) {
+        if let Some(bar) = self.bar.take() {
+            bar.finish_and_clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn describe_progress() {
+        assert_eq!(describe(512, Some(1024)), "50.0%");
+        assert_eq!(describe(1024, Some(1024)), "100.0%");
+        assert_eq!(describe(512, None), "512 bytes");
+        assert_eq!(describe(0, Some(0)), "0 bytes");
+    }
+
+    #[test]
+    fn report_line_oriented_progress() {
+        let url = Url::parse("https://example.com/hello.tar.gz").unwrap();
+        let started = Instant::now();
+        let mut progress = DownloadProgress {
+            url: &url,
+            bar: None,
+            total: None,
+            received: 0,
+            last_report: started,
+        };
+        progress.set_total(Some(2048));
+        progress.received = 512;
+        assert_eq!(
+            progress.describe(),
+            "Downloading https://example.com/hello.tar.gz: 25.0%"
+        );
+        assert!(!progress.report_due(started + Duration::from_secs(1)));
+        assert!(progress.report_due(started + PROGRESS_INTERVAL));
+        assert!(!progress.report_due(started + PROGRESS_INTERVAL + Duration::from_secs(1)));
+        assert!(progress.report_due(started + PROGRESS_INTERVAL * 2));
+    }
+
+    #[test]
+    fn valid_templates() {
+        for template in [
+            DOWNLOAD_TEMPLATE,
+            DOWNLOAD_SPINNER_TEMPLATE,
+            SPINNER_TEMPLATE,
+        ] {
+            assert!(
+                ProgressStyle::with_template(template).is_ok(),
+                "{}",
+                template
+            );
+        }
+    }
+}
@@ -0,0 +1,326 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Provenance of installed files.
+//!
+//! Homebins records where every file it installs comes from: The manifest and its version, the
+//! URL and SHA256 checksum of the download, the manifest repository and its revision, and when
+//! it installed the file.  Records live in a JSON file, and go away when homebins removes the
+//! manifest of a file.
+
+use std::collections::BTreeSet;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::{Context, Error};
+use fehler::throws;
+use serde::{Deserialize, Serialize};
+
+use crate::state::{read_state, write_atomically};
+
+/// Where an installed file comes from.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Provenance {
+    /// The installed file.
+    pub file: PathBuf,
+    /// The name of the manifest which installed the file.
+    pub manifest: String,
+    /// The version of the manifest.
+    pub version: String,
+    /// The URL of the download the file comes from.
+    pub url: String,
+    /// The SHA256 checksum of the download, as hex string.
+    pub sha256: String,
+    /// The name of the manifest repository the manifest comes from, if known.
+    #[serde(default)]
+    pub repository: Option<String>,
+    /// The Git revision of the manifest repository, if known.
+    pub revision: Option<String>,
+    /// When homebins installed the file.
+    pub installed_at: SystemTime,
+}
+
+/// A manifest of a repository, to reconcile provenance records with.
+#[derive(Debug, Clone)]
+pub struct KnownManifest {
+    /// The name of the manifest.
+    pub name: String,
+    /// The installed version of the manifest as its binary reports it, if installed.
+    pub installed_version: Option<String>,
+    /// All files of the manifest which exist.
+    pub files: Vec<PathBuf>,
+}
+
+/// A discrepancy between provenance records and installed manifests.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Discrepancy {
+    /// Records of a manifest which is not installed.
+    NotInstalled(String),
+    /// A recorded file which no longer exists.
+    MissingFile(PathBuf),
+    /// An installed manifest whose recorded version differs from its installed version.
+    VersionMismatch {
+        /// The name of the manifest.
+        manifest: String,
+        /// The recorded version.
+        recorded: String,
+        /// The installed version.
+        installed: String,
+    },
+    /// An existing file of an installed manifest without record.
+    Unrecorded(PathBuf),
+}
+
+impl Discrepancy {
+    /// Whether reconciling repairs this discrepancy by forgetting records.
+    ///
+    /// Homebins can't recover records of unrecorded files or of other versions; reinstalling
+    /// the manifest records them again.
+    pub fn is_repaired(&self) -> bool {
+        matches!(
+            self,
+            Discrepancy::NotInstalled(_) | Discrepancy::MissingFile(_)
+        )
+    }
+}
+
+impl Display for Discrepancy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Discrepancy::NotInstalled(name) => write!(f, "{}: recorded, but not installed", name),
+            Discrepancy::MissingFile(file) => {
+                write!(f, "{}: recorded, but missing", file.display())
+            }
+            Discrepancy::VersionMismatch {
+                manifest,
+                recorded,
+                installed,
+            } => write!(
+                f,
+                "{}: recorded version {}, but version {} installed",
+                manifest, recorded, installed
+            ),
+            Discrepancy::Unrecorded(file) => {
+                write!(f, "{}: installed, but not recorded", file.display())
+            }
+        }
+    }
+}
+
+/// The provenance records of all installed files.
+#[derive(Debug)]
+pub struct ProvenanceStore {
+    path: PathBuf,
+    records: Vec<Provenance>,
+}
+
+impl ProvenanceStore {
+    /// Open provenance records in the file at `path`.
+    ///
+    /// Start without records if `path` does not exist.
+    #[throws]
+    pub fn open<P: AsRef<Path>>(path: P) -> ProvenanceStore {
+        let path = path.as_ref().to_path_buf();
+        let records = match read_state(&path)? {
+            Some(contents) => serde_json::from_str(&contents)
+                .with_context(|| format!("Invalid provenance records in {}", path.display()))?,
+            None => Vec::new(),
+        };
+        ProvenanceStore { path, records }
+    }
+
+    /// Get the provenance of `file`, if any.
+    pub fn for_file(&self, file: &Path) -> Option<&Provenance> {
+        self.records.iter().find(|record| record.file == file)
+    }
+
+    /// Get the provenance of all files installed by the manifest with the given `name`.
+    pub fn for_manifest<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Provenance> {
+        self.records
+            .iter()
+            .filter(move |record| record.manifest == name)
+    }
+
+    /// Get the manifest repository the installed manifest with the given `name` comes from.
+    ///
+    /// Return `None` if homebins didn't record the repository, e.g. for manifest files, or for
+    /// manifests installed before homebins recorded repositories.
+    pub fn repository_of(&self, name: &str) -> Option<&str> {
+        self.records
+            .iter()
+            .filter(|record| record.manifest == name)
+            .find_map(|record| record.repository.as_deref())
+    }
+
+    /// Add `records`, replacing all records of the same files.
+    pub fn record<I: IntoIterator<Item = Provenance>>(&mut self, records: I) {
+        for record in records {
+            self.records.retain(|existing| existing.file != record.file);
+            self.records.push(record);
+        }
+        self.records.sort_by(|a, b| a.file.cmp(&b.file));
+    }
+
+    /// Forget the provenance of all files installed by the manifest with the given `name`.
+    pub fn forget_manifest(&mut self, name: &str) {
+        self.records.retain(|record| record.manifest != name);
+    }
+
+    /// Forget the provenance of `file`.
+    pub fn forget_file(&mut self, file: &Path) {
+        self.records.retain(|record| record.file != file);
+    }
+
+    /// Reconcile all records with the `known` manifests.
+    ///
+    /// Forget records of manifests which are not installed and of files which no longer exist,
+    /// and report these as well as recorded versions which differ from installed versions, and
+    /// existing files of installed manifests without records.  Keep records of manifests which
+    /// aren't `known`, e.g. of manifest files, unless their files no longer exist.
+    ///
+    /// Leave it to the caller to save the repaired records.
+    pub fn reconcile(&mut self, known: &[KnownManifest]) -> Vec<Discrepancy> {
+        let not_installed: BTreeSet<String> = self
+            .records
+            .iter()
+            .filter(|record| {
+                known.iter().any(|manifest| {
+                    manifest.name == record.manifest && manifest.installed_version.is_none()
+                })
+            })
+            .map(|record| record.manifest.clone())
+            .collect();
+        let mut discrepancies = Vec::new();
+        for name in not_installed {
+            self.forget_manifest(&name);
+            discrepancies.push(Discrepancy::NotInstalled(name));
+        }
+        let missing: Vec<PathBuf> = self
+            .records
+            .iter()
+            .filter(|record| !record.file.exists())
+            .map(|record| record.file.clone())
+            .collect();
+        for file in missing {
+            self.forget_file(&file);
+            discrepancies.push(Discrepancy::MissingFile(file));
+        }
+        for manifest in known {
+            let version = match &manifest.installed_version {
+                Some(version) => version,
+                None => continue,
+            };
+            if let Some(record) = self
+                .for_manifest(&manifest.name)
+                .find(|record| &record.version != version)
+            {
+                discrepancies.push(Discrepancy::VersionMismatch {
+                    manifest: manifest.name.clone(),
+                    recorded: record.version.clone(),
+                    installed: version.clone(),
+                });
+            }
+            discrepancies.extend(
+                manifest
+                    .files
+                    .iter()
+                    .filter(|file| self.for_file(file).is_none())
+                    .map(|file| Discrepancy::Unrecorded(file.clone())),
+            );
+        }
+        discrepancies
+    }
+
+    /// Write all records back to the file.
+    #[throws]
+    pub fn save(&self) -> () {
+        write_atomically(&self.path, serde_json::to_vec_pretty(&self.records)?)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn record(file: &Path, manifest: &str, version: &str) -> Provenance {
+        Provenance {
+            file: file.to_path_buf(),
+            manifest: manifest.to_string(),
+            version: version.to_string(),
+            url: format!("https://example.com/{}", manifest),
+            sha256: "00".to_string(),
+            repository: None,
+            revision: None,
+            installed_at: SystemTime::UNIX_EPOCH,
+        }
+    }
+
+    #[test]
+    fn reconcile_with_installed_manifests() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("provenance.json");
+        let file = |name: &str| dir.path().join(name);
+        for name in &["rg", "rg.1", "fd", "exa", "hello"] {
+            std::fs::write(file(name), name).unwrap();
+        }
+        let mut store = ProvenanceStore::open(&path).unwrap();
+        store.record(vec![
+            record(&file("rg"), "ripgrep", "12.1.1"),
+            record(&file("rg.bash"), "ripgrep", "12.1.1"),
+            record(&file("fd"), "fd", "8.0.0"),
+            record(&file("exa"), "exa", "0.9.0"),
+            record(&file("hello"), "hello", "1.0.0"),
+        ]);
+        store.save().unwrap();
+
+        let known = vec![
+            KnownManifest {
+                name: "ripgrep".to_string(),
+                installed_version: Some("12.1.1".to_string()),
+                files: vec![file("rg"), file("rg.1")],
+            },
+            KnownManifest {
+                name: "fd".to_string(),
+                installed_version: Some("8.1.0".to_string()),
+                files: vec![file("fd")],
+            },
+            KnownManifest {
+                name: "exa".to_string(),
+                installed_version: None,
+                files: Vec::new(),
+            },
+        ];
+        let mut store = ProvenanceStore::open(&path).unwrap();
+        assert_eq!(
+            store.reconcile(&known),
+            vec![
+                Discrepancy::NotInstalled("exa".to_string()),
+                Discrepancy::MissingFile(file("rg.bash")),
+                Discrepancy::Unrecorded(file("rg.1")),
+                Discrepancy::VersionMismatch {
+                    manifest: "fd".to_string(),
+                    recorded: "8.0.0".to_string(),
+                    installed: "8.1.0".to_string()
+                },
+            ]
+        );
+        store.save().unwrap();
+
+        let mut store = ProvenanceStore::open(&path).unwrap();
+        assert_eq!(store.for_manifest("exa").count(), 0);
+        // Keep records of unknown manifests
+        assert!(store.for_file(&file("hello")).is_some());
+        assert!(store.for_file(&file("rg.bash")).is_none());
+        assert!(store.for_file(&file("rg")).is_some());
+        // Only unrepairable discrepancies remain
+        assert!(store
+            .reconcile(&known)
+            .iter()
+            .all(|discrepancy| !discrepancy.is_repaired()));
+    }
+}
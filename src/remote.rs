@@ -0,0 +1,205 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Install manifests to remote hosts over SSH.
+//!
+//! Homebins downloads and installs all files of a manifest into a local staging directory for the
+//! platform of the remote host, and then copies the staging directory to the remote $HOME with
+//! `tar` over SSH.  Operations which affect the running system, like hooks and systemd units,
+//! run on the remote host afterwards.
+
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use anyhow::{anyhow, Context, Error};
+use colored::Colorize;
+use fehler::{throw, throws};
+
+use crate::manifest::{Arch, Os, Platform};
+use crate::operations::{self, Operation};
+use crate::process::CommandExt;
+use crate::tools::{shell_quote, ssh};
+use crate::{ApplyOptions, HomebinProjectDirs, InstallDirs, Manifest};
+
+/// Parse the platform from the output of `uname -sm`.
+#[throws]
+fn parse_uname(uname: &str) -> Platform {
+    let mut parts = uname.split_whitespace();
+    let (os, arch) = match (parts.next(), parts.next()) {
+        (Some(os), Some(arch)) => (os, arch),
+        _ => throw!(anyhow!("Unexpected output from uname: {:?}", uname)),
+    };
+    let os = match os {
+        "Linux" => Os::Linux,
+        "Darwin" => Os::MacOs,
+        other => throw!(anyhow!("Unsupported operating system: {}", other)),
+    };
+    let arch = match arch {
+        "x86_64" | "amd64" => Arch::X86_64,
+        "aarch64" | "arm64" => Arch::Aarch64,
+        "armv7l" => Arch::Armv7,
+        other => throw!(anyhow!("Unsupported architecture: {}", other)),
+    };
+    Platform { os, arch }
+}
+
+/// Get the shell command to run `operation` on the remote host, if it needs to run there.
+fn remote_command(operation: &Operation<'_>) -> Option<String> {
+    use Operation::*;
+    match operation {
+        Run(command) => {
+            let command: Vec<String> = command.iter().map(|s| shell_quote(s)).collect();
+            Some(format!(
+                "cd && PATH=\"$HOME/.local/bin:$PATH\" {}",
+                command.join(" ")
+            ))
+        }
+        SystemdDaemonReload => Some("systemctl --user daemon-reload".to_string()),
+        SystemdEnable(unit) => Some(format!(
+            "systemctl --user enable --now {}",
+            shell_quote(unit)
+        )),
+        _ => None,
+    }
+}
+
+/// Get the platform of the remote host at `destination`.
+#[throws]
+fn remote_platform(destination: &str) -> Platform {
+    let output = ssh(destination)
+        .args(["uname", "-sm"])
+        .checked_output()
+        .with_context(|| format!("Failed to determine platform of {}", destination))?;
+    parse_uname(&String::from_utf8_lossy(&output.stdout))?
+}
+
+/// Copy all files below `staging` to the home directory at `destination`.
+#[throws]
+fn push_files(staging: &Path, destination: &str) -> () {
+    let mut entries = Vec::new();
+    for entry in staging.read_dir()? {
+        entries.push(entry?.file_name());
+    }
+    if entries.is_empty() {
+        return;
+    }
+    let mut tar = Command::new("tar")
+        .arg("-C")
+        .arg(staging)
+        .arg("-cf")
+        .arg("-")
+        .args(&entries)
+        .stdout(Stdio::piped())
+        .spawn()
+        .with_context(|| "Failed to start tar")?;
+    let stdout = tar.stdout.take().expect("tar has stdout");
+    ssh(destination)
+        .args(["tar", "-C", "\"$HOME\"", "-xf", "-"])
+        .stdin(stdout)
+        .checked_call()
+        .with_context(|| format!("Failed to copy files to {}", destination))?;
+    let status = tar.wait()?;
+    if !status.success() {
+        throw!(anyhow!("tar failed with exit code {}", status));
+    }
+}
+
+/// Install `manifest` to the home directory of the remote host at the SSH `destination`.
+///
+/// Download and install `manifest` into a temporary staging directory for the platform of the
+/// remote host, copy all files to the remote host, and then run hooks and enable systemd units
+/// on the remote host.  Use `dirs` for downloads.
+#[throws]
+pub fn install_manifest_over_ssh(
+    dirs: &HomebinProjectDirs,
+    destination: &str,
+    manifest: &Manifest,
+    options: &ApplyOptions,
+) -> () {
+    let platform = remote_platform(destination)?;
+    let host = Platform::host()?;
+    if platform.os != host.os {
+        throw!(anyhow!(
+            "Cannot install to {} which runs {}, only to hosts running {}",
+            destination,
+            platform.os,
+            host.os
+        ));
+    }
+    manifest.check_platform(platform)?;
+    let staging = tempfile::Builder::new()
+        .prefix("homebins-staging")
+        .tempdir()
+        .with_context(|| "Failed to create staging directory")?;
+    let mut staging_dirs = InstallDirs::in_home(staging.path());
+    let staging_options = ApplyOptions {
+        target_arch: Some(platform.arch),
+        skip_host_operations: true,
+        ..options.clone()
+    };
+    crate::install_manifest(dirs, &mut staging_dirs, manifest, &staging_options)?;
+
+    crate::status!("Copying files to {}", destination.bold());
+    push_files(staging.path(), destination)?;
+
+    let operations = operations::skip_destinations(
+        operations::install_manifest(manifest, platform),
+        &options.skip_destinations,
+    );
+    for command in operations.iter().filter_map(remote_command) {
+        crate::status!("ssh {} {}", destination, command);
+        ssh(destination)
+            .arg(&command)
+            .checked_call()
+            .with_context(|| format!("Failed to run {} on {}", command, destination))?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::borrow::Cow;
+
+    #[test]
+    fn platform_from_uname() {
+        assert_eq!(
+            parse_uname("Linux aarch64\n").unwrap(),
+            Platform {
+                os: Os::Linux,
+                arch: Arch::Aarch64
+            }
+        );
+        assert_eq!(
+            parse_uname("Darwin arm64\n").unwrap(),
+            Platform {
+                os: Os::MacOs,
+                arch: Arch::Aarch64
+            }
+        );
+        assert_eq!(
+            parse_uname("Linux riscv64").unwrap_err().to_string(),
+            "Unsupported architecture: riscv64"
+        );
+    }
+
+    #[test]
+    fn remote_commands_for_host_operations() {
+        assert_eq!(
+            remote_command(&Operation::Run(Cow::Owned(vec![
+                "tldr".to_string(),
+                "--update".to_string(),
+                "it's".to_string()
+            ]))),
+            Some("cd && PATH=\"$HOME/.local/bin:$PATH\" 'tldr' '--update' 'it'\\''s'".to_string())
+        );
+        assert_eq!(
+            remote_command(&Operation::SystemdEnable(Cow::from("foo.service"))),
+            Some("systemctl --user enable --now 'foo.service'".to_string())
+        );
+        assert_eq!(remote_command(&Operation::UpdateFontCache), None);
+    }
+}
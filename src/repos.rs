@@ -4,22 +4,375 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
+use crate::manifest::{fetch_error, git_environment, GitToken, IndexEntry, ManifestIndex};
+use crate::secrets::{token_for, Secret};
+use crate::state::{read_state, write_atomically, StateLock};
+use crate::trust::TrustedKey;
 use crate::{HomebinProjectDirs, ManifestRepo, ManifestStore};
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Error, Result};
+use colored::Colorize;
+use fehler::{throw, throws};
+use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, SystemTime};
+use url::Url;
+
+/// A manifest repository to install from, from a `[[repos]]` table in the configuration.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RepoConfig {
+    /// The name of the repository, to qualify manifest names with, as in `name/ripgrep`.
+    pub name: String,
+    /// The Git URL to clone the repository from, the absolute path of a plain directory of
+    /// manifests, see [`RepoConfig::directory`], or the HTTP URL of an archive of the repository,
+    /// see [`RepoConfig::archive_extension`].
+    pub url: String,
+    /// The branch, tag or commit to pin this repository to.
+    ///
+    /// If absent use the latest commit on the `main` branch.  Archives can only be pinned to
+    /// their SHA256 checksum, as in `sha256:<checksum>`, see [`RepoConfig::archive_sha256`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reference: Option<String>,
+    /// The priority of this repository.
+    ///
+    /// If multiple repositories have a manifest of the same name, homebins installs the manifest
+    /// from the repository with the highest priority, and among repositories of equal priority
+    /// from the first one in configuration order.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub priority: u32,
+    /// Whether to install from this repository.
+    ///
+    /// Homebins ignores the manifests of disabled repositories, but keeps their clones, so
+    /// enabling a repository again doesn't need to clone it again.
+    #[serde(default = "enabled_by_default", skip_serializing_if = "is_enabled")]
+    pub enabled: bool,
+}
+
+/// Whether `priority` is the default priority, to leave it out of the configuration.
+fn is_zero(priority: &u32) -> bool {
+    *priority == 0
+}
+
+/// Enable repositories unless configured otherwise.
+fn enabled_by_default() -> bool {
+    true
+}
+
+/// Whether `enabled` is the default, to leave it out of the configuration.
+fn is_enabled(enabled: &bool) -> bool {
+    *enabled
+}
+
+impl RepoConfig {
+    /// The plain directory of manifests of this repository, if any.
+    ///
+    /// Absolute paths refer to plain directories of manifests, without Git.  Local Git
+    /// repositories need `file://` URLs.
+    pub fn directory(&self) -> Option<&Path> {
+        Some(Path::new(&self.url)).filter(|path| path.is_absolute())
+    }
+
+    /// The type of archive of this repository, as file extension, if it's an archive.
+    ///
+    /// HTTP URLs of archives refer to archives of repositories, to download and extract instead
+    /// of cloning with Git, see [`archive_extension`].
+    pub fn archive_extension(&self) -> Option<&'static str> {
+        archive_extension(&self.url)
+    }
+
+    /// The SHA256 checksum the archive of this repository must match, if any.
+    pub fn archive_sha256(&self) -> Option<&str> {
+        self.archive_extension()?;
+        self.reference
+            .as_deref()
+            .and_then(|reference| reference.strip_prefix("sha256:"))
+    }
+}
+
+/// File extensions of archives to use as manifest repositories.
+const ARCHIVE_EXTENSIONS: [&str; 5] = [".tar.gz", ".tgz", ".tar.bz2", ".tar.xz", ".zip"];
+
+/// The type of archive at `url` as file extension, if `url` is the HTTP URL of an archive.
+///
+/// Recognize URLs whose path ends with the extension of an archive, and URLs of GitHub's
+/// archive service, as in `https://codeload.github.com/owner/repo/tar.gz/refs/heads/main`.
+pub fn archive_extension(url: &str) -> Option<&'static str> {
+    let url = Url::parse(url)
+        .ok()
+        .filter(|url| matches!(url.scheme(), "http" | "https"))?;
+    if url.host_str() == Some("codeload.github.com") {
+        match url.path_segments()?.nth(2) {
+            Some("tar.gz") => Some(".tar.gz"),
+            Some("zip") => Some(".zip"),
+            _ => None,
+        }
+    } else {
+        ARCHIVE_EXTENSIONS
+            .iter()
+            .copied()
+            .find(|extension| url.path().ends_with(extension))
+    }
+}
+
+/// Check that `reference` pins an archive repository to a SHA256 checksum.
+#[throws]
+pub fn check_archive_pin(reference: &str) -> () {
+    let valid = reference
+        .strip_prefix("sha256:")
+        .is_some_and(|checksum| checksum.len() == 64 && hex::decode(checksum).is_ok());
+    if !valid {
+        throw!(anyhow!(
+            "Archives can only be pinned to their SHA256 checksum, as in sha256:<checksum>, not {}",
+            reference
+        ));
+    }
+}
+
+/// How long to use manifest repositories without fetching them again, by default.
+pub const DEFAULT_FETCH_TTL: Duration = Duration::from_secs(3600);
+
+/// The lock file of manifest repos, in the manifest repos dir.
+///
+/// Repository names never start with a dot, see [`check_repo_name`], so no repo clashes with
+/// this file.
+const REPOS_LOCK_FILE: &str = ".lock";
+
+/// Format `age` in whole minutes, hours or days.
+fn format_age(age: Duration) -> String {
+    let minutes = age.as_secs() / 60;
+    let (value, unit) = if minutes < 60 {
+        (minutes, "minute")
+    } else if minutes < 24 * 60 {
+        (minutes / 60, "hour")
+    } else {
+        (minutes / (24 * 60), "day")
+    };
+    if value == 1 {
+        format!("1 {}", unit)
+    } else {
+        format!("{} {}s", value, unit)
+    }
+}
+
+/// The manifest repositories to install from if not configured otherwise.
+pub fn default_repos() -> Vec<RepoConfig> {
+    vec![RepoConfig {
+        name: "lunaryorn".to_string(),
+        url: "https://github.com/lunaryorn/homebin-manifests".to_string(),
+        reference: None,
+        priority: 0,
+        enabled: true,
+    }]
+}
+
+/// Check that `name` is a valid name for a repository.
+///
+/// Names must not be empty, must not contain `/` and must not start with `.`.
+#[throws]
+pub fn check_repo_name(name: &str) -> () {
+    if name.is_empty() || name.contains('/') || name.starts_with('.') {
+        throw!(anyhow!("Invalid repository name: {:?}", name));
+    }
+}
+
+/// Check that `url` refers to a Git repository with a `main` branch, or an archive.
+///
+/// Homebins installs manifests from the `main` branch of a manifest repository.  For archives
+/// check that the archive exists, see [`archive_extension`].  Ask Git repositories with `token`
+/// if given, and tell authentication failures from network failures, see [`FetchError`].
+#[throws]
+pub fn check_repo_remote(url: &str, token: Option<&GitToken>) -> () {
+    if archive_extension(url).is_some() {
+        let parsed = Url::parse(url)?;
+        crate::tools::check_url(&parsed)
+            .with_context(|| format!("Archive {} does not exist", url))?;
+        return;
+    }
+    let output = Command::new("git")
+        .args(["ls-remote", "--exit-code", "--heads", url, "main"])
+        .envs(git_environment(token))
+        .stdout(Stdio::null())
+        .output()
+        .with_context(|| format!("Failed to run git to check {}", url))?;
+    match output.status.code() {
+        Some(0) => {}
+        // ls-remote exits with 2 if the repository has no matching branch
+        Some(2) => throw!(anyhow!("{} has no main branch", url)),
+        _ => throw!(fetch_error(url, &String::from_utf8_lossy(&output.stderr))),
+    }
+}
+
+/// Get the token to fetch the manifest repository at `url` with from `tokens`, by host name.
+///
+/// Only HTTP remotes use tokens; see [`token_for`] for where tokens come from.  Return `None`
+/// if there's no token for the host of `url`; Git then asks its credential helpers.  If getting
+/// the token fails warn and return `None` as well, so that a broken token never keeps homebins
+/// from fetching public repositories.
+pub fn repo_token(tokens: &HashMap<String, Secret>, url: &str) -> Option<GitToken> {
+    match Url::parse(url) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => match token_for(tokens, &url) {
+            Ok(token) => token.map(GitToken::new),
+            Err(error) => {
+                crate::estatus!(
+                    "{}",
+                    format!("WARNING: {:#}; fetching {} without token", error, url).yellow()
+                );
+                None
+            }
+        },
+        _ => None,
+    }
+}
+
+/// Check manifest `repos` from the configuration.
+///
+/// Names must be unique, and valid, see [`check_repo_name`].  Plain directories cannot be
+/// pinned, and archives only to their SHA256 checksum, see [`check_archive_pin`].
+#[throws]
+pub fn check_repo_configs(repos: &[RepoConfig]) -> () {
+    for (index, repo) in repos.iter().enumerate() {
+        check_repo_name(&repo.name)?;
+        if repos[..index].iter().any(|other| other.name == repo.name) {
+            throw!(anyhow!("Duplicate repository: {}", repo.name));
+        }
+        if let Some(reference) = &repo.reference {
+            if repo.directory().is_some() {
+                throw!(anyhow!(
+                    "Cannot pin plain directory {} of repository {}",
+                    repo.url,
+                    repo.name
+                ));
+            }
+            if repo.archive_extension().is_some() {
+                check_archive_pin(reference)
+                    .with_context(|| format!("Invalid pin of repository {}", repo.name))?;
+            }
+        }
+    }
+}
+
+/// Split a manifest `name` of the form `repo/name` into the repository and the bare name.
+///
+/// Return `None` for the repository if `name` has no repository.
+pub fn split_manifest_name(name: &str) -> (Option<&str>, &str) {
+    match name.split_once('/') {
+        Some((repo, name)) => (Some(repo), name),
+        None => (None, name),
+    }
+}
+
+/// Pick the repository to load the manifest `name` from.
+///
+/// `repos` has the name and the priority of every repository, and whether the repository has a
+/// manifest with the bare name of `name`.  If `name` is of the form `repo/name` pick the given
+/// repository, and fail if it doesn't exist.  Otherwise pick the repository with the highest
+/// priority which has the manifest, and the first of these if multiple repositories with the
+/// same priority have it.  Return `None` if no repository has the manifest.
+fn pick_repo<'r>(name: &str, repos: &[(&'r str, u32, bool)]) -> Result<Option<&'r str>> {
+    match split_manifest_name(name) {
+        (Some(repo), _) => repos
+            .iter()
+            .find(|(candidate, _, _)| *candidate == repo)
+            .map(|(candidate, _, _)| Some(*candidate))
+            .ok_or_else(|| {
+                let known: Vec<&str> = repos.iter().map(|(repo, _, _)| *repo).collect();
+                anyhow!(
+                    "Unknown repository {} in {}, expected one of: {}",
+                    repo,
+                    name,
+                    known.join(", ")
+                )
+            }),
+        (None, _) => Ok(repos
+            .iter()
+            .filter(|(_, _, has_manifest)| *has_manifest)
+            // max_by_key returns the last of equal elements, so reverse to get the first
+            .rev()
+            .max_by_key(|(_, priority, _)| *priority)
+            .map(|(repo, _, _)| *repo)),
+    }
+}
+
+/// Whether `line` of a TOML document starts the array of tables `name`, as in `[[repos]]`.
+fn is_array_table_header(line: &str, name: &str) -> bool {
+    line.trim_start()
+        .strip_prefix("[[")
+        .and_then(|header| header.split_once("]]"))
+        .is_some_and(|(header, _)| header.trim() == name)
+}
+
+/// Write manifest `repos` to the configuration file at `path`, as `[[repos]]` tables.
+///
+/// Replace all `[[repos]]` tables of the file, and keep all other lines of the file, including
+/// comments, as they are.
+#[throws]
+pub fn write_repo_configs<P: AsRef<Path>>(path: P, repos: &[RepoConfig]) -> () {
+    #[derive(Serialize)]
+    struct Repos<'a> {
+        repos: &'a [RepoConfig],
+    }
+
+    let path = path.as_ref();
+    let mut in_repos = false;
+    let mut in_table = false;
+    let existing = read_state(path)?.unwrap_or_default();
+    let mut lines = Vec::new();
+    for line in existing.lines() {
+        if line.trim_start().starts_with('[') {
+            in_table = true;
+            in_repos = is_array_table_header(line, "repos");
+        }
+        let top_level_repos = !in_table
+            && line
+                .split_once('=')
+                .is_some_and(|(key, _)| key.trim() == "repos");
+        if !in_repos && !top_level_repos {
+            lines.push(line);
+        }
+    }
+    let mut contents = lines.join("\n").trim_end().to_string();
+    if repos.is_empty() {
+        // Top-level keys must come before all tables
+        contents = format!("repos = []\n{}", contents);
+    } else {
+        if !contents.is_empty() {
+            contents.push_str("\n\n");
+        }
+        contents.push_str(&toml::to_string(&Repos { repos })?);
+    }
+    if !contents.ends_with('\n') {
+        contents.push('\n');
+    }
+    write_atomically(path, contents)?;
+}
 
 /// All homebin repos
 #[derive(Debug)]
 pub struct HomebinRepos<'a> {
     repos_dir: Cow<'a, Path>,
+    index_dir: Cow<'a, Path>,
+    trusted_keys: Vec<TrustedKey>,
+    require_trusted_keys: bool,
+    repos: Vec<RepoConfig>,
+    fetch_ttl: Duration,
+    tokens: HashMap<String, Secret>,
 }
 
 impl<'a> HomebinRepos<'a> {
     /// Load homebin manifest repositorie from the given path.
-    pub fn new(repos_dir: PathBuf) -> HomebinRepos<'a> {
+    ///
+    /// Cache indexes of the manifest repos in `index_dir`.
+    pub fn new(repos_dir: PathBuf, index_dir: PathBuf) -> HomebinRepos<'a> {
         HomebinRepos {
             repos_dir: Cow::Owned(repos_dir),
+            index_dir: Cow::Owned(index_dir),
+            trusted_keys: Vec::new(),
+            require_trusted_keys: false,
+            repos: default_repos(),
+            fetch_ttl: DEFAULT_FETCH_TTL,
+            tokens: HashMap::new(),
         }
     }
 
@@ -29,31 +382,608 @@ impl<'a> HomebinRepos<'a> {
     pub fn open(dirs: &HomebinProjectDirs) -> HomebinRepos {
         HomebinRepos {
             repos_dir: Cow::Borrowed(dirs.repos_dir()),
+            index_dir: Cow::Borrowed(dirs.index_dir()),
+            trusted_keys: Vec::new(),
+            require_trusted_keys: false,
+            repos: default_repos(),
+            fetch_ttl: DEFAULT_FETCH_TTL,
+            tokens: HashMap::new(),
         }
     }
 
-    /// Clone a manifest repository from the given remote under the given name.
+    /// Set the tokens to fetch private manifest repos with, by host name.
+    ///
+    /// See [`repo_token`] for how homebins finds the token for a repository.
+    pub fn set_tokens(&mut self, tokens: HashMap<String, Secret>) {
+        self.tokens = tokens;
+    }
+
+    /// Set the keys to verify signed manifest repos with.
     ///
-    /// The repository gets cloned to a subdirectory of the manifest repos dir.
-    /// See [`ManifestRepo::cloned`] for details.
-    fn cloned_manifest_repo(&mut self, remote: String, name: &str) -> Result<ManifestRepo> {
+    /// With any trusted key, or if `require` is set, refuse manifest repos without signature.
+    pub fn set_trusted_keys(&mut self, trusted_keys: Vec<TrustedKey>, require: bool) {
+        self.trusted_keys = trusted_keys;
+        self.require_trusted_keys = require;
+    }
+
+    /// Whether to refuse manifest repos without signature.
+    fn require_signature(&self) -> bool {
+        self.require_trusted_keys || !self.trusted_keys.is_empty()
+    }
+
+    /// Set the manifest repositories to install from.
+    ///
+    /// Use the [`default_repos`] unless set otherwise.
+    pub fn set_repos(&mut self, repos: Vec<RepoConfig>) {
+        self.repos = repos;
+    }
+
+    /// Set how long to use manifest repos without fetching them again.
+    ///
+    /// Use [`DEFAULT_FETCH_TTL`] unless set otherwise; a zero `ttl` fetches repos every time.
+    pub fn set_fetch_ttl(&mut self, ttl: Duration) {
+        self.fetch_ttl = ttl;
+    }
+
+    /// Clone the manifest repository of `config`.
+    ///
+    /// The repository gets cloned to a subdirectory of the manifest repos dir, and pinned to
+    /// its reference if any.  See [`ManifestRepo::cloned`] for details.  Download and extract
+    /// archives instead, see [`ManifestRepo::archive`].
+    ///
+    /// Use an existing clone as it is if homebins fetched it less than `ttl` ago, see
+    /// [`ManifestRepo::fresh`].  If fetching fails, e.g. when offline, fall back to the existing
+    /// clone regardless of its age, with a warning.
+    fn cloned_manifest_repo(&self, config: &RepoConfig, ttl: Duration) -> Result<ManifestRepo> {
+        let target_directory = self.repos_dir.join(&config.name);
+        let fresh = |ttl| match config.archive_extension() {
+            Some(_) => ManifestRepo::fresh_archive(
+                config.url.clone(),
+                target_directory.clone(),
+                config.archive_sha256(),
+                &self.trusted_keys,
+                self.require_signature(),
+                ttl,
+            ),
+            None => ManifestRepo::fresh(
+                config.url.clone(),
+                target_directory.clone(),
+                config.reference.clone(),
+                &self.trusted_keys,
+                self.require_signature(),
+                ttl,
+            ),
+        };
+        if let Some(repo) = fresh(ttl)? {
+            return Ok(repo);
+        }
         std::fs::create_dir_all(&self.repos_dir).with_context(|| {
             format!(
                 "Failed to create directory for manifest repos at {}",
                 self.repos_dir.display()
             )
         })?;
-        ManifestRepo::cloned(remote, self.repos_dir.join(name))
+        let token = repo_token(&self.tokens, &config.url);
+        let cloned = match config.archive_extension() {
+            Some(extension) => ManifestRepo::archive(
+                config.url.clone(),
+                extension,
+                target_directory.clone(),
+                config.archive_sha256(),
+                &self.trusted_keys,
+                self.require_signature(),
+                token.as_ref(),
+            ),
+            None => ManifestRepo::cloned(
+                config.url.clone(),
+                target_directory.clone(),
+                config.reference.clone(),
+                &self.trusted_keys,
+                self.require_signature(),
+                token,
+            ),
+        };
+        let repo = match cloned {
+            Ok(repo) => repo,
+            Err(error) => match (fresh(Duration::MAX)?, self.last_fetched(&config.name)) {
+                (Some(repo), Some(fetched)) => {
+                    crate::estatus!(
+                        "{}",
+                        format!(
+                            "{:#}; using manifests fetched {} ago",
+                            error,
+                            format_age(fetched.elapsed().unwrap_or_default())
+                        )
+                        .yellow()
+                    );
+                    repo
+                }
+                _ => return Err(error),
+            },
+        };
+        if let Some(key) = repo.signed_by() {
+            crate::estatus!(
+                "Verified manifests of {} with trusted key {}",
+                repo.remote(),
+                key.name
+            );
+        }
+        Ok(repo)
+    }
+
+    /// The enabled manifest repositories, by priority.
+    ///
+    /// Order repositories of equal priority by their configuration.
+    fn repos_by_priority(&self) -> Vec<RepoConfig> {
+        let mut repos: Vec<RepoConfig> = self
+            .repos
+            .iter()
+            .filter(|repo| repo.enabled)
+            .cloned()
+            .collect();
+        repos.sort_by_key(|repo| std::cmp::Reverse(repo.priority));
+        repos
+    }
+
+    /// Get all manifest repositories to install from, in the order to search them for manifests.
+    ///
+    /// Skip disabled repositories, and order all others by priority, see
+    /// [`RepoConfig::priority`].  Clone repositories which
+    /// don't exist yet, and fetch all others, except for plain directories which homebins uses
+    /// as they are.
+    ///
+    /// Don't fetch repositories which homebins fetched recently, see
+    /// [`HomebinRepos::set_fetch_ttl`].
+    pub fn manifest_repos(&mut self) -> Result<Vec<ManifestRepo>> {
+        self.manifest_repos_with_ttl(self.fetch_ttl)
+    }
+
+    /// Lock manifest repos exclusively, to fetch or remove them.
+    ///
+    /// This lock is separate from the lock of homebins state, so that fetching manifests never
+    /// waits for a running installation or update, which works on a snapshot of the manifests,
+    /// see [`HomebinRepos::manifest_store_of`].
+    fn lock(&self) -> Result<StateLock> {
+        StateLock::acquire(self.repos_dir.join(REPOS_LOCK_FILE))
+    }
+
+    /// Get all manifest repositories, and fetch those not fetched for `ttl`.
+    fn manifest_repos_with_ttl(&self, ttl: Duration) -> Result<Vec<ManifestRepo>> {
+        let _lock = self.lock()?;
+        self.repos_by_priority()
+            .into_iter()
+            .map(|repo| match repo.directory() {
+                Some(directory) => ManifestRepo::directory(directory.to_path_buf()),
+                None => self.cloned_manifest_repo(&repo, ttl),
+            })
+            .collect()
     }
 
     /// Get the manifest store to install from.
     ///
-    /// This store aggregates all manifest repos.
+    /// This store aggregates all manifest repos, see [`HomebinRepos::manifest_store_of`].
     pub fn manifest_store(&mut self) -> Result<ManifestStore> {
-        self.cloned_manifest_repo(
-            "https://github.com/lunaryorn/homebin-manifests".into(),
-            "lunaryorn",
+        let repos = self.manifest_repos()?;
+        self.manifest_store_of(&repos)
+    }
+
+    /// Get the manifest store to install from, for the given manifest `repos`.
+    ///
+    /// `repos` are the repos of [`HomebinRepos::manifest_repos`].  This store aggregates all
+    /// `repos`, and loads manifests from the repo with the highest priority which has them.
+    /// It's a snapshot of the manifests at the time of this call, so refreshing the repos
+    /// concurrently doesn't change manifests while they're being installed or updated.
+    ///
+    /// Take aliases of manifests and the revisions of the manifest repos from the indexes of the
+    /// manifest repos, see [`ManifestStore::set_aliases`].
+    pub fn manifest_store_of(&self, repos: &[ManifestRepo]) -> Result<ManifestStore> {
+        // Keep fetches from changing the repos while we take the snapshot
+        let _lock = StateLock::acquire_shared(self.repos_dir.join(REPOS_LOCK_FILE))?;
+        let mut stores = Vec::with_capacity(repos.len());
+        for (config, repo) in self.repos_by_priority().iter().zip(repos) {
+            let index = repo.index(&self.index_file(&config.name))?;
+            let mut store = repo.store().snapshot()?;
+            store.set_aliases(index.aliases());
+            if !repo.is_directory() {
+                store.set_revision(index.revision().to_string());
+            }
+            stores.push((config.name.clone(), store));
+        }
+        Ok(ManifestStore::combined(stores))
+    }
+
+    /// When homebins last fetched the manifest repo with the given `name`.
+    ///
+    /// Return `None` if homebins never fetched the repo.
+    pub fn last_fetched(&self, name: &str) -> Option<SystemTime> {
+        ManifestRepo::fetched_at(&self.repos_dir.join(name))
+    }
+
+    /// The number of manifests in the manifest repo with the given `name`.
+    ///
+    /// Take the number from the cached index of the repo, as of the last time homebins fetched
+    /// the repo.  Return `None` if there's no index for the repo yet.
+    pub fn manifest_count(&self, name: &str) -> Result<Option<usize>> {
+        Ok(
+            ManifestIndex::read_from_path(self.index_file(name))?
+                .map(|index| index.entries().len()),
         )
-        .map(|repo| repo.store())
+    }
+
+    /// Remove the clone and the index of the manifest repo with the given `name`.
+    pub fn remove_cached(&self, name: &str) -> Result<()> {
+        let _lock = self.lock()?;
+        let working_copy = self.repos_dir.join(name);
+        if working_copy.exists() {
+            std::fs::remove_dir_all(&working_copy)
+                .with_context(|| format!("Failed to remove {}", working_copy.display()))?;
+        }
+        let index_file = self.index_file(name);
+        if index_file.exists() {
+            std::fs::remove_file(&index_file)
+                .with_context(|| format!("Failed to remove {}", index_file.display()))?;
+        }
+        Ok(())
+    }
+
+    /// Repair the manifest repo with the given `name`, by cloning it again from scratch.
+    ///
+    /// Remove the clone and the index of the repo first, see [`HomebinRepos::remove_cached`].
+    /// Do nothing for plain directories, and fail if no repo has the given `name`.
+    #[throws]
+    pub fn repair(&mut self, name: &str) -> () {
+        let config = self
+            .repos
+            .iter()
+            .find(|repo| repo.name == name)
+            .cloned()
+            .ok_or_else(|| anyhow!("{} is no repository", name))?;
+        if config.directory().is_none() {
+            self.remove_cached(name)?;
+            let _lock = self.lock()?;
+            let repo = self.cloned_manifest_repo(&config, Duration::ZERO)?;
+            repo.index(&self.index_file(name))?;
+        }
+    }
+
+    /// The file to cache the index of the manifest repo with the given `name` in.
+    fn index_file(&self, name: &str) -> PathBuf {
+        self.index_dir.join(name).with_extension("json")
+    }
+
+    /// Resolve manifest `names` against `store`, i.e. the manifest store of these repos.
+    ///
+    /// Names are either bare names of manifests, or qualified with the repository as in
+    /// `repo/name`, see [`split_manifest_name`].  Return the bare names of the manifests to load
+    /// from `store`, with aliases resolved to the names of their manifests, or fail if a name
+    /// refers to an unknown repository.  Tell `store` to load every manifest
+    /// from the repository the name refers to, see [`ManifestStore::choose_repository`].
+    pub fn resolve_names<S: AsRef<str>>(
+        &self,
+        store: &ManifestStore,
+        names: &[S],
+    ) -> Result<Vec<String>> {
+        names
+            .iter()
+            .map(|name| {
+                if let (Some(repo), _) = split_manifest_name(name.as_ref()) {
+                    if self.repos.iter().any(|config| config.name == repo && !config.enabled) {
+                        return Err(anyhow!(
+                            "Repository {} of {} is disabled; enable it with homebins repo enable {}",
+                            repo,
+                            name.as_ref(),
+                            repo
+                        ));
+                    }
+                }
+                let bare = split_manifest_name(name.as_ref()).1;
+                let bare = if store.contains(bare) {
+                    bare.to_string()
+                } else {
+                    store
+                        .resolve_alias(bare)?
+                        .unwrap_or_else(|| bare.to_string())
+                };
+                let repos: Vec<(&str, u32, bool)> = store
+                    .repositories()
+                    .into_iter()
+                    .map(|repo| {
+                        let priority = self
+                            .repos
+                            .iter()
+                            .find(|config| config.name == repo)
+                            .map_or(0, |config| config.priority);
+                        (repo, priority, store.repository_contains(repo, &bare))
+                    })
+                    .collect();
+                if let Some(repo) = pick_repo(name.as_ref(), &repos)? {
+                    store.choose_repository(&bare, repo);
+                }
+                Ok(bare)
+            })
+            .collect()
+    }
+
+    /// Get an index of all manifests to install from.
+    ///
+    /// Listing and searching manifests with the index is much faster than reading all manifests
+    /// in the store.  Update the cached index of every manifest repo which changed, and combine
+    /// the indexes of all repos.
+    ///
+    /// Return every manifest along with the name of its repo, ordered by name.  If multiple
+    /// repos have a manifest of the same name, only return the manifest from the repo which
+    /// homebins installs it from, see [`RepoConfig::priority`].
+    pub fn manifest_index(&mut self) -> Result<Vec<(String, IndexEntry)>> {
+        let mut entries = Vec::new();
+        let mut seen = HashSet::new();
+        for (config, repo) in self.repos_by_priority().iter().zip(self.manifest_repos()?) {
+            let index = repo.index(&self.index_file(&config.name))?;
+            for entry in index.entries() {
+                if seen.insert(entry.summary.name.clone()) {
+                    entries.push((config.name.clone(), entry.clone()));
+                }
+            }
+        }
+        entries.sort_by(|(_, a), (_, b)| a.summary.name.cmp(&b.summary.name));
+        Ok(entries)
+    }
+
+    /// Fetch all manifest repositories and update their indexes.
+    ///
+    /// Fetch repositories regardless of when homebins last fetched them.
+    ///
+    /// Return the name of every Git repository, along with how many commits its pinned branch,
+    /// tag or commit is behind the `main` branch, if it's pinned, see
+    /// [`ManifestRepo::commits_behind`].
+    pub fn update(&mut self) -> Result<Vec<(String, Option<u64>)>> {
+        let mut updated = Vec::new();
+        for (config, repo) in self
+            .repos_by_priority()
+            .iter()
+            .zip(self.manifest_repos_with_ttl(Duration::ZERO)?)
+        {
+            repo.index(&self.index_file(&config.name))?;
+            if !repo.is_directory() {
+                updated.push((config.name.clone(), repo.commits_behind()?));
+            }
+        }
+        Ok(updated)
+    }
+
+    /// Whether more than one manifest repository is enabled.
+    pub fn has_multiple_repos(&self) -> bool {
+        self.repos.iter().filter(|repo| repo.enabled).count() > 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn repo(name: &str, url: &str) -> RepoConfig {
+        RepoConfig {
+            name: name.to_string(),
+            url: url.to_string(),
+            reference: None,
+            priority: 0,
+            enabled: true,
+        }
+    }
+
+    #[test]
+    fn check_repos() {
+        let repos = vec![
+            repo(
+                "lunaryorn",
+                "https://github.com/lunaryorn/homebin-manifests",
+            ),
+            RepoConfig {
+                reference: Some("v1.0".to_string()),
+                priority: 10,
+                ..repo("work", "git@git.example.com:tools/manifests.git")
+            },
+            RepoConfig {
+                enabled: false,
+                ..repo("fork", "https://github.com/me/homebin-manifests")
+            },
+        ];
+        check_repo_configs(&repos).unwrap();
+        assert_eq!(
+            format!(
+                "{:#}",
+                check_repo_configs(&[repo("a/b", "https://example.com")]).unwrap_err()
+            ),
+            "Invalid repository name: \"a/b\""
+        );
+        assert_eq!(
+            check_repo_configs(&[RepoConfig {
+                reference: Some("main".to_string()),
+                ..repo("me", "/home/me/manifests")
+            }])
+            .unwrap_err()
+            .to_string(),
+            "Cannot pin plain directory /home/me/manifests of repository me"
+        );
+        assert_eq!(
+            check_repo_configs(&[
+                repo("a", "https://example.com/a"),
+                repo("a", "https://example.com/b")
+            ])
+            .unwrap_err()
+            .to_string(),
+            "Duplicate repository: a"
+        );
+    }
+
+    #[test]
+    fn write_repos_to_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config").join("config.toml");
+        let repos = vec![
+            repo(
+                "lunaryorn",
+                "https://github.com/lunaryorn/homebin-manifests",
+            ),
+            RepoConfig {
+                reference: Some("v1.0".to_string()),
+                priority: 10,
+                enabled: false,
+                ..repo("work", "git@git.example.com:tools/manifests.git")
+            },
+        ];
+        write_repo_configs(&path, &repos).unwrap();
+        assert_eq!(
+            crate::Config::read_from_path(&path).unwrap().repos,
+            Some(repos.clone())
+        );
+
+        std::fs::write(
+            &path,
+            "# Keep downloads\nkeep_workdir = true\nrepos = []\n\n[[repos]]\nname = \"old\"\nurl = \"https://example.com/old\"\n\n[tokens]\n# Work\n\"git.example.com\" = { age = \"/token.age\", identity = \"/key.txt\" }\n\n[[ repos ]] # More\nname = \"older\"\nurl = \"https://example.com/older\"\n",
+        )
+        .unwrap();
+        write_repo_configs(&path, &repos).unwrap();
+        let config = crate::Config::read_from_path(&path).unwrap();
+        assert_eq!(config.repos, Some(repos));
+        assert!(config.keep_workdir);
+        assert_eq!(config.tokens.len(), 1);
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert!(contents.starts_with("# Keep downloads\n"));
+        assert!(contents.contains("# Work\n"));
+
+        write_repo_configs(&path, &[]).unwrap();
+        let config = crate::Config::read_from_path(&path).unwrap();
+        assert_eq!(config.repos, Some(Vec::new()));
+        assert_eq!(config.tokens.len(), 1);
+    }
+
+    #[test]
+    fn repo_directory() {
+        let repo = |url: &str| RepoConfig {
+            name: "repo".to_string(),
+            url: url.to_string(),
+            reference: None,
+            priority: 0,
+            enabled: true,
+        };
+        assert_eq!(
+            repo("/home/me/dotfiles/manifests").directory(),
+            Some(Path::new("/home/me/dotfiles/manifests"))
+        );
+        assert_eq!(repo("https://example.com/manifests").directory(), None);
+        assert_eq!(repo("file:///srv/git/manifests.git").directory(), None);
+        assert_eq!(repo("git@example.com:manifests.git").directory(), None);
+    }
+
+    #[test]
+    fn repo_archive() {
+        let checksum = "b5bb9d8014a0f9b1d61e21e796d78dccdf1352f23cd32812f4850b878ae4944c";
+        let repo = |url: &str, reference: Option<&str>| RepoConfig {
+            name: "repo".to_string(),
+            url: url.to_string(),
+            reference: reference.map(str::to_string),
+            priority: 0,
+            enabled: true,
+        };
+        assert_eq!(
+            repo("https://example.com/manifests.tar.gz", None).archive_extension(),
+            Some(".tar.gz")
+        );
+        assert_eq!(
+            repo(
+                "https://codeload.github.com/lunaryorn/homebin-manifests/zip/refs/heads/main",
+                None
+            )
+            .archive_extension(),
+            Some(".zip")
+        );
+        assert_eq!(
+            repo("https://github.com/lunaryorn/homebin-manifests", None).archive_extension(),
+            None
+        );
+        assert_eq!(
+            repo("/srv/manifests.tar.gz", None).archive_extension(),
+            None
+        );
+        let pin = format!("sha256:{}", checksum);
+        assert_eq!(
+            repo("https://example.com/manifests.tgz", Some(&pin)).archive_sha256(),
+            Some(checksum)
+        );
+        assert_eq!(
+            repo("https://example.com/manifests.git", Some(&pin)).archive_sha256(),
+            None
+        );
+
+        check_repo_configs(&[repo("https://example.com/manifests.zip", Some(&pin))]).unwrap();
+        assert_eq!(
+            format!(
+                "{:#}",
+                check_repo_configs(&[repo("https://example.com/manifests.zip", Some("main"))])
+                    .unwrap_err()
+            ),
+            "Invalid pin of repository repo: Archives can only be pinned to their SHA256 checksum, as in sha256:<checksum>, not main"
+        );
+    }
+
+    #[test]
+    fn fetch_without_broken_token() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut tokens = HashMap::new();
+        tokens.insert(
+            "broken.example.com".to_string(),
+            Secret::Age {
+                age: dir.path().join("missing.age"),
+                identity: dir.path().join("missing.txt"),
+            },
+        );
+        assert!(repo_token(&tokens, "https://broken.example.com/manifests.git").is_none());
+        assert!(repo_token(&tokens, "https://github.com/lunaryorn/homebins-repo.git").is_none());
+    }
+
+    #[test]
+    fn lock_repos_apart_from_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let dirs = HomebinProjectDirs::in_directory(dir.path());
+        let manifests = std::env::current_dir().unwrap().join("tests/manifests");
+        let mut repos = HomebinRepos::open(&dirs);
+        repos.set_repos(vec![repo("local", &manifests.display().to_string())]);
+
+        // A running installation holds the state lock, but fetching doesn't wait for it
+        let _state = StateLock::acquire(dirs.lock_file()).unwrap();
+        assert!(repos.manifest_store().unwrap().contains("hello"));
+        let repos_lock = dirs.repos_dir().join(REPOS_LOCK_FILE);
+        assert!(repos_lock.is_file());
+        // Fetching and taking snapshots release the lock of the repos again
+        assert!(StateLock::try_acquire(&repos_lock).unwrap().is_some());
+    }
+
+    #[test]
+    fn format_fetch_age() {
+        assert_eq!(format_age(Duration::from_secs(30)), "0 minutes");
+        assert_eq!(format_age(Duration::from_secs(90)), "1 minute");
+        assert_eq!(format_age(Duration::from_secs(3 * 3600 + 60)), "3 hours");
+        assert_eq!(format_age(Duration::from_secs(86400)), "1 day");
+    }
+
+    #[test]
+    fn pick_repo_by_name_and_priority() {
+        let repos = [("main", 10, true), ("extra", 0, true), ("other", 10, false)];
+        assert_eq!(pick_repo("rg", &repos).unwrap(), Some("main"));
+        assert_eq!(pick_repo("extra/rg", &repos).unwrap(), Some("extra"));
+        assert_eq!(pick_repo("rg", &[("main", 0, false)]).unwrap(), None);
+        assert_eq!(
+            pick_repo("nope/rg", &repos).unwrap_err().to_string(),
+            "Unknown repository nope in nope/rg, expected one of: main, extra, other"
+        );
+        assert_eq!(
+            pick_repo("rg", &[("main", 0, true), ("extra", 0, true)]).unwrap(),
+            Some("main")
+        );
+        assert_eq!(
+            pick_repo("rg", &[("main", 0, true), ("extra", 5, true)]).unwrap(),
+            Some("extra")
+        );
     }
 }
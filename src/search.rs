@@ -0,0 +1,144 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Search manifests.
+//!
+//! A manifest matches a query if it matches every word of the query, case-insensitively: Either
+//! its name, one of its keywords, or its description contains the word, or the word is an
+//! abbreviation of the name, i.e. all letters of the word appear in the name in order, as in
+//! `rg` for `ripgrep`.  Matches in the name rank above matches in keywords, which rank above
+//! matches in the description; an alias ranks like the name if it matches exactly.
+
+use crate::manifest::ManifestSummary;
+
+/// Whether all characters of `word` appear in `text` in order.
+fn is_subsequence(word: &str, text: &str) -> bool {
+    let mut text = text.chars();
+    word.chars().all(|c| text.any(|t| t == c))
+}
+
+/// Score how well `summary` matches a single lowercase `word`.
+///
+/// Return `None` if `summary` doesn't match `word`.
+fn score_word(summary: &ManifestSummary, word: &str) -> Option<u32> {
+    let name = summary.name.to_lowercase();
+    if name == word
+        || summary
+            .aliases
+            .iter()
+            .any(|alias| alias.to_lowercase() == word)
+    {
+        Some(100)
+    } else if name.starts_with(word) {
+        Some(50)
+    } else if name.contains(word) {
+        Some(30)
+    } else if summary
+        .keywords
+        .iter()
+        .any(|keyword| keyword.to_lowercase() == word)
+    {
+        Some(20)
+    } else if summary
+        .keywords
+        .iter()
+        .any(|keyword| keyword.to_lowercase().contains(word))
+    {
+        Some(10)
+    } else if summary
+        .description
+        .as_ref()
+        .is_some_and(|description| description.to_lowercase().contains(word))
+    {
+        Some(5)
+    } else if is_subsequence(word, &name) {
+        Some(1)
+    } else {
+        None
+    }
+}
+
+/// Score how well `summary` matches `query`.
+///
+/// Return `None` if `summary` doesn't match all words of `query`.
+pub fn score(summary: &ManifestSummary, query: &str) -> Option<u32> {
+    query
+        .split_whitespace()
+        .map(|word| score_word(summary, &word.to_lowercase()))
+        .sum()
+}
+
+/// Search `summaries` for `query`.
+///
+/// Return all matching summaries, best matches first, and ordered by name among equally good
+/// matches.
+pub fn search<I: IntoIterator<Item = ManifestSummary>>(
+    summaries: I,
+    query: &str,
+) -> Vec<ManifestSummary> {
+    let mut matches: Vec<(u32, ManifestSummary)> = summaries
+        .into_iter()
+        .filter_map(|summary| score(&summary, query).map(|score| (score, summary)))
+        .collect();
+    matches.sort_by(|(a, a_summary), (b, b_summary)| {
+        b.cmp(a).then_with(|| a_summary.name.cmp(&b_summary.name))
+    });
+    matches.into_iter().map(|(_, summary)| summary).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn summary(name: &str, description: &str, keywords: &[&str]) -> ManifestSummary {
+        ManifestSummary {
+            name: name.to_string(),
+            aliases: Vec::new(),
+            version: "1.0.0".to_string(),
+            url: format!("https://example.com/{}", name),
+            homepage: None,
+            license: "MIT".to_string(),
+            description: Some(description.to_string()),
+            keywords: keywords.iter().map(|k| k.to_string()).collect(),
+            deprecated: false,
+            replaced_by: None,
+        }
+    }
+
+    #[test]
+    fn search_summaries() {
+        let summaries = vec![
+            summary("fx", "Terminal JSON viewer", &["json"]),
+            summary("jq", "Command-line JSON processor", &["json", "query"]),
+            summary(
+                "jless",
+                "A JSON viewer for the command line",
+                &["json", "pager"],
+            ),
+            summary(
+                "ripgrep",
+                "Recursively search directories",
+                &["search", "grep"],
+            ),
+        ];
+        let names = |query: &str| -> Vec<String> {
+            search(summaries.clone(), query)
+                .into_iter()
+                .map(|s| s.name)
+                .collect()
+        };
+        assert_eq!(names("json viewer"), vec!["fx", "jless"]);
+        assert_eq!(names("JSON"), vec!["fx", "jless", "jq"]);
+        assert_eq!(names("jq"), vec!["jq"]);
+        assert_eq!(names("rg"), vec!["ripgrep"]);
+        let mut aliased = summaries.clone();
+        aliased[3].aliases.push("rg".to_string());
+        assert_eq!(score(&aliased[3], "rg"), Some(100));
+        assert_eq!(names("grep"), vec!["ripgrep"]);
+        assert!(names("yaml").is_empty());
+    }
+}
@@ -0,0 +1,357 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Secrets for authenticated downloads.
+//!
+//! The configuration maps hosts to download tokens, but never contains the tokens themselves:
+//! Each token is either a file encrypted with [age](https://age-encryption.org), or an item in
+//! the secret service of the desktop, which homebins looks up with `secret-tool` from libsecret.
+//! Homebins only decrypts a token right before downloading from its host.
+//!
+//! For hosts without a token in the configuration homebins looks for a token in the environment,
+//! see [`env_token`], and then for a token which `homebins auth login` stored in the secret
+//! service.  Homebins only asks the secret service for hosts which `homebins auth login`
+//! recorded in [`LoginHosts`], so that downloads from other hosts never talk to the secret
+//! service.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{anyhow, Context, Error};
+use colored::Colorize;
+use fehler::{throw, throws};
+use serde::Deserialize;
+use url::Url;
+
+use crate::process::CommandExt;
+use crate::state::{read_state, write_atomically};
+
+/// Where to get a secret from.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum Secret {
+    /// A file encrypted with age.
+    Age {
+        /// The encrypted file.
+        age: PathBuf,
+        /// The file with the identity to decrypt `age` with.
+        identity: PathBuf,
+    },
+    /// An item in the secret service.
+    ///
+    /// Homebins looks up the item with the attributes `homebins` and the given name, i.e.
+    /// `secret-tool lookup homebins <keyring>`.
+    Keyring {
+        /// The name of the item.
+        keyring: String,
+    },
+    /// The token which `homebins auth login` stored for `host` in the secret service.
+    ///
+    /// Never read from the configuration; homebins adds these for all [`LoginHosts`] without a
+    /// token in the configuration.
+    #[serde(skip_deserializing)]
+    Login {
+        /// The host of the token.
+        host: String,
+    },
+}
+
+impl Secret {
+    /// Decrypt or look up this secret.
+    #[throws]
+    pub fn reveal(&self) -> String {
+        let mut command = match self {
+            Secret::Age { age, identity } => {
+                let mut command = Command::new("age");
+                command.arg("--decrypt").arg("-i").arg(identity).arg(age);
+                command
+            }
+            Secret::Keyring { keyring: name } | Secret::Login { host: name } => {
+                let mut command = Command::new("secret-tool");
+                command.args(["lookup", "homebins", name]);
+                command
+            }
+        };
+        let output = command
+            .checked_output()
+            .with_context(|| format!("Failed to reveal secret {}", self))?;
+        let secret = String::from_utf8(output.stdout)
+            .with_context(|| format!("Secret {} is not valid UTF-8", self))?;
+        let secret = secret.trim();
+        if secret.is_empty() {
+            throw!(anyhow!("Secret {} is empty", self));
+        }
+        secret.to_string()
+    }
+}
+
+impl std::fmt::Display for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Secret::Age { age, .. } => write!(f, "in {}", age.display()),
+            Secret::Keyring { keyring } => write!(f, "homebins {} in keyring", keyring),
+            Secret::Login { host } => write!(f, "from auth login for {} in keyring", host),
+        }
+    }
+}
+
+/// Look up the token `homebins auth login` stored for `host` in the secret service.
+///
+/// Return `None` if the secret service has no token for `host`, or if `secret-tool` isn't
+/// installed.
+#[throws]
+pub fn keyring_token(host: &str) -> Option<String> {
+    match Command::new("secret-tool")
+        .args(["lookup", "homebins", host])
+        .output()
+    {
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => None,
+        Err(error) => Err(error).with_context(|| "Failed to run secret-tool")?,
+        // secret-tool fails without output if it doesn't find the item
+        Ok(output) if !output.status.success() && output.stderr.is_empty() => None,
+        Ok(output) if !output.status.success() => throw!(anyhow!(
+            "Failed to look up token for {}: {}",
+            host,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )),
+        Ok(output) => {
+            let token = String::from_utf8(output.stdout)
+                .with_context(|| format!("Token for {} is not valid UTF-8", host))?;
+            Some(token.trim().to_string()).filter(|token| !token.is_empty())
+        }
+    }
+}
+
+/// The environment variable with the token for `host`, e.g. `HOMEBINS_TOKEN_GITHUB_COM`.
+///
+/// Take the upper case host name, with all characters other than letters and digits replaced
+/// by underscores.
+pub fn token_variable(host: &str) -> String {
+    let host: String = host
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("HOMEBINS_TOKEN_{}", host)
+}
+
+/// Get the token for `host` from the environment, see [`token_variable`].
+///
+/// Return `None` if the variable isn't set or empty.
+pub fn env_token(host: &str) -> Option<String> {
+    std::env::var(token_variable(host))
+        .ok()
+        .map(|token| token.trim().to_string())
+        .filter(|token| !token.is_empty())
+}
+
+/// The hosts with a token from `homebins auth login` in the secret service.
+///
+/// Homebins keeps these hosts in a JSON file, to look up tokens in the secret service only for
+/// these hosts.
+#[derive(Debug)]
+pub struct LoginHosts {
+    path: PathBuf,
+    hosts: Vec<String>,
+}
+
+impl LoginHosts {
+    /// Open the hosts in the file at `path`.
+    ///
+    /// Start without hosts if `path` doesn't exist.
+    #[throws]
+    pub fn open<P: AsRef<Path>>(path: P) -> LoginHosts {
+        let path = path.as_ref().to_path_buf();
+        let hosts = match read_state(&path)? {
+            Some(contents) => serde_json::from_str(&contents)
+                .with_context(|| format!("Invalid login hosts in {}", path.display()))?,
+            None => Vec::new(),
+        };
+        LoginHosts { path, hosts }
+    }
+
+    /// All hosts with a token from `homebins auth login`.
+    pub fn hosts(&self) -> &[String] {
+        &self.hosts
+    }
+
+    /// Add `host`.
+    pub fn add(&mut self, host: &str) {
+        if !self.hosts.iter().any(|existing| existing == host) {
+            self.hosts.push(host.to_string());
+            self.hosts.sort();
+        }
+    }
+
+    /// Add a [`Secret::Login`] to `tokens` for every host which has no token in `tokens`.
+    pub fn add_to_tokens(&self, tokens: &mut HashMap<String, Secret>) {
+        for host in &self.hosts {
+            tokens
+                .entry(host.clone())
+                .or_insert_with(|| Secret::Login { host: host.clone() });
+        }
+    }
+
+    /// Write the hosts back to the file.
+    #[throws]
+    pub fn save(&self) -> () {
+        write_atomically(&self.path, serde_json::to_vec(&self.hosts)?)?;
+    }
+}
+
+/// Store a token for `host` in the secret service, for `homebins auth login`.
+///
+/// `secret-tool` asks for the token on the terminal, or reads it from stdin.
+#[throws]
+pub fn login(host: &str) -> () {
+    Command::new("secret-tool")
+        .args(["store", "--label"])
+        .arg(format!("homebins token for {}", host))
+        .args(["homebins", host])
+        .checked_call()
+        .with_context(|| format!("Failed to store token for {}", host))?;
+}
+
+/// Get the token to download `url` with from `tokens`, by the host of `url`.
+///
+/// If `tokens` has no token for the host of `url` look for a token in the environment, see
+/// [`env_token`].  Look up [`Secret::Login`] tokens in the secret service, see
+/// [`keyring_token`], after the environment, and warn and use no token if the lookup fails.
+/// Return `None` if there's no token for the host of `url`.
+#[throws]
+pub fn token_for(tokens: &HashMap<String, Secret>, url: &Url) -> Option<String> {
+    match url.host_str() {
+        None => None,
+        Some(host) => match tokens.get(host) {
+            None => env_token(host),
+            Some(Secret::Login { host }) => env_token(host).or_else(|| {
+                keyring_token(host).unwrap_or_else(|error| {
+                    crate::estatus!(
+                        "{}",
+                        format!("WARNING: {:#}; continuing without token", error).yellow()
+                    );
+                    None
+                })
+            }),
+            Some(secret) => Some(
+                secret
+                    .reveal()
+                    .with_context(|| format!("Failed to get token for {}", url))?,
+            ),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn deserialize_secrets() {
+        let tokens: HashMap<String, Secret> = toml::from_str(
+            r#"
+"github.com" = { age = "/home/me/github.age", identity = "/home/me/key.txt" }
+"gitlab.example.com" = { keyring = "gitlab" }
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            tokens["github.com"],
+            Secret::Age {
+                age: PathBuf::from("/home/me/github.age"),
+                identity: PathBuf::from("/home/me/key.txt")
+            }
+        );
+        assert_eq!(
+            tokens["gitlab.example.com"],
+            Secret::Keyring {
+                keyring: "gitlab".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn no_token_for_other_hosts() {
+        let mut tokens = HashMap::new();
+        tokens.insert(
+            "example.com".to_string(),
+            Secret::Keyring {
+                keyring: "example".to_string(),
+            },
+        );
+        let url = Url::parse("https://github.com/foo/bar").unwrap();
+        assert_eq!(token_for(&tokens, &url).unwrap(), None);
+    }
+
+    #[test]
+    fn login_hosts() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data").join("login_hosts.json");
+        let mut hosts = LoginHosts::open(&path).unwrap();
+        hosts.add("gitlab.example.com");
+        hosts.add("example.com");
+        hosts.add("gitlab.example.com");
+        hosts.save().unwrap();
+
+        let hosts = LoginHosts::open(&path).unwrap();
+        assert_eq!(hosts.hosts(), ["example.com", "gitlab.example.com"]);
+        let mut tokens = HashMap::new();
+        tokens.insert(
+            "example.com".to_string(),
+            Secret::Keyring {
+                keyring: "example".to_string(),
+            },
+        );
+        hosts.add_to_tokens(&mut tokens);
+        assert_eq!(
+            tokens["example.com"],
+            Secret::Keyring {
+                keyring: "example".to_string()
+            }
+        );
+        assert_eq!(
+            tokens["gitlab.example.com"],
+            Secret::Login {
+                host: "gitlab.example.com".to_string()
+            }
+        );
+        assert!(toml::from_str::<Secret>(r#"host = "example.com""#).is_err());
+    }
+
+    #[test]
+    fn no_token_without_host() {
+        let mut tokens = HashMap::new();
+        tokens.insert(
+            "example.com".to_string(),
+            Secret::Keyring {
+                keyring: "example".to_string(),
+            },
+        );
+        let url = Url::parse("file:///foo/bar").unwrap();
+        assert_eq!(token_for(&tokens, &url).unwrap(), None);
+    }
+
+    #[test]
+    fn token_from_environment() {
+        assert_eq!(
+            token_variable("git.example-corp.com"),
+            "HOMEBINS_TOKEN_GIT_EXAMPLE_CORP_COM"
+        );
+        std::env::set_var("HOMEBINS_TOKEN_TOKENS_EXAMPLE_COM", " s3cret\n");
+        let url = Url::parse("https://tokens.example.com/manifests").unwrap();
+        assert_eq!(
+            token_for(&HashMap::new(), &url).unwrap(),
+            Some("s3cret".to_string())
+        );
+    }
+}
@@ -0,0 +1,218 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Safe writes of state files.
+//!
+//! Homebins keeps its state in plain files, e.g. provenance records, pins and trusted keys.
+//! Replace these files atomically, so that a crash or power loss during a write leaves either
+//! the old or the new contents but never a truncated file, and serialize homebins processes with
+//! a lock, so that concurrent commands don't overwrite each other's changes.
+
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Error};
+use fehler::throws;
+
+/// Read the state file at `path`.
+///
+/// Return `None` if `path` doesn't exist, e.g. because homebins didn't write any state yet.
+#[throws]
+pub fn read_state<P: AsRef<Path>>(path: P) -> Option<String> {
+    let path = path.as_ref();
+    match std::fs::read_to_string(path) {
+        Ok(contents) => Some(contents),
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => None,
+        Err(error) => Err(error).with_context(|| format!("Failed to read {}", path.display()))?,
+    }
+}
+
+/// Replace the file at `path` with `contents` atomically.
+///
+/// Write `contents` to a temporary file next to `path`, flush it to disk, and then rename it to
+/// `path` and flush the directory, so that `path` has either its old or its new contents even if
+/// the system crashes.  Keep the permissions of an existing file at `path`.
+#[throws]
+pub fn write_atomically<P: AsRef<Path>, C: AsRef<[u8]>>(path: P, contents: C) -> () {
+    let path = path.as_ref();
+    let directory = path
+        .parent()
+        .filter(|directory| !directory.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+    std::fs::create_dir_all(directory)
+        .with_context(|| format!("Failed to create {}", directory.display()))?;
+    let permissions = match std::fs::metadata(path) {
+        Ok(metadata) => metadata.permissions(),
+        Err(_) => std::fs::Permissions::from_mode(0o644),
+    };
+    let mut temp = tempfile::Builder::new()
+        .prefix(&format!(
+            ".{}",
+            path.file_name().unwrap_or_default().to_string_lossy()
+        ))
+        .tempfile_in(directory)
+        .with_context(|| format!("Failed to create temporary file in {}", directory.display()))?;
+    temp.write_all(contents.as_ref())
+        .and_then(|_| temp.as_file().set_permissions(permissions))
+        .and_then(|_| temp.as_file().sync_all())
+        .with_context(|| format!("Failed to write {}", temp.path().display()))?;
+    temp.persist(path)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    File::open(directory)
+        .and_then(|directory| directory.sync_all())
+        .with_context(|| format!("Failed to flush {}", directory.display()))?;
+}
+
+/// A lock of homebins state, held until dropped.
+///
+/// An exclusive lock excludes all other locks; a shared lock only excludes exclusive locks, so
+/// that commands which only read state run alongside each other.
+#[derive(Debug)]
+pub struct StateLock {
+    _file: File,
+}
+
+/// Open the lock file at `path`, creating it and its directory if needed.
+#[throws]
+fn open_lock_file(path: &Path) -> File {
+    if let Some(directory) = path.parent() {
+        std::fs::create_dir_all(directory)
+            .with_context(|| format!("Failed to create {}", directory.display()))?;
+    }
+    OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+        .with_context(|| format!("Failed to open lock file {}", path.display()))?
+}
+
+impl StateLock {
+    /// Lock homebins state exclusively with the lock file at `path`.
+    ///
+    /// Wait for the lock if another process holds it.  The system releases the lock when the
+    /// process exits, so a crashed process never leaves a stale lock behind.
+    #[throws]
+    pub fn acquire<P: AsRef<Path>>(path: P) -> StateLock {
+        let path = path.as_ref();
+        let file = open_lock_file(path)?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                crate::estatus!("Waiting for another homebins process to finish");
+                file.lock()
+                    .with_context(|| format!("Failed to lock {}", path.display()))?;
+            }
+            Err(TryLockError::Error(error)) => {
+                Err(anyhow!(error)).with_context(|| format!("Failed to lock {}", path.display()))?
+            }
+        }
+        StateLock { _file: file }
+    }
+
+    /// Lock homebins state for reading with the lock file at `path`.
+    ///
+    /// Wait for the lock if another process holds an exclusive lock.
+    #[throws]
+    pub fn acquire_shared<P: AsRef<Path>>(path: P) -> StateLock {
+        let path = path.as_ref();
+        let file = open_lock_file(path)?;
+        match file.try_lock_shared() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                crate::estatus!("Waiting for another homebins process to finish");
+                file.lock_shared()
+                    .with_context(|| format!("Failed to lock {}", path.display()))?;
+            }
+            Err(TryLockError::Error(error)) => {
+                Err(anyhow!(error)).with_context(|| format!("Failed to lock {}", path.display()))?
+            }
+        }
+        StateLock { _file: file }
+    }
+
+    /// Try to lock homebins state exclusively with the lock file at `path`, without waiting.
+    ///
+    /// Return `None` if another process holds the lock.
+    #[throws]
+    pub fn try_acquire<P: AsRef<Path>>(path: P) -> Option<StateLock> {
+        let path = path.as_ref();
+        let file = open_lock_file(path)?;
+        match file.try_lock() {
+            Ok(()) => Some(StateLock { _file: file }),
+            Err(TryLockError::WouldBlock) => None,
+            Err(TryLockError::Error(error)) => {
+                Err(anyhow!(error)).with_context(|| format!("Failed to lock {}", path.display()))?
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn write_state_atomically() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data").join("state.json");
+        write_atomically(&path, "old").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "old");
+        assert_eq!(
+            std::fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o644
+        );
+
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+        write_atomically(&path, "new").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(
+            std::fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+        // No temporary files left behind
+        assert_eq!(
+            std::fs::read_dir(path.parent().unwrap()).unwrap().count(),
+            1
+        );
+    }
+
+    #[test]
+    fn read_missing_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        assert_eq!(read_state(&path).unwrap(), None);
+        write_atomically(&path, "state").unwrap();
+        assert_eq!(read_state(&path).unwrap(), Some("state".to_string()));
+        assert!(read_state(dir.path()).is_err());
+    }
+
+    #[test]
+    fn lock_state_exclusively() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("lock");
+        let lock = StateLock::acquire(&path).unwrap();
+        assert!(StateLock::try_acquire(&path).unwrap().is_none());
+        drop(lock);
+        assert!(StateLock::try_acquire(&path).unwrap().is_some());
+    }
+
+    #[test]
+    fn lock_state_shared() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("lock");
+        let first = StateLock::acquire_shared(&path).unwrap();
+        let second = StateLock::acquire_shared(&path).unwrap();
+        assert!(StateLock::try_acquire(&path).unwrap().is_none());
+        drop(first);
+        assert!(StateLock::try_acquire(&path).unwrap().is_none());
+        drop(second);
+        assert!(StateLock::try_acquire(&path).unwrap().is_some());
+    }
+}
@@ -0,0 +1,132 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Status output for terminals and logs.
+//!
+//! On a terminal homebins prints plain status messages with colours, and draws progress bars for
+//! downloads on stderr, see [`crate::progress`].  When stdout is not a terminal, e.g. in CI,
+//! homebins prints every status message as a single line with a UTC timestamp and without any
+//! ANSI control sequences, and reports download progress in separate lines on stderr every few
+//! seconds, so that progress never ends up in output redirected from stdout.
+
+use std::fmt::Arguments;
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::manifest::ReleaseDate;
+
+static LINE_ORIENTED: AtomicBool = AtomicBool::new(false);
+
+static TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+/// Switch to line-oriented output if stdout is not a terminal.
+///
+/// Disable colours for line-oriented output.
+pub fn detect() {
+    set_line_oriented(!std::io::stdout().is_terminal());
+}
+
+/// Enable or disable line-oriented output.
+pub fn set_line_oriented(line_oriented: bool) {
+    LINE_ORIENTED.store(line_oriented, Ordering::Relaxed);
+    if line_oriented {
+        colored::control::set_override(false);
+    } else {
+        colored::control::unset_override();
+    }
+}
+
+/// Whether homebins prints line-oriented output.
+pub fn is_line_oriented() -> bool {
+    LINE_ORIENTED.load(Ordering::Relaxed)
+}
+
+/// Print all status messages to stderr if `to_stderr` is set.
+///
+/// Use this to keep stdout for machine-readable output, e.g. JSON.
+pub fn set_stderr(to_stderr: bool) {
+    TO_STDERR.store(to_stderr, Ordering::Relaxed);
+}
+
+/// Format `time` as RFC 3339 timestamp in UTC, with whole seconds.
+pub fn format_timestamp(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let time_of_day = seconds % 86400;
+    format!(
+        "{}T{:02}:{:02}:{:02}Z",
+        ReleaseDate::from_system_time(time),
+        time_of_day / 3600,
+        time_of_day % 3600 / 60,
+        time_of_day % 60
+    )
+}
+
+/// Format a status `message` for the current output mode.
+fn format_status(message: Arguments<'_>) -> String {
+    if is_line_oriented() {
+        format!("[{}] {}", format_timestamp(SystemTime::now()), message)
+    } else {
+        message.to_string()
+    }
+}
+
+/// Print a status `message` to stdout, see [`status!`](crate::status!).
+///
+/// Print to stderr instead after [`set_stderr`].
+pub fn print(message: Arguments<'_>) {
+    if TO_STDERR.load(Ordering::Relaxed) {
+        eprint(message);
+    } else {
+        let message = format_status(message);
+        crate::progress::suspend(|| println!("{}", message));
+    }
+}
+
+/// Print a status `message` to stderr, see [`estatus!`](crate::estatus!).
+pub fn eprint(message: Arguments<'_>) {
+    let message = format_status(message);
+    crate::progress::suspend(|| eprintln!("{}", message));
+}
+
+/// Print a status message to stdout, with a timestamp for line-oriented output.
+#[macro_export]
+macro_rules! status {
+    ($($arg:tt)*) => {
+        $crate::status::print(format_args!($($arg)*))
+    };
+}
+
+/// Print a status message to stderr, with a timestamp for line-oriented output.
+#[macro_export]
+macro_rules! estatus {
+    ($($arg:tt)*) => {
+        $crate::status::eprint(format_args!($($arg)*))
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::time::Duration;
+
+    #[test]
+    fn format_timestamps() {
+        assert_eq!(format_timestamp(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+        assert_eq!(
+            format_timestamp(UNIX_EPOCH + Duration::from_secs(1_608_508_800 + 3723)),
+            "2020-12-21T01:02:03Z"
+        );
+        assert_eq!(
+            format_timestamp(UNIX_EPOCH + Duration::from_secs(951_868_799)),
+            "2000-02-29T23:59:59Z"
+        );
+    }
+}
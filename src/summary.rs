@@ -0,0 +1,187 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Summaries of commands which install, update or remove many manifests.
+//!
+//! A summary counts the manifests which a command installed, updated, removed, or failed to
+//! apply, and the bytes they downloaded, so that users needn't scroll back through the output
+//! of a long update to know whether everything succeeded.
+
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+/// Format `bytes` in binary units, with one decimal.
+pub(crate) fn format_bytes(bytes: u64) -> String {
+    let units = ["KiB", "MiB", "GiB"];
+    if bytes < 1024 {
+        format!("{} B", bytes)
+    } else {
+        let mut value = bytes as f64 / 1024.0;
+        let mut unit = 0;
+        while value >= 1024.0 && unit + 1 < units.len() {
+            value /= 1024.0;
+            unit += 1;
+        }
+        format!("{:.1} {}", value, units[unit])
+    }
+}
+
+/// The outcome of a command, for JSON output.
+#[derive(Debug, PartialEq, Serialize)]
+struct Outcome<'a> {
+    installed: &'a [String],
+    updated: &'a [String],
+    removed: &'a [String],
+    failed: &'a [String],
+    downloaded_bytes: u64,
+    elapsed_seconds: f64,
+}
+
+/// A summary of what a command did.
+#[derive(Debug, Clone)]
+pub struct Summary {
+    installed: Vec<String>,
+    updated: Vec<String>,
+    removed: Vec<String>,
+    failed: Vec<String>,
+    started: Instant,
+    downloaded: u64,
+}
+
+impl Summary {
+    /// Start summarizing a command now.
+    pub fn start() -> Summary {
+        Summary {
+            installed: Vec::new(),
+            updated: Vec::new(),
+            removed: Vec::new(),
+            failed: Vec::new(),
+            started: Instant::now(),
+            downloaded: 0,
+        }
+    }
+
+    /// Count the manifest `name` as installed.
+    pub fn installed(&mut self, name: &str) {
+        self.installed.push(name.to_string());
+    }
+
+    /// Count the manifest `name` as updated.
+    pub fn updated(&mut self, name: &str) {
+        self.updated.push(name.to_string());
+    }
+
+    /// Count the manifest `name` as removed.
+    pub fn removed(&mut self, name: &str) {
+        self.removed.push(name.to_string());
+    }
+
+    /// Count `bytes` as downloaded, e.g. the bytes [`crate::install_manifest`] downloaded.
+    pub fn downloaded(&mut self, bytes: u64) {
+        self.downloaded += bytes;
+    }
+
+    /// Count the manifest `name` as failed.
+    pub fn failed(&mut self, name: &str) {
+        self.failed.push(name.to_string());
+    }
+
+    /// The names of all failed manifests.
+    pub fn failures(&self) -> &[String] {
+        &self.failed
+    }
+
+    /// Whether the command changed or tried to change any manifest at all.
+    pub fn is_empty(&self) -> bool {
+        self.installed.is_empty()
+            && self.updated.is_empty()
+            && self.removed.is_empty()
+            && self.failed.is_empty()
+    }
+
+    fn describe_with(&self, downloaded: u64, elapsed: Duration) -> String {
+        let mut parts = Vec::new();
+        for (names, what) in [
+            (&self.installed, "installed"),
+            (&self.updated, "updated"),
+            (&self.removed, "removed"),
+        ] {
+            if !names.is_empty() {
+                parts.push(format!("{} {}", names.len(), what));
+            }
+        }
+        if !self.failed.is_empty() {
+            parts.push(format!(
+                "{} failed ({})",
+                self.failed.len(),
+                self.failed.join(", ")
+            ));
+        }
+        if parts.is_empty() {
+            parts.push("nothing to do".to_string());
+        }
+        format!(
+            "{}; {} downloaded in {:.1}s",
+            parts.join(", "),
+            format_bytes(downloaded),
+            elapsed.as_secs_f64()
+        )
+    }
+
+    /// Describe this summary in a single line of text.
+    pub fn describe(&self) -> String {
+        self.describe_with(self.downloaded, self.started.elapsed())
+    }
+
+    fn outcome_with(&self, downloaded: u64, elapsed: Duration) -> Outcome<'_> {
+        Outcome {
+            installed: &self.installed,
+            updated: &self.updated,
+            removed: &self.removed,
+            failed: &self.failed,
+            downloaded_bytes: downloaded,
+            elapsed_seconds: elapsed.as_secs_f64(),
+        }
+    }
+
+    /// Describe this summary as JSON.
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(&self.outcome_with(self.downloaded, self.started.elapsed()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn describe_summary() {
+        let mut summary = Summary::start();
+        assert!(summary.is_empty());
+        assert_eq!(
+            summary.describe_with(0, Duration::from_millis(120)),
+            "nothing to do; 0 B downloaded in 0.1s"
+        );
+        summary.installed("ripgrep");
+        summary.updated("fd");
+        summary.updated("jq");
+        summary.failed("bat");
+        summary.downloaded(1024);
+        summary.downloaded(2048);
+        assert_eq!(summary.downloaded, 3072);
+        assert_eq!(
+            summary.describe_with(3 * 1024 * 1024 + 512 * 1024, Duration::from_secs(42)),
+            "1 installed, 2 updated, 1 failed (bat); 3.5 MiB downloaded in 42.0s"
+        );
+        assert_eq!(
+            serde_json::to_string(&summary.outcome_with(2048, Duration::from_millis(1500)))
+                .unwrap(),
+            r#"{"installed":["ripgrep"],"updated":["fd","jq"],"removed":[],"failed":["bat"],"downloaded_bytes":2048,"elapsed_seconds":1.5}"#
+        );
+    }
+}
@@ -0,0 +1,1020 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Fixtures and a sandbox to test installing, updating and removing manifests.
+//!
+//! [`ManifestBuilder`] creates manifests along with the contents of all their downloads, and
+//! [`Sandbox`] applies these manifests to install directories in a temporary directory.  The
+//! sandbox puts all downloads in place before applying a manifest, so nothing hits the network.
+
+use std::path::PathBuf;
+
+use anyhow::{Context, Error, Result};
+use digest::Digest;
+use fehler::throws;
+use tempfile::TempDir;
+use url::Url;
+use versions::Versioning;
+
+use crate::manifest::*;
+use crate::{ApplyOptions, HomebinProjectDirs, InstallDirs};
+
+/// A file of a manifest fixture.
+#[derive(Debug)]
+struct FixtureFile {
+    name: String,
+    target: Target,
+    contents: Vec<u8>,
+}
+
+/// Build manifest fixtures.
+///
+/// Every fixture has a binary named after the manifest which prints the version of the manifest,
+/// so that homebins can discover installed fixtures.  Each file of the fixture becomes a separate
+/// download.
+#[derive(Debug)]
+pub struct ManifestBuilder {
+    name: String,
+    version: String,
+    files: Vec<FixtureFile>,
+    post: Vec<Hook>,
+    pre_remove: Vec<Hook>,
+}
+
+impl ManifestBuilder {
+    /// Start a manifest with the given `name` and `version`.
+    pub fn new<S: Into<String>, V: Into<String>>(name: S, version: V) -> ManifestBuilder {
+        let name = name.into();
+        let version = version.into();
+        let binary = FixtureFile {
+            name: name.clone(),
+            target: Target::Binary { links: Vec::new() },
+            contents: format!("#!/bin/sh\necho '{} {}'\n", name, version).into_bytes(),
+        };
+        ManifestBuilder {
+            name,
+            version,
+            files: vec![binary],
+            post: Vec::new(),
+            pre_remove: Vec::new(),
+        }
+    }
+
+    /// Add a file with the given `name`, `target` and `contents`.
+    pub fn file<S: Into<String>, C: Into<Vec<u8>>>(
+        mut self,
+        name: S,
+        target: Target,
+        contents: C,
+    ) -> ManifestBuilder {
+        self.files.push(FixtureFile {
+            name: name.into(),
+            target,
+            contents: contents.into(),
+        });
+        self
+    }
+
+    /// Add a post-install hook which runs `command`.
+    pub fn post<S: Into<String>>(mut self, command: Vec<S>) -> ManifestBuilder {
+        self.post.push(Hook {
+            command: command.into_iter().map(Into::into).collect(),
+        });
+        self
+    }
+
+    /// Add a pre-remove hook which runs `command`.
+    pub fn pre_remove<S: Into<String>>(mut self, command: Vec<S>) -> ManifestBuilder {
+        self.pre_remove.push(Hook {
+            command: command.into_iter().map(Into::into).collect(),
+        });
+        self
+    }
+
+    /// Build the manifest fixture.
+    ///
+    /// Panics if the name or version aren't valid.
+    pub fn build(self) -> ManifestFixture {
+        let mut install = Vec::with_capacity(self.files.len());
+        let mut downloads = Vec::with_capacity(self.files.len());
+        let mut post = Some(self.post);
+        for file in self.files {
+            let download = Url::parse(&format!(
+                "https://example.com/{}/{}/{}",
+                self.name, self.version, file.name
+            ))
+            .expect("Invalid fixture URL");
+            install.push(InstallDownload {
+                download,
+                filename: None,
+                arch: None,
+                os: None,
+                checksums: Checksums {
+                    sha256: Some(sha2::Sha256::digest(&file.contents).to_vec()),
+                    ..Checksums::default()
+                },
+                minisign: None,
+                install: Install::SingleFile {
+                    name: None,
+                    target: file.target,
+                },
+                // Attach all hooks to the first download, i.e. the binary
+                post: post.take().unwrap_or_default(),
+            });
+            downloads.push((file.name, file.contents));
+        }
+        let manifest = Manifest {
+            info: Info {
+                name: self.name.clone(),
+                aliases: Vec::new(),
+                version: Versioning::new(&self.version).expect("Invalid fixture version"),
+                url: format!("https://example.com/{}", self.name),
+                description: None,
+                keywords: Vec::new(),
+                homepage: None,
+                repository: None,
+                license: spdx::Expression::parse("MIT").unwrap(),
+                released: None,
+                requires: Vec::new(),
+                conflicts: Vec::new(),
+                versioned_binaries: None,
+                deprecated: false,
+                replaced_by: None,
+            },
+            discover: Discover::Binary {
+                binary: vec![self.name.clone()],
+                version_check: VersionCheck {
+                    args: vec!["--version".to_string()],
+                    pattern: format!("{} (\\S+)", regex::escape(&self.name)),
+                    stream: VersionStream::Stdout,
+                },
+            },
+            install,
+            remove: Remove {
+                additional_files: Vec::new(),
+                pre: self.pre_remove,
+            },
+            upstream: None,
+        };
+        ManifestFixture {
+            manifest,
+            downloads,
+        }
+    }
+}
+
+/// A manifest along with the contents of its downloads.
+#[derive(Debug)]
+pub struct ManifestFixture {
+    /// The manifest.
+    pub manifest: Manifest,
+    downloads: Vec<(String, Vec<u8>)>,
+}
+
+/// A sandbox to apply manifests in.
+///
+/// All directories of a sandbox are inside a temporary directory which gets deleted when the
+/// sandbox is dropped.
+#[derive(Debug)]
+pub struct Sandbox {
+    root: TempDir,
+    dirs: HomebinProjectDirs,
+    install_dirs: InstallDirs,
+}
+
+impl Sandbox {
+    /// Create a new empty sandbox.
+    #[throws]
+    pub fn new() -> Sandbox {
+        let root = tempfile::Builder::new()
+            .prefix("homebins-sandbox")
+            .tempdir()
+            .with_context(|| "Failed to create sandbox directory")?;
+        let dirs = HomebinProjectDirs::in_directory(root.path());
+        let install_dirs = InstallDirs::in_directory(&root.path().join("home"));
+        Sandbox {
+            root,
+            dirs,
+            install_dirs,
+        }
+    }
+
+    /// The project directories of this sandbox.
+    pub fn dirs(&self) -> &HomebinProjectDirs {
+        &self.dirs
+    }
+
+    /// The install directories of this sandbox.
+    pub fn install_dirs(&self) -> &InstallDirs {
+        &self.install_dirs
+    }
+
+    /// Put all downloads of `fixture` into the download directory of this sandbox.
+    #[throws]
+    pub fn put_downloads(&self, fixture: &ManifestFixture) -> () {
+        let download_dir = self.dirs.manifest_download_dir(&fixture.manifest);
+        std::fs::create_dir_all(&download_dir)
+            .with_context(|| format!("Failed to create {}", download_dir.display()))?;
+        for (name, contents) in &fixture.downloads {
+            let file = download_dir.join(name);
+            std::fs::write(&file, contents)
+                .with_context(|| format!("Failed to write {}", file.display()))?;
+        }
+    }
+
+    /// Install the manifest of `fixture` in this sandbox.
+    ///
+    /// Return the number of bytes downloaded, like [`crate::install_manifest`].
+    pub fn install(&mut self, fixture: &ManifestFixture, options: &ApplyOptions) -> Result<u64> {
+        self.put_downloads(fixture)?;
+        crate::install_manifest(
+            &self.dirs,
+            &mut self.install_dirs,
+            &fixture.manifest,
+            options,
+        )
+    }
+
+    /// Update to the manifest of `fixture` in this sandbox.
+    pub fn update(&mut self, fixture: &ManifestFixture, options: &ApplyOptions) -> Result<u64> {
+        self.put_downloads(fixture)?;
+        crate::update_manifest(
+            &self.dirs,
+            &mut self.install_dirs,
+            &fixture.manifest,
+            options,
+        )
+    }
+
+    /// Remove the manifest of `fixture` from this sandbox.
+    pub fn remove(&mut self, fixture: &ManifestFixture, options: &ApplyOptions) -> Result<u64> {
+        crate::remove_manifest(
+            &self.dirs,
+            &mut self.install_dirs,
+            &fixture.manifest,
+            options,
+        )
+    }
+
+    /// All files in this sandbox's install directories, relative to the sandbox.
+    #[throws]
+    pub fn installed_files(&self) -> Vec<PathBuf> {
+        let home = self.root.path().join("home");
+        let mut files = Vec::new();
+        let mut pending = vec![home.clone()];
+        while let Some(directory) = pending.pop() {
+            if !directory.is_dir() {
+                continue;
+            }
+            for entry in directory.read_dir()? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    pending.push(path);
+                } else {
+                    files.push(path.strip_prefix(&home)?.to_path_buf());
+                }
+            }
+        }
+        files.sort();
+        files
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::operations::Operation;
+    use crate::provenance::ProvenanceStore;
+    use crate::{ConflictResolution, HookIsolation};
+    use pretty_assertions::assert_eq;
+    use std::borrow::Cow;
+    use std::path::Path;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    fn fixture(version: &str) -> ManifestFixture {
+        ManifestBuilder::new("hello", version)
+            .file("hello.1", Target::Manpage { section: 1 }, ".TH HELLO 1\n")
+            .build()
+    }
+
+    #[test]
+    fn install_update_and_remove() {
+        let options = ApplyOptions::default();
+        let mut sandbox = Sandbox::new().unwrap();
+
+        let installed = fixture("1.0.0");
+        sandbox.install(&installed, &options).unwrap();
+        assert_eq!(
+            sandbox.installed_files().unwrap(),
+            vec![
+                Path::new("bin/hello").to_path_buf(),
+                Path::new("share/man/man1/hello.1").to_path_buf()
+            ]
+        );
+        assert_eq!(
+            crate::installed_manifest_version(
+                sandbox.install_dirs(),
+                &installed.manifest,
+                &ApplyOptions::default()
+            )
+            .unwrap(),
+            Some(Versioning::new("1.0.0").unwrap())
+        );
+
+        let updated = fixture("1.1.0");
+        sandbox.update(&updated, &options).unwrap();
+        assert_eq!(
+            crate::installed_manifest_version(
+                sandbox.install_dirs(),
+                &updated.manifest,
+                &ApplyOptions::default()
+            )
+            .unwrap(),
+            Some(Versioning::new("1.1.0").unwrap())
+        );
+
+        sandbox.remove(&updated, &options).unwrap();
+        assert!(sandbox.installed_files().unwrap().is_empty());
+    }
+
+    #[test]
+    fn update_versioned_binaries_and_remove_installed_version() {
+        let options = ApplyOptions {
+            versioned_binaries: true,
+            ..ApplyOptions::default()
+        };
+        let mut sandbox = Sandbox::new().unwrap();
+
+        sandbox.install(&fixture("1.0.0"), &options).unwrap();
+        sandbox.update(&fixture("1.1.0"), &options).unwrap();
+        assert_eq!(
+            sandbox.installed_files().unwrap(),
+            vec![
+                Path::new("bin/hello").to_path_buf(),
+                Path::new("bin/hello-1.0.0").to_path_buf(),
+                Path::new("bin/hello-1.1.0").to_path_buf(),
+                Path::new("share/man/man1/hello.1").to_path_buf()
+            ]
+        );
+        let bin_dir = sandbox.install_dirs().bin_dir();
+        assert_eq!(
+            std::fs::read(bin_dir.join("hello")).unwrap(),
+            std::fs::read(bin_dir.join("hello-1.1.0")).unwrap()
+        );
+
+        // The manifest has a newer version by now, but remove must still remove the version
+        // actually installed, and leave the older version in place.
+        sandbox.remove(&fixture("1.2.0"), &options).unwrap();
+        assert_eq!(
+            sandbox.installed_files().unwrap(),
+            vec![Path::new("bin/hello-1.0.0").to_path_buf()]
+        );
+    }
+
+    #[test]
+    fn install_records_provenance_of_files() {
+        let mut sandbox = Sandbox::new().unwrap();
+        let fixture = fixture("1.0.0");
+        let options = ApplyOptions {
+            manifest_revision: Some("abc123".to_string()),
+            manifest_repository: Some("overlay".to_string()),
+            ..ApplyOptions::default()
+        };
+        sandbox.install(&fixture, &options).unwrap();
+        let store = ProvenanceStore::open(sandbox.dirs().provenance_file()).unwrap();
+        let manpage = sandbox
+            .install_dirs()
+            .man_dir()
+            .join("man1")
+            .join("hello.1");
+        let record = store.for_file(&manpage).unwrap();
+        assert_eq!(record.manifest, "hello");
+        assert_eq!(record.version, "1.0.0");
+        assert_eq!(record.url, "https://example.com/hello/1.0.0/hello.1");
+        assert_eq!(
+            record.sha256,
+            hex::encode(sha2::Sha256::digest(b".TH HELLO 1\n"))
+        );
+        assert_eq!(record.revision.as_deref(), Some("abc123"));
+        assert_eq!(record.repository.as_deref(), Some("overlay"));
+        assert_eq!(store.repository_of("hello"), Some("overlay"));
+        assert_eq!(store.for_manifest("hello").count(), 2);
+
+        sandbox.remove(&fixture, &options).unwrap();
+        let store = ProvenanceStore::open(sandbox.dirs().provenance_file()).unwrap();
+        assert!(store.for_file(&manpage).is_none());
+    }
+
+    #[test]
+    fn install_despite_failing_provenance() {
+        let mut sandbox = Sandbox::new().unwrap();
+        let fixture = fixture("1.0.0");
+        // A directory in place of the provenance file makes recording provenance fail
+        std::fs::create_dir_all(sandbox.dirs().provenance_file()).unwrap();
+        sandbox.install(&fixture, &ApplyOptions::default()).unwrap();
+        assert!(sandbox.install_dirs().bin_dir().join("hello").is_file());
+    }
+
+    /// Serve the download at `index` of `fixture` with `contents` from a local directory.
+    fn serve_download(fixture: &mut ManifestFixture, index: usize, contents: &[u8]) -> TempDir {
+        let remote = TempDir::new().unwrap();
+        let file = remote.path().join(&fixture.downloads[index].0);
+        std::fs::write(&file, contents).unwrap();
+        fixture.manifest.install[index].download = Url::from_file_path(&file).unwrap();
+        remote
+    }
+
+    #[test]
+    fn install_fails_on_corrupt_download() {
+        let mut sandbox = Sandbox::new().unwrap();
+        let mut fixture = fixture("1.0.0");
+        let _remote = serve_download(&mut fixture, 1, b"corrupt");
+        fixture.downloads[1].1 = b"corrupt".to_vec();
+        let error = sandbox
+            .install(&fixture, &ApplyOptions::default())
+            .unwrap_err();
+        assert!(format!("{:#}", error).contains("The checksum didn't match"));
+        assert!(sandbox.installed_files().unwrap().is_empty());
+    }
+
+    #[test]
+    fn install_downloads_corrupt_download_again() {
+        let mut sandbox = Sandbox::new().unwrap();
+        let mut fixture = fixture("1.0.0");
+        let contents = fixture.downloads[1].1.clone();
+        let _remote = serve_download(&mut fixture, 1, &contents);
+        fixture.downloads[1].1 = b"corrupt".to_vec();
+        sandbox.install(&fixture, &ApplyOptions::default()).unwrap();
+        let manpage = sandbox.install_dirs().man_dir().join("man1/hello.1");
+        assert_eq!(std::fs::read(&manpage).unwrap(), contents);
+    }
+
+    #[test]
+    fn install_resumes_truncated_download() {
+        let mut sandbox = Sandbox::new().unwrap();
+        let mut fixture = fixture("1.0.0");
+        let contents = fixture.downloads[1].1.clone();
+        let _remote = serve_download(&mut fixture, 1, &contents);
+        fixture.downloads[1].1 = contents[..4].to_vec();
+        sandbox.install(&fixture, &ApplyOptions::default()).unwrap();
+        let download = sandbox
+            .dirs()
+            .manifest_download_dir(&fixture.manifest)
+            .join(&fixture.downloads[1].0);
+        assert_eq!(std::fs::read(&download).unwrap(), contents);
+    }
+
+    #[test]
+    fn install_downloads_all_files_at_once() {
+        let mut fixture = fixture("1.0.0");
+        let _remotes: Vec<TempDir> = (0..fixture.downloads.len())
+            .map(|index| {
+                let contents = fixture.downloads[index].1.clone();
+                serve_download(&mut fixture, index, &contents)
+            })
+            .collect();
+        fixture.downloads.clear();
+        for jobs in [1, 2] {
+            let mut sandbox = Sandbox::new().unwrap();
+            let options = ApplyOptions {
+                jobs: Some(jobs),
+                ..ApplyOptions::default()
+            };
+            sandbox.install(&fixture, &options).unwrap();
+            assert_eq!(sandbox.installed_files().unwrap().len(), 2);
+        }
+    }
+
+    fn download_operation(url: &str, name: &str) -> Operation<'static> {
+        Operation::Download(
+            Cow::Owned(Url::parse(url).unwrap()),
+            Cow::Owned(name.to_string()),
+            Cow::Owned(Checksums::default()),
+        )
+    }
+
+    #[test]
+    fn download_all_runs_at_most_jobs_downloads_at_once() {
+        let operations: Vec<Operation<'_>> = (0..4)
+            .map(|index| {
+                download_operation(
+                    &format!("https://example.com/{}", index),
+                    &index.to_string(),
+                )
+            })
+            .collect();
+        let downloads: Vec<&Operation<'_>> = operations.iter().collect();
+        for jobs in [1, 2] {
+            let running = AtomicUsize::new(0);
+            let most_running = AtomicUsize::new(0);
+            crate::download_all(&downloads, jobs, |_| {
+                let now_running = running.fetch_add(1, Ordering::SeqCst) + 1;
+                most_running.fetch_max(now_running, Ordering::SeqCst);
+                std::thread::sleep(Duration::from_millis(100));
+                running.fetch_sub(1, Ordering::SeqCst);
+                Ok(())
+            })
+            .unwrap();
+            assert_eq!(most_running.load(Ordering::SeqCst), jobs);
+        }
+    }
+
+    #[test]
+    fn download_all_refuses_downloads_to_the_same_file() {
+        let operations = [
+            download_operation("https://example.com/a/foo", "foo"),
+            download_operation("https://example.com/b/foo", "foo"),
+        ];
+        let downloads: Vec<&Operation<'_>> = operations.iter().collect();
+        let applied = AtomicUsize::new(0);
+        let error = crate::download_all(&downloads, 2, |_| {
+            applied.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        })
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Several downloads go to the same file: foo from https://example.com/a/foo, https://example.com/b/foo"
+        );
+        assert_eq!(applied.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn install_skips_missing_optional_files() {
+        let mut sandbox = Sandbox::new().unwrap();
+        let mut fixture = fixture("1.0.0");
+        let source = TempDir::new().unwrap();
+        std::fs::create_dir(source.path().join("hello-1.0.0")).unwrap();
+        std::fs::write(source.path().join("hello-1.0.0").join("README"), "Hello\n").unwrap();
+        let archive = source.path().join("hello-1.0.0.tar.gz");
+        let status = std::process::Command::new("tar")
+            .arg("-czf")
+            .arg(&archive)
+            .arg("-C")
+            .arg(source.path())
+            .arg("hello-1.0.0")
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let contents = std::fs::read(&archive).unwrap();
+        let file = |source: &str, optional| InstallFile {
+            source: source.to_string(),
+            name: None,
+            target: Target::Data {
+                path: "hello".to_string(),
+            },
+            optional,
+        };
+        fixture.manifest.install.push(InstallDownload {
+            download: Url::parse("https://example.com/hello/1.0.0/hello-1.0.0.tar.gz").unwrap(),
+            filename: None,
+            arch: None,
+            os: None,
+            checksums: Checksums {
+                sha256: Some(sha2::Sha256::digest(&contents).to_vec()),
+                ..Checksums::default()
+            },
+            minisign: None,
+            install: Install::FilesFromArchive {
+                files: vec![
+                    file("hello-1.0.0/README", false),
+                    file("hello-1.0.0/CHANGES", true),
+                ],
+                strip_prefix: 0,
+            },
+            post: Vec::new(),
+        });
+        fixture
+            .downloads
+            .push(("hello-1.0.0.tar.gz".to_string(), contents));
+
+        sandbox.install(&fixture, &ApplyOptions::default()).unwrap();
+        let data_dir = sandbox.install_dirs().data_dir().join("hello");
+        assert!(data_dir.join("README").is_file());
+        assert!(!data_dir.join("CHANGES").exists());
+
+        // Without optional the missing file fails the installation
+        if let Install::FilesFromArchive { files, .. } = &mut fixture.manifest.install[2].install {
+            files[1].optional = false;
+        }
+        let mut sandbox = Sandbox::new().unwrap();
+        assert!(sandbox.install(&fixture, &ApplyOptions::default()).is_err());
+    }
+
+    #[test]
+    fn resume_refuses_untrusted_keys() {
+        let mut sandbox = Sandbox::new().unwrap();
+        let mut fixture = fixture("1.0.0");
+        fixture.manifest.install[1].minisign = Some(Minisign {
+            public_key: "RWSGOq2NVecA2UPNdBUZykf1CCb147pkmdtYxgb3Ti+JO/wCYvhbAb/U".to_string(),
+            signature: None,
+        });
+        let options = ApplyOptions {
+            require_trusted_keys: true,
+            ..ApplyOptions::default()
+        };
+        let error = sandbox.install(&fixture, &options).unwrap_err();
+        assert!(format!("{:#}", error).contains("Refusing to verify hello.1 with untrusted key"));
+        let signature = sandbox
+            .dirs()
+            .manifest_download_dir(&fixture.manifest)
+            .join("hello.1.minisig");
+        assert!(!signature.exists());
+
+        // Even with a signature left behind, resuming mustn't skip the refusal
+        std::fs::write(&signature, "untrusted comment: leftover\n").unwrap();
+        let options = ApplyOptions {
+            resume: true,
+            ..options
+        };
+        let error = sandbox.install(&fixture, &options).unwrap_err();
+        assert!(format!("{:#}", error).contains("Refusing to verify hello.1 with untrusted key"));
+        assert!(!sandbox
+            .install_dirs()
+            .man_dir()
+            .join("man1/hello.1")
+            .exists());
+    }
+
+    #[test]
+    fn install_refuses_escaping_file_names() {
+        let mut sandbox = Sandbox::new().unwrap();
+        let mut fixture = fixture("1.0.0");
+        if let Install::SingleFile { name, .. } = &mut fixture.manifest.install[1].install {
+            *name = Some("../../.bashrc".to_string());
+        }
+        let error = sandbox
+            .install(&fixture, &ApplyOptions::default())
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "hello has file names outside of their directories: ../../.bashrc"
+        );
+        assert!(sandbox.installed_files().unwrap().is_empty());
+    }
+
+    #[test]
+    fn install_runs_post_install_hooks() {
+        let mut sandbox = Sandbox::new().unwrap();
+        let marker = sandbox.root.path().join("hello.out");
+        let fixture = ManifestBuilder::new("hello", "1.0.0")
+            .post(vec![
+                "sh".to_string(),
+                "-c".to_string(),
+                format!("hello > {}", marker.display()),
+            ])
+            .build();
+        sandbox.install(&fixture, &ApplyOptions::default()).unwrap();
+        assert_eq!(std::fs::read_to_string(&marker).unwrap(), "hello 1.0.0\n");
+    }
+
+    #[test]
+    fn install_runs_isolated_hooks_with_clean_environment() {
+        let mut sandbox = Sandbox::new().unwrap();
+        // Cargo sets CARGO_MANIFEST_DIR for tests, but isolated hooks shouldn't see it
+        let fixture = ManifestBuilder::new("hello", "1.0.0")
+            .post(vec![
+                "sh",
+                "-c",
+                "test -z \"$CARGO_MANIFEST_DIR\" && test -n \"$HOME\" && hello",
+            ])
+            .build();
+        let options = ApplyOptions {
+            hook_isolation: HookIsolation::Clean,
+            ..ApplyOptions::default()
+        };
+        sandbox.install(&fixture, &options).unwrap();
+
+        let mut sandbox = Sandbox::new().unwrap();
+        let error = sandbox
+            .install(&fixture, &ApplyOptions::default())
+            .unwrap_err();
+        assert!(format!("{:#}", error).contains("Failed to run sh -c"));
+    }
+
+    #[test]
+    fn install_fails_on_failing_post_install_hook() {
+        let mut sandbox = Sandbox::new().unwrap();
+        let fixture = ManifestBuilder::new("hello", "1.0.0")
+            .post(vec!["sh", "-c", "echo broken >&2; exit 1"])
+            .build();
+        let error = sandbox
+            .install(&fixture, &ApplyOptions::default())
+            .unwrap_err();
+        let message = format!("{:#}", error);
+        assert!(message.contains("Failed to run sh -c echo broken >&2; exit 1"));
+        assert!(message.contains("broken"));
+    }
+
+    #[test]
+    fn remove_runs_pre_remove_hooks_before_removing_files() {
+        let mut sandbox = Sandbox::new().unwrap();
+        let marker = sandbox.root.path().join("hello.out");
+        let fixture = ManifestBuilder::new("hello", "1.0.0")
+            .pre_remove(vec![
+                "sh".to_string(),
+                "-c".to_string(),
+                format!("hello > {}", marker.display()),
+            ])
+            .build();
+        let options = ApplyOptions::default();
+        sandbox.install(&fixture, &options).unwrap();
+        assert!(!marker.exists());
+        sandbox.remove(&fixture, &options).unwrap();
+        assert_eq!(std::fs::read_to_string(&marker).unwrap(), "hello 1.0.0\n");
+        assert!(sandbox.installed_files().unwrap().is_empty());
+    }
+
+    #[test]
+    fn install_with_checksums_from_cached_checksum_file() {
+        let mut fixture = fixture("1.0.0");
+        let mut sums = String::new();
+        for (download, (name, _)) in fixture.manifest.install.iter_mut().zip(&fixture.downloads) {
+            let sha256 = download.checksums.sha256.take().unwrap();
+            sums.push_str(&format!("{}  {}\n", hex::encode(sha256), name));
+            download.checksums.sha256_url =
+                Some(Url::parse("https://example.com/hello/1.0.0/SHA256SUMS").unwrap());
+        }
+        let sandbox_with_sums = |fixture: &ManifestFixture| {
+            let sandbox = Sandbox::new().unwrap();
+            let download_dir = sandbox.dirs().manifest_download_dir(&fixture.manifest);
+            std::fs::create_dir_all(&download_dir).unwrap();
+            for (name, _) in &fixture.downloads {
+                std::fs::write(download_dir.join(format!("{}.sha256sums", name)), &sums).unwrap();
+            }
+            sandbox
+        };
+
+        let mut sandbox = sandbox_with_sums(&fixture);
+        sandbox.install(&fixture, &ApplyOptions::default()).unwrap();
+        assert_eq!(sandbox.installed_files().unwrap().len(), 2);
+
+        let _remote = serve_download(&mut fixture, 1, b"corrupt");
+        fixture.downloads[1].1 = b"corrupt".to_vec();
+        let mut sandbox = sandbox_with_sums(&fixture);
+        let error = sandbox
+            .install(&fixture, &ApplyOptions::default())
+            .unwrap_err();
+        assert!(format!("{:#}", error).contains("The checksum didn't match"));
+    }
+
+    #[test]
+    fn retry_failing_version_checks() {
+        let mut sandbox = Sandbox::new().unwrap();
+        let fixture = fixture("1.0.0");
+        sandbox.install(&fixture, &ApplyOptions::default()).unwrap();
+        let binary = sandbox.install_dirs().bin_dir().join("hello");
+
+        // Fail only the first time
+        std::fs::write(
+            &binary,
+            "#!/bin/sh\nif [ -e \"$0.ran\" ]; then echo 'hello 1.0.0'; else touch \"$0.ran\"; exit 1; fi\n",
+        )
+        .unwrap();
+        assert_eq!(
+            crate::installed_manifest_version(
+                sandbox.install_dirs(),
+                &fixture.manifest,
+                &ApplyOptions::default()
+            )
+            .unwrap(),
+            Some(Versioning::new("1.0.0").unwrap())
+        );
+
+        std::fs::write(&binary, "#!/bin/sh\nexit 1\n").unwrap();
+        let error = crate::installed_manifest_version(
+            sandbox.install_dirs(),
+            &fixture.manifest,
+            &ApplyOptions::default(),
+        )
+        .unwrap_err();
+        let failed = error.downcast_ref::<crate::VersionCheckFailed>().unwrap();
+        assert_eq!(failed.attempts, 3);
+        assert_eq!(failed.reason, "exit status: 1");
+    }
+
+    #[test]
+    fn kill_hanging_version_checks() {
+        let mut sandbox = Sandbox::new().unwrap();
+        let fixture = fixture("1.0.0");
+        sandbox.install(&fixture, &ApplyOptions::default()).unwrap();
+        let binary = sandbox.install_dirs().bin_dir().join("hello");
+        std::fs::write(&binary, "#!/bin/sh\nexec sleep 10\n").unwrap();
+
+        let options = ApplyOptions {
+            version_check_timeout: Some(std::time::Duration::from_millis(100)),
+            ..ApplyOptions::default()
+        };
+        let error =
+            crate::installed_manifest_version(sandbox.install_dirs(), &fixture.manifest, &options)
+                .unwrap_err();
+        let failed = error.downcast_ref::<crate::VersionCheckFailed>().unwrap();
+        assert_eq!(failed.attempts, 1);
+        assert_eq!(failed.reason, "timed out after 0.1s");
+    }
+
+    #[test]
+    fn discover_multiple_binaries() {
+        let mut sandbox = Sandbox::new().unwrap();
+        let mut fixture = fixture("1.0.0");
+        sandbox.install(&fixture, &ApplyOptions::default()).unwrap();
+        let options = ApplyOptions::default();
+        let dirs = sandbox.install_dirs();
+        assert_eq!(
+            crate::outdated_manifest_version(dirs, &fixture.manifest, &options).unwrap(),
+            None
+        );
+
+        if let Discover::Binary { binary, .. } = &mut fixture.manifest.discover {
+            binary.insert(0, "hello-missing".to_string());
+        }
+        assert_eq!(
+            crate::installed_manifest_version(dirs, &fixture.manifest, &options).unwrap(),
+            Some(Versioning::new("1.0.0").unwrap())
+        );
+        assert_eq!(
+            crate::missing_binaries(dirs, &fixture.manifest),
+            vec!["hello-missing"]
+        );
+        assert_eq!(
+            crate::outdated_manifest_version(dirs, &fixture.manifest, &options).unwrap(),
+            Some(Versioning::new("1.0.0").unwrap())
+        );
+    }
+
+    #[test]
+    fn discover_installed_files() {
+        let mut sandbox = Sandbox::new().unwrap();
+        let mut fixture = fixture("1.0.0");
+        sandbox.install(&fixture, &ApplyOptions::default()).unwrap();
+        let version = |fixture: &ManifestFixture| {
+            crate::installed_manifest_version(
+                sandbox.install_dirs(),
+                &fixture.manifest,
+                &ApplyOptions::default(),
+            )
+        };
+
+        fixture.manifest.discover = Discover::File {
+            file: "man/man1/hello.1".to_string(),
+        };
+        assert_eq!(
+            version(&fixture).unwrap(),
+            Some(fixture.manifest.info.version.clone())
+        );
+
+        fixture.manifest.discover = Discover::VersionFile {
+            version_file: "man/man1/hello.1".to_string(),
+            pattern: "TH (?P<name>\\S+) (?P<version>\\d+)".to_string(),
+        };
+        assert_eq!(
+            version(&fixture).unwrap(),
+            Some(Versioning::new("1").unwrap())
+        );
+
+        fixture.manifest.discover = Discover::Checksum {
+            file: "man/man1/hello.1".to_string(),
+            checksums: Checksums {
+                sha256: Some(sha2::Sha256::digest(b".TH HELLO 1\n").to_vec()),
+                ..Checksums::default()
+            },
+        };
+        assert_eq!(
+            version(&fixture).unwrap(),
+            Some(fixture.manifest.info.version.clone())
+        );
+        std::fs::write(
+            sandbox.install_dirs().man_section_dir(1).join("hello.1"),
+            ".TH HELLO 1 modified\n",
+        )
+        .unwrap();
+        let error = version(&fixture).unwrap_err();
+        assert!(error.downcast_ref::<crate::VersionCheckFailed>().is_some());
+
+        fixture.manifest.discover = Discover::File {
+            file: "man/man1/missing.1".to_string(),
+        };
+        assert_eq!(version(&fixture).unwrap(), None);
+    }
+
+    #[test]
+    fn install_refuses_to_overwrite_existing_files() {
+        let mut sandbox = Sandbox::new().unwrap();
+        let fixture = fixture("1.0.0");
+        let manpage = sandbox.root.path().join("home/share/man/man1/hello.1");
+        std::fs::create_dir_all(manpage.parent().unwrap()).unwrap();
+        std::fs::write(&manpage, "foreign").unwrap();
+
+        let error = sandbox
+            .install(&fixture, &ApplyOptions::default())
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!("Files of hello already exist: {}", manpage.display())
+        );
+        assert_eq!(std::fs::read_to_string(&manpage).unwrap(), "foreign");
+
+        let force = ApplyOptions {
+            force: true,
+            ..ApplyOptions::default()
+        };
+        sandbox.install(&fixture, &force).unwrap();
+        assert_eq!(std::fs::read_to_string(&manpage).unwrap(), ".TH HELLO 1\n");
+    }
+
+    #[test]
+    fn install_ignores_existing_files_of_skipped_destinations() {
+        let mut sandbox = Sandbox::new().unwrap();
+        let fixture = fixture("1.0.0");
+        let manpage = sandbox.root.path().join("home/share/man/man1/hello.1");
+        std::fs::create_dir_all(manpage.parent().unwrap()).unwrap();
+        std::fs::write(&manpage, "foreign").unwrap();
+
+        let options = ApplyOptions {
+            skip_destinations: vec![crate::operations::DestinationKind::Manpages],
+            ..ApplyOptions::default()
+        };
+        assert!(
+            crate::conflicting_files(sandbox.install_dirs(), &fixture.manifest, &options)
+                .unwrap()
+                .is_empty()
+        );
+        sandbox.install(&fixture, &options).unwrap();
+        assert_eq!(std::fs::read_to_string(&manpage).unwrap(), "foreign");
+        assert!(sandbox.install_dirs().bin_dir().join("hello").exists());
+    }
+
+    #[test]
+    fn install_resolves_conflicts_with_existing_files() {
+        let fixture = fixture("1.0.0");
+        for (resolution, manpage_contents, backup_contents) in [
+            (ConflictResolution::Skip, "foreign", None),
+            (ConflictResolution::Backup, ".TH HELLO 1\n", Some("foreign")),
+        ] {
+            let mut sandbox = Sandbox::new().unwrap();
+            let manpage = sandbox.root.path().join("home/share/man/man1/hello.1");
+            std::fs::create_dir_all(manpage.parent().unwrap()).unwrap();
+            std::fs::write(&manpage, "foreign").unwrap();
+
+            let mut options = ApplyOptions::default();
+            options
+                .conflict_resolutions
+                .insert(manpage.clone(), resolution);
+            sandbox.install(&fixture, &options).unwrap();
+            assert_eq!(std::fs::read_to_string(&manpage).unwrap(), manpage_contents);
+            assert_eq!(
+                std::fs::read_to_string(manpage.with_extension("1.bak")).ok(),
+                backup_contents.map(String::from)
+            );
+            assert!(sandbox.install_dirs().bin_dir().join("hello").exists());
+        }
+    }
+
+    #[test]
+    fn install_keeps_earlier_backups() {
+        let mut sandbox = Sandbox::new().unwrap();
+        let manpage = sandbox.root.path().join("home/share/man/man1/hello.1");
+        std::fs::create_dir_all(manpage.parent().unwrap()).unwrap();
+        std::fs::write(&manpage, "foreign").unwrap();
+        std::fs::write(manpage.with_extension("1.bak"), "earlier backup").unwrap();
+
+        let mut options = ApplyOptions::default();
+        options
+            .conflict_resolutions
+            .insert(manpage.clone(), ConflictResolution::Backup);
+        sandbox.install(&fixture("1.0.0"), &options).unwrap();
+        assert_eq!(std::fs::read_to_string(&manpage).unwrap(), ".TH HELLO 1\n");
+        assert_eq!(
+            std::fs::read_to_string(manpage.with_extension("1.bak")).unwrap(),
+            "earlier backup"
+        );
+        assert_eq!(
+            std::fs::read_to_string(manpage.with_extension("1.bak.1")).unwrap(),
+            "foreign"
+        );
+    }
+
+    #[test]
+    fn install_restores_backups_on_failure() {
+        let mut sandbox = Sandbox::new().unwrap();
+        let fixture = ManifestBuilder::new("hello", "1.0.0")
+            .file("hello.1", Target::Manpage { section: 1 }, ".TH HELLO 1\n")
+            .post(vec!["false"])
+            .build();
+        let manpage = sandbox.root.path().join("home/share/man/man1/hello.1");
+        std::fs::create_dir_all(manpage.parent().unwrap()).unwrap();
+        std::fs::write(&manpage, "foreign").unwrap();
+
+        let mut options = ApplyOptions::default();
+        options
+            .conflict_resolutions
+            .insert(manpage.clone(), ConflictResolution::Backup);
+        assert!(sandbox.install(&fixture, &options).is_err());
+        assert_eq!(std::fs::read_to_string(&manpage).unwrap(), "foreign");
+        assert!(!manpage.with_extension("1.bak").exists());
+    }
+}
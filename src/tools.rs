@@ -6,21 +6,32 @@
 
 //! External tools.
 
+use std::collections::HashMap;
 use std::ffi::{OsStr, OsString};
-use std::io::{Error, ErrorKind, Result};
+use std::io::{Error, ErrorKind, Read, Result, Write};
 use std::os::unix::ffi::OsStringExt;
-use std::path::Path;
-use std::process::Command;
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::Duration;
 
 use url::Url;
 
 use crate::process::CommandExt;
+use crate::progress::DownloadProgress;
+
+/// How often to check the progress of curl downloads.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
 
 /// Whether a path variable such as `$PATH`. contains the given path.
 pub fn path_contains<S: AsRef<OsStr>, P: AsRef<Path>>(path: &S, wanted: P) -> bool {
     std::env::split_paths(path).any(|path| path.as_path() == wanted.as_ref())
 }
 
+/// Quote `s` for a POSIX shell.
+pub fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
 /// Get the manpath.
 pub fn manpath() -> Result<OsString> {
     Ok(OsString::from_vec(
@@ -28,15 +39,79 @@ pub fn manpath() -> Result<OsString> {
     ))
 }
 
+/// The environment variable to choose curl for downloads even with the `native-http` feature.
+#[cfg(feature = "native-http")]
+const DOWNLOADER_VARIABLE: &str = "HOMEBINS_DOWNLOADER";
+
+/// Whether to download natively instead of with curl.
+///
+/// Download natively with the `native-http` feature, unless `$HOMEBINS_DOWNLOADER` is `curl`.
+#[cfg(feature = "native-http")]
+pub(crate) fn native_downloads() -> bool {
+    std::env::var_os(DOWNLOADER_VARIABLE).is_none_or(|downloader| downloader != "curl")
+}
+
+/// Download `url` to `target`.
+///
+/// Follow redirects, retry transient failures, and resume a partial download at `target`.  If
+/// given, send `token` as bearer token in the `Authorization` header.
+///
+/// Return the file name from the `Content-Disposition` header of the response, if any.
+///
+/// Download with curl, or natively with the `native-http` feature, see [`crate::http`].
+pub fn download(url: &Url, target: &Path, token: Option<&str>) -> Result<Option<String>> {
+    #[cfg(feature = "native-http")]
+    if native_downloads() {
+        return crate::http::download(url, target, token);
+    }
+    curl(url, target, token)
+}
+
+/// Fetch the contents of `url` as text.
+///
+/// Fetch with curl, or natively with the `native-http` feature, see [`crate::http`].
+pub fn fetch_text(url: &str) -> Result<String> {
+    #[cfg(feature = "native-http")]
+    if native_downloads() {
+        return crate::http::fetch_text(url);
+    }
+    curl_text(url)
+}
+
+/// Check that `url` is reachable with a HEAD request.
+///
+/// Check with curl, or natively with the `native-http` feature, see [`crate::http`].
+pub fn check_url(url: &Url) -> Result<()> {
+    #[cfg(feature = "native-http")]
+    if native_downloads() {
+        return crate::http::check_url(url);
+    }
+    curl_head(url)
+}
+
+/// The exit code of curl if the server doesn't support range requests.
+const CURL_RANGE_ERROR: i32 = 33;
+
 /// Download a URL with curl.
-pub fn curl(url: &Url, target: &Path) -> Result<()> {
-    Command::new("curl")
+///
+/// If given, send `token` as bearer token in the `Authorization` header.
+///
+/// Report progress from the size of `target` and the headers of the response, see
+/// [`DownloadProgress`].
+///
+/// Resume a partial download at `target`; if the server doesn't support ranges download all of
+/// `url` again.
+///
+/// Return the file name from the `Content-Disposition` header of the response, if any.
+fn curl(url: &Url, target: &Path, token: Option<&str>) -> Result<Option<String>> {
+    let headers = tempfile::NamedTempFile::new()?;
+    let mut command = Command::new("curl");
+    command
         .args(&[
             "-gqb",
             "",
-            "-fLC",
+            "-fsSLC",
             "-",
-            "--progress-bar",
             "--retry",
             "3",
             "--retry-delay",
@@ -44,13 +119,209 @@ pub fn curl(url: &Url, target: &Path) -> Result<()> {
         ])
         .arg("--output")
         .arg(target)
+        .arg("--dump-header")
+        .arg(headers.path())
         .arg(url.as_str())
-        .checked_call()
+        .stderr(Stdio::piped());
+    if token.is_some() {
+        // Read the header from stdin to keep the token out of the process list
+        command.args(["--header", "@-"]).stdin(Stdio::piped());
+    }
+    let size = |file: &Path| file.metadata().map_or(0, |metadata| metadata.len());
+    let read_headers = || {
+        std::fs::read(headers.path()).map(|headers| String::from_utf8_lossy(&headers).into_owned())
+    };
+    let offset = size(target);
+    let mut child = command.spawn()?;
+    if let (Some(token), Some(mut stdin)) = (token, child.stdin.take()) {
+        stdin.write_all(format!("Authorization: Bearer {}\n", token).as_bytes())?;
+    }
+    // Read errors in the background, lest curl block on a full pipe
+    let stderr = child.stderr.take().map(|mut stderr| {
+        std::thread::spawn(move || {
+            let mut errors = String::new();
+            stderr.read_to_string(&mut errors).map(|_| errors)
+        })
+    });
+    let mut progress = DownloadProgress::start(url);
+    let exit_status = loop {
+        if let Some(exit_status) = child.try_wait()? {
+            break exit_status;
+        }
+        progress.set_total(download_total(&read_headers()?, offset));
+        progress.set_received(size(target));
+        std::thread::sleep(POLL_INTERVAL);
+    };
+    let errors = match stderr.map(|thread| thread.join()) {
+        Some(Ok(errors)) => errors?,
+        _ => String::new(),
+    };
+    if exit_status.success() {
+        let headers = read_headers()?;
+        progress.set_total(download_total(&headers, offset));
+        progress.set_received(size(target));
+        progress.finish();
+        Ok(content_disposition_filename(&headers))
+    } else if exit_status.code() == Some(CURL_RANGE_ERROR) && target.exists() {
+        drop(progress);
+        std::fs::remove_file(target)?;
+        curl(url, target, token)
+    } else {
+        Err(Error::other(format!(
+            "{:?} failed with exit code {}: {}",
+            command,
+            exit_status,
+            errors.trim()
+        )))
+    }
+}
+
+/// The total size of a download resumed after `offset` bytes, from the response `headers`.
+///
+/// Take the size from `Content-Range` of the last response, or add `offset` to its
+/// `Content-Length`.
+fn download_total(headers: &str, offset: u64) -> Option<u64> {
+    let (mut range_total, mut length) = (None, None);
+    for line in headers.lines() {
+        // Headers of redirects come first, so only look at the last response
+        if line.starts_with("HTTP/") {
+            range_total = None;
+            length = None;
+        } else if let Some((name, value)) = line.split_once(':') {
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("Content-Range") {
+                range_total = value.rsplit('/').next().and_then(|t| t.parse::<u64>().ok());
+            } else if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.parse::<u64>().ok();
+            }
+        }
+    }
+    range_total.or_else(|| length.map(|length| offset + length))
+}
+
+/// Decode percent-encoded bytes in `s`.
+fn percent_decode(s: &str) -> Vec<u8> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let hex = bytes
+            .get(index + 1..index + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[index], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                index += 1;
+            }
+        }
+    }
+    decoded
+}
+
+/// Get the file name from the `Content-Disposition` header of the last response in `headers`.
+///
+/// `headers` has the headers of all responses curl received, including redirects, as written
+/// by `curl --dump-header`.  Prefer an encoded `filename*` over a plain `filename`, and take
+/// only the final component of the name, to never write outside the download directory.
+pub fn content_disposition_filename(headers: &str) -> Option<String> {
+    let mut filename = None;
+    for line in headers.lines() {
+        if line.starts_with("HTTP/") {
+            // A new response, e.g. after a redirect
+            filename = None;
+        }
+        let value = match line.split_once(':') {
+            Some((name, value)) if name.eq_ignore_ascii_case("content-disposition") => value,
+            _ => continue,
+        };
+        filename = disposition_filename(value);
+    }
+    filename
+}
+
+/// Get the file name from the `value` of a `Content-Disposition` header.
+///
+/// See [`content_disposition_filename`].
+pub(crate) fn disposition_filename(value: &str) -> Option<String> {
+    let mut plain = None;
+    let mut encoded = None;
+    for parameter in value.split(';').map(str::trim) {
+        if let Some((key, value)) = parameter.split_once('=') {
+            match key.trim().to_ascii_lowercase().as_str() {
+                "filename" => plain = Some(value.trim().trim_matches('"').to_string()),
+                "filename*" => {
+                    // RFC 5987: charset'language'percent-encoded-value
+                    encoded = value
+                        .splitn(3, '\'')
+                        .nth(2)
+                        .map(|value| String::from_utf8_lossy(&percent_decode(value)).into_owned())
+                }
+                _ => {}
+            }
+        }
+    }
+    encoded.or(plain).and_then(|name| {
+        name.rsplit(&['/', '\\'][..])
+            .next()
+            .map(str::trim)
+            .filter(|name| !name.is_empty() && *name != "." && *name != "..")
+            .map(String::from)
+    })
+}
+
+/// Fetch the contents of a URL as text with curl.
+fn curl_text(url: &str) -> Result<String> {
+    let output = Command::new("curl")
+        .args(["-gqb", "", "-fsSL", "--retry", "3", "--retry-delay", "3"])
+        .arg(url)
+        .checked_output()?;
+    String::from_utf8(output.stdout).map_err(|error| Error::new(ErrorKind::InvalidData, error))
+}
+
+/// Check that `url` is reachable with a HEAD request with curl.
+fn curl_head(url: &Url) -> Result<()> {
+    Command::new("curl")
+        .args(["-gqb", "", "-fsSLI", "--retry", "3", "--retry-delay", "3"])
+        .args(["--output", "/dev/null"])
+        .arg(url.as_str())
+        .checked_output()
+        .map(|_| ())
 }
 
 /// Newtype wrapper identifying an archive.
 pub struct Archive<'a>(&'a Path);
 
+/// List the names of all members of a tar `archive`.
+pub fn list_tar(archive: Archive) -> Result<Vec<String>> {
+    let Archive(archive) = archive;
+    let output = Command::new("tar")
+        .arg("tf")
+        .arg(archive)
+        .checked_output()?;
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(String::from)
+        .collect())
+}
+
+/// List the names of all members of a zip `archive`.
+pub fn list_zip(archive: Archive) -> Result<Vec<String>> {
+    let Archive(archive) = archive;
+    let output = Command::new("unzip")
+        .arg("-Z1")
+        .arg(archive)
+        .checked_output()?;
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(String::from)
+        .collect())
+}
+
 pub fn untar(archive: Archive, target_directory: &Path) -> Result<()> {
     let Archive(archive) = archive;
     Command::new("tar")
@@ -72,19 +343,213 @@ pub fn unzip(archive: Archive, target_directory: &Path) -> Result<()> {
 
 type ExtractFn = fn(Archive<'_>, &Path) -> Result<()>;
 
-static ARCHIVE_PATTERNS: [(&str, ExtractFn); 5] = [
-    (".tar.gz", untar),
-    (".tgz", untar),
-    (".tar.bz2", untar),
-    (".tar.xz", untar),
-    ("zip", unzip),
+type ListFn = fn(Archive<'_>) -> Result<Vec<String>>;
+
+static ARCHIVE_PATTERNS: [(&str, ExtractFn, ListFn); 5] = [
+    (".tar.gz", untar, list_tar),
+    (".tgz", untar, list_tar),
+    (".tar.bz2", untar, list_tar),
+    (".tar.xz", untar, list_tar),
+    ("zip", unzip, list_zip),
 ];
 
-/// Extract the given file if its an archive.
-pub fn extract(file: &Path, directory: &Path) -> Result<()> {
-    for (extension, extract) in &ARCHIVE_PATTERNS {
-        if file.as_os_str().to_string_lossy().ends_with(extension) {
-            extract(Archive(file), directory)?;
+/// Whether `path` is relative and stays within the directory it's relative to.
+///
+/// That is, `path` is not empty, and has no root and no `..` components.  `./` is contained,
+/// because archives created with `tar -C dir .` have a member for the directory itself.
+pub fn is_contained_path(path: &Path) -> bool {
+    path.components().next().is_some()
+        && path
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+}
+
+/// Whether the symlink `link` in `root` points outside of `root`.
+///
+/// Resolve the link if its target exists, and otherwise look at its target lexically, as if
+/// all directories in the target were real directories.
+fn symlink_escapes(root: &Path, link: &Path) -> Result<bool> {
+    if let (Ok(root), Ok(resolved)) = (root.canonicalize(), link.canonicalize()) {
+        return Ok(!resolved.starts_with(root));
+    }
+    let target = std::fs::read_link(link)?;
+    let mut depth = link
+        .parent()
+        .and_then(|parent| parent.strip_prefix(root).ok())
+        .map_or(0, |parent| parent.components().count());
+    for component in target.components() {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+            Component::ParentDir if 0 < depth => depth -= 1,
+            _ => return Ok(true),
+        }
+    }
+    Ok(false)
+}
+
+/// Fail if any symlink below `directory` points outside of `root`.
+fn check_symlinks(root: &Path, directory: &Path) -> Result<()> {
+    for entry in std::fs::read_dir(directory)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_symlink() {
+            if symlink_escapes(root, &entry.path())? {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "Symlink {} points outside of {}",
+                        entry.path().display(),
+                        root.display()
+                    ),
+                ));
+            }
+        } else if file_type.is_dir() {
+            check_symlinks(root, &entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+/// Fail if any symlink `strip` levels below `source` points outside of the directory it ends up
+/// in after moving it with [`move_stripped`].
+fn check_stripped_symlinks(source: &Path, strip: usize) -> Result<()> {
+    if strip == 0 {
+        check_symlinks(source, source)
+    } else {
+        for entry in std::fs::read_dir(source)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                check_stripped_symlinks(&entry.path(), strip - 1)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Collect the paths of all entries below `directory`, relative to `root`, and whether each is
+/// a directory.
+fn collect_entries(
+    root: &Path,
+    directory: &Path,
+    entries: &mut Vec<(PathBuf, bool)>,
+) -> Result<()> {
+    for entry in std::fs::read_dir(directory)? {
+        let entry = entry?;
+        let path = entry.path();
+        let is_dir = entry.file_type()?.is_dir();
+        entries.push((
+            path.strip_prefix(root).unwrap_or(&path).to_path_buf(),
+            is_dir,
+        ));
+        if is_dir {
+            collect_entries(root, &path, entries)?;
+        }
+    }
+    Ok(())
+}
+
+/// Fail if stripping `strip` leading directories from the entries below `source` moves
+/// different entries to the same path, see [`move_stripped`].
+///
+/// Directories merge, but a file would silently replace another file, or clash with a directory.
+fn check_stripped_collisions(source: &Path, strip: usize) -> Result<()> {
+    if strip == 0 {
+        return Ok(());
+    }
+    let mut entries = Vec::new();
+    collect_entries(source, source, &mut entries)?;
+    entries.sort();
+    let mut stripped: HashMap<PathBuf, (PathBuf, bool)> = HashMap::new();
+    for (member, is_dir) in entries {
+        if member.components().count() <= strip {
+            continue;
+        }
+        let target: PathBuf = member.components().skip(strip).collect();
+        match stripped.get(&target) {
+            Some((_, true)) if is_dir => {}
+            Some((other, _)) => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "Stripping {} leading directories moves both {} and {} to {}",
+                        strip,
+                        other.display(),
+                        member.display(),
+                        target.display()
+                    ),
+                ))
+            }
+            None => {
+                stripped.insert(target, (member, is_dir));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Move `source` to `target`, merging directories with existing directories like tar does.
+fn move_merged(source: &Path, target: &Path) -> Result<()> {
+    let is_dir = |path: &Path| std::fs::symlink_metadata(path).is_ok_and(|m| m.is_dir());
+    if is_dir(source) && is_dir(target) {
+        for entry in std::fs::read_dir(source)? {
+            let entry = entry?;
+            move_merged(&entry.path(), &target.join(entry.file_name()))?;
+        }
+        Ok(())
+    } else {
+        std::fs::rename(source, target)
+    }
+}
+
+/// Move all entries `strip` levels below `source` into `target`.
+///
+/// Drop all files less than `strip` levels below `source`, like `--strip-components` of tar.
+fn move_stripped(source: &Path, target: &Path, strip: usize) -> Result<()> {
+    for entry in std::fs::read_dir(source)? {
+        let entry = entry?;
+        if strip == 0 {
+            move_merged(&entry.path(), &target.join(entry.file_name()))?;
+        } else if entry.file_type()?.is_dir() {
+            move_stripped(&entry.path(), target, strip - 1)?;
+        }
+    }
+    Ok(())
+}
+
+/// Extract the given file if its an archive, stripping `strip` leading directories.
+///
+/// Tell the type of archive from `name`, which can differ from the name of `file`, e.g. if the
+/// server named the download.
+///
+/// Extract archives to a temporary directory in `directory` first, and then move the stripped
+/// contents into `directory`.
+///
+/// Refuse to extract archives with absolute member names or member names with `..`, and fail
+/// if any extracted symlink points outside of `directory` before moving anything into
+/// `directory`, so that no archive escapes from `directory`.  Likewise fail if stripping moves
+/// several members of the archive to the same path, instead of letting one replace the other.
+pub fn extract(file: &Path, name: &str, directory: &Path, strip: usize) -> Result<()> {
+    for (extension, extract, list) in &ARCHIVE_PATTERNS {
+        if name.ends_with(extension) {
+            if let Some(member) = list(Archive(file))?
+                .into_iter()
+                .find(|member| !is_contained_path(Path::new(member)))
+            {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "Refusing to extract {}: Member {} escapes the target directory",
+                        file.display(),
+                        member
+                    ),
+                ));
+            }
+            let temp = tempfile::tempdir_in(directory)?;
+            extract(Archive(file), temp.path())?;
+            check_stripped_symlinks(temp.path(), strip)?;
+            check_stripped_collisions(temp.path(), strip)?;
+            move_stripped(temp.path(), directory, strip)?;
             return Ok(());
         }
     }
@@ -94,9 +559,358 @@ pub fn extract(file: &Path, directory: &Path) -> Result<()> {
     ))
 }
 
+/// Update the cache of MIME types of desktop entries in the given `directory`.
+pub fn update_desktop_database(directory: &Path) -> Result<()> {
+    Command::new("update-desktop-database")
+        .arg(directory)
+        .checked_call()
+}
+
+/// Rebuild the font cache for the given `directory`.
+pub fn update_font_cache(directory: &Path) -> Result<()> {
+    Command::new("fc-cache")
+        .arg("-f")
+        .arg(directory)
+        .checked_call()
+}
+
+/// Create an install-info command for the info pages in the given `directory`.
+pub fn install_info(directory: &Path) -> Command {
+    let mut command = Command::new("install-info");
+    command.arg(format!("--info-dir={}", directory.display()));
+    command
+}
+
+/// Create a systemctl command for the systemd user manager.
+pub fn systemctl_user() -> Command {
+    let mut command = Command::new("systemctl");
+    command.arg("--user");
+    command
+}
+
 /// Create a git command for the given repo
+#[cfg(any(test, not(feature = "native-git")))]
 pub fn git(repo: &Path) -> Command {
     let mut command = Command::new("git");
     command.arg("-C").arg(repo);
     command
 }
+
+/// Create an ssh command to run commands on the given `destination`.
+///
+/// Run ssh in batch mode, to fail rather than prompt if it needs a password.
+pub fn ssh(destination: &str) -> Command {
+    let mut command = Command::new("ssh");
+    command.args(["-o", "BatchMode=yes", "--", destination]);
+    command
+}
+
+/// Create an unshare command which runs a command without network access.
+///
+/// The command runs in new user and network namespaces, as root within the user namespace.
+pub fn unshare_without_network() -> Command {
+    let mut command = Command::new("unshare");
+    command.args(["--user", "--map-root-user", "--net", "--"]);
+    command
+}
+
+/// Whether this system lets us create user and network namespaces to run commands in.
+pub fn network_isolation_available() -> bool {
+    unshare_without_network()
+        .arg("true")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// Verify `file` against the minisign `signature` file with the given `public_key`.
+pub fn minisign_verify(public_key: &str, file: &Path, signature: &Path) -> Result<()> {
+    Command::new("minisign")
+        .args(["-Vq", "-P", public_key, "-m"])
+        .arg(file)
+        .arg("-x")
+        .arg(signature)
+        .checked_call()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn extract_stripping_leading_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        let contents = dir.path().join("contents");
+        std::fs::create_dir_all(contents.join("foo-1.0/doc")).unwrap();
+        std::fs::write(contents.join("foo-1.0/foo"), "foo").unwrap();
+        std::fs::write(contents.join("foo-1.0/doc/foo.1"), "foo.1").unwrap();
+        std::fs::write(contents.join("README"), "dropped").unwrap();
+        let archive = dir.path().join("foo-1.0.tar.gz");
+        Command::new("tar")
+            .arg("czf")
+            .arg(&archive)
+            .arg("-C")
+            .arg(&contents)
+            .args(["foo-1.0", "README"])
+            .checked_call()
+            .unwrap();
+
+        let work = dir.path().join("work");
+        std::fs::create_dir(&work).unwrap();
+        extract(&archive, "foo-1.0.tar.gz", &work, 1).unwrap();
+        let mut entries: Vec<_> = std::fs::read_dir(&work)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        entries.sort();
+        assert_eq!(entries, vec![OsString::from("doc"), OsString::from("foo")]);
+        assert_eq!(
+            std::fs::read_to_string(work.join("doc/foo.1")).unwrap(),
+            "foo.1"
+        );
+    }
+
+    #[test]
+    fn extract_refuses_colliding_stripped_members() {
+        let dir = tempfile::tempdir().unwrap();
+        let contents = dir.path().join("contents");
+        std::fs::create_dir_all(contents.join("linux/doc")).unwrap();
+        std::fs::create_dir_all(contents.join("macos/doc")).unwrap();
+        std::fs::write(contents.join("linux/doc/foo.1"), "linux").unwrap();
+        std::fs::write(contents.join("macos/doc/foo.1"), "macos").unwrap();
+        let archive = dir.path().join("foo.tar.gz");
+        Command::new("tar")
+            .arg("czf")
+            .arg(&archive)
+            .arg("-C")
+            .arg(&contents)
+            .args(["linux", "macos"])
+            .checked_call()
+            .unwrap();
+
+        let work = dir.path().join("work");
+        std::fs::create_dir(&work).unwrap();
+        let error = extract(&archive, "foo.tar.gz", &work, 1).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Stripping 1 leading directories moves both linux/doc/foo.1 and macos/doc/foo.1 to doc/foo.1"
+        );
+        assert_eq!(std::fs::read_dir(&work).unwrap().count(), 0);
+
+        // Directories merge
+        std::fs::rename(
+            contents.join("macos/doc/foo.1"),
+            contents.join("macos/doc/bar.1"),
+        )
+        .unwrap();
+        Command::new("tar")
+            .arg("czf")
+            .arg(&archive)
+            .arg("-C")
+            .arg(&contents)
+            .args(["linux", "macos"])
+            .checked_call()
+            .unwrap();
+        extract(&archive, "foo.tar.gz", &work, 1).unwrap();
+        assert_eq!(std::fs::read_dir(work.join("doc")).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn extract_tarball_of_current_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let contents = dir.path().join("contents");
+        std::fs::create_dir_all(contents.join("doc")).unwrap();
+        std::fs::write(contents.join("foo"), "foo").unwrap();
+        std::fs::write(contents.join("doc/foo.1"), "foo.1").unwrap();
+        let archive = dir.path().join("foo.tar.gz");
+        Command::new("tar")
+            .arg("czf")
+            .arg(&archive)
+            .arg("-C")
+            .arg(&contents)
+            .arg(".")
+            .checked_call()
+            .unwrap();
+        assert!(list_tar(Archive(&archive))
+            .unwrap()
+            .contains(&"./".to_string()));
+
+        let work = dir.path().join("work");
+        std::fs::create_dir(&work).unwrap();
+        extract(&archive, "foo.tar.gz", &work, 0).unwrap();
+        let mut entries: Vec<_> = std::fs::read_dir(&work)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        entries.sort();
+        assert_eq!(entries, vec![OsString::from("doc"), OsString::from("foo")]);
+        assert_eq!(std::fs::read_to_string(work.join("foo")).unwrap(), "foo");
+        assert_eq!(
+            std::fs::read_to_string(work.join("doc/foo.1")).unwrap(),
+            "foo.1"
+        );
+
+        // Extracting another archive merges its directories with existing ones
+        std::fs::remove_dir_all(&contents).unwrap();
+        std::fs::create_dir_all(contents.join("doc")).unwrap();
+        std::fs::write(contents.join("doc/bar.1"), "bar.1").unwrap();
+        let other = dir.path().join("bar.tar.gz");
+        Command::new("tar")
+            .arg("czf")
+            .arg(&other)
+            .arg("-C")
+            .arg(&contents)
+            .arg(".")
+            .checked_call()
+            .unwrap();
+        extract(&other, "bar.tar.gz", &work, 0).unwrap();
+        assert!(work.join("doc/foo.1").is_file());
+        assert!(work.join("doc/bar.1").is_file());
+    }
+
+    #[test]
+    fn contained_paths() {
+        assert!(is_contained_path(Path::new("foo")));
+        assert!(is_contained_path(Path::new("./foo-1.0/doc/")));
+        assert!(!is_contained_path(Path::new("")));
+        assert!(is_contained_path(Path::new("./")));
+        assert!(is_contained_path(Path::new(".")));
+        assert!(!is_contained_path(Path::new("/etc/passwd")));
+        assert!(!is_contained_path(Path::new("foo/../../bar")));
+        assert!(!is_contained_path(Path::new("..")));
+    }
+
+    #[test]
+    fn refuse_escaping_archives() {
+        let dir = tempfile::tempdir().unwrap();
+        let contents = dir.path().join("contents");
+        std::fs::create_dir_all(contents.join("foo/sub")).unwrap();
+        std::fs::write(contents.join("foo/foo"), "foo").unwrap();
+        std::os::unix::fs::symlink("../foo", contents.join("foo/sub/inside")).unwrap();
+        let work = dir.path().join("work");
+        let archive = |name: &str, members: &[&str]| {
+            let archive = dir.path().join(name);
+            Command::new("tar")
+                .arg("czf")
+                .arg(&archive)
+                .arg("-C")
+                .arg(&contents)
+                .args(members)
+                .checked_call()
+                .unwrap();
+            std::fs::create_dir_all(&work).unwrap();
+            archive
+        };
+
+        let good = archive("good.tar.gz", &["foo"]);
+        extract(&good, "good.tar.gz", &work, 0).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(work.join("foo/sub/inside")).unwrap(),
+            "foo"
+        );
+
+        // Stripping leading directories moves the symlink up, so that it points outside
+        std::fs::remove_dir_all(&work).unwrap();
+        let stripped = archive("stripped.tar.gz", &["foo"]);
+        let error = extract(&stripped, "stripped.tar.gz", &work, 2).unwrap_err();
+        assert!(error.to_string().starts_with("Symlink "), "{}", error);
+        // Nothing gets written if the archive escapes
+        assert_eq!(std::fs::read_dir(&work).unwrap().count(), 0);
+
+        std::fs::remove_dir_all(&work).unwrap();
+        std::os::unix::fs::symlink("/etc", contents.join("etc")).unwrap();
+        let absolute = archive("absolute.tar.gz", &["etc"]);
+        let error = extract(&absolute, "absolute.tar.gz", &work, 0).unwrap_err();
+        assert!(error.to_string().starts_with("Symlink "), "{}", error);
+        assert_eq!(std::fs::read_dir(&work).unwrap().count(), 0);
+
+        let escaping = dir.path().join("escaping.tar.gz");
+        Command::new("tar")
+            .arg("czf")
+            .arg(&escaping)
+            .arg("-C")
+            .arg(&contents)
+            .args(["--transform", "s,^foo,../foo,", "foo/foo"])
+            .checked_call()
+            .unwrap();
+        let error = extract(&escaping, "escaping.tar.gz", &work, 0).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Refusing to extract {}: Member ../foo/foo escapes the target directory",
+                escaping.display()
+            )
+        );
+    }
+
+    #[test]
+    fn filename_from_content_disposition() {
+        assert_eq!(
+            content_disposition_filename("HTTP/2 200\r\ncontent-type: text/plain\r\n\r\n"),
+            None
+        );
+        assert_eq!(
+            content_disposition_filename(
+                "HTTP/2 200\r\nContent-Disposition: attachment; filename=\"foo-1.0.tar.gz\"\r\n"
+            ),
+            Some("foo-1.0.tar.gz".to_string())
+        );
+        assert_eq!(
+            content_disposition_filename(
+                "HTTP/2 200\r\ncontent-disposition: attachment; filename=foo.zip; filename*=UTF-8''f%C3%B6%C3%B6.zip\r\n"
+            ),
+            Some("föö.zip".to_string())
+        );
+        // Only consider the final response after redirects, and never leave the directory
+        assert_eq!(
+            content_disposition_filename(
+                "HTTP/1.1 302 Found\r\nContent-Disposition: attachment; filename=a.zip\r\n\r\nHTTP/2 200\r\n\r\n"
+            ),
+            None
+        );
+        assert_eq!(
+            content_disposition_filename(
+                "HTTP/2 200\r\nContent-Disposition: attachment; filename=\"../../bin/foo.tar.gz\"\r\n"
+            ),
+            Some("foo.tar.gz".to_string())
+        );
+        assert_eq!(
+            content_disposition_filename(
+                "HTTP/2 200\r\nContent-Disposition: attachment; filename=\"..\"\r\n"
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn total_of_download() {
+        assert_eq!(download_total("", 0), None);
+        assert_eq!(
+            download_total("HTTP/2 200\r\ncontent-length: 1024\r\n\r\n", 0),
+            Some(1024)
+        );
+        // Only consider the final response after redirects
+        assert_eq!(
+            download_total(
+                "HTTP/1.1 302 Found\r\nContent-Length: 10\r\n\r\nHTTP/2 200\r\n\r\n",
+                0
+            ),
+            None
+        );
+        // Resumed downloads
+        assert_eq!(
+            download_total(
+                "HTTP/2 206\r\nContent-Range: bytes 100-1023/1024\r\nContent-Length: 924\r\n",
+                100
+            ),
+            Some(1024)
+        );
+        assert_eq!(
+            download_total("HTTP/2 206\r\nContent-Length: 924\r\n", 100),
+            Some(1024)
+        );
+    }
+}
@@ -0,0 +1,218 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Trusted minisign keys.
+//!
+//! Manifests carry the public keys to verify signatures of their downloads with, so a signature
+//! only proves that a download matches the manifest.  Trusted keys are keys the user vouches for
+//! with `homebins trust add`: Homebins tells whether a trusted key verified a download, can
+//! refuse downloads signed with other keys, and verifies signed manifest repositories with
+//! trusted keys only.  Once any key is trusted, homebins refuses unsigned manifest repositories.
+//!
+//! Trusted keys live in a plain text file with one key per line, followed by a name for the key.
+
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Error};
+use fehler::{throw, throws};
+
+use crate::state::{read_state, write_atomically};
+
+/// A trusted minisign public key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrustedKey {
+    /// The base64-encoded minisign public key.
+    pub public_key: String,
+    /// A name for the key, e.g. the project or person it belongs to.
+    pub name: String,
+}
+
+impl Display for TrustedKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({})", self.name, self.public_key)
+    }
+}
+
+/// Check that `public_key` is a base64-encoded minisign public key.
+///
+/// A minisign public key has the two bytes `Ed` for the signature algorithm, an eight byte key
+/// ID and a 32 byte Ed25519 key, which makes 56 base64 characters starting with `RW`.
+#[throws]
+pub fn check_public_key(public_key: &str) -> () {
+    let base64 = |c: char| c.is_ascii_alphanumeric() || c == '+' || c == '/';
+    let valid = public_key.len() == 56
+        && public_key.chars().all(base64)
+        && public_key.starts_with("RW")
+        && public_key[2..].starts_with(['Q', 'R', 'S', 'T']);
+    if !valid {
+        throw!(anyhow!("Not a minisign public key: {:?}", public_key));
+    }
+}
+
+/// Parse trusted keys from `text`.
+///
+/// Each line has a public key, followed by whitespace and a name.  Ignore empty lines and
+/// comments starting with `#`.
+#[throws]
+pub fn parse_trusted_keys(text: &str) -> Vec<TrustedKey> {
+    let mut keys = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line.split_once(char::is_whitespace) {
+            Some((public_key, name)) if !name.trim().is_empty() => keys.push(TrustedKey {
+                public_key: public_key.to_string(),
+                name: name.trim().to_string(),
+            }),
+            _ => throw!(anyhow!(
+                "Line {}: Missing name for key: {:?}",
+                index + 1,
+                line
+            )),
+        }
+    }
+    keys
+}
+
+/// The trusted keys in a file.
+#[derive(Debug)]
+pub struct TrustStore {
+    path: PathBuf,
+    keys: Vec<TrustedKey>,
+}
+
+impl TrustStore {
+    /// Open the trusted keys in the file at `path`.
+    ///
+    /// Start without trusted keys if `path` does not exist.
+    #[throws]
+    pub fn open<P: AsRef<Path>>(path: P) -> TrustStore {
+        let path = path.as_ref().to_path_buf();
+        let keys = match read_state(&path)? {
+            Some(contents) => parse_trusted_keys(&contents)
+                .with_context(|| format!("Invalid trusted keys in {}", path.display()))?,
+            None => Vec::new(),
+        };
+        TrustStore { path, keys }
+    }
+
+    /// Get the trusted key with the given `public_key`, if any.
+    pub fn get(&self, public_key: &str) -> Option<&TrustedKey> {
+        self.keys.iter().find(|key| key.public_key == public_key)
+    }
+
+    /// All trusted keys, in the order they were added.
+    pub fn keys(&self) -> &[TrustedKey] {
+        &self.keys
+    }
+
+    /// Trust `key`, replacing the name of the key if it's already trusted.
+    pub fn add(&mut self, key: TrustedKey) {
+        match self
+            .keys
+            .iter_mut()
+            .find(|existing| existing.public_key == key.public_key)
+        {
+            Some(existing) => existing.name = key.name,
+            None => self.keys.push(key),
+        }
+    }
+
+    /// Stop trusting all keys whose public key or name is `key_or_name`.
+    ///
+    /// Return the keys no longer trusted.
+    pub fn remove(&mut self, key_or_name: &str) -> Vec<TrustedKey> {
+        let (removed, kept) = self
+            .keys
+            .drain(..)
+            .partition(|key| key.public_key == key_or_name || key.name == key_or_name);
+        self.keys = kept;
+        removed
+    }
+
+    /// Write all trusted keys back to the file.
+    #[throws]
+    pub fn save(&self) -> () {
+        let contents: String = self
+            .keys
+            .iter()
+            .map(|key| format!("{} {}\n", key.public_key, key.name))
+            .collect();
+        write_atomically(&self.path, contents)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn add_and_remove_trusted_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config").join("trusted_keys");
+        let mut store = TrustStore::open(&path).unwrap();
+        store.add(TrustedKey {
+            public_key: "RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3".to_string(),
+            name: "minisign".to_string(),
+        });
+        store.add(TrustedKey {
+            public_key: "RWTAPRW2qy9FjsBiMWtPxNSIiSnNhcFNbSLfZY3Vw2GNvDBpkgWJzOHC".to_string(),
+            name: "zig".to_string(),
+        });
+        store.add(TrustedKey {
+            public_key: "RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3".to_string(),
+            name: "Frank Denis".to_string(),
+        });
+        store.save().unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3 Frank Denis\n\
+             RWTAPRW2qy9FjsBiMWtPxNSIiSnNhcFNbSLfZY3Vw2GNvDBpkgWJzOHC zig\n"
+        );
+
+        let mut store = TrustStore::open(&path).unwrap();
+        assert_eq!(
+            store
+                .get("RWTAPRW2qy9FjsBiMWtPxNSIiSnNhcFNbSLfZY3Vw2GNvDBpkgWJzOHC")
+                .map(|key| key.name.as_str()),
+            Some("zig")
+        );
+        assert_eq!(store.remove("Frank Denis").len(), 1);
+        assert!(store.remove("Frank Denis").is_empty());
+        assert_eq!(store.keys().len(), 1);
+
+        assert_eq!(
+            parse_trusted_keys("# Keys\nRWQ\n").unwrap_err().to_string(),
+            "Line 2: Missing name for key: \"RWQ\""
+        );
+    }
+
+    #[test]
+    fn check_minisign_public_keys() {
+        assert!(
+            check_public_key("RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3").is_ok()
+        );
+        assert!(
+            check_public_key("RWSGOq2NVecA2UPNdBUZykf1CCb147pkmdtYxgb3Ti+JO/wCYvhbAb/U").is_ok()
+        );
+        for key in [
+            "RWQ",
+            "untrusted comment: minisign public key",
+            "RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO",
+            "AAQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3",
+            "RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GF=3",
+        ] {
+            assert_eq!(
+                check_public_key(key).unwrap_err().to_string(),
+                format!("Not a minisign public key: {:?}", key)
+            );
+        }
+    }
+}
@@ -0,0 +1,562 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! A [`VersionProvider`] finds the latest version of a binary upstream.  Manifests select one of
+//! the builtin providers in their `[upstream]` table; other crates can implement
+//! [`VersionProvider`] to add their own providers.
+//!
+//! An [`UpstreamCache`] keeps responses of upstream URLs for a while, and revalidates stale
+//! responses with their ETag, to avoid running into rate limits of upstream APIs.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Context, Error, Result};
+use colored::*;
+use fehler::{throw, throws};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use versions::Versioning;
+
+use crate::manifest::{Manifest, Upstream};
+use crate::process::CommandExt;
+use crate::state::{read_state, write_atomically};
+use crate::tools::fetch_text;
+
+/// How long to use cached upstream responses without revalidating them.
+pub const DEFAULT_UPSTREAM_CACHE_TTL: Duration = Duration::from_secs(3600);
+
+/// A cached response of an upstream URL.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct CachedResponse {
+    /// The ETag of the response, if any.
+    etag: Option<String>,
+    /// When homebins last fetched or revalidated the response, in seconds since the epoch.
+    fetched_at: u64,
+    /// The body of the response.
+    body: String,
+}
+
+/// The response to a conditional request.
+enum Response {
+    /// The cached response is still valid.
+    NotModified,
+    /// A new response, with an optional ETag and the body.
+    Modified(Option<String>, String),
+}
+
+/// Get the last ETag in `headers`.
+///
+/// With redirects `headers` contain the headers of all responses; the last ETag belongs to the
+/// final response.
+fn etag_from_headers(headers: &str) -> Option<String> {
+    headers.lines().rev().find_map(|line| {
+        let (name, value) = line.split_at(line.find(':')?);
+        if name.eq_ignore_ascii_case("etag") {
+            Some(value[1..].trim().to_string())
+        } else {
+            None
+        }
+    })
+}
+
+/// Fetch `url`, unless its response still has the given `etag`.
+///
+/// Fetch with curl, or natively with the `native-http` feature, see [`crate::http`].
+#[throws]
+fn fetch_conditional(url: &str, etag: Option<&str>) -> Response {
+    #[cfg(feature = "native-http")]
+    if crate::tools::native_downloads() {
+        return match crate::http::fetch_conditional(url, etag)? {
+            None => Response::NotModified,
+            Some((etag, body)) => Response::Modified(etag, body),
+        };
+    }
+    curl_conditional(url, etag)?
+}
+
+/// Fetch `url` with curl, unless its response still has the given `etag`.
+#[throws]
+fn curl_conditional(url: &str, etag: Option<&str>) -> Response {
+    let dir = tempfile::tempdir()?;
+    let headers = dir.path().join("headers");
+    let body = dir.path().join("body");
+    let mut command = Command::new("curl");
+    command
+        .args(["-gqb", "", "-sSL", "--retry", "3", "--retry-delay", "3"])
+        .args(["-w", "%{http_code}"])
+        .arg("--dump-header")
+        .arg(&headers)
+        .arg("--output")
+        .arg(&body);
+    if let Some(etag) = etag {
+        command
+            .arg("--header")
+            .arg(format!("If-None-Match: {}", etag));
+    }
+    let output = command.arg(url).checked_output()?;
+    let status = String::from_utf8_lossy(&output.stdout);
+    match status.trim() {
+        "304" => Response::NotModified,
+        code if code.starts_with('2') => Response::Modified(
+            etag_from_headers(&std::fs::read_to_string(&headers)?),
+            std::fs::read_to_string(&body)
+                .with_context(|| format!("Invalid response from {}", url))?,
+        ),
+        code => throw!(anyhow!("HTTP status {} from {}", code, url)),
+    }
+}
+
+/// Seconds since the epoch.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// A cache of responses of upstream URLs.
+///
+/// The cache uses responses younger than its TTL as they are, and revalidates older responses
+/// with their ETag.  If fetching a URL fails, e.g. because of a rate limit, it falls back to a
+/// stale response with a warning.
+///
+/// The default cache is disabled: It neither keeps responses nor reads any file.
+#[derive(Debug, Default)]
+pub struct UpstreamCache {
+    path: Option<PathBuf>,
+    ttl: Duration,
+    responses: HashMap<String, CachedResponse>,
+    hits: usize,
+}
+
+impl UpstreamCache {
+    /// Open the cache in the file at `path`, using responses younger than `ttl` as they are.
+    ///
+    /// Start with an empty cache if the file doesn't exist or has invalid contents.
+    #[throws]
+    pub fn open(path: PathBuf, ttl: Duration) -> UpstreamCache {
+        let responses = read_state(&path)?
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+        UpstreamCache {
+            path: Some(path),
+            ttl,
+            responses,
+            hits: 0,
+        }
+    }
+
+    /// The number of fetches this cache answered without a new response.
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+
+    /// Write all responses back to the cache file.
+    #[throws]
+    pub fn save(&self) -> () {
+        if let Some(path) = &self.path {
+            write_atomically(path, serde_json::to_string(&self.responses)?)?;
+        }
+    }
+
+    /// Fetch the contents of `url` as text, through this cache.
+    #[throws]
+    pub fn fetch(&mut self, url: &str) -> String {
+        if self.path.is_none() {
+            return fetch_text(url)?;
+        }
+        let now = now();
+        if let Some(cached) = self.responses.get(url) {
+            if now.saturating_sub(cached.fetched_at) < self.ttl.as_secs() {
+                self.hits += 1;
+                return cached.body.clone();
+            }
+        }
+        let cached = self.responses.get_mut(url);
+        let etag = cached.as_ref().and_then(|cached| cached.etag.as_deref());
+        match (fetch_conditional(url, etag), cached) {
+            (Ok(Response::NotModified), Some(cached)) => {
+                self.hits += 1;
+                cached.fetched_at = now;
+                cached.body.clone()
+            }
+            (Ok(Response::NotModified), None) => {
+                throw!(anyhow!("Unexpected HTTP status 304 from {}", url))
+            }
+            (Ok(Response::Modified(etag, body)), _) => {
+                let response = CachedResponse {
+                    etag,
+                    fetched_at: now,
+                    body: body.clone(),
+                };
+                self.responses.insert(url.to_string(), response);
+                body
+            }
+            (Err(error), Some(cached)) => {
+                eprintln!(
+                    "{}",
+                    format!("WARNING: Using stale response of {}: {:#}", url, error).yellow()
+                );
+                self.hits += 1;
+                cached.body.clone()
+            }
+            (Err(error), None) => throw!(error),
+        }
+    }
+}
+
+/// Find the latest version of a binary upstream.
+pub trait VersionProvider {
+    /// Get the latest upstream version.
+    fn latest_version(&self) -> Result<Versioning>;
+
+    /// Get the latest upstream version, fetching URLs through `cache`.
+    ///
+    /// The default implementation ignores `cache`.
+    fn latest_version_cached(&self, cache: &mut UpstreamCache) -> Result<Versioning> {
+        let _ = cache;
+        self.latest_version()
+    }
+}
+
+/// Parse a version from a release tag, ignoring any prefix like `v`.
+#[throws]
+fn version_from_tag(tag: &str) -> Versioning {
+    let version = tag.trim_start_matches(|c: char| !c.is_ascii_digit());
+    Versioning::new(version).ok_or_else(|| anyhow!("Invalid version in tag {}", tag))?
+}
+
+/// Get the highest version in `text`, extracted with the first group of `pattern`.
+#[throws]
+fn highest_version(text: &str, pattern: &Regex) -> Versioning {
+    pattern
+        .captures_iter(text)
+        .filter_map(|caps| caps.get(1))
+        .filter_map(|m| Versioning::new(m.as_str()))
+        .max()
+        .ok_or_else(|| anyhow!("No version matching {} found", pattern))?
+}
+
+/// A release from the GitHub or GitLab API.
+#[derive(Debug, Deserialize)]
+struct Release {
+    tag_name: String,
+}
+
+/// The latest release of a GitHub repository.
+#[derive(Debug)]
+pub struct GitHubReleases {
+    /// The repository, as `owner/name`.
+    pub repo: String,
+}
+
+impl VersionProvider for GitHubReleases {
+    fn latest_version(&self) -> Result<Versioning> {
+        self.latest_version_cached(&mut UpstreamCache::default())
+    }
+
+    #[throws]
+    fn latest_version_cached(&self, cache: &mut UpstreamCache) -> Versioning {
+        let url = format!("https://api.github.com/repos/{}/releases/latest", self.repo);
+        let release: Release = serde_json::from_str(&cache.fetch(&url)?)
+            .with_context(|| format!("Invalid release from {}", url))?;
+        version_from_tag(&release.tag_name)?
+    }
+}
+
+/// The latest release of a GitLab project on gitlab.com.
+#[derive(Debug)]
+pub struct GitLabReleases {
+    /// The project, as `namespace/name`.
+    pub project: String,
+}
+
+impl VersionProvider for GitLabReleases {
+    fn latest_version(&self) -> Result<Versioning> {
+        self.latest_version_cached(&mut UpstreamCache::default())
+    }
+
+    #[throws]
+    fn latest_version_cached(&self, cache: &mut UpstreamCache) -> Versioning {
+        let url = format!(
+            "https://gitlab.com/api/v4/projects/{}/releases",
+            self.project.replace('/', "%2F")
+        );
+        // GitLab returns releases sorted by release date, newest first
+        let releases: Vec<Release> = serde_json::from_str(&cache.fetch(&url)?)
+            .with_context(|| format!("Invalid releases from {}", url))?;
+        let release = releases
+            .first()
+            .ok_or_else(|| anyhow!("No releases for {}", self.project))?;
+        version_from_tag(&release.tag_name)?
+    }
+}
+
+/// The newest entry of an RSS or Atom feed.
+#[derive(Debug)]
+pub struct Feed {
+    /// The URL of the feed.
+    pub url: String,
+    /// The pattern to extract the version from the title of the newest entry.
+    pub pattern: Regex,
+}
+
+impl Feed {
+    /// Get the version from the title of the first entry in `feed`.
+    #[throws]
+    fn version_in_feed(&self, feed: &str) -> Versioning {
+        let title = Regex::new(r"(?s)<(?:entry|item)\b.*?<title[^>]*>(.*?)</title>")
+            .unwrap()
+            .captures(feed)
+            .and_then(|caps| caps.get(1))
+            .ok_or_else(|| anyhow!("No entries in feed"))?
+            .as_str();
+        self.pattern
+            .captures(title)
+            .and_then(|caps| caps.get(1))
+            .and_then(|m| Versioning::new(m.as_str()))
+            .ok_or_else(|| anyhow!("No version in title {:?} of newest entry", title))?
+    }
+}
+
+impl VersionProvider for Feed {
+    fn latest_version(&self) -> Result<Versioning> {
+        self.latest_version_cached(&mut UpstreamCache::default())
+    }
+
+    #[throws]
+    fn latest_version_cached(&self, cache: &mut UpstreamCache) -> Versioning {
+        self.version_in_feed(&cache.fetch(&self.url)?)
+            .with_context(|| format!("Failed to get version from feed at {}", self.url))?
+    }
+}
+
+/// The highest version on a web page.
+#[derive(Debug)]
+pub struct Scrape {
+    /// The URL of the page.
+    pub url: String,
+    /// The pattern to extract versions from the page.
+    pub pattern: Regex,
+}
+
+impl VersionProvider for Scrape {
+    fn latest_version(&self) -> Result<Versioning> {
+        self.latest_version_cached(&mut UpstreamCache::default())
+    }
+
+    #[throws]
+    fn latest_version_cached(&self, cache: &mut UpstreamCache) -> Versioning {
+        highest_version(&cache.fetch(&self.url)?, &self.pattern)
+            .with_context(|| format!("Failed to get version from {}", self.url))?
+    }
+}
+
+/// The highest version in the output of a command.
+#[derive(Debug)]
+pub struct CommandOutput {
+    /// The command and its arguments.
+    pub command: Vec<String>,
+    /// The pattern to extract versions from the output.
+    pub pattern: Regex,
+}
+
+impl VersionProvider for CommandOutput {
+    #[throws]
+    fn latest_version(&self) -> Versioning {
+        let (program, args) = self
+            .command
+            .split_first()
+            .ok_or_else(|| anyhow!("Empty command"))?;
+        let output = Command::new(program)
+            .args(args)
+            .checked_output()
+            .with_context(|| format!("Failed to run {:?}", self.command))?;
+        highest_version(&String::from_utf8_lossy(&output.stdout), &self.pattern)
+            .with_context(|| format!("Failed to get version from output of {:?}", self.command))?
+    }
+}
+
+/// Compile a version `pattern`.
+#[throws]
+fn version_pattern(pattern: &str) -> Regex {
+    Regex::new(pattern).with_context(|| format!("Invalid version pattern {}", pattern))?
+}
+
+/// Get the builtin version provider for `upstream`.
+///
+/// Command upstreams run arbitrary commands from manifests, so refuse them unless
+/// `allow_commands` is set.
+#[throws]
+pub fn provider(upstream: &Upstream, allow_commands: bool) -> Box<dyn VersionProvider> {
+    let provider: Box<dyn VersionProvider> = match upstream {
+        Upstream::GitHub { repo } => Box::new(GitHubReleases { repo: repo.clone() }),
+        Upstream::GitLab { project } => Box::new(GitLabReleases {
+            project: project.clone(),
+        }),
+        Upstream::Feed { url, pattern } => Box::new(Feed {
+            url: url.clone(),
+            pattern: version_pattern(pattern)?,
+        }),
+        Upstream::Scrape { url, pattern } => Box::new(Scrape {
+            url: url.clone(),
+            pattern: version_pattern(pattern)?,
+        }),
+        Upstream::Command { command, .. } if !allow_commands => throw!(anyhow!(
+            "Refusing to run upstream command {:?}, allow upstream commands with --allow-commands",
+            command
+        )),
+        Upstream::Command { command, pattern } => Box::new(CommandOutput {
+            command: command.clone(),
+            pattern: version_pattern(pattern)?,
+        }),
+    };
+    provider
+}
+
+/// Get the latest upstream version of `manifest`, fetching URLs through `cache`.
+///
+/// Only run upstream commands if `allow_commands` is set, see [`provider`].  Return `None` if
+/// `manifest` has no upstream.
+#[throws]
+pub fn upstream_version(
+    manifest: &Manifest,
+    cache: &mut UpstreamCache,
+    allow_commands: bool,
+) -> Option<Versioning> {
+    match &manifest.upstream {
+        None => None,
+        Some(upstream) => Some(
+            provider(upstream, allow_commands)?
+                .latest_version_cached(cache)
+                .with_context(|| {
+                    format!("Failed to get upstream version of {}", manifest.info.name)
+                })?,
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn version_from_release_tag() {
+        assert_eq!(
+            version_from_tag("v12.1.1").unwrap(),
+            Versioning::new("12.1.1").unwrap()
+        );
+        assert_eq!(
+            version_from_tag("jq-1.6").unwrap(),
+            Versioning::new("1.6").unwrap()
+        );
+        assert!(version_from_tag("nightly").is_err());
+    }
+
+    #[test]
+    fn highest_version_in_text() {
+        let pattern = Regex::new(r"foo-(\d+(?:\.\d+)+)\.tar\.gz").unwrap();
+        let text = "foo-1.9.0.tar.gz foo-1.10.0.tar.gz foo-1.2.0.tar.gz bar-2.0.0.tar.gz";
+        assert_eq!(
+            highest_version(text, &pattern).unwrap(),
+            Versioning::new("1.10.0").unwrap()
+        );
+        assert!(highest_version("nothing", &pattern).is_err());
+    }
+
+    #[test]
+    fn version_in_atom_feed() {
+        let feed = Feed {
+            url: "https://example.com/releases.atom".to_string(),
+            pattern: version_pattern(r"Release (\S+)").unwrap(),
+        };
+        let atom = r#"<?xml version="1.0" encoding="UTF-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+  <title>Releases</title>
+  <entry><title type="text">Release 2.1.0</title></entry>
+  <entry><title type="text">Release 2.0.0</title></entry>
+</feed>"#;
+        assert_eq!(
+            feed.version_in_feed(atom).unwrap(),
+            Versioning::new("2.1.0").unwrap()
+        );
+    }
+
+    #[test]
+    fn version_from_command_output() {
+        let provider = CommandOutput {
+            command: vec!["echo".to_string(), "1.2.0 1.10.1 1.9.9".to_string()],
+            pattern: version_pattern(r"(\d+(?:\.\d+)+)").unwrap(),
+        };
+        assert_eq!(
+            provider.latest_version().unwrap(),
+            Versioning::new("1.10.1").unwrap()
+        );
+    }
+
+    #[test]
+    fn no_upstream_version_without_upstream() {
+        let manifest = Manifest::read_from_path("tests/manifests/shfmt.toml").unwrap();
+        let mut cache = UpstreamCache::default();
+        assert!(upstream_version(&manifest, &mut cache, false)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn etag_of_last_response() {
+        let headers = "HTTP/2 302\r\nlocation: https://example.com/b\r\netag: \"a\"\r\n\r\n\
+                       HTTP/2 200\r\nContent-Type: text/plain\r\nETag: W/\"b\"\r\n\r\n";
+        assert_eq!(etag_from_headers(headers), Some("W/\"b\"".to_string()));
+        assert_eq!(etag_from_headers("HTTP/2 200\r\n\r\n"), None);
+    }
+
+    #[test]
+    fn use_fresh_cached_responses() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("upstream.json");
+        let mut cache = UpstreamCache::open(path.clone(), DEFAULT_UPSTREAM_CACHE_TTL).unwrap();
+        let url = "https://example.invalid/releases";
+        cache.responses.insert(
+            url.to_string(),
+            CachedResponse {
+                etag: Some("\"abc\"".to_string()),
+                fetched_at: now(),
+                body: "foo-1.2.0.tar.gz".to_string(),
+            },
+        );
+        cache.save().unwrap();
+
+        let mut cache = UpstreamCache::open(path, DEFAULT_UPSTREAM_CACHE_TTL).unwrap();
+        let scrape = Scrape {
+            url: url.to_string(),
+            pattern: version_pattern(r"foo-(\d+(?:\.\d+)+)\.tar\.gz").unwrap(),
+        };
+        assert_eq!(
+            scrape.latest_version_cached(&mut cache).unwrap(),
+            Versioning::new("1.2.0").unwrap()
+        );
+        assert_eq!(cache.hits(), 1);
+    }
+
+    #[test]
+    fn refuse_upstream_commands_unless_allowed() {
+        let upstream = Upstream::Command {
+            command: vec!["echo".to_string(), "foo-1.2.3".to_string()],
+            pattern: r"foo-(\d+(?:\.\d+)+)".to_string(),
+        };
+        let error = provider(&upstream, false).err().unwrap();
+        assert!(error.to_string().contains("--allow-commands"));
+        assert_eq!(
+            provider(&upstream, true).unwrap().latest_version().unwrap(),
+            Versioning::new("1.2.3").unwrap()
+        );
+    }
+}
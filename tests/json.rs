@@ -0,0 +1,90 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Machine-readable output of the homebins binary.
+
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+use sha2::Digest;
+
+/// Run homebins with `args` in the home directory `home`.
+///
+/// Point all XDG directories into `home`, to keep homebins away from the real home directory.
+fn homebins(home: &Path, args: &[&str]) -> Output {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_homebins"));
+    for name in homebins::INSTALL_DIRS_ENV_VARS.iter() {
+        command.env_remove(name);
+    }
+    command
+        .args(args)
+        .env("HOME", home)
+        .env("XDG_CONFIG_HOME", home.join(".config"))
+        .env("XDG_DATA_HOME", home.join(".local/share"))
+        .env("XDG_CACHE_HOME", home.join(".cache"))
+        .stdin(Stdio::null())
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn install_json_with_hook_output() {
+    let home = tempfile::tempdir().unwrap();
+    let remote = home.path().join("remote");
+    std::fs::create_dir(&remote).unwrap();
+    let binary = b"#!/bin/sh\necho 'hello 1.0.0'\n";
+    std::fs::write(remote.join("hello"), binary).unwrap();
+    let manifests = home.path().join("manifests");
+    std::fs::create_dir(&manifests).unwrap();
+    std::fs::write(
+        manifests.join("hello.toml"),
+        format!(
+            r#"[info]
+name = "hello"
+version = "1.0.0"
+url = "https://example.com/hello"
+license = "MIT"
+
+[discover]
+binary = "hello"
+version_check.args = ["--version"]
+version_check.pattern = "hello (\\S+)"
+
+[[install]]
+download = "file://{}/hello"
+checksums.sha256 = "{}"
+name = "hello"
+type = "bin"
+
+[[install.post]]
+command = ["sh", "-c", "echo hook output"]
+"#,
+            remote.display(),
+            hex::encode(sha2::Sha256::digest(binary))
+        ),
+    )
+    .unwrap();
+    let config_dir = home.path().join(".config/homebins");
+    std::fs::create_dir_all(&config_dir).unwrap();
+    std::fs::write(
+        config_dir.join("config.toml"),
+        format!(
+            "[[repos]]\nname = \"local\"\nurl = \"{}\"\n",
+            manifests.display()
+        ),
+    )
+    .unwrap();
+
+    let output = homebins(home.path(), &["install", "--json", "hello"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}", stderr);
+    assert!(home.path().join(".local/bin/hello").is_file());
+    // Status output, including the output of hooks, goes to stderr, and only the summary to
+    // stdout
+    assert!(stderr.contains("hook output"), "{}", stderr);
+    let summary: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(summary["installed"], serde_json::json!(["hello"]));
+}